@@ -468,6 +468,7 @@ class Dialog2FaField extends ValidationField {
     this.errorText,
     this.readyCallback,
     this.onChanged,
+    this.acceptRecoveryCode = false,
   }) : super(key: key);
 
   final TextEditingController controller;
@@ -478,7 +479,12 @@ class Dialog2FaField extends ValidationField {
   final String? errorText;
   final VoidCallback? readyCallback;
   final VoidCallback? onChanged;
-  final errMsg = translate('2FA code must be 6 digits.');
+  // Also accept 7 or 8 digit codes of other devices and recovery codes, which are 16 letters and
+  // digits with optional dashes, e.g. ABCD-EFGH-JKLM-NPQR.
+  final bool acceptRecoveryCode;
+  late final errMsg = translate(acceptRecoveryCode
+      ? '2fa-code-or-recovery-code-tip'
+      : '2FA code must be 6 digits.');
 
   @override
   Widget build(BuildContext context) {
@@ -491,18 +497,27 @@ class Dialog2FaField extends ValidationField {
       hintText: hintText,
       readyCallback: readyCallback,
       onChanged: _onChanged,
-      keyboardType: TextInputType.number,
+      keyboardType:
+          acceptRecoveryCode ? TextInputType.text : TextInputType.number,
       inputFormatters: [
-        FilteringTextInputFormatter.allow(RegExp(r'[0-9]')),
+        FilteringTextInputFormatter.allow(
+            RegExp(acceptRecoveryCode ? r'[0-9A-Za-z\- ]' : r'[0-9]')),
       ],
     );
   }
 
   String get text => controller.text;
   bool get isAllDigits => text.codeUnits.every((e) => e >= 48 && e <= 57);
+  bool get isRecoveryCode =>
+      text.replaceAll(RegExp(r'[\- ]'), '').length == 16 &&
+      RegExp(r'^[0-9A-Za-z\- ]+$').hasMatch(text);
+
+  int get maxLength => acceptRecoveryCode ? 19 : 6;
 
   @override
-  bool get isReady => text.length == 6 && isAllDigits;
+  bool get isReady => acceptRecoveryCode
+      ? (isAllDigits && [6, 7, 8].contains(text.length)) || isRecoveryCode
+      : isAllDigits && text.length == 6;
 
   @override
   String? validate() => isReady ? null : errMsg;
@@ -510,12 +525,12 @@ class Dialog2FaField extends ValidationField {
   _onChanged(StateSetter setState, SimpleWrapper<String?> errText) {
     onChanged?.call();
 
-    if (text.length > 6) {
+    if (text.length > maxLength) {
       setState(() => errText.value = errMsg);
       return;
     }
 
-    if (!isAllDigits) {
+    if (!isAllDigits && !acceptRecoveryCode) {
       setState(() => errText.value = errMsg);
       return;
     }
@@ -1960,6 +1975,8 @@ void changeBot({Function()? callback}) async {
 void change2fa({Function()? callback}) async {
   if (bind.mainHasValid2FaSync()) {
     await bind.mainSetOption(key: "2fa", value: "");
+    await bind.mainSetOption(key: "2fa-devices", value: "");
+    await bind.mainSetOption(key: "2fa-recovery-codes", value: "");
     await bind.mainClearTrustedDevices();
     callback?.call();
     return;
@@ -2050,6 +2067,7 @@ void enter2FaDialog(
       controller: controller,
       title: translate('Verification code'),
       onChanged: () => submitReady.value = codeField.isReady,
+      acceptRecoveryCode: true,
     );
 
     final trustField = Obx(() => CheckboxListTile(
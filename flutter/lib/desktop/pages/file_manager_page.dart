@@ -276,6 +276,41 @@ class _FileManagerPageState extends State<FileManagerPage>
                         Row(
                           mainAxisAlignment: MainAxisAlignment.end,
                           children: [
+                            Offstage(
+                              offstage: isWeb ||
+                                  item.type != JobType.transfer ||
+                                  item.state != JobState.inProgress,
+                              child: MenuButton(
+                                tooltip: translate('Speed limit'),
+                                onPressed: () {
+                                  jobController.editJobLimit(item.id);
+                                },
+                                child: Icon(
+                                  Icons.speed_rounded,
+                                  color: Colors.white,
+                                ),
+                                color: MyTheme.accent,
+                                hoverColor: MyTheme.accent80,
+                              ),
+                            ),
+                            Offstage(
+                              offstage: isWeb ||
+                                  item.type != JobType.transfer ||
+                                  item.state != JobState.done,
+                              child: MenuButton(
+                                tooltip:
+                                    '${translate('Export manifest')}\n${translate('export-manifest-tip')}',
+                                onPressed: () {
+                                  jobController.exportManifest(item.id);
+                                },
+                                child: Icon(
+                                  Icons.receipt_long_rounded,
+                                  color: Colors.white,
+                                ),
+                                color: MyTheme.accent,
+                                hoverColor: MyTheme.accent80,
+                              ),
+                            ),
                             Offstage(
                               offstage: item.state != JobState.paused,
                               child: MenuButton(
@@ -797,6 +832,40 @@ class _FileManagerViewState extends State<FileManagerView> {
                       color: Theme.of(context).cardColor,
                       hoverColor: Theme.of(context).hoverColor,
                     ),
+                    if (!isLocal && !isWeb)
+                      MenuButton(
+                        tooltip: translate('Synchronize folders'),
+                        onPressed: () => _ffi.fileModel.syncFolders(),
+                        child: Icon(
+                          Icons.sync_rounded,
+                          color: Theme.of(context).tabBarTheme.labelColor,
+                        ),
+                        color: Theme.of(context).cardColor,
+                        hoverColor: Theme.of(context).hoverColor,
+                      ),
+                    if (!isLocal && !isWeb)
+                      MenuButton(
+                        tooltip: translate('Search remote files'),
+                        onPressed: () => _ffi.fileModel.searchRemoteFiles(),
+                        child: Icon(
+                          Icons.manage_search_rounded,
+                          color: Theme.of(context).tabBarTheme.labelColor,
+                        ),
+                        color: Theme.of(context).cardColor,
+                        hoverColor: Theme.of(context).hoverColor,
+                      ),
+                    // The limits are options of this device.
+                    if (isLocal && !isWeb)
+                      MenuButton(
+                        tooltip: translate('Transfer limits'),
+                        onPressed: () => _ffi.fileModel.editTransferLimits(),
+                        child: Icon(
+                          Icons.speed_rounded,
+                          color: Theme.of(context).tabBarTheme.labelColor,
+                        ),
+                        color: Theme.of(context).cardColor,
+                        hoverColor: Theme.of(context).hoverColor,
+                      ),
                     Obx(() => MenuButton(
                           tooltip: translate('Delete'),
                           onPressed: SelectedItems.valid(selectedItems.items)
@@ -972,6 +1041,21 @@ class _FileManagerViewState extends State<FileManagerView> {
         padding: kDesktopMenuPadding,
         dismissOnClicked: true,
       ),
+      if (!isWeb)
+        MenuEntrySwitch<String>(
+          switchType: SwitchType.scheckbox,
+          text: translate("Transfer changed blocks only"),
+          getter: () async {
+            return bind.mainGetLocalOption(key: kOptionFileTransferDelta) ==
+                'Y';
+          },
+          setter: (bool v) async {
+            await bind.mainSetLocalOption(
+                key: kOptionFileTransferDelta, value: v ? 'Y' : '');
+          },
+          padding: kDesktopMenuPadding,
+          dismissOnClicked: true,
+        ),
       MenuEntryButton(
           childBuilder: (style) => Text(translate("Select All"), style: style),
           proc: () => setState(() =>
@@ -945,6 +945,91 @@ class _CmControlPanel extends StatelessWidget {
             ],
           ),
         ),
+        Offstage(
+          offstage: client.permissionTemplate.isEmpty,
+          child: Column(
+            children: [
+              Text(
+                '${translate('Permission template')}: ${client.permissionTemplate} (${client.permissionTemplatePermissions.join(', ')})',
+              ).marginOnly(bottom: 4),
+              Row(
+                children: [
+                  Expanded(
+                    child: buildButton(context,
+                        color: MyTheme.accent,
+                        onClick: () =>
+                            model.handlePermissionTemplate(client, true),
+                        icon: Icon(
+                          Icons.check_rounded,
+                          color: Colors.white,
+                          size: 14,
+                        ),
+                        text: "Accept",
+                        textColor: Colors.white),
+                  ),
+                  Expanded(
+                    child: buildButton(
+                      context,
+                      color: Colors.red,
+                      onClick: () =>
+                          model.handlePermissionTemplate(client, false),
+                      icon: Icon(
+                        Icons.close_rounded,
+                        color: Colors.white,
+                        size: 14,
+                      ),
+                      text: "Dismiss",
+                      textColor: Colors.white,
+                    ),
+                  )
+                ],
+              ),
+            ],
+          ),
+        ),
+        Offstage(
+          offstage: client.sessionExtension <= 0,
+          child: Column(
+            children: [
+              Text(
+                translate(
+                    'extend-session-by-{${client.sessionExtension}}-minutes-tip'),
+              ).marginOnly(bottom: 4),
+              Row(
+                children: [
+                  Expanded(
+                    child: buildButton(context,
+                        color: MyTheme.accent,
+                        onClick: () =>
+                            model.handleSessionExtension(client, true),
+                        icon: Icon(
+                          Icons.more_time_rounded,
+                          color: Colors.white,
+                          size: 14,
+                        ),
+                        text: "Accept",
+                        textColor: Colors.white),
+                  ),
+                  Expanded(
+                    child: buildButton(
+                      context,
+                      color: Colors.red,
+                      onClick: () =>
+                          model.handleSessionExtension(client, false),
+                      icon: Icon(
+                        Icons.close_rounded,
+                        color: Colors.white,
+                        size: 14,
+                      ),
+                      text: "Dismiss",
+                      textColor: Colors.white,
+                    ),
+                  )
+                ],
+              ),
+            ],
+          ),
+        ),
         Offstage(
           offstage: !client.fromSwitch,
           child: buildButton(context,
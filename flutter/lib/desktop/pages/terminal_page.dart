@@ -17,6 +17,7 @@ class TerminalPage extends StatefulWidget {
     required this.terminalId,
     this.forceRelay,
     this.connToken,
+    this.profile = '',
   }) : super(key: key);
   final String id;
   final String? password;
@@ -25,6 +26,7 @@ class TerminalPage extends StatefulWidget {
   final bool? isSharedPassword;
   final String? connToken;
   final int terminalId;
+  final String profile;
 
   @override
   State<TerminalPage> createState() => _TerminalPageState();
@@ -49,7 +51,7 @@ class _TerminalPageState extends State<TerminalPage>
     );
 
     // Create terminal model with specific terminal ID
-    _terminalModel = TerminalModel(_ffi, widget.terminalId);
+    _terminalModel = TerminalModel(_ffi, widget.terminalId, widget.profile);
     debugPrint(
         '[TerminalPage] Terminal model created for terminal ${widget.terminalId}');
 
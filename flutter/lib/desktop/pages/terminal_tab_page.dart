@@ -59,11 +59,14 @@ class _TerminalTabPageState extends State<TerminalTabPage> {
     bool? isSharedPassword,
     bool? forceRelay,
     String? connToken,
+    String profile = '',
   }) {
     final tabKey = '${peerId}_$terminalId';
     return TabInfo(
       key: tabKey,
-      label: '$peerId #$terminalId',
+      label: profile.isEmpty
+          ? '$peerId #$terminalId'
+          : '$peerId #$terminalId ($profile)',
       selectedIcon: selectedIcon,
       unselectedIcon: unselectedIcon,
       onTabCloseButton: () async {
@@ -87,6 +90,7 @@ class _TerminalTabPageState extends State<TerminalTabPage> {
         tabController: tabController,
         forceRelay: forceRelay,
         connToken: connToken,
+        profile: profile,
       ),
     );
   }
@@ -110,6 +114,28 @@ class _TerminalTabPageState extends State<TerminalTabPage> {
       padding: padding,
     ));
 
+    // The shell profiles published by the peer.
+    final profiles = TerminalConnectionManager.getExistingConnection(peerId)
+        ?.ffiModel
+        .pi
+        .platformAdditions[kPlatformAdditionsTerminalShellProfiles];
+    if (profiles is List) {
+      for (final profile in profiles.whereType<String>()) {
+        menu.add(MenuEntryButton<String>(
+          childBuilder: (TextStyle? style) => Text(
+            '${translate('New tab')}: $profile',
+            style: style,
+          ),
+          proc: () {
+            _addNewTerminal(peerId, profile: profile);
+            cancelFunc();
+            BotToast.cleanAll();
+          },
+          padding: padding,
+        ));
+      }
+    }
+
     menu.add(MenuEntryDivider());
 
     menu.add(MenuEntrySwitch<String>(
@@ -316,7 +342,7 @@ class _TerminalTabPageState extends State<TerminalTabPage> {
     return false;
   }
 
-  void _addNewTerminal(String peerId, {int? terminalId}) {
+  void _addNewTerminal(String peerId, {int? terminalId, String profile = ''}) {
     // Find first tab for this peer to get connection parameters
     final firstTab = tabController.state.value.tabs.firstWhere(
       (tab) => tab.key.startsWith('$peerId\_'),
@@ -334,6 +360,7 @@ class _TerminalTabPageState extends State<TerminalTabPage> {
         isSharedPassword: page.isSharedPassword,
         forceRelay: page.forceRelay,
         connToken: page.connToken,
+        profile: profile,
       ));
     }
   }
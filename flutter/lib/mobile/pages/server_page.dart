@@ -688,6 +688,11 @@ class ConnectionManager extends StatelessWidget {
                       : _buildNewConnectionHint(serverModel, client),
                   if (client.incomingVoiceCall && !client.inVoiceCall)
                     ..._buildNewVoiceCallHint(context, serverModel, client),
+                  if (client.authorized && client.permissionTemplate.isNotEmpty)
+                    ..._buildPermissionTemplateHint(
+                        context, serverModel, client),
+                  if (client.authorized && client.sessionExtension > 0)
+                    ..._buildSessionExtensionHint(context, serverModel, client),
                 ])))
             .toList());
   }
@@ -749,6 +754,52 @@ class ConnectionManager extends StatelessWidget {
     ]);
   }
 
+  List<Widget> _buildPermissionTemplateHint(
+      BuildContext context, ServerModel serverModel, Client client) {
+    return [
+      Text(
+        '${translate('Permission template')}: ${client.permissionTemplate} (${client.permissionTemplatePermissions.join(', ')})',
+        style: Theme.of(context).textTheme.bodyMedium,
+      ).marginOnly(bottom: 5),
+      Row(mainAxisAlignment: MainAxisAlignment.end, children: [
+        TextButton(
+            child: Text(translate("Dismiss")),
+            onPressed: () {
+              serverModel.handlePermissionTemplate(client, false);
+            }).marginOnly(right: 15),
+        ElevatedButton.icon(
+            icon: const Icon(Icons.check),
+            label: Text(translate("Accept")),
+            onPressed: () {
+              serverModel.handlePermissionTemplate(client, true);
+            }),
+      ]),
+    ];
+  }
+
+  List<Widget> _buildSessionExtensionHint(
+      BuildContext context, ServerModel serverModel, Client client) {
+    return [
+      Text(
+        translate('extend-session-by-{${client.sessionExtension}}-minutes-tip'),
+        style: Theme.of(context).textTheme.bodyMedium,
+      ).marginOnly(bottom: 5),
+      Row(mainAxisAlignment: MainAxisAlignment.end, children: [
+        TextButton(
+            child: Text(translate("Dismiss")),
+            onPressed: () {
+              serverModel.handleSessionExtension(client, false);
+            }).marginOnly(right: 15),
+        ElevatedButton.icon(
+            icon: const Icon(Icons.check),
+            label: Text(translate("Accept")),
+            onPressed: () {
+              serverModel.handleSessionExtension(client, true);
+            }),
+      ]),
+    ];
+  }
+
   List<Widget> _buildNewVoiceCallHint(
       BuildContext context, ServerModel serverModel, Client client) {
     return [
@@ -1,7 +1,9 @@
 import 'dart:async';
 import 'dart:convert';
 
+import 'package:file_picker/file_picker.dart';
 import 'package:flutter/material.dart';
+import 'package:flutter/services.dart';
 import 'package:flutter_hbb/common.dart';
 import 'package:flutter_hbb/common/widgets/dialog.dart';
 import 'package:flutter_hbb/utils/event_loop.dart';
@@ -109,6 +111,522 @@ class FileModel {
     fileFetcher.tryCompleteTask(evt['value'], evt['is_local']);
   }
 
+  final _syncPlans = <int, Completer<Map<String, dynamic>>>{};
+
+  void receiveSyncPlan(Map<String, dynamic> evt) {
+    final id = int.tryParse(evt['id'] ?? '');
+    _syncPlans.remove(id)?.complete(evt);
+  }
+
+  /// Synchronize the current local and remote directories, with a preview of the plan.
+  Future<void> syncFolders() async {
+    final dialogManager = parent.target?.dialogManager;
+    if (dialogManager == null) return;
+    final local = localController.directory.value.path;
+    final remote = remoteController.directory.value.path;
+    var direction = 'upload';
+    var conflict = 'newer';
+    var delete = false;
+    final ok = await dialogManager.show<bool>((setState, close, context) {
+      submit() => close(true);
+      cancel() => close(false);
+      radio(String value, String label) => RadioListTile<String>(
+            contentPadding: EdgeInsets.zero,
+            dense: true,
+            title: Text(translate(label)),
+            value: value,
+            groupValue: direction,
+            onChanged: (v) => setState(() => direction = v ?? direction),
+          );
+      return CustomAlertDialog(
+        title: Text(translate('Synchronize folders')),
+        contentBoxConstraints: BoxConstraints(minWidth: 400, maxWidth: 400),
+        content: Column(
+          crossAxisAlignment: CrossAxisAlignment.start,
+          mainAxisSize: MainAxisSize.min,
+          children: [
+            Text('$local\n$remote', style: const TextStyle(fontSize: 12)),
+            radio('upload', 'Local to remote'),
+            radio('download', 'Remote to local'),
+            radio('both', 'Both directions'),
+            if (direction == 'both')
+              Row(
+                children: [
+                  Text(translate('On conflict')),
+                  const SizedBox(width: 10),
+                  DropdownButton<String>(
+                    value: conflict,
+                    items: const [
+                      ('newer', 'Newer wins'),
+                      ('local', 'Local wins'),
+                      ('remote', 'Remote wins'),
+                      ('skip', 'Skip'),
+                    ]
+                        .map((e) => DropdownMenuItem(
+                            value: e.$1, child: Text(translate(e.$2))))
+                        .toList(),
+                    onChanged: (v) => setState(() => conflict = v ?? conflict),
+                  ),
+                ],
+              ),
+            CheckboxListTile(
+              contentPadding: EdgeInsets.zero,
+              dense: true,
+              controlAffinity: ListTileControlAffinity.leading,
+              title: Text(translate('Delete files missing on the other side')),
+              value: delete,
+              onChanged: (v) => setState(() => delete = v ?? false),
+            ),
+            Text(translate('sync-compare-tip'),
+                style: const TextStyle(fontSize: 12)),
+          ],
+        ),
+        actions: [
+          dialogButton('Cancel',
+              icon: Icon(Icons.close_rounded),
+              onPressed: cancel,
+              isOutline: true),
+          dialogButton('OK', icon: Icon(Icons.done_rounded), onPressed: submit),
+        ],
+        onSubmit: submit,
+        onCancel: cancel,
+      );
+    });
+    if (ok != true) return;
+
+    final showHidden = remoteController.options.value.showHidden;
+    final id = JobController.jobID.next();
+    final completer = Completer<Map<String, dynamic>>();
+    _syncPlans[id] = completer;
+    dialogManager.showLoading(translate('Waiting'));
+    await bind.sessionSyncPlan(
+        sessionId: sessionId,
+        actId: id,
+        local: local,
+        remote: remote,
+        options: jsonEncode({
+          'direction': direction,
+          'conflict': conflict,
+          'delete': delete,
+          'include_hidden': showHidden,
+        }));
+    final evt = await completer.future.timeout(const Duration(seconds: 120),
+        onTimeout: () {
+      _syncPlans.remove(id);
+      return {'err': 'Timeout'};
+    });
+    dialogManager.dismissByTag(tag);
+    final err = evt['err'] ?? '';
+    if (err.isNotEmpty) {
+      showToast(translate(err));
+      return;
+    }
+    final items = List<Map<String, dynamic>>.from(jsonDecode(evt['plan']));
+    if (items.isEmpty) {
+      showToast(translate('Nothing to synchronize'));
+      return;
+    }
+    if (await _showSyncPlan(items) == true) {
+      await _runSyncPlan(id, items, showHidden);
+    }
+  }
+
+  static IconData _syncActionIcon(String action) {
+    switch (action) {
+      case 'upload':
+        return Icons.arrow_forward_rounded;
+      case 'download':
+        return Icons.arrow_back_rounded;
+      case 'delete_local':
+      case 'delete_remote':
+        return Icons.delete_outline_rounded;
+      default:
+        return Icons.warning_amber_rounded;
+    }
+  }
+
+  Future<bool?> _showSyncPlan(List<Map<String, dynamic>> items) async {
+    final counts = <String, int>{};
+    for (var item in items) {
+      counts[item['action']] = (counts[item['action']] ?? 0) + 1;
+    }
+    return await parent.target?.dialogManager
+        .show<bool>((setState, close, context) {
+      submit() => close(true);
+      cancel() => close(false);
+      return CustomAlertDialog(
+        title: Text(translate('Synchronize folders')),
+        contentBoxConstraints: BoxConstraints(minWidth: 500, maxWidth: 500),
+        content: Column(
+          crossAxisAlignment: CrossAxisAlignment.start,
+          mainAxisSize: MainAxisSize.min,
+          children: [
+            Wrap(
+              spacing: 16,
+              children: counts.entries
+                  .map((e) => Row(mainAxisSize: MainAxisSize.min, children: [
+                        Icon(_syncActionIcon(e.key), size: 16),
+                        Text(' ${e.key.replaceAll('_', ' ')}: ${e.value}'),
+                      ]))
+                  .toList(),
+            ),
+            const SizedBox(height: 8),
+            SizedBox(
+              height: 300,
+              child: ListView.builder(
+                itemCount: items.length,
+                itemBuilder: (context, i) => Row(
+                  children: [
+                    Icon(_syncActionIcon(items[i]['action']), size: 16),
+                    Expanded(
+                      child: Text(items[i]['name'],
+                              overflow: TextOverflow.ellipsis)
+                          .paddingOnly(left: 6),
+                    ),
+                    Text(items[i]['reason'],
+                        style: const TextStyle(fontSize: 12)),
+                  ],
+                ),
+              ),
+            ),
+          ],
+        ),
+        actions: [
+          dialogButton('Cancel',
+              icon: Icon(Icons.close_rounded),
+              onPressed: cancel,
+              isOutline: true),
+          dialogButton('OK', icon: Icon(Icons.done_rounded), onPressed: submit),
+        ],
+        onSubmit: submit,
+        onCancel: cancel,
+      );
+    });
+  }
+
+  Future<void> _runSyncPlan(
+      int id, List<Map<String, dynamic>> items, bool showHidden) async {
+    final jobs = <(Map<String, dynamic>, Entry, int)>[];
+    for (var item in items) {
+      final String action = item['action'];
+      final isRemote = action == 'download' || action == 'delete_remote';
+      final entry = Entry()
+        ..name = item['name']
+        ..path = isRemote ? item['remote'] : item['local']
+        ..size = item['size'];
+      if (action == 'upload' || action == 'download') {
+        jobs.add(
+            (item, entry, jobController.addTransferJob(entry, isRemote)));
+      } else if (action == 'delete_local' || action == 'delete_remote') {
+        jobs.add(
+            (item, entry, jobController.addDeleteFileJob(entry, isRemote)));
+      }
+    }
+    // The sync state is saved once the jobs are done, pass them before any ends.
+    await bind.sessionSyncRun(
+        sessionId: sessionId,
+        actId: id,
+        jobs: jsonEncode({for (var j in jobs) j.$1['name']: j.$3}));
+    for (var (item, entry, jobID) in jobs) {
+      final String action = item['action'];
+      final isRemote = action == 'download' || action == 'delete_remote';
+      if (action == 'upload' || action == 'download') {
+        bind.sessionSendFiles(
+            sessionId: sessionId,
+            actId: jobID,
+            path: entry.path,
+            to: isRemote ? item['local'] : item['remote'],
+            fileNum: 0,
+            includeHidden: showHidden,
+            isRemote: isRemote,
+            isDir: false);
+      } else {
+        bind.sessionRemoveFile(
+            sessionId: sessionId,
+            actId: jobID,
+            path: entry.path,
+            fileNum: 0,
+            isRemote: isRemote);
+      }
+    }
+  }
+
+  final _searches = <int, void Function(Map<String, dynamic>)>{};
+
+  void receiveSearchResult(Map<String, dynamic> evt) {
+    final id = int.tryParse(evt['id'] ?? '');
+    _searches[id]?.call(evt);
+  }
+
+  /// Search the current remote directory on the remote side and download the selected files.
+  Future<void> searchRemoteFiles() async {
+    final dialogManager = parent.target?.dialogManager;
+    if (dialogManager == null) return;
+    final dir = remoteController.directory.value.path;
+    final pattern = TextEditingController();
+    final content = TextEditingController();
+    final minSize = TextEditingController();
+    final maxSize = TextEditingController();
+    final days = TextEditingController();
+    var regex = false;
+    var caseSensitive = false;
+    final ok = await dialogManager.show<bool>((setState, close, context) {
+      submit() => close(true);
+      cancel() => close(false);
+      checkbox(String label, bool value, void Function(bool) onChanged) =>
+          CheckboxListTile(
+            contentPadding: EdgeInsets.zero,
+            dense: true,
+            controlAffinity: ListTileControlAffinity.leading,
+            title: Text(translate(label)),
+            value: value,
+            onChanged: (v) => setState(() => onChanged(v ?? false)),
+          );
+      return CustomAlertDialog(
+        title: Text(translate('Search remote files')),
+        contentBoxConstraints: BoxConstraints(minWidth: 400, maxWidth: 400),
+        content: Column(
+          crossAxisAlignment: CrossAxisAlignment.start,
+          mainAxisSize: MainAxisSize.min,
+          children: [
+            Text(dir, style: const TextStyle(fontSize: 12)),
+            DialogTextField(
+              title: translate('Name'),
+              hintText: regex ? '^log/.*\\.txt\$' : '*.log',
+              controller: pattern,
+            ),
+            checkbox('Regular expression', regex, (v) => regex = v),
+            checkbox('Case sensitive', caseSensitive, (v) => caseSensitive = v),
+            DialogTextField(
+              title: translate('Containing text'),
+              controller: content,
+            ),
+            Row(
+              children: [
+                Expanded(child: _numberField('Min size (KB)', minSize)),
+                const SizedBox(width: 10),
+                Expanded(child: _numberField('Max size (KB)', maxSize)),
+              ],
+            ),
+            _numberField('Modified in the last days', days),
+            Text(translate('remote-search-tip'),
+                style: const TextStyle(fontSize: 12)),
+          ],
+        ),
+        actions: [
+          dialogButton('Cancel',
+              icon: Icon(Icons.close_rounded),
+              onPressed: cancel,
+              isOutline: true),
+          dialogButton('OK', icon: Icon(Icons.done_rounded), onPressed: submit),
+        ],
+        onSubmit: submit,
+        onCancel: cancel,
+      );
+    });
+    if (ok != true) return;
+
+    final showHidden = remoteController.options.value.showHidden;
+    final min = int.tryParse(minSize.text);
+    final max = int.tryParse(maxSize.text);
+    final d = int.tryParse(days.text);
+    final id = JobController.jobID.next();
+    final items = <Map<String, dynamic>>[].obs;
+    final done = false.obs;
+    final truncated = false.obs;
+    final error = ''.obs;
+    _searches[id] = (evt) {
+      final err = evt['err'] ?? '';
+      if (err.isNotEmpty) {
+        error.value = err;
+      } else {
+        final result = jsonDecode(evt['result']);
+        items.addAll(List<Map<String, dynamic>>.from(result['items']));
+        if (result['truncated'] == true) truncated.value = true;
+        if (result['done'] != true) return;
+      }
+      done.value = true;
+      _searches.remove(id);
+    };
+    await bind.sessionSearchFiles(
+        sessionId: sessionId,
+        actId: id,
+        path: dir,
+        query: jsonEncode({
+          'pattern': pattern.text,
+          'regex': regex,
+          'case_sensitive': caseSensitive,
+          'content': content.text,
+          'include_hidden': showHidden,
+          if (min != null) 'min_size': min * 1024,
+          if (max != null) 'max_size': max * 1024,
+          if (d != null)
+            'modified_after':
+                DateTime.now().millisecondsSinceEpoch ~/ 1000 - d * 86400,
+        }));
+    final selected = await _showSearchResults(items, done, truncated, error);
+    if (_searches.remove(id) != null) {
+      bind.sessionCancelJob(sessionId: sessionId, actId: id);
+    }
+    if (selected == null || selected.isEmpty) return;
+    final entry = Entry()
+      ..name = (remoteController.options.value.isWindows
+              ? PathUtil.windowsContext
+              : PathUtil.posixContext)
+          .basename(dir)
+      ..path = dir
+      ..size = selected.fold<int>(0, (sum, item) => sum + (item['size'] as int));
+    final jobID = jobController.addTransferJob(entry, true);
+    await bind.sessionDownloadFiles(
+        sessionId: sessionId,
+        actId: jobID,
+        path: dir,
+        to: localController.directory.value.path,
+        names: jsonEncode(selected.map((item) => item['name']).toList()),
+        includeHidden: showHidden);
+  }
+
+  /// Show the results of a search as they come, return the selected ones to download.
+  Future<List<Map<String, dynamic>>?> _showSearchResults(
+      RxList<Map<String, dynamic>> items,
+      RxBool done,
+      RxBool truncated,
+      RxString error) async {
+    final selected = <int>{}.obs;
+    return await parent.target?.dialogManager
+        .show<List<Map<String, dynamic>>?>((setState, close, context) {
+      submit() => close(selected.map((i) => items[i]).toList());
+      cancel() => close(null);
+      return CustomAlertDialog(
+        title: Text(translate('Search remote files')),
+        contentBoxConstraints: BoxConstraints(minWidth: 500, maxWidth: 500),
+        content: Obx(() => Column(
+              crossAxisAlignment: CrossAxisAlignment.start,
+              mainAxisSize: MainAxisSize.min,
+              children: [
+                Row(
+                  children: [
+                    Checkbox(
+                      value: items.isNotEmpty &&
+                          selected.length == items.length,
+                      onChanged: (v) {
+                        selected.clear();
+                        if (v == true) {
+                          selected.addAll(
+                              List.generate(items.length, (i) => i));
+                        }
+                      },
+                    ),
+                    Expanded(
+                      child: Text(error.value.isNotEmpty
+                          ? translate(error.value)
+                          : done.value && items.isEmpty
+                              ? translate('No results')
+                              : '${items.length}${truncated.value ? '+' : ''} ${translate('files')}'),
+                    ),
+                    if (!done.value)
+                      const SizedBox(
+                          width: 16,
+                          height: 16,
+                          child: CircularProgressIndicator(strokeWidth: 2)),
+                  ],
+                ),
+                SizedBox(
+                  height: 300,
+                  child: ListView.builder(
+                    itemCount: items.length,
+                    itemBuilder: (context, i) => Obx(() => Row(
+                          children: [
+                            Checkbox(
+                              value: selected.contains(i),
+                              onChanged: (v) => v == true
+                                  ? selected.add(i)
+                                  : selected.remove(i),
+                            ),
+                            Expanded(
+                              child: Text(items[i]['name'],
+                                  overflow: TextOverflow.ellipsis),
+                            ),
+                            Text(
+                                readableFileSize(
+                                    (items[i]['size'] as int).toDouble()),
+                                style: const TextStyle(fontSize: 12)),
+                          ],
+                        )),
+                  ),
+                ),
+              ],
+            )),
+        actions: [
+          dialogButton('Cancel',
+              icon: Icon(Icons.close_rounded),
+              onPressed: cancel,
+              isOutline: true),
+          dialogButton('Download',
+              icon: Icon(Icons.download_rounded), onPressed: submit),
+        ],
+        onSubmit: submit,
+        onCancel: cancel,
+      );
+    });
+  }
+
+  /// Edit the bandwidth limits of the file transfers, applied to running jobs.
+  Future<void> editTransferLimits() async {
+    Map<String, dynamic> limits = {};
+    try {
+      limits = jsonDecode(
+          bind.mainGetOptionSync(key: _kOptionFileTransferLimits));
+    } catch (_) {}
+    final session = TextEditingController(
+        text: (limits['session_kbps'] ?? 0).toString());
+    final job =
+        TextEditingController(text: (limits['job_kbps'] ?? 0).toString());
+    var pause = limits['pause_while_controlling'] == true;
+    await parent.target?.dialogManager.show((setState, close, context) {
+      submit() async {
+        limits['session_kbps'] = int.tryParse(session.text) ?? 0;
+        limits['job_kbps'] = int.tryParse(job.text) ?? 0;
+        limits['pause_while_controlling'] = pause;
+        await bind.mainSetOption(
+            key: _kOptionFileTransferLimits, value: jsonEncode(limits));
+        close();
+      }
+
+      return CustomAlertDialog(
+        title: Text(translate('Transfer limits')),
+        contentBoxConstraints: BoxConstraints(minWidth: 400, maxWidth: 400),
+        content: Column(
+          crossAxisAlignment: CrossAxisAlignment.start,
+          mainAxisSize: MainAxisSize.min,
+          children: [
+            _kbpsField('Session limit (KB/s)', session),
+            _kbpsField('Job limit (KB/s)', job),
+            CheckboxListTile(
+              contentPadding: EdgeInsets.zero,
+              dense: true,
+              controlAffinity: ListTileControlAffinity.leading,
+              title: Text(translate('Pause transfers while controlling')),
+              value: pause,
+              onChanged: (v) => setState(() => pause = v ?? false),
+            ),
+            Text(translate('transfer-limits-tip'),
+                style: const TextStyle(fontSize: 12)),
+          ],
+        ),
+        actions: [
+          dialogButton('Cancel',
+              icon: Icon(Icons.close_rounded),
+              onPressed: close,
+              isOutline: true),
+          dialogButton('OK', icon: Icon(Icons.done_rounded), onPressed: submit),
+        ],
+        onSubmit: submit,
+        onCancel: close,
+      );
+    });
+  }
+
   void receiveEmptyDirs(Map<String, dynamic> evt) {
     fileFetcher.tryCompleteEmptyDirsTask(evt['value'], evt['is_local']);
   }
@@ -517,8 +1035,24 @@ class FileController {
     final toPath = otherSideData.directory.path;
     final isWindows = otherSideData.options.isWindows;
     final showHidden = otherSideData.options.showHidden;
+    final delta =
+        !isWeb && bind.mainGetLocalOption(key: kOptionFileTransferDelta) == 'Y';
     for (var from in items.items) {
       final jobID = jobController.addTransferJob(from, isRemoteToLocal);
+      // An older version of the file is on the other side, transfer the changed blocks only.
+      if (delta &&
+          from.isFile &&
+          from.size >= _kDeltaMinSize &&
+          otherSideData.directory.entries
+              .any((e) => e.isFile && e.name == from.name)) {
+        bind.sessionDeltaTransfer(
+            sessionId: sessionId,
+            actId: jobID,
+            path: from.path,
+            to: PathUtil.join(toPath, from.name, isWindows),
+            isRemote: isRemoteToLocal);
+        continue;
+      }
       bind.sessionSendFiles(
           sessionId: sessionId,
           actId: jobID,
@@ -846,6 +1380,25 @@ class FileController {
 }
 
 const _kOneWayFileTransferError = 'one-way-file-transfer-tip';
+const _kOptionFileTransferLimits = 'file-transfer-limits';
+// Smaller files are transferred whole, even with `kOptionFileTransferDelta`.
+const _kDeltaMinSize = 1024 * 1024;
+
+Widget _numberField(String title, TextEditingController controller,
+    {String? helperText}) {
+  return DialogTextField(
+    title: translate(title),
+    helperText: helperText,
+    controller: controller,
+    keyboardType: TextInputType.number,
+    inputFormatters: [FilteringTextInputFormatter.digitsOnly],
+  );
+}
+
+Widget _kbpsField(String title, TextEditingController controller) {
+  return _numberField(title, controller,
+      helperText: translate('0 means no limit'));
+}
 
 class JobController {
   static final JobID jobID = JobID();
@@ -904,6 +1457,24 @@ class JobController {
     return jobID;
   }
 
+  /// Add the job transferring a file again which did not match after job `retry_of`.
+  void retryJob(Map<String, dynamic> evt) {
+    final id = int.tryParse(evt['id'] ?? '');
+    if (id == null || getJob(id) >= 0) return;
+    final isRemoteToLocal = evt['is_remote'] == 'true';
+    final String from = evt['path'] ?? '';
+    final String to = evt['to'] ?? '';
+    jobTable.add(JobProgress()
+      ..type = JobType.transfer
+      ..fileName = path.basename(from)
+      ..jobName = from
+      ..state = JobState.inProgress
+      ..id = id
+      ..isRemoteToLocal = isRemoteToLocal
+      ..remote = isRemoteToLocal ? from : to
+      ..to = isRemoteToLocal ? to : from);
+  }
+
   void tryUpdateJobProgress(Map<String, dynamic> evt) {
     try {
       int id = int.parse(evt['id']);
@@ -1033,6 +1604,81 @@ class JobController {
     await bind.sessionCancelJob(sessionId: sessionId, actId: id);
   }
 
+  /// Export the manifest of the local files of job [id], with their SHA-256 hashes.
+  Future<void> exportManifest(int id) async {
+    final outputFile = await FilePicker.platform.saveFile(
+      dialogTitle: '${translate('Export manifest')}...',
+      fileName: 'manifest_$id.json',
+      allowedExtensions: ['json'],
+      type: FileType.custom,
+    );
+    if (outputFile == null) return;
+    await bind.sessionExportManifest(
+        sessionId: sessionId, actId: id, path: outputFile);
+  }
+
+  /// Edit the speed limit and priority of the job [id], the limit of a
+  /// download is set on the controlled side.
+  Future<void> editJobLimit(int id) async {
+    final jobIndex = getJob(id);
+    if (jobIndex == -1) return;
+    final job = jobTable[jobIndex];
+    final kbps = TextEditingController(text: job.speedLimit.toString());
+    var priority = job.priority;
+    await alogManager?.show((setState, close, context) {
+      submit() async {
+        job.speedLimit = int.tryParse(kbps.text) ?? 0;
+        job.priority = priority;
+        await bind.sessionSetJobLimit(
+            sessionId: sessionId,
+            actId: id,
+            kbps: job.speedLimit,
+            priority: priority);
+        close();
+      }
+
+      return CustomAlertDialog(
+        title: Text(translate('Speed limit')),
+        contentBoxConstraints: BoxConstraints(minWidth: 400, maxWidth: 400),
+        content: Column(
+          crossAxisAlignment: CrossAxisAlignment.start,
+          mainAxisSize: MainAxisSize.min,
+          children: [
+            Text(job.jobName, style: const TextStyle(fontSize: 12)),
+            _kbpsField('Job limit (KB/s)', kbps),
+            Row(
+              children: [
+                Text(translate('Priority')),
+                const SizedBox(width: 10),
+                DropdownButton<String>(
+                  value: priority,
+                  items: const [
+                    ('high', 'High'),
+                    ('normal', 'Normal'),
+                    ('low', 'Low'),
+                  ]
+                      .map((e) => DropdownMenuItem(
+                          value: e.$1, child: Text(translate(e.$2))))
+                      .toList(),
+                  onChanged: (v) => setState(() => priority = v ?? priority),
+                ),
+              ],
+            ),
+          ],
+        ),
+        actions: [
+          dialogButton('Cancel',
+              icon: Icon(Icons.close_rounded),
+              onPressed: close,
+              isOutline: true),
+          dialogButton('OK', icon: Icon(Icons.done_rounded), onPressed: submit),
+        ],
+        onSubmit: submit,
+        onCancel: close,
+      );
+    });
+  }
+
   void loadLastJob(Map<String, dynamic> evt) {
     debugPrint("load last job: $evt");
     Map<String, dynamic> jobDetail = json.decode(evt['value']);
@@ -1403,6 +2049,9 @@ class JobProgress {
   var showHidden = false;
   var err = "";
   int lastTransferredSize = 0;
+  // KB/s, 0 for no limit.
+  var speedLimit = 0;
+  var priority = 'normal';
 
   clear() {
     type = JobType.none;
@@ -344,6 +344,10 @@ class FfiModel with ChangeNotifier {
         parent.target?.fileModel.receiveFileDir(evt);
       } else if (name == 'empty_dirs') {
         parent.target?.fileModel.receiveEmptyDirs(evt);
+      } else if (name == 'sync_plan') {
+        parent.target?.fileModel.receiveSyncPlan(evt);
+      } else if (name == 'search_result') {
+        parent.target?.fileModel.receiveSearchResult(evt);
       } else if (name == 'job_progress') {
         parent.target?.fileModel.jobController.tryUpdateJobProgress(evt);
       } else if (name == 'job_done') {
@@ -358,6 +362,8 @@ class FfiModel with ChangeNotifier {
         parent.target?.fileModel.jobController.jobError(evt);
       } else if (name == 'override_file_confirm') {
         parent.target?.fileModel.postOverrideFileConfirm(evt);
+      } else if (name == 'retry_job') {
+        parent.target?.fileModel.jobController.retryJob(evt);
       } else if (name == 'load_last_job') {
         parent.target?.fileModel.jobController.loadLastJob(evt);
       } else if (name == 'update_folder_files') {
@@ -401,6 +407,10 @@ class FfiModel with ChangeNotifier {
         parent.target?.chatModel.onVoiceCallIncoming();
       } else if (name == 'update_voice_call_state') {
         parent.target?.serverModel.updateVoiceCallState(evt);
+      } else if (name == 'update_permission_template') {
+        parent.target?.serverModel.updatePermissionTemplate(evt);
+      } else if (name == 'update_session_extension') {
+        parent.target?.serverModel.updateSessionExtension(evt);
       } else if (name == 'fingerprint') {
         FingerprintState.find(peerId).value = evt['fingerprint'] ?? '';
       } else if (name == 'plugin_manager') {
@@ -785,6 +785,67 @@ class ServerModel with ChangeNotifier {
     }
   }
 
+  void updatePermissionTemplate(Map<String, dynamic> evt) {
+    try {
+      final client = Client.fromJson(jsonDecode(evt["client"]));
+      final index = _clients.indexWhere((element) => element.id == client.id);
+      if (index != -1) {
+        _clients[index].permissionTemplate = client.permissionTemplate;
+        _clients[index].permissionTemplatePermissions =
+            client.permissionTemplatePermissions;
+        if (client.permissionTemplate.isNotEmpty && isDesktop) {
+          Future.delayed(Duration.zero, () {
+            windowOnTop(null);
+          });
+        }
+        notifyListeners();
+      }
+    } catch (e) {
+      debugPrint("updatePermissionTemplate failed: $e");
+    }
+  }
+
+  void handlePermissionTemplate(Client client, bool approve) {
+    bind.cmHandlePermissionTemplate(id: client.id, approve: approve);
+    if (approve) {
+      final permissions = client.permissionTemplatePermissions;
+      client.keyboard = permissions.contains('keyboard');
+      client.clipboard = permissions.contains('clipboard');
+      client.audio = permissions.contains('audio');
+      client.file = permissions.contains('file');
+      client.restart = permissions.contains('restart');
+      client.recording = permissions.contains('recording');
+      client.blockInput = permissions.contains('block_input');
+    }
+    client.permissionTemplate = "";
+    client.permissionTemplatePermissions = [];
+    notifyListeners();
+  }
+
+  void updateSessionExtension(Map<String, dynamic> evt) {
+    try {
+      final client = Client.fromJson(jsonDecode(evt["client"]));
+      final index = _clients.indexWhere((element) => element.id == client.id);
+      if (index != -1) {
+        _clients[index].sessionExtension = client.sessionExtension;
+        if (client.sessionExtension > 0 && isDesktop) {
+          Future.delayed(Duration.zero, () {
+            windowOnTop(null);
+          });
+        }
+        notifyListeners();
+      }
+    } catch (e) {
+      debugPrint("updateSessionExtension failed: $e");
+    }
+  }
+
+  void handleSessionExtension(Client client, bool approve) {
+    bind.cmHandleSessionExtension(id: client.id, approve: approve);
+    client.sessionExtension = 0;
+    notifyListeners();
+  }
+
   void androidUpdatekeepScreenOn() async {
     if (!isAndroid) return;
     var floatingWindowDisabled =
@@ -835,6 +896,11 @@ class Client {
   bool fromSwitch = false;
   bool inVoiceCall = false;
   bool incomingVoiceCall = false;
+  // The permission template requested by the peer, empty if none.
+  String permissionTemplate = "";
+  List<String> permissionTemplatePermissions = [];
+  // Minutes of the session extension the peer asks for, 0 if none.
+  int sessionExtension = 0;
 
   RxInt unreadChatMessageCount = 0.obs;
 
@@ -862,6 +928,13 @@ class Client {
     fromSwitch = json['from_switch'];
     inVoiceCall = json['in_voice_call'];
     incomingVoiceCall = json['incoming_voice_call'];
+    final template = json['permission_template'];
+    if (template != null) {
+      permissionTemplate = template['name'];
+      permissionTemplatePermissions =
+          List<String>.from(template['permissions']);
+    }
+    sessionExtension = json['session_extension'] ?? 0;
   }
 
   Map<String, dynamic> toJson() {
@@ -885,6 +958,13 @@ class Client {
     data['from_switch'] = fromSwitch;
     data['in_voice_call'] = inVoiceCall;
     data['incoming_voice_call'] = incomingVoiceCall;
+    data['permission_template'] = permissionTemplate.isEmpty
+        ? null
+        : {
+            'name': permissionTemplate,
+            'permissions': permissionTemplatePermissions,
+          };
+    data['session_extension'] = sessionExtension;
     return data;
   }
 
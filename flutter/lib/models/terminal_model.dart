@@ -15,6 +15,8 @@ class TerminalModel with ChangeNotifier {
   final String id; // peer id
   final FFI parent;
   final int terminalId;
+  // The shell profile of the peer to open, the default shell if empty.
+  final String profile;
   late final Terminal terminal;
   late final TerminalController terminalController;
 
@@ -56,7 +58,8 @@ class TerminalModel with ChangeNotifier {
     }
   }
 
-  TerminalModel(this.parent, [this.terminalId = 0]) : id = parent.id {
+  TerminalModel(this.parent, [this.terminalId = 0, this.profile = ''])
+      : id = parent.id {
     terminal = Terminal(maxLines: 10000);
     terminalController = TerminalController();
 
@@ -116,13 +119,20 @@ class TerminalModel with ChangeNotifier {
     debugPrint(
         '[TerminalModel] Opening terminal $terminalId, sessionId: ${parent.sessionId}, size: ${cols}x$rows');
     try {
-      await bind
-          .sessionOpenTerminal(
-        sessionId: parent.sessionId,
-        terminalId: terminalId,
-        rows: rows,
-        cols: cols,
-      )
+      await (profile.isEmpty
+              ? bind.sessionOpenTerminal(
+                  sessionId: parent.sessionId,
+                  terminalId: terminalId,
+                  rows: rows,
+                  cols: cols,
+                )
+              : bind.sessionOpenTerminalWithProfile(
+                  sessionId: parent.sessionId,
+                  terminalId: terminalId,
+                  rows: rows,
+                  cols: cols,
+                  profile: profile,
+                ))
           .timeout(
         const Duration(seconds: 5),
         onTimeout: () {
@@ -601,6 +601,71 @@ class RustdeskImpl {
         ]));
   }
 
+  Future<void> sessionSyncPlan(
+      {required UuidValue sessionId,
+      required int actId,
+      required String local,
+      required String remote,
+      required String options,
+      dynamic hint}) {
+    throw UnimplementedError("sessionSyncPlan");
+  }
+
+  Future<void> sessionSyncRun(
+      {required UuidValue sessionId,
+      required int actId,
+      required String jobs,
+      dynamic hint}) {
+    throw UnimplementedError("sessionSyncRun");
+  }
+
+  Future<void> sessionSetJobLimit(
+      {required UuidValue sessionId,
+      required int actId,
+      required int kbps,
+      required String priority,
+      dynamic hint}) {
+    throw UnimplementedError("sessionSetJobLimit");
+  }
+
+  Future<void> sessionExportManifest(
+      {required UuidValue sessionId,
+      required int actId,
+      required String path,
+      dynamic hint}) {
+    throw UnimplementedError("sessionExportManifest");
+  }
+
+  Future<void> sessionSearchFiles(
+      {required UuidValue sessionId,
+      required int actId,
+      required String path,
+      required String query,
+      dynamic hint}) {
+    throw UnimplementedError("sessionSearchFiles");
+  }
+
+  Future<void> sessionDownloadFiles(
+      {required UuidValue sessionId,
+      required int actId,
+      required String path,
+      required String to,
+      required String names,
+      required bool includeHidden,
+      dynamic hint}) {
+    throw UnimplementedError("sessionDownloadFiles");
+  }
+
+  Future<void> sessionDeltaTransfer(
+      {required UuidValue sessionId,
+      required int actId,
+      required String path,
+      required String to,
+      required bool isRemote,
+      dynamic hint}) {
+    throw UnimplementedError("sessionDeltaTransfer");
+  }
+
   Future<void> sessionRemoveAllEmptyDirs(
       {required UuidValue sessionId,
       required int actId,
@@ -1135,6 +1200,16 @@ class RustdeskImpl {
     throw UnimplementedError("cmHandleIncomingVoiceCall");
   }
 
+  Future<void> cmHandlePermissionTemplate(
+      {required int id, required bool approve, dynamic hint}) {
+    throw UnimplementedError("cmHandlePermissionTemplate");
+  }
+
+  Future<void> cmHandleSessionExtension(
+      {required int id, required bool approve, dynamic hint}) {
+    throw UnimplementedError("cmHandleSessionExtension");
+  }
+
   Future<void> cmCloseVoiceCall({required int id, dynamic hint}) {
     throw UnimplementedError("cmCloseVoiceCall");
   }
@@ -1939,6 +2014,16 @@ class RustdeskImpl {
         ]));
   }
 
+  Future<void> sessionOpenTerminalWithProfile(
+      {required UuidValue sessionId,
+      required int terminalId,
+      required int rows,
+      required int cols,
+      required String profile,
+      dynamic hint}) {
+    throw UnimplementedError("sessionOpenTerminalWithProfile");
+  }
+
   Future<void> sessionSendTerminalInput(
       {required UuidValue sessionId,
       required int terminalId,
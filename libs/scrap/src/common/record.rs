@@ -4,7 +4,8 @@ use hbb_common::anyhow::anyhow;
 use hbb_common::{
     bail, chrono, log,
     message_proto::{message, video_frame, EncodedVideoFrame, Message},
-    ResultType,
+    serde_derive::Serialize,
+    serde_json, ResultType,
 };
 #[cfg(feature = "hwcodec")]
 use hwcodec::mux::{MuxContext, Muxer};
@@ -13,12 +14,106 @@ use std::{
     io,
     ops::{Deref, DerefMut},
     path::PathBuf,
-    sync::mpsc::Sender,
-    time::Instant,
+    sync::{
+        mpsc::{Receiver, Sender, SyncSender, TrySendError},
+        Mutex,
+    },
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
-use webm::mux::{self, Segment, Track, VideoTrack, Writer};
+use webm::mux::{self, AudioTrack, Segment, Track, VideoTrack, Writer};
 
 const MIN_SECS: u64 = 1;
+// Inputs are written before the next video frame, which may not come for a while on a static
+// screen. Inputs beyond this are dropped, it is about 20 seconds of audio.
+const MAX_PENDING_INPUTS: usize = 1000;
+
+lazy_static::lazy_static! {
+    // Inputs of the active recorders, keyed by `RecorderContext::server` and `id`.
+    static ref INPUTS: Mutex<Vec<(bool, String, SyncSender<RecordInput>)>> = Default::default();
+    // Last audio format (sample rate, channels), used by recorders created later.
+    static ref AUDIO_FORMATS: Mutex<Vec<(bool, String, (u32, u16))>> = Default::default();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordEventKind {
+    DisplaySwitch,
+    Clipboard,
+    FileTransfer,
+    Chat,
+    PrivacyMode,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordEvent {
+    /// Milliseconds from the first video frame of the file.
+    pub time_ms: i64,
+    pub unix_ms: u64,
+    pub kind: RecordEventKind,
+    pub detail: String,
+}
+
+#[derive(Debug)]
+pub enum RecordInput {
+    Event(Instant, SystemTime, RecordEventKind, String),
+    /// An opus packet.
+    Audio(Instant, Vec<u8>),
+}
+
+/// Feed the recorders of `server` side whose id is `id`, or all of them if `id` is None.
+pub fn push(server: bool, id: Option<&str>, input: impl Fn() -> RecordInput) {
+    let mut inputs = INPUTS.lock().unwrap();
+    inputs.retain(|(s, i, tx)| {
+        if *s != server || id.map_or(false, |id| id != i) {
+            return true;
+        }
+        match tx.try_send(input()) {
+            Ok(()) | Err(TrySendError::Full(_)) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    });
+}
+
+pub fn push_event(server: bool, id: Option<&str>, kind: RecordEventKind, detail: String) {
+    let (now, time) = (Instant::now(), SystemTime::now());
+    push(server, id, || {
+        RecordInput::Event(now, time, kind, detail.clone())
+    });
+}
+
+pub fn push_audio(server: bool, id: Option<&str>, data: &[u8]) {
+    let now = Instant::now();
+    push(server, id, || RecordInput::Audio(now, data.to_vec()));
+}
+
+/// The audio format is needed before the first audio packet, only files created after it have audio.
+pub fn set_audio_format(server: bool, id: &str, sample_rate: u32, channels: u16) {
+    let mut formats = AUDIO_FORMATS.lock().unwrap();
+    formats.retain(|(s, i, _)| *s != server || i != id);
+    formats.push((server, id.to_owned(), (sample_rate, channels)));
+}
+
+fn get_audio_format(server: bool, id: &str) -> Option<(u32, u16)> {
+    AUDIO_FORMATS
+        .lock()
+        .unwrap()
+        .iter()
+        // The server side has only one audio source.
+        .find(|(s, i, _)| *s == server && (server || i == id))
+        .map(|x| x.2)
+}
+
+// https://wiki.xiph.org/OggOpus#ID_Header
+fn opus_head(sample_rate: u32, channels: u16) -> Vec<u8> {
+    let mut v = b"OpusHead".to_vec();
+    v.push(1);
+    v.push(channels as u8);
+    v.extend_from_slice(&312u16.to_le_bytes()); // pre-skip
+    v.extend_from_slice(&sample_rate.to_le_bytes());
+    v.extend_from_slice(&0i16.to_le_bytes()); // output gain
+    v.push(0); // channel mapping family
+    v
+}
 
 #[derive(Debug, Clone)]
 pub struct RecorderContext {
@@ -36,6 +131,30 @@ pub struct RecorderContext2 {
     pub width: usize,
     pub height: usize,
     pub format: CodecFormat,
+    /// Sample rate and channels of the opus audio track.
+    pub audio: Option<(u32, u16)>,
+}
+
+impl RecorderContext {
+    // incoming_<id>_<time>_
+    fn filename_prefix(&self) -> String {
+        if self.server { "incoming" } else { "outgoing" }.to_string()
+            + "_"
+            + &self.id.clone()
+            + &chrono::Local::now().format("_%Y%m%d%H%M%S%3f_").to_string()
+    }
+
+    /// Path of the asciicast recording of a terminal, named like the video recordings.
+    pub fn terminal_filename(&self, terminal_id: i32) -> ResultType<String> {
+        if !PathBuf::from(&self.dir).exists() {
+            std::fs::create_dir_all(&self.dir)?;
+        }
+        let file = self.filename_prefix() + &format!("terminal{}.cast", terminal_id);
+        Ok(PathBuf::from(&self.dir)
+            .join(file)
+            .to_string_lossy()
+            .to_string())
+    }
 }
 
 impl RecorderContext2 {
@@ -43,10 +162,7 @@ impl RecorderContext2 {
         if !PathBuf::from(&ctx.dir).exists() {
             std::fs::create_dir_all(&ctx.dir)?;
         }
-        let file = if ctx.server { "incoming" } else { "outgoing" }.to_string()
-            + "_"
-            + &ctx.id.clone()
-            + &chrono::Local::now().format("_%Y%m%d%H%M%S%3f_").to_string()
+        let file = ctx.filename_prefix()
             + &format!(
                 "{}{}_",
                 if ctx.camera { "camera" } else { "display" },
@@ -77,6 +193,12 @@ pub trait RecorderApi {
     where
         Self: Sized;
     fn write_video(&mut self, frame: &EncodedVideoFrame) -> bool;
+    /// Write an opus packet captured at `time`.
+    fn write_audio(&mut self, _data: &[u8], _time: Instant) -> bool {
+        false
+    }
+    /// Milliseconds from the first video frame to `time`.
+    fn offset_ms(&self, time: Instant) -> Option<i64>;
 }
 
 #[derive(Debug)]
@@ -93,6 +215,63 @@ pub struct Recorder {
     ctx2: Option<RecorderContext2>,
     pts: Option<i64>,
     check_failed: bool,
+    input: Receiver<RecordInput>,
+    index: Option<RecordIndex>,
+}
+
+/// The index of the events of the recording file `video`, one json `RecordEvent` per line.
+///
+/// Events are kept beside the recording, they are not embedded as chapters or cues: the webm
+/// muxer binding has no chapter api and the hardware muxer writes only the video track.
+pub fn index_path(video: &str) -> String {
+    format!("{}.jsonl", video)
+}
+
+/// Events of a recording file, appended to `index_path`.
+struct RecordIndex {
+    video: String,
+    filename: String,
+    file: Option<File>,
+}
+
+impl RecordIndex {
+    fn new(video: &str) -> Self {
+        Self {
+            video: video.to_owned(),
+            filename: index_path(video),
+            file: None,
+        }
+    }
+
+    fn add(&mut self, event: RecordEvent) {
+        use std::io::Write;
+        if self.file.is_none() {
+            match OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.filename)
+            {
+                Ok(file) => self.file = Some(file),
+                Err(e) => {
+                    log::error!("Failed to create record index {}: {}", self.filename, e);
+                    return;
+                }
+            }
+        }
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+        let line = serde_json::to_string(&event).unwrap_or_default();
+        if let Err(e) = writeln!(file, "{}", line) {
+            log::error!("Failed to write record index {}: {}", self.filename, e);
+        }
+    }
+
+    fn remove(mut self) {
+        // Close it first, an open file can not be removed on Windows.
+        self.file = None;
+        std::fs::remove_file(&self.filename).ok();
+    }
 }
 
 impl Deref for Recorder {
@@ -111,12 +290,19 @@ impl DerefMut for Recorder {
 
 impl Recorder {
     pub fn new(ctx: RecorderContext) -> ResultType<Self> {
+        let (tx, input) = std::sync::mpsc::sync_channel(MAX_PENDING_INPUTS);
+        INPUTS
+            .lock()
+            .unwrap()
+            .push((ctx.server, ctx.id.clone(), tx));
         Ok(Self {
             inner: None,
             ctx,
             ctx2: None,
             pts: None,
             check_failed: false,
+            input,
+            index: None,
         })
     }
 
@@ -129,6 +315,7 @@ impl Recorder {
                         height: h,
                         format,
                         filename: Default::default(),
+                        audio: get_audio_format(self.ctx.server, &self.ctx.id),
                     };
                     ctx2.set_filename(&self.ctx)?;
                     self.ctx2 = Some(ctx2);
@@ -141,6 +328,7 @@ impl Recorder {
                     height: h,
                     format,
                     filename: Default::default(),
+                    audio: get_audio_format(self.ctx.server, &self.ctx.id),
                 };
                 ctx2.set_filename(&self.ctx)?;
                 self.ctx2 = Some(ctx2);
@@ -165,6 +353,8 @@ impl Recorder {
             };
             // pts is None when new inner is created
             self.pts = None;
+            self.finish_index();
+            self.index = Some(RecordIndex::new(&ctx2.filename));
             self.send_state(RecordState::NewFile(ctx2.filename.clone()));
         }
         Ok(())
@@ -197,6 +387,8 @@ impl Recorder {
             log::error!("check failed: {:?}", res);
             res?;
         }
+        // Inputs are earlier than this frame, write them first to keep timestamps in order.
+        self.handle_inputs();
         match frame {
             video_frame::Union::Vp8s(vp8s) => {
                 for f in vp8s.frames.iter() {
@@ -265,13 +457,58 @@ impl Recorder {
         Ok(())
     }
 
+    fn handle_inputs(&mut self) {
+        while let Ok(input) = self.input.try_recv() {
+            let Some(inner) = self.inner.as_mut() else {
+                continue;
+            };
+            match input {
+                RecordInput::Audio(time, data) => {
+                    inner.write_audio(&data, time);
+                }
+                RecordInput::Event(time, system_time, kind, detail) => {
+                    let event = RecordEvent {
+                        time_ms: inner.offset_ms(time).unwrap_or_default().max(0),
+                        unix_ms: system_time
+                            .duration_since(UNIX_EPOCH)
+                            .map(|d| d.as_millis() as _)
+                            .unwrap_or_default(),
+                        kind,
+                        detail,
+                    };
+                    self.index.as_mut().map(|index| index.add(event));
+                }
+            }
+        }
+    }
+
+    // The index is removed with its recording file.
+    fn finish_index(&mut self) {
+        if let Some(index) = self.index.take() {
+            if !PathBuf::from(&index.video).exists() {
+                index.remove();
+            }
+        }
+    }
+
     fn send_state(&self, state: RecordState) {
         self.ctx.tx.as_ref().map(|tx| tx.send(state));
     }
 }
 
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        // Finalize the file before checking whether it is kept.
+        self.inner = None;
+        self.finish_index();
+    }
+}
+
 struct WebmRecorder {
     vt: VideoTrack,
+    at: Option<AudioTrack>,
+    // pts and time of the first video frame, audio is aligned to it.
+    video_start: Option<(i64, Instant)>,
     webm: Option<Segment<Writer<File>>>,
     ctx: RecorderContext,
     ctx2: RecorderContext2,
@@ -315,8 +552,27 @@ impl RecorderApi for WebmRecorder {
                 bail!("Failed to set codec private");
             }
         }
+        let at = match ctx2.audio {
+            Some((sample_rate, channels)) => {
+                let at = webm.add_audio_track(
+                    sample_rate as _,
+                    channels as _,
+                    None,
+                    mux::AudioCodecId::Opus,
+                );
+                if webm.set_codec_private(at.track_number(), &opus_head(sample_rate, channels)) {
+                    Some(at)
+                } else {
+                    log::error!("Failed to set opus codec private, record without audio");
+                    None
+                }
+            }
+            None => None,
+        };
         Ok(WebmRecorder {
             vt,
+            at,
+            video_start: None,
             webm: Some(webm),
             ctx,
             ctx2,
@@ -336,12 +592,38 @@ impl RecorderApi for WebmRecorder {
                 .add_frame(&frame.data, frame.pts as u64 * 1_000_000, frame.key);
             if ok {
                 self.written = true;
+                if self.video_start.is_none() {
+                    self.video_start = Some((frame.pts, Instant::now()));
+                }
             }
             ok
         } else {
             false
         }
     }
+
+    fn write_audio(&mut self, data: &[u8], time: Instant) -> bool {
+        let (Some(at), Some((pts, _))) = (self.at.as_mut(), self.video_start) else {
+            return false;
+        };
+        let Some(offset) = self.offset_ms(time) else {
+            return false;
+        };
+        let ts = pts + offset;
+        if ts < 0 {
+            return false;
+        }
+        at.add_frame(data, ts as u64 * 1_000_000, true)
+    }
+
+    fn offset_ms(&self, time: Instant) -> Option<i64> {
+        let (_, start) = self.video_start?;
+        Some(if time >= start {
+            (time - start).as_millis() as i64
+        } else {
+            -((start - time).as_millis() as i64)
+        })
+    }
 }
 
 impl Drop for WebmRecorder {
@@ -359,6 +641,7 @@ impl Drop for WebmRecorder {
 #[cfg(feature = "hwcodec")]
 struct HwRecorder {
     muxer: Option<Muxer>,
+    video_start: Option<Instant>,
     ctx: RecorderContext,
     ctx2: RecorderContext2,
     written: bool,
@@ -379,6 +662,7 @@ impl RecorderApi for HwRecorder {
         .map_err(|_| anyhow!("Failed to create hardware muxer"))?;
         Ok(HwRecorder {
             muxer: Some(muxer),
+            video_start: None,
             ctx,
             ctx2,
             written: false,
@@ -399,12 +683,20 @@ impl RecorderApi for HwRecorder {
                 .unwrap_or_default();
             if ok {
                 self.written = true;
+                self.video_start.get_or_insert_with(Instant::now);
             }
             ok
         } else {
             false
         }
     }
+
+    // The hardware muxer has no audio track, mp4 recordings have no audio, only the index is
+    // written.
+    fn offset_ms(&self, time: Instant) -> Option<i64> {
+        let start = self.video_start?;
+        Some(time.saturating_duration_since(start).as_millis() as _)
+    }
 }
 
 #[cfg(feature = "hwcodec")]
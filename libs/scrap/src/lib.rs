@@ -3,6 +3,7 @@ extern crate block;
 #[macro_use]
 extern crate cfg_if;
 pub use hbb_common::libc;
+pub use webm;
 #[cfg(dxgi)]
 extern crate winapi;
 
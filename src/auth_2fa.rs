@@ -1,36 +1,89 @@
+// Second factors of incoming connections.
+//
+// The primary TOTP device is kept in the `2fa` option, 2FA is enabled as long as it is set.
+// More devices, each with a label, algorithm and digits, are kept in `2fa-devices`, and the
+// salted sha256 hashes of one-time recovery codes in `2fa-recovery-codes`. A recovery code has
+// 16 random characters of a 32 letter alphabet, 80 bits, shown in groups of 4 separated by `-`,
+// which are optional when entered, as is the case of the letters. Any of them passes the
+// 2FA check, a recovery code is removed once used. `2fa-skew` is the number of 30s steps
+// before and after the current one that are accepted too. Manage them with `rustdesk --2fa`.
+
 use hbb_common::{
     anyhow::anyhow,
     bail,
     config::Config,
-    get_time,
+    get_time, log,
     password_security::{decrypt_vec_or_original, encrypt_vec_or_original},
+    rand::{self, Rng},
     ResultType,
 };
 use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::sync::Mutex;
 use totp_rs::{Algorithm, Secret, TOTP};
 
 lazy_static::lazy_static! {
     static ref CURRENT_2FA: Mutex<Option<(TOTPInfo, TOTP)>> = Mutex::new(None);
+    // Held while a recovery code is matched and removed, so that it is used only once.
+    static ref RECOVERY_CODES_LOCK: Mutex<()> = Mutex::new(());
 }
 
 const ISSUER: &str = "RustDesk";
 const TAG_LOGIN: &str = "Connection";
 
+pub const OPTION_2FA: &str = "2fa";
+pub const OPTION_2FA_DEVICES: &str = "2fa-devices";
+pub const OPTION_2FA_RECOVERY_CODES: &str = "2fa-recovery-codes";
+pub const OPTION_2FA_SKEW: &str = "2fa-skew";
+pub const PRIMARY_LABEL: &str = "primary";
+pub const RECOVERY_CODE_LEN: usize = 16;
+const RECOVERY_CODE_COUNT: usize = 10;
+// No 0, 1, I and O, which are easily mistaken for each other.
+const RECOVERY_CODE_CHARS: &[u8] = b"23456789ABCDEFGHJKLMNPQRSTUVWXYZ";
+const RECOVERY_SALT_LEN: usize = 16;
+const DEFAULT_SKEW: u8 = 1;
+const MAX_SKEW: u8 = 10;
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TOTPInfo {
     pub name: String,
     pub secret: Vec<u8>,
     pub digits: usize,
     pub created_at: i64,
+    /// "SHA1" if empty, "SHA256" or "SHA512".
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub algorithm: String,
+    /// The device name of an additional device, empty for the primary one.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub label: String,
+}
+
+fn parse_algorithm(s: &str) -> Option<Algorithm> {
+    match s.to_uppercase().replace('-', "").as_str() {
+        "" | "SHA1" => Some(Algorithm::SHA1),
+        "SHA256" => Some(Algorithm::SHA256),
+        "SHA512" => Some(Algorithm::SHA512),
+        _ => None,
+    }
+}
+
+fn skew() -> u8 {
+    Config::get_option(OPTION_2FA_SKEW)
+        .trim()
+        .parse::<u8>()
+        .unwrap_or(DEFAULT_SKEW)
+        .min(MAX_SKEW)
 }
 
 impl TOTPInfo {
     fn new_totp(&self) -> ResultType<TOTP> {
+        let Some(algorithm) = parse_algorithm(&self.algorithm) else {
+            bail!("unsupported 2fa algorithm {}", self.algorithm);
+        };
         let totp = TOTP::new(
-            Algorithm::SHA1,
+            algorithm,
             self.digits,
-            1,
+            skew(),
             30,
             self.secret.clone(),
             Some(format!("{} {}", ISSUER, TAG_LOGIN)),
@@ -51,53 +104,104 @@ impl TOTPInfo {
         Ok(totp)
     }
 
-    pub fn into_string(&self) -> ResultType<String> {
-        let secret = encrypt_vec_or_original(self.secret.as_slice(), "00", 1024);
-        let totp_info = TOTPInfo {
-            secret,
+    fn encrypted(&self) -> TOTPInfo {
+        TOTPInfo {
+            secret: encrypt_vec_or_original(self.secret.as_slice(), "00", 1024),
             ..self.clone()
-        };
-        let s = serde_json::to_string(&totp_info)?;
-        Ok(s)
+        }
     }
 
-    pub fn from_str(data: &str) -> ResultType<TOTP> {
-        let mut totp_info = serde_json::from_str::<TOTPInfo>(data)?;
-        let (secret, success, _) = decrypt_vec_or_original(&totp_info.secret, "00");
-        if success {
-            totp_info.secret = secret;
-            return Ok(totp_info.new_totp()?);
-        } else {
+    fn decrypted(mut self) -> ResultType<TOTPInfo> {
+        let (secret, success, _) = decrypt_vec_or_original(&self.secret, "00");
+        if !success {
             bail!("decrypt_vec_or_original 2fa secret failed")
         }
+        self.secret = secret;
+        Ok(self)
+    }
+
+    pub fn into_string(&self) -> ResultType<String> {
+        let s = serde_json::to_string(&self.encrypted())?;
+        Ok(s)
+    }
+
+    pub fn from_str(data: &str) -> ResultType<TOTP> {
+        serde_json::from_str::<TOTPInfo>(data)?
+            .decrypted()?
+            .new_totp()
     }
 }
 
-pub fn generate2fa() -> String {
+fn get_option(name: &str) -> String {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    return crate::ipc::get_options()
+        .get(name)
+        .cloned()
+        .unwrap_or_default();
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    return Config::get_option(name);
+}
+
+fn set_option(name: &str, value: String) {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    crate::ipc::set_option(name, &value);
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    Config::set_option(name.to_owned(), value);
+}
+
+fn new_2fa(label: String, digits: usize, algorithm: String) -> ResultType<String> {
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     let id = crate::ipc::get_id();
     #[cfg(any(target_os = "android", target_os = "ios"))]
     let id = Config::get_id();
-    if let Ok(info) = TOTPInfo::gen_totp_info(id, 6) {
-        if let Ok(totp) = info.new_totp() {
-            let code = totp.get_url();
-            *CURRENT_2FA.lock().unwrap() = Some((info, totp));
-            return code;
-        }
+    let mut info = TOTPInfo::gen_totp_info(id, digits)?;
+    info.algorithm = algorithm;
+    info.label = label;
+    let totp = info.new_totp()?;
+    let url = totp.get_url();
+    *CURRENT_2FA.lock().unwrap() = Some((info, totp));
+    Ok(url)
+}
+
+pub fn generate2fa() -> String {
+    new_2fa("".to_owned(), 6, "".to_owned()).unwrap_or_default()
+}
+
+/// Start enrolling an additional device, returns the otpauth url to scan, confirmed with
+/// `verify2fa`.
+pub fn generate2fa_device(label: String, digits: usize, algorithm: String) -> ResultType<String> {
+    let label = label.trim().to_owned();
+    if label.is_empty() || label == PRIMARY_LABEL {
+        bail!("invalid device name");
     }
-    "".to_owned()
+    if list_devices().iter().any(|d| d.label == label) {
+        bail!("device {} already exists", label);
+    }
+    new_2fa(label, digits, algorithm)
 }
 
 pub fn verify2fa(code: String) -> bool {
     if let Some((info, totp)) = CURRENT_2FA.lock().unwrap().as_ref() {
         if let Ok(res) = totp.check_current(&code) {
             if res {
-                if let Ok(v) = info.into_string() {
-                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
-                    crate::ipc::set_option("2fa", &v);
-                    #[cfg(any(target_os = "android", target_os = "ios"))]
-                    Config::set_option("2fa".to_owned(), v);
-                    return res;
+                // The first device is the primary one, whatever it is named.
+                if info.label.is_empty() || get_option(OPTION_2FA).is_empty() {
+                    let info = TOTPInfo {
+                        label: "".to_owned(),
+                        ..info.clone()
+                    };
+                    if let Ok(v) = info.into_string() {
+                        set_option(OPTION_2FA, v);
+                        return res;
+                    }
+                } else {
+                    let mut devices = parse_devices(&get_option(OPTION_2FA_DEVICES));
+                    devices.retain(|d| d.label != info.label);
+                    devices.push(info.encrypted());
+                    if let Ok(v) = serde_json::to_string(&devices) {
+                        set_option(OPTION_2FA_DEVICES, v);
+                        return res;
+                    }
                 }
             }
         }
@@ -106,11 +210,221 @@ pub fn verify2fa(code: String) -> bool {
 }
 
 pub fn get_2fa(raw: Option<String>) -> Option<TOTP> {
-    TOTPInfo::from_str(&raw.unwrap_or(Config::get_option("2fa")))
+    TOTPInfo::from_str(&raw.unwrap_or(Config::get_option(OPTION_2FA)))
         .map(|x| Some(x))
         .unwrap_or_default()
 }
 
+fn parse_devices(s: &str) -> Vec<TOTPInfo> {
+    if s.trim().is_empty() {
+        return vec![];
+    }
+    serde_json::from_str::<Vec<TOTPInfo>>(s).unwrap_or_else(|e| {
+        log::error!("Invalid {}: {}", OPTION_2FA_DEVICES, e);
+        vec![]
+    })
+}
+
+fn parse_recovery_codes(s: &str) -> Vec<String> {
+    serde_json::from_str::<Vec<String>>(s).unwrap_or_default()
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+// `salt$hash` in hex.
+fn hash_recovery_code(code: &str, salt: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(normalize_recovery_code(code).as_bytes());
+    format!("{}${}", hex::encode(salt), hex::encode(hasher.finalize()))
+}
+
+fn new_recovery_hash(code: &str) -> String {
+    let salt: [u8; RECOVERY_SALT_LEN] = rand::thread_rng().gen();
+    hash_recovery_code(code, &salt)
+}
+
+fn recovery_code_matches(code: &str, hash: &str) -> bool {
+    let Some((salt, _)) = hash.split_once('$') else {
+        return false;
+    };
+    match hex::decode(salt) {
+        Ok(salt) => hash_recovery_code(code, &salt) == hash,
+        Err(_) => false,
+    }
+}
+
+/// All second factors of the controlled side, `None` if 2FA is not enabled.
+pub struct SecondFactors {
+    totps: Vec<(String, TOTP)>,
+}
+
+impl SecondFactors {
+    pub fn get() -> Option<Self> {
+        let primary = get_2fa(None)?;
+        let mut totps = vec![(PRIMARY_LABEL.to_owned(), primary)];
+        for info in parse_devices(&Config::get_option(OPTION_2FA_DEVICES)) {
+            let label = info.label.clone();
+            match info.decrypted().and_then(|x| x.new_totp()) {
+                Ok(totp) => totps.push((label, totp)),
+                Err(e) => log::error!("Invalid 2fa device {}: {}", label, e),
+            }
+        }
+        Some(Self { totps })
+    }
+
+    /// The name of the factor `code` belongs to, a used recovery code is removed.
+    ///
+    /// Recovery codes are read from the config on every check, not when the connection opened,
+    /// so that a code used by another connection is not accepted again.
+    pub fn check(&self, code: &str) -> Option<String> {
+        let code = code.trim();
+        for (label, totp) in self.totps.iter() {
+            if code.len() == totp.digits && totp.check_current(code).unwrap_or(false) {
+                return Some(label.clone());
+            }
+        }
+        if normalize_recovery_code(code).len() != RECOVERY_CODE_LEN {
+            return None;
+        }
+        let _lock = RECOVERY_CODES_LOCK.lock().unwrap();
+        let mut codes = parse_recovery_codes(&Config::get_option(OPTION_2FA_RECOVERY_CODES));
+        if let Some(i) = codes.iter().position(|x| recovery_code_matches(code, x)) {
+            codes.remove(i);
+            Config::set_option(
+                OPTION_2FA_RECOVERY_CODES.to_owned(),
+                serde_json::to_string(&codes).unwrap_or_default(),
+            );
+            log::warn!("2FA recovery code used, {} left", codes.len());
+            return Some("recovery code".to_owned());
+        }
+        None
+    }
+
+    /// The current code of the primary device, e.g. to send to telegram.
+    pub fn generate_current(&self) -> ResultType<String> {
+        match self.totps.first() {
+            Some((_, totp)) => Ok(totp.generate_current()?),
+            None => bail!("no 2fa device"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    pub label: String,
+    pub algorithm: String,
+    pub digits: usize,
+    pub created_at: i64,
+}
+
+impl From<&TOTPInfo> for DeviceInfo {
+    fn from(info: &TOTPInfo) -> Self {
+        Self {
+            label: if info.label.is_empty() {
+                PRIMARY_LABEL.to_owned()
+            } else {
+                info.label.clone()
+            },
+            algorithm: if info.algorithm.is_empty() {
+                "SHA1".to_owned()
+            } else {
+                info.algorithm.to_uppercase()
+            },
+            digits: info.digits,
+            created_at: info.created_at,
+        }
+    }
+}
+
+/// The enrolled devices, the primary one first.
+pub fn list_devices() -> Vec<DeviceInfo> {
+    let Ok(primary) = serde_json::from_str::<TOTPInfo>(&get_option(OPTION_2FA)) else {
+        return vec![];
+    };
+    let mut v = vec![DeviceInfo::from(&primary)];
+    v.extend(
+        parse_devices(&get_option(OPTION_2FA_DEVICES))
+            .iter()
+            .map(DeviceInfo::from),
+    );
+    v
+}
+
+/// Revoke one device. Revoking the primary device promotes the next one, 2FA is disabled
+/// when none is left.
+pub fn remove_device(label: &str) -> ResultType<()> {
+    let mut devices = parse_devices(&get_option(OPTION_2FA_DEVICES));
+    if label == PRIMARY_LABEL {
+        if get_option(OPTION_2FA).is_empty() {
+            bail!("2FA is not enabled");
+        }
+        if devices.is_empty() {
+            set_option(OPTION_2FA, "".to_owned());
+            set_option(OPTION_2FA_RECOVERY_CODES, "".to_owned());
+            return Ok(());
+        }
+        let next = devices.remove(0);
+        log::info!("2FA device {} becomes the primary one", next.label);
+        let next = TOTPInfo {
+            label: "".to_owned(),
+            ..next
+        };
+        set_option(OPTION_2FA, serde_json::to_string(&next)?);
+    } else {
+        let n = devices.len();
+        devices.retain(|d| d.label != label);
+        if devices.len() == n {
+            bail!("no 2FA device named {}", label);
+        }
+    }
+    let v = if devices.is_empty() {
+        "".to_owned()
+    } else {
+        serde_json::to_string(&devices)?
+    };
+    set_option(OPTION_2FA_DEVICES, v);
+    Ok(())
+}
+
+fn new_recovery_codes() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let chars: Vec<char> = (0..RECOVERY_CODE_LEN)
+                .map(|_| {
+                    char::from(RECOVERY_CODE_CHARS[rng.gen_range(0..RECOVERY_CODE_CHARS.len())])
+                })
+                .collect();
+            chars
+                .chunks(4)
+                .map(|x| x.iter().collect::<String>())
+                .collect::<Vec<_>>()
+                .join("-")
+        })
+        .collect()
+}
+
+/// Replace the recovery codes with new ones, which are returned and not stored in clear.
+pub fn generate_recovery_codes() -> ResultType<Vec<String>> {
+    if get_option(OPTION_2FA).is_empty() {
+        bail!("2FA is not enabled");
+    }
+    let codes = new_recovery_codes();
+    let hashes: Vec<_> = codes.iter().map(|c| new_recovery_hash(c)).collect();
+    set_option(OPTION_2FA_RECOVERY_CODES, serde_json::to_string(&hashes)?);
+    Ok(codes)
+}
+
+pub fn recovery_codes_left() -> usize {
+    parse_recovery_codes(&get_option(OPTION_2FA_RECOVERY_CODES)).len()
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TelegramBot {
     #[serde(skip)]
@@ -202,3 +516,54 @@ pub fn get_chatid_telegram(bot_token: &str) -> ResultType<Option<String>> {
 
     Ok(chat_id)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_second_factors() {
+        let mut info = TOTPInfo::gen_totp_info("test".to_owned(), 8).unwrap();
+        info.algorithm = "sha-256".to_owned();
+        let sha256 = info.new_totp().unwrap();
+        assert_eq!(sha256.algorithm, Algorithm::SHA256);
+        let sha1 = TOTPInfo::gen_totp_info("test".to_owned(), 6)
+            .unwrap()
+            .new_totp()
+            .unwrap();
+        info.algorithm = "md5".to_owned();
+        assert!(info.new_totp().is_err());
+
+        let factors = SecondFactors {
+            totps: vec![
+                (PRIMARY_LABEL.to_owned(), sha1.clone()),
+                ("backup".to_owned(), sha256.clone()),
+            ],
+        };
+        let code = sha256.generate_current().unwrap();
+        assert_eq!(factors.check(&code), Some("backup".to_owned()));
+        let code = sha1.generate_current().unwrap();
+        assert_eq!(
+            factors.check(&format!(" {} ", code)),
+            Some(PRIMARY_LABEL.to_owned())
+        );
+        assert_eq!(factors.generate_current().unwrap(), code);
+        assert_eq!(factors.check("0123456789"), None);
+
+        let codes = new_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert!(codes.iter().all(|c| {
+            let code = normalize_recovery_code(c);
+            code.len() == RECOVERY_CODE_LEN
+                && code.bytes().all(|b| RECOVERY_CODE_CHARS.contains(&b))
+        }));
+        let hash = new_recovery_hash(&codes[0]);
+        assert_ne!(hash, new_recovery_hash(&codes[0]));
+        assert!(recovery_code_matches(
+            &codes[0].to_lowercase().replace('-', " "),
+            &hash
+        ));
+        assert!(!recovery_code_matches(&codes[1], &hash));
+        assert!(!recovery_code_matches(&codes[0], "not a hash"));
+    }
+}
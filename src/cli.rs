@@ -1,17 +1,32 @@
 use crate::client::*;
 use async_trait::async_trait;
 use hbb_common::{
-    config::PeerConfig,
-    config::READ_TIMEOUT,
+    allow_err, bail,
+    compress::decompress,
+    config::{PeerConfig, READ_TIMEOUT},
+    fs::{self, can_enable_overwrite_detection, new_send_confirm, DigestCheckResult},
     futures::{SinkExt, StreamExt},
     log,
     message_proto::*,
     protobuf::Message as _,
     rendezvous_proto::ConnType,
-    tokio::{self, sync::mpsc},
-    Stream,
+    timeout,
+    tokio::{
+        self,
+        sync::mpsc,
+        time::{self, Duration, Instant},
+    },
+    ResultType, Stream,
 };
-use std::sync::{Arc, RwLock};
+use serde_json::{json, Value};
+use std::{
+    io::BufRead,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+const DEFAULT_WAIT_MS: u64 = 3_000;
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Clone)]
 pub struct Session {
@@ -19,27 +34,43 @@ pub struct Session {
     lc: Arc<RwLock<LoginConfigHandler>>,
     sender: mpsc::UnboundedSender<Data>,
     password: String,
+    // Whether we may prompt on the tty when the password is missing or wrong.
+    interactive: bool,
+    // Shell profiles published by the peer for terminal sessions.
+    terminal_profiles: Arc<RwLock<Vec<String>>>,
 }
 
 impl Session {
-    pub fn new(id: &str, sender: mpsc::UnboundedSender<Data>) -> Self {
-        let mut password = "".to_owned();
-        if PeerConfig::load(id).password.is_empty() {
-            password = rpassword::prompt_password("Enter password: ").unwrap();
-        }
+    pub fn new(
+        id: &str,
+        conn_type: ConnType,
+        password: Option<String>,
+        sender: mpsc::UnboundedSender<Data>,
+    ) -> Self {
+        let interactive = password.is_none();
+        let password = match password {
+            Some(p) => p,
+            None if PeerConfig::load(id).password.is_empty() => {
+                rpassword::prompt_password("Enter password: ").unwrap_or_default()
+            }
+            None => "".to_owned(),
+        };
         let session = Self {
             id: id.to_owned(),
             sender,
             password,
+            interactive,
             lc: Default::default(),
+            terminal_profiles: Default::default(),
         };
         session.lc.write().unwrap().initialize(
             id.to_owned(),
-            ConnType::PORT_FORWARD,
+            conn_type,
             None,
             false,
             None,
             None,
+            None,
         );
         session
     }
@@ -47,22 +78,32 @@ impl Session {
 
 #[async_trait]
 impl Interface for Session {
-    fn get_login_config_handler(&self) -> Arc<RwLock<LoginConfigHandler>> {
+    fn get_lch(&self) -> Arc<RwLock<LoginConfigHandler>> {
         return self.lc.clone();
     }
 
-    fn msgbox(&self, msgtype: &str, title: &str, text: &str, link: &str) {
+    fn msgbox(&self, msgtype: &str, title: &str, text: &str, _link: &str) {
         match msgtype {
             "input-password" => {
                 self.sender
-                    .send(Data::Login((self.password.clone(), true)))
+                    .send(Data::Login((
+                        "".to_owned(),
+                        "".to_owned(),
+                        self.password.clone(),
+                        true,
+                    )))
                     .ok();
             }
             "re-input-password" => {
                 log::error!("{}: {}", title, text);
+                if !self.interactive {
+                    self.sender.send(Data::Close).ok();
+                    return;
+                }
                 match rpassword::prompt_password("Enter password: ") {
                     Ok(password) => {
-                        let login_data = Data::Login((password, true));
+                        let login_data =
+                            Data::Login(("".to_owned(), "".to_owned(), password, true));
                         self.sender.send(login_data).ok();
                     }
                     Err(e) => {
@@ -84,14 +125,20 @@ impl Interface for Session {
     }
 
     fn handle_peer_info(&self, pi: PeerInfo) {
+        if let Ok(Value::Object(additions)) = serde_json::from_str(&pi.platform_additions) {
+            if let Some(Value::Array(profiles)) = additions.get("terminal_shell_profiles") {
+                *self.terminal_profiles.write().unwrap() = profiles
+                    .iter()
+                    .filter_map(|p| p.as_str().map(|p| p.to_owned()))
+                    .collect();
+            }
+        }
         self.lc.write().unwrap().handle_peer_info(&pi);
     }
 
+    fn set_multiple_windows_session(&self, _sessions: Vec<WindowsSession>) {}
+
     async fn handle_hash(&self, pass: &str, hash: Hash, peer: &mut Stream) {
-        log::info!(
-            "password={}",
-            hbb_common::password_security::temporary_password()
-        );
         handle_hash(self.lc.clone(), &pass, hash, self, peer).await;
     }
 
@@ -123,43 +170,92 @@ impl Interface for Session {
     }
 }
 
-#[tokio::main(flavor = "current_thread")]
-pub async fn connect_test(id: &str, key: String, token: String) {
-    let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
-    let handler = Session::new(&id, sender);
-    match crate::client::Client::start(id, &key, &token, ConnType::PORT_FORWARD, handler).await {
-        Err(err) => {
-            log::error!("Failed to connect {}: {}", &id, err);
-        }
-        Ok((mut stream, direct)) => {
-            log::info!("direct: {}", direct);
-            // rpassword::prompt_password("Input anything to exit").ok();
-            loop {
-                tokio::select! {
-                    res = hbb_common::timeout(READ_TIMEOUT, stream.next()) => match res {
-                        Err(_) => {
-                            log::error!("Timeout");
-                            break;
+/// Connect to `id` and drive the login exchange until the peer sends its `PeerInfo`.
+async fn login(
+    handler: &Session,
+    receiver: &mut mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+) -> ResultType<Stream> {
+    let conn_type = handler.lc.read().unwrap().conn_type;
+    let ((mut stream, direct, _pk, _kcp, _stream_type), (feedback, rendezvous_server)) =
+        Client::start(&handler.id, key, token, conn_type, handler.clone()).await?;
+    handler.update_direct(Some(direct));
+    let _keep_it = hc_connection(feedback, rendezvous_server, token).await;
+    loop {
+        tokio::select! {
+            res = timeout(READ_TIMEOUT, stream.next()) => match res {
+                Err(_) => {
+                    bail!("Timeout");
+                }
+                Ok(Some(Ok(bytes))) => {
+                    handler.update_received(true);
+                    let msg_in = Message::parse_from_bytes(&bytes)?;
+                    match msg_in.union {
+                        Some(message::Union::Hash(hash)) => {
+                            handler.handle_hash(&handler.password, hash, &mut stream).await;
                         }
-                        Ok(Some(Ok(bytes))) => {
-                            if let Ok(msg_in) = Message::parse_from_bytes(&bytes) {
-                                match msg_in.union {
-                                    Some(message::Union::Hash(hash)) => {
-                                        log::info!("Got hash");
-                                        break;
-                                    }
-                                    _ => {}
+                        Some(message::Union::LoginResponse(lr)) => match lr.union {
+                            Some(login_response::Union::Error(err)) => {
+                                if !handler.handle_login_error(&err) {
+                                    bail!("Login failed: {}", err);
                                 }
                             }
+                            Some(login_response::Union::PeerInfo(pi)) => {
+                                handler.handle_peer_info(pi);
+                                return Ok(stream);
+                            }
+                            _ => {}
+                        }
+                        Some(message::Union::TestDelay(t)) => {
+                            handler.handle_test_delay(t, &mut stream).await;
                         }
                         _ => {}
                     }
                 }
+                Ok(Some(Err(err))) => {
+                    bail!("Connection closed: {}", err);
+                }
+                _ => {
+                    bail!("Reset by the peer");
+                }
+            },
+            d = receiver.recv() => {
+                match d {
+                    Some(Data::Login((os_username, os_password, password, remember))) => {
+                        handler.handle_login_from_ui(os_username, os_password, password, remember, &mut stream).await;
+                    }
+                    Some(Data::Message(msg)) => {
+                        allow_err!(stream.send(&msg).await);
+                    }
+                    Some(Data::Close) | None => {
+                        bail!("Login aborted");
+                    }
+                    _ => {}
+                }
             }
         }
     }
 }
 
+#[tokio::main(flavor = "current_thread")]
+pub async fn connect_test(id: &str, key: String, token: String) {
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
+    let handler = Session::new(&id, ConnType::PORT_FORWARD, None, sender);
+    match login(&handler, &mut receiver, &key, &token).await {
+        Err(err) => {
+            log::error!("Failed to connect {}: {}", &id, err);
+        }
+        Ok(_) => {
+            log::info!(
+                "Logged in to {}, direct: {:?}",
+                id,
+                handler.lc.read().unwrap().direct
+            );
+        }
+    }
+}
+
 #[tokio::main(flavor = "current_thread")]
 pub async fn start_one_port_forward(
     id: String,
@@ -171,8 +267,8 @@ pub async fn start_one_port_forward(
 ) {
     crate::common::test_rendezvous_server();
     crate::common::test_nat_type();
-    let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
-    let handler = Session::new(&id, sender);
+    let (sender, receiver) = mpsc::unbounded_channel::<Data>();
+    let handler = Session::new(&id, ConnType::PORT_FORWARD, None, sender);
     if let Err(err) = crate::port_forward::listen(
         handler.id.clone(),
         handler.password.clone(),
@@ -191,3 +287,896 @@ pub async fn start_one_port_forward(
     }
     log::info!("port forward (:{}) exit", port);
 }
+
+/// Serve the rules of a port-forward daemon config file, see `port_forward::ForwardConfig`.
+#[tokio::main]
+pub async fn start_port_forward_daemon(path: String, key: String, token: String) {
+    let mut config = match crate::port_forward::ForwardConfig::load(&path) {
+        Ok(config) => config,
+        Err(err) => {
+            log::error!("Failed to load {}: {}", path, err);
+            return;
+        }
+    };
+    if config.key.is_empty() {
+        config.key = key;
+    }
+    run_port_forward_daemon(config, token).await;
+}
+
+/// Serve a SOCKS5 proxy on local `port`, connections are tunneled through peer `id`.
+#[tokio::main]
+pub async fn start_socks5(id: String, port: i32, key: String, token: String) {
+    let mut password = "".to_owned();
+    if PeerConfig::load(&id).password.is_empty() {
+        password = rpassword::prompt_password("Enter password: ").unwrap_or_default();
+    }
+    let config = crate::port_forward::ForwardConfig {
+        key,
+        max_retries: 1,
+        max_backoff_secs: 1,
+        rules: vec![crate::port_forward::ForwardRule {
+            name: "socks5".to_owned(),
+            id,
+            password,
+            local_port: port,
+            remote_host: "".to_owned(),
+            remote_port: 0,
+            socks5: true,
+        }],
+    };
+    run_port_forward_daemon(config, token).await;
+}
+
+async fn run_port_forward_daemon(config: crate::port_forward::ForwardConfig, token: String) {
+    // Passwords come from the config or the saved peer config, never from a prompt.
+    let new_session = |rule: &crate::port_forward::ForwardRule| {
+        let (sender, receiver) = mpsc::unbounded_channel::<Data>();
+        let session = Session::new(
+            &rule.id,
+            ConnType::PORT_FORWARD,
+            Some(rule.password.clone()),
+            sender,
+        );
+        (session, receiver)
+    };
+    if let Err(err) = crate::port_forward::run_daemon(config, token, new_session).await {
+        log::error!("Port forward daemon exited: {}", err);
+    }
+}
+
+/// Parse the `--conn-type` argument of the headless client.
+pub fn parse_conn_type(s: &str) -> Option<ConnType> {
+    match s {
+        "remote" | "default" => Some(ConnType::DEFAULT_CONN),
+        "file" | "file-transfer" => Some(ConnType::FILE_TRANSFER),
+        "terminal" => Some(ConnType::TERMINAL),
+        _ => None,
+    }
+}
+
+/// Pick the connection type needed by the commands of a script.
+/// File commands need a file transfer session, `term` and `exec` a terminal session,
+/// `screenshot` and `clipboard` a remote desktop session.
+fn infer_conn_type(lines: &[String]) -> ResultType<ConnType> {
+    let mut conn_type = None;
+    for line in lines {
+        let args = split_args(line);
+        let Some(cmd) = args.first() else {
+            continue;
+        };
+        let t = match cmd.as_str() {
+            "ls" | "get" | "put" => ConnType::FILE_TRANSFER,
+            "term" | "exec" => ConnType::TERMINAL,
+            "screenshot" | "clipboard" => ConnType::DEFAULT_CONN,
+            _ => continue,
+        };
+        match conn_type {
+            None => conn_type = Some(t),
+            Some(c) if c != t => {
+                bail!("Script mixes commands of different connection types, use --conn-type")
+            }
+            _ => {}
+        }
+    }
+    Ok(conn_type.unwrap_or(ConnType::DEFAULT_CONN))
+}
+
+/// Split a script line into arguments. Double quotes group words and
+/// `#` starts a comment outside of quotes.
+fn split_args(line: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut cur = String::new();
+    let mut in_quotes = false;
+    let mut has_arg = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                has_arg = true;
+            }
+            '\\' if in_quotes => {
+                if let Some(n) = chars.next() {
+                    cur.push(n);
+                }
+            }
+            '#' if !in_quotes => break,
+            c if c.is_whitespace() && !in_quotes => {
+                if has_arg {
+                    args.push(std::mem::take(&mut cur));
+                    has_arg = false;
+                }
+            }
+            c => {
+                cur.push(c);
+                has_arg = true;
+            }
+        }
+    }
+    if has_arg {
+        args.push(cur);
+    }
+    args
+}
+
+/// Expand `\n`, `\r`, `\t` and `\\` in terminal input.
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+fn emit(v: Value) {
+    println!("{}", v);
+}
+
+/// Runs script commands against a logged-in peer and reports one JSON line per command.
+struct ScriptRunner {
+    handler: Session,
+    stream: Stream,
+    read_jobs: Vec<fs::TransferJob>,
+    write_jobs: Vec<fs::TransferJob>,
+    // File responses not consumed by a command yet.
+    pending: Vec<FileResponse>,
+    terminal_id: Option<i32>,
+    terminal_output: Vec<u8>,
+    terminal_events: Vec<terminal_response::Union>,
+    // Results of exec requests by their terminal id.
+    exec_results: Vec<(i32, Vec<u8>)>,
+    next_exec_id: i32,
+    screenshots: Vec<ScreenshotResponse>,
+    clipboard: Option<String>,
+}
+
+impl ScriptRunner {
+    async fn run(&mut self, line: &str) -> ResultType<Value> {
+        let args = split_args(line);
+        let arg = |i: usize| -> ResultType<&str> {
+            match args.get(i) {
+                Some(a) => Ok(a.as_str()),
+                None => bail!("Missing argument {}", i),
+            }
+        };
+        let ms = |i: usize| -> u64 {
+            args.get(i)
+                .and_then(|a| a.parse().ok())
+                .unwrap_or(DEFAULT_WAIT_MS)
+        };
+        match arg(0)? {
+            "sleep" => {
+                self.wait(Duration::from_millis(ms(1)), |_| false).await?;
+                Ok(json!({}))
+            }
+            "ls" => {
+                self.ls(arg(1)?, args.get(2).map(|a| a == "-a") == Some(true))
+                    .await
+            }
+            "get" => self.get(arg(1)?, arg(2)?).await,
+            "put" => self.put(arg(1)?, arg(2)?).await,
+            "term" => match arg(1)? {
+                "open" => {
+                    let rows = args.get(2).and_then(|a| a.parse().ok()).unwrap_or(24);
+                    let cols = args.get(3).and_then(|a| a.parse().ok()).unwrap_or(80);
+                    self.term_open(rows, cols, args.get(4).cloned()).await
+                }
+                "send" => {
+                    let data = unescape(&args[2..].join(" "));
+                    self.term_send(data.into_bytes()).await
+                }
+                "read" => self.term_read(ms(2)).await,
+                "close" => self.term_close().await,
+                cmd => bail!("Unknown terminal command: {}", cmd),
+            },
+            "exec" => {
+                let timeout_ms = args
+                    .get(1)
+                    .and_then(|a| a.strip_prefix("--timeout="))
+                    .and_then(|a| a.parse().ok());
+                let start = if timeout_ms.is_some() { 2 } else { 1 };
+                self.exec(
+                    arg(start)?,
+                    args[start + 1..].to_vec(),
+                    timeout_ms.unwrap_or(0),
+                )
+                .await
+            }
+            "screenshot" => self.screenshot(arg(1)?.parse()?, arg(2)?).await,
+            "clipboard" => match arg(1)? {
+                "get" => self.clipboard_get(ms(2)).await,
+                "set" => self.clipboard_set(args[2..].join(" ")).await,
+                cmd => bail!("Unknown clipboard command: {}", cmd),
+            },
+            cmd => bail!("Unknown command: {}", cmd),
+        }
+    }
+
+    /// Read messages from the peer until `done` returns true or `dur` elapses.
+    /// Returns whether `done` was satisfied.
+    async fn wait(
+        &mut self,
+        dur: Duration,
+        mut done: impl FnMut(&mut Self) -> bool,
+    ) -> ResultType<bool> {
+        let deadline = Instant::now() + dur;
+        let mut timer = crate::rustdesk_interval(time::interval(MILLI1 * 10));
+        loop {
+            if done(self) {
+                return Ok(true);
+            }
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+            tokio::select! {
+                res = self.stream.next() => match res {
+                    Some(Ok(bytes)) => {
+                        let msg = Message::parse_from_bytes(&bytes)?;
+                        self.handle_msg(msg).await?;
+                    }
+                    Some(Err(err)) => bail!("Connection closed: {}", err),
+                    None => bail!("Reset by the peer"),
+                },
+                _ = timer.tick() => {
+                    if !self.read_jobs.is_empty() {
+                        fs::handle_read_jobs(&mut self.read_jobs, &mut self.stream).await?;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn handle_msg(&mut self, msg: Message) -> ResultType<()> {
+        match msg.union {
+            Some(message::Union::TestDelay(t)) => {
+                self.handler.handle_test_delay(t, &mut self.stream).await;
+            }
+            Some(message::Union::FileResponse(fr)) => self.pending.push(fr),
+            Some(message::Union::TerminalResponse(tr)) => match tr.union {
+                Some(terminal_response::Union::Data(d))
+                    if crate::server::terminal_exec::is_exec_id(d.terminal_id) =>
+                {
+                    let data = if d.compressed {
+                        decompress(&d.data)
+                    } else {
+                        d.data.to_vec()
+                    };
+                    self.exec_results.push((d.terminal_id, data));
+                }
+                Some(terminal_response::Union::Data(d)) => {
+                    if d.compressed {
+                        self.terminal_output.extend(decompress(&d.data));
+                    } else {
+                        self.terminal_output.extend(d.data.to_vec());
+                    }
+                }
+                Some(terminal_response::Union::Closed(c)) => {
+                    if self.terminal_id == Some(c.terminal_id) {
+                        self.terminal_id = None;
+                    }
+                }
+                Some(u) => self.terminal_events.push(u),
+                None => {}
+            },
+            Some(message::Union::Clipboard(cb)) => self.set_clipboard(cb),
+            Some(message::Union::MultiClipboards(mcb)) => {
+                for cb in mcb.clipboards {
+                    self.set_clipboard(cb);
+                }
+            }
+            Some(message::Union::ScreenshotResponse(r)) => self.screenshots.push(r),
+            Some(message::Union::Misc(misc)) => {
+                if let Some(misc::Union::CloseReason(c)) = misc.union {
+                    bail!("Closed by the peer: {}", c);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn set_clipboard(&mut self, cb: Clipboard) {
+        if cb.format.enum_value() != Ok(ClipboardFormat::Text) {
+            return;
+        }
+        let content = if cb.compress {
+            decompress(&cb.content)
+        } else {
+            cb.content.into()
+        };
+        self.clipboard = Some(String::from_utf8_lossy(&content).to_string());
+    }
+
+    fn take_file_response(&mut self, id: i32) -> Option<file_response::Union> {
+        let pos = self.pending.iter().position(|fr| match &fr.union {
+            Some(file_response::Union::Dir(d)) => d.id == id,
+            Some(file_response::Union::Block(b)) => b.id == id,
+            Some(file_response::Union::Digest(d)) => d.id == id,
+            Some(file_response::Union::Done(d)) => d.id == id,
+            Some(file_response::Union::Error(e)) => e.id == id,
+            _ => false,
+        })?;
+        self.pending.remove(pos).union
+    }
+
+    async fn ls(&mut self, path: &str, include_hidden: bool) -> ResultType<Value> {
+        let mut msg = Message::new();
+        let mut fa = FileAction::new();
+        fa.set_read_dir(ReadDir {
+            path: path.to_owned(),
+            include_hidden,
+            ..Default::default()
+        });
+        msg.set_file_action(fa);
+        self.stream.send(&msg).await?;
+        let mut fd = None;
+        self.wait(Duration::from_millis(READ_TIMEOUT), |s| {
+            let pos = s.pending.iter().position(|fr| {
+                matches!(&fr.union, Some(file_response::Union::Dir(_)))
+                    || matches!(&fr.union, Some(file_response::Union::Error(_)))
+            });
+            if let Some(pos) = pos {
+                fd = s.pending.remove(pos).union;
+                true
+            } else {
+                false
+            }
+        })
+        .await?;
+        match fd {
+            Some(file_response::Union::Dir(fd)) => Ok(json!({
+                "path": fd.path,
+                "entries": fd.entries.iter().map(|e| json!({
+                    "name": e.name,
+                    "type": e.entry_type.value(),
+                    "size": e.size,
+                    "modified_time": e.modified_time,
+                })).collect::<Vec<_>>(),
+            })),
+            Some(file_response::Union::Error(e)) => bail!("{}", e.error),
+            _ => bail!("Timeout"),
+        }
+    }
+
+    /// Transfer `remote` on the peer to `local`.
+    async fn get(&mut self, remote: &str, local: &str) -> ResultType<Value> {
+        let id = fs::get_next_job_id();
+        let od = can_enable_overwrite_detection(self.handler.lc.read().unwrap().version);
+        self.write_jobs.push(fs::TransferJob::new_write(
+            id,
+            fs::JobType::Generic,
+            remote.to_owned(),
+            fs::DataSource::FilePath(PathBuf::from(local)),
+            0,
+            false,
+            true,
+            Vec::new(),
+            od,
+        ));
+        self.stream
+            .send(&fs::new_send(
+                id,
+                fs::JobType::Generic,
+                remote.to_owned(),
+                0,
+                false,
+            ))
+            .await?;
+        self.drive_job(id).await
+    }
+
+    /// Transfer `local` to `remote` on the peer.
+    async fn put(&mut self, local: &str, remote: &str) -> ResultType<Value> {
+        let id = fs::get_next_job_id();
+        let od = can_enable_overwrite_detection(self.handler.lc.read().unwrap().version);
+        let job = fs::TransferJob::new_read(
+            id,
+            fs::JobType::Generic,
+            remote.to_owned(),
+            fs::DataSource::FilePath(PathBuf::from(local)),
+            0,
+            false,
+            true,
+            od,
+        )?;
+        let files = job.files().clone();
+        let total_size = job.total_size();
+        self.read_jobs.push(job);
+        self.stream
+            .send(&fs::new_receive(
+                id,
+                remote.to_owned(),
+                0,
+                files,
+                total_size,
+            ))
+            .await?;
+        self.drive_job(id).await
+    }
+
+    /// Pump file responses of job `id` until it is done. Existing files are
+    /// overwritten unless they are identical, a script cannot answer prompts.
+    async fn drive_job(&mut self, id: i32) -> ResultType<Value> {
+        let mut last_activity = Instant::now();
+        loop {
+            let Some(fr) = self.take_file_response(id) else {
+                if last_activity.elapsed() > TRANSFER_TIMEOUT {
+                    fs::remove_job(id, &mut self.write_jobs);
+                    fs::remove_job(id, &mut self.read_jobs);
+                    bail!("Timeout");
+                }
+                self.wait(MILLI1 * 100, |s| !s.pending.is_empty()).await?;
+                continue;
+            };
+            last_activity = Instant::now();
+            match fr {
+                file_response::Union::Dir(fd) => {
+                    if let Some(job) = fs::get_job(id, &mut self.write_jobs) {
+                        job.set_files(fd.entries.to_vec());
+                    }
+                }
+                file_response::Union::Digest(digest) => {
+                    let mut overwrite = true;
+                    if digest.is_upload {
+                        if fs::get_job(id, &mut self.read_jobs).is_none() {
+                            continue;
+                        }
+                        if digest.is_identical {
+                            overwrite = false;
+                        }
+                        self.confirm(job_confirm(&digest, overwrite), true).await?;
+                    } else {
+                        let Some(job) = fs::get_job(id, &mut self.write_jobs) else {
+                            continue;
+                        };
+                        job.set_digest(digest.file_size, digest.last_modified);
+                        if let (Some(file), fs::DataSource::FilePath(p)) =
+                            (job.files().get(digest.file_num as usize), &job.data_source)
+                        {
+                            let path = fs::get_string(&fs::TransferJob::join(p, &file.name));
+                            if let Ok(DigestCheckResult::IsSame) =
+                                fs::is_write_need_confirmation(false, &path, &digest)
+                            {
+                                overwrite = false;
+                            }
+                        }
+                        self.confirm(job_confirm(&digest, overwrite), false).await?;
+                    }
+                }
+                file_response::Union::Block(block) => {
+                    if let Some(job) = fs::get_job(id, &mut self.write_jobs) {
+                        job.write(block).await?;
+                    }
+                }
+                file_response::Union::Done(d) => {
+                    let mut files = 0;
+                    let mut size = 0;
+                    if let Some(job) = fs::remove_job(id, &mut self.write_jobs) {
+                        job.modify_time();
+                        if let Some(err) = job.job_error() {
+                            bail!("{}", err);
+                        }
+                        files = job.files().len();
+                        size = job.total_size();
+                    }
+                    return Ok(json!({
+                        "job": d.id,
+                        "files": files,
+                        "size": size,
+                    }));
+                }
+                file_response::Union::Error(e) => {
+                    fs::remove_job(id, &mut self.write_jobs);
+                    fs::remove_job(id, &mut self.read_jobs);
+                    bail!("{}", e.error);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    async fn confirm(
+        &mut self,
+        req: FileTransferSendConfirmRequest,
+        is_upload: bool,
+    ) -> ResultType<()> {
+        let jobs = if is_upload {
+            &mut self.read_jobs
+        } else {
+            &mut self.write_jobs
+        };
+        if let Some(job) = fs::get_job(req.id, jobs) {
+            job.confirm(&req).await;
+        }
+        self.stream.send(&new_send_confirm(req)).await
+    }
+
+    async fn term_open(
+        &mut self,
+        rows: u32,
+        cols: u32,
+        profile: Option<String>,
+    ) -> ResultType<Value> {
+        let terminal_id = 0;
+        if let Some(profile) = profile {
+            let mut action = TerminalAction::new();
+            action.set_data(TerminalData {
+                terminal_id,
+                data: serde_json::to_vec(&json!({ "profile": profile }))?.into(),
+                ..Default::default()
+            });
+            let mut msg = Message::new();
+            msg.set_terminal_action(action);
+            self.stream.send(&msg).await?;
+        }
+        let mut action = TerminalAction::new();
+        action.set_open(OpenTerminal {
+            terminal_id,
+            rows,
+            cols,
+            ..Default::default()
+        });
+        let mut msg = Message::new();
+        msg.set_terminal_action(action);
+        self.stream.send(&msg).await?;
+        let mut event = None;
+        self.wait(Duration::from_millis(READ_TIMEOUT), |s| {
+            event = s.terminal_events.pop();
+            event.is_some()
+        })
+        .await?;
+        match event {
+            Some(terminal_response::Union::Opened(opened)) if opened.success => {
+                self.terminal_id = Some(terminal_id);
+                Ok(json!({ "terminal_id": terminal_id, "pid": opened.pid }))
+            }
+            Some(terminal_response::Union::Opened(opened)) => bail!("{}", opened.message),
+            Some(terminal_response::Union::Error(e)) => bail!("{}", e.message),
+            _ => bail!("Timeout"),
+        }
+    }
+
+    async fn term_send(&mut self, data: Vec<u8>) -> ResultType<Value> {
+        let Some(terminal_id) = self.terminal_id else {
+            bail!("No terminal opened");
+        };
+        let mut action = TerminalAction::new();
+        action.set_data(TerminalData {
+            terminal_id,
+            data: data.into(),
+            ..Default::default()
+        });
+        let mut msg = Message::new();
+        msg.set_terminal_action(action);
+        self.stream.send(&msg).await?;
+        Ok(json!({}))
+    }
+
+    /// Collect terminal output until it has been idle for `idle_ms`.
+    async fn term_read(&mut self, idle_ms: u64) -> ResultType<Value> {
+        let idle = Duration::from_millis(idle_ms);
+        loop {
+            let len = self.terminal_output.len();
+            self.wait(idle, |s| {
+                s.terminal_output.len() != len || s.terminal_id.is_none()
+            })
+            .await?;
+            if self.terminal_output.len() == len || self.terminal_id.is_none() {
+                break;
+            }
+        }
+        let output = std::mem::take(&mut self.terminal_output);
+        Ok(json!({
+            "output": String::from_utf8_lossy(&output),
+            "closed": self.terminal_id.is_none(),
+        }))
+    }
+
+    async fn term_close(&mut self) -> ResultType<Value> {
+        let Some(terminal_id) = self.terminal_id.take() else {
+            bail!("No terminal opened");
+        };
+        let mut action = TerminalAction::new();
+        action.set_close(CloseTerminal {
+            terminal_id,
+            ..Default::default()
+        });
+        let mut msg = Message::new();
+        msg.set_terminal_action(action);
+        self.stream.send(&msg).await?;
+        Ok(json!({}))
+    }
+
+    /// Run a command on the peer without opening a terminal.
+    async fn exec(
+        &mut self,
+        program: &str,
+        args: Vec<String>,
+        timeout_ms: u64,
+    ) -> ResultType<Value> {
+        use crate::server::terminal_exec::{ExecRequest, DEFAULT_TIMEOUT_MS};
+
+        let terminal_id = self.next_exec_id;
+        self.next_exec_id -= 1;
+        let req = ExecRequest {
+            program: program.to_owned(),
+            args,
+            timeout_ms,
+            ..Default::default()
+        };
+        let mut action = TerminalAction::new();
+        action.set_data(TerminalData {
+            terminal_id,
+            data: serde_json::to_vec(&req)?.into(),
+            ..Default::default()
+        });
+        let mut msg = Message::new();
+        msg.set_terminal_action(action);
+        self.stream.send(&msg).await?;
+        let wait_ms = if timeout_ms == 0 {
+            DEFAULT_TIMEOUT_MS
+        } else {
+            timeout_ms
+        };
+        let mut result = None;
+        let mut error = None;
+        self.wait(Duration::from_millis(wait_ms + READ_TIMEOUT), |s| {
+            if let Some(i) = s.exec_results.iter().position(|(id, _)| *id == terminal_id) {
+                result = Some(s.exec_results.remove(i).1);
+            } else if let Some(i) = s.terminal_events.iter().position(
+                |e| matches!(e, terminal_response::Union::Error(e) if e.terminal_id == terminal_id),
+            ) {
+                error = Some(s.terminal_events.remove(i));
+            }
+            result.is_some() || error.is_some()
+        })
+        .await?;
+        match (result, error) {
+            (Some(data), _) => Ok(serde_json::from_slice(&data)?),
+            (_, Some(terminal_response::Union::Error(e))) => bail!("{}", e.message),
+            _ => bail!("Timeout"),
+        }
+    }
+
+    async fn screenshot(&mut self, display: i32, path: &str) -> ResultType<Value> {
+        let sid = uuid::Uuid::new_v4().to_string();
+        let mut msg = Message::new();
+        msg.set_screenshot_request(ScreenshotRequest {
+            display,
+            sid: sid.clone(),
+            ..Default::default()
+        });
+        self.stream.send(&msg).await?;
+        let mut resp = None;
+        self.wait(Duration::from_millis(READ_TIMEOUT), |s| {
+            if let Some(pos) = s.screenshots.iter().position(|r| r.sid == sid) {
+                resp = Some(s.screenshots.remove(pos));
+            }
+            resp.is_some()
+        })
+        .await?;
+        let Some(resp) = resp else {
+            bail!("Timeout");
+        };
+        if !resp.msg.is_empty() {
+            bail!("{}", resp.msg);
+        }
+        std::fs::write(path, &resp.data)?;
+        Ok(json!({ "path": path, "size": resp.data.len() }))
+    }
+
+    /// The peer pushes its clipboard on change, so `get` reports the latest
+    /// text received, waiting up to `ms` for one if none arrived yet.
+    async fn clipboard_get(&mut self, ms: u64) -> ResultType<Value> {
+        self.wait(Duration::from_millis(ms), |s| s.clipboard.is_some())
+            .await?;
+        Ok(json!({ "text": self.clipboard }))
+    }
+
+    async fn clipboard_set(&mut self, text: String) -> ResultType<Value> {
+        let compressed = hbb_common::compress::compress(text.as_bytes());
+        let compress = compressed.len() < text.len();
+        let mut msg = Message::new();
+        msg.set_clipboard(Clipboard {
+            compress,
+            content: if compress {
+                compressed.into()
+            } else {
+                text.into_bytes().into()
+            },
+            format: ClipboardFormat::Text.into(),
+            ..Default::default()
+        });
+        self.stream.send(&msg).await?;
+        Ok(json!({}))
+    }
+}
+
+fn job_confirm(digest: &FileTransferDigest, overwrite: bool) -> FileTransferSendConfirmRequest {
+    FileTransferSendConfirmRequest {
+        id: digest.id,
+        file_num: digest.file_num,
+        union: Some(if overwrite {
+            file_transfer_send_confirm_request::Union::OffsetBlk(0)
+        } else {
+            file_transfer_send_confirm_request::Union::Skip(true)
+        }),
+        ..Default::default()
+    }
+}
+
+/// Log in to `id` and run the commands of `script` (stdin if `None`), one per line.
+/// Each command prints one JSON object to stdout. Returns the process exit code:
+/// 0 if every command succeeded, 1 if any failed, 2 if the session could not be set up.
+///
+/// Commands:
+///   ls <remote-dir> [-a]            list a remote directory
+///   get <remote-path> <local-path>  download a file or directory
+///   put <local-path> <remote-path>  upload a file or directory
+///   term open [rows] [cols]         open a terminal
+///   term send <text>                write to the terminal, `\n` `\r` `\t` are expanded
+///   term read [idle-ms]             read terminal output until idle
+///   term close                      close the terminal
+///   screenshot <display> <file>     save a screenshot of a display
+///   clipboard get [wait-ms]         print the peer's clipboard text
+///   clipboard set <text>            set the peer's clipboard text
+///   sleep <ms>                      keep the session alive
+#[tokio::main(flavor = "current_thread")]
+pub async fn run_script(
+    id: String,
+    key: String,
+    token: String,
+    script: Option<String>,
+    conn_type: Option<ConnType>,
+    password: Option<String>,
+    permission_template: Option<String>,
+    stop_on_error: bool,
+) -> i32 {
+    let lines: Vec<String> = match &script {
+        Some(path) => match std::fs::read_to_string(path) {
+            Ok(s) => s.lines().map(|l| l.to_owned()).collect(),
+            Err(err) => {
+                emit(json!({ "ok": false, "error": format!("{}: {}", path, err) }));
+                return 2;
+            }
+        },
+        None => std::io::stdin()
+            .lock()
+            .lines()
+            .filter_map(|l| l.ok())
+            .collect(),
+    };
+    let conn_type = match conn_type.map(Ok).unwrap_or_else(|| infer_conn_type(&lines)) {
+        Ok(t) => t,
+        Err(err) => {
+            emit(json!({ "ok": false, "error": err.to_string() }));
+            return 2;
+        }
+    };
+    let password = password.or_else(|| std::env::var("RUSTDESK_PASSWORD").ok());
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
+    let handler = Session::new(&id, conn_type, password, sender);
+    if let Some(t) = permission_template {
+        handler
+            .lc
+            .write()
+            .unwrap()
+            .set_option(OPTION_PERMISSION_TEMPLATE.to_owned(), t);
+    }
+    let stream = match login(&handler, &mut receiver, &key, &token).await {
+        Ok(stream) => stream,
+        Err(err) => {
+            emit(json!({ "cmd": "login", "ok": false, "error": err.to_string() }));
+            return 2;
+        }
+    };
+    emit(json!({
+        "cmd": "login",
+        "ok": true,
+        "id": id,
+        "direct": handler.lc.read().unwrap().direct,
+        "version": handler.lc.read().unwrap().version,
+        "terminal_profiles": *handler.terminal_profiles.read().unwrap(),
+    }));
+    let mut runner = ScriptRunner {
+        handler,
+        stream,
+        read_jobs: Vec::new(),
+        write_jobs: Vec::new(),
+        pending: Vec::new(),
+        terminal_id: None,
+        terminal_output: Vec::new(),
+        terminal_events: Vec::new(),
+        exec_results: Vec::new(),
+        next_exec_id: -1,
+        screenshots: Vec::new(),
+        clipboard: None,
+    };
+    let mut code = 0;
+    for (i, line) in lines.iter().enumerate() {
+        if split_args(line).is_empty() {
+            continue;
+        }
+        let start = Instant::now();
+        let mut res = match runner.run(line).await {
+            Ok(v) => v,
+            Err(err) => {
+                code = 1;
+                json!({ "ok": false, "error": err.to_string() })
+            }
+        };
+        if let Value::Object(m) = &mut res {
+            m.entry("ok").or_insert(json!(true));
+            m.insert("line".to_owned(), json!(i + 1));
+            m.insert("cmd".to_owned(), json!(line.trim()));
+            m.insert(
+                "elapsed_ms".to_owned(),
+                json!(start.elapsed().as_millis() as u64),
+            );
+        }
+        emit(res);
+        if code != 0 && stop_on_error {
+            break;
+        }
+    }
+    if runner.terminal_id.is_some() {
+        runner.term_close().await.ok();
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_args() {
+        assert_eq!(split_args("ls /tmp"), vec!["ls", "/tmp"]);
+        assert_eq!(
+            split_args(r#"get "/a b/c" d # comment"#),
+            vec!["get", "/a b/c", "d"]
+        );
+        assert_eq!(
+            split_args(r#"clipboard set "" "#),
+            vec!["clipboard", "set", ""]
+        );
+        assert_eq!(
+            split_args(r#"term send "say \"hi\"""#),
+            vec!["term", "send", "say \"hi\""]
+        );
+        assert!(split_args("   # only a comment").is_empty());
+        assert_eq!(unescape(r"ls\n"), "ls\n");
+    }
+}
@@ -89,6 +89,7 @@ use crate::ui_session_interface::SessionPermissionConfig;
 
 pub use super::lang::*;
 
+pub mod file_sync;
 pub mod file_trait;
 pub mod helper;
 pub mod io_loop;
@@ -118,6 +119,11 @@ pub const REQUIRE_2FA: &'static str = "2FA Required";
 pub const LOGIN_MSG_NO_PASSWORD_ACCESS: &str = "No Password Access";
 pub const LOGIN_MSG_OFFLINE: &str = "Offline";
 pub const LOGIN_SCREEN_WAYLAND: &str = "Wayland login screen is not supported";
+// The peer option of the permission template to request.
+pub const OPTION_PERMISSION_TEMPLATE: &str = "permission-template";
+// The `LoginRequest` field number the permission template is sent in. It is not in the message
+// definition yet, so older versions skip it as an unknown field.
+pub const LOGIN_REQUEST_FIELD_PERMISSION_TEMPLATE: u32 = 1001;
 #[cfg(target_os = "linux")]
 pub const SCRAP_UBUNTU_HIGHER_REQUIRED: &str = "Wayland requires Ubuntu 21.04 or higher version.";
 #[cfg(target_os = "linux")]
@@ -2672,6 +2678,13 @@ impl LoginConfigHandler {
             hwid,
             ..Default::default()
         };
+        let template = self.get_option(OPTION_PERMISSION_TEMPLATE);
+        if !template.trim().is_empty() {
+            lr.mut_unknown_fields().add_length_delimited(
+                LOGIN_REQUEST_FIELD_PERMISSION_TEMPLATE,
+                template.trim().as_bytes().to_vec(),
+            );
+        }
         match self.conn_type {
             ConnType::FILE_TRANSFER => lr.set_file_transfer(FileTransfer {
                 dir: self.get_remote_dir(),
@@ -3681,6 +3694,16 @@ pub enum Data {
     ResetDecoder(Option<usize>),
     RenameFile((i32, String, String, bool)),
     TakeScreenshot((i32, String)),
+    SyncPlan((i32, String, String, String)),
+    SyncRun((i32, String)),
+    SetJobLimit((i32, u32, crate::file_scheduler::Priority)),
+    ExportManifest((i32, String)),
+    FileCheck(crate::file_manifest::Check),
+    SearchFiles((i32, String, String)),
+    DownloadFiles((i32, String, String, Vec<String>, bool)),
+    DeltaTransfer((i32, String, String, bool)),
+    DeltaTimeout(i32),
+    DeltaError((i32, String)),
 }
 
 /// Keycode for key events.
@@ -0,0 +1,364 @@
+// Folder synchronization plans for the file transfer.
+//
+// A sync job compares the recursive listings of a local and a remote directory and makes a plan
+// of uploads, downloads and deletes, which is shown as a preview and run as ordinary one-file
+// transfer and remove jobs. Files are compared by size and modification time only. There is no
+// hash comparison: the listings of the protocol carry no content hashes and the resume digest
+// compares size and modification time too, so hashing the remote files needs protocol support.
+// A file changed without a change of size and modification time is not synchronized.
+// Directions are `upload` (mirror local onto remote), `download` (mirror remote onto local) and
+// `both`. For `both`, the files which were in sync after the previous run of a plan of the same
+// folder pair are kept in `file_sync/<key>.json`, to tell which side changed or deleted a file.
+// They are saved once the jobs of the plan are done, a plan which is not run keeps the previous
+// ones, and an item whose job failed keeps its previous state. A file changed on both sides is a
+// conflict, resolved by the `conflict` rule: `newer`, `local`, `remote` or `skip`. Deletes are
+// only planned with `delete`.
+
+use hbb_common::{
+    config::Config,
+    log,
+    message_proto::{FileEntry, FileType},
+};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::PathBuf,
+};
+
+// Seconds, file systems like FAT keep the modification time in 2 seconds.
+const MTIME_TOLERANCE: u64 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Upload,
+    Download,
+    Both,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Conflict {
+    #[default]
+    Newer,
+    Local,
+    Remote,
+    Skip,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Options {
+    pub direction: Direction,
+    #[serde(default)]
+    pub conflict: Conflict,
+    #[serde(default)]
+    pub delete: bool,
+    #[serde(default)]
+    pub include_hidden: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Upload,
+    Download,
+    DeleteLocal,
+    DeleteRemote,
+    /// A conflict skipped by the `skip` rule.
+    Conflict,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Item {
+    /// Relative to the synchronized directories, `/` separated.
+    pub name: String,
+    pub action: Action,
+    /// `new`, `changed`, `deleted` or `conflict`.
+    pub reason: &'static str,
+    pub size: u64,
+    pub local: String,
+    pub remote: String,
+    /// The state of both sides once the job of the item is done, for the base.
+    #[serde(skip)]
+    pub synced: Option<Stat>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Stat {
+    pub size: u64,
+    pub mtime: u64,
+}
+
+/// Files in sync at the previous plan, by name.
+pub type Base = HashMap<String, Stat>;
+
+fn same(a: &Stat, b: &Stat) -> bool {
+    a.size == b.size && a.mtime.abs_diff(b.mtime) <= MTIME_TOLERANCE
+}
+
+fn stats(entries: &[FileEntry]) -> HashMap<String, Stat> {
+    entries
+        .iter()
+        .filter(|e| {
+            matches!(
+                e.entry_type.enum_value(),
+                Ok(FileType::File) | Ok(FileType::FileLink)
+            )
+        })
+        .map(|e| {
+            (
+                e.name.replace('\\', "/"),
+                Stat {
+                    size: e.size,
+                    mtime: e.modified_time,
+                },
+            )
+        })
+        .collect()
+}
+
+fn resolve(conflict: Conflict, local: &Stat, remote: &Stat) -> Action {
+    match conflict {
+        Conflict::Newer if local.mtime >= remote.mtime => Action::Upload,
+        Conflict::Newer => Action::Download,
+        Conflict::Local => Action::Upload,
+        Conflict::Remote => Action::Download,
+        Conflict::Skip => Action::Conflict,
+    }
+}
+
+/// Plan the synchronization, returns the items without their full paths and the new base.
+pub fn plan(
+    local: &[FileEntry],
+    remote: &[FileEntry],
+    base: &Base,
+    options: &Options,
+) -> (Vec<Item>, Base) {
+    let (local, remote) = (stats(local), stats(remote));
+    let names: BTreeSet<&String> = local.keys().chain(remote.keys()).collect();
+    let mut items = vec![];
+    let mut new_base = Base::new();
+    let unchanged = |name: &str, stat: &Stat| base.get(name).map_or(false, |b| same(b, stat));
+    for name in names {
+        let (action, reason, size) = match (local.get(name), remote.get(name)) {
+            (Some(l), Some(r)) if same(l, r) => {
+                new_base.insert(name.clone(), *l);
+                continue;
+            }
+            (Some(l), Some(r)) => match options.direction {
+                Direction::Upload => (Action::Upload, "changed", l.size),
+                Direction::Download => (Action::Download, "changed", r.size),
+                Direction::Both => match (unchanged(name, l), unchanged(name, r)) {
+                    (false, true) => (Action::Upload, "changed", l.size),
+                    (true, false) => (Action::Download, "changed", r.size),
+                    _ => {
+                        let action = resolve(options.conflict, l, r);
+                        let size = if action == Action::Download {
+                            r.size
+                        } else {
+                            l.size
+                        };
+                        (action, "conflict", size)
+                    }
+                },
+            },
+            (Some(l), None) => match options.direction {
+                Direction::Upload => (Action::Upload, "new", l.size),
+                Direction::Download if options.delete => (Action::DeleteLocal, "deleted", l.size),
+                Direction::Both if options.delete && unchanged(name, l) => {
+                    (Action::DeleteLocal, "deleted", l.size)
+                }
+                Direction::Both => (Action::Upload, "new", l.size),
+                Direction::Download => continue,
+            },
+            (None, Some(r)) => match options.direction {
+                Direction::Download => (Action::Download, "new", r.size),
+                Direction::Upload if options.delete => (Action::DeleteRemote, "deleted", r.size),
+                Direction::Both if options.delete && unchanged(name, r) => {
+                    (Action::DeleteRemote, "deleted", r.size)
+                }
+                Direction::Both => (Action::Download, "new", r.size),
+                Direction::Upload => continue,
+            },
+            (None, None) => continue,
+        };
+        let synced = match action {
+            Action::Upload => local.get(name).copied(),
+            Action::Download => remote.get(name).copied(),
+            _ => None,
+        };
+        items.push(Item {
+            name: name.clone(),
+            action,
+            reason,
+            size,
+            local: "".to_owned(),
+            remote: "".to_owned(),
+            synced,
+        });
+    }
+    (items, new_base)
+}
+
+/// The base after the jobs of the plan `items` ran, `base` is the one returned by `plan` and `old`
+/// the one it was made with. `done` tells if the job of an item succeeded.
+pub fn base_after_run(
+    old: &Base,
+    mut base: Base,
+    items: &[Item],
+    done: impl Fn(&str) -> bool,
+) -> Base {
+    for item in items {
+        if done(&item.name) {
+            if let Some(stat) = item.synced {
+                base.insert(item.name.clone(), stat);
+            }
+        } else if let Some(stat) = old.get(&item.name) {
+            base.insert(item.name.clone(), *stat);
+        }
+    }
+    base
+}
+
+/// Join `name` of a plan item to `dir`.
+pub fn join(dir: &str, sep: &str, name: &str) -> String {
+    format!(
+        "{}{}{}",
+        dir.trim_end_matches(|c| c == '/' || c == '\\'),
+        sep,
+        name.replace('/', sep)
+    )
+}
+
+fn base_path(peer_id: &str, local: &str, remote: &str) -> PathBuf {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{}\n{}\n{}", peer_id, local, remote));
+    let key = hex::encode(&hasher.finalize()[..8]);
+    Config::path("file_sync").join(format!("{}.json", key))
+}
+
+pub fn load_base(peer_id: &str, local: &str, remote: &str) -> Base {
+    std::fs::read_to_string(base_path(peer_id, local, remote))
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_base(peer_id: &str, local: &str, remote: &str, base: &Base) {
+    let path = base_path(peer_id, local, remote);
+    let res = path
+        .parent()
+        .map(|p| std::fs::create_dir_all(p))
+        .transpose()
+        .map_err(|e| e.to_string())
+        .and_then(|_| serde_json::to_string(base).map_err(|e| e.to_string()))
+        .and_then(|s| std::fs::write(&path, s).map_err(|e| e.to_string()));
+    if let Err(e) = res {
+        log::error!("Failed to save the sync state: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, size: u64, mtime: u64) -> FileEntry {
+        FileEntry {
+            name: name.to_owned(),
+            entry_type: FileType::File.into(),
+            size,
+            modified_time: mtime,
+            ..Default::default()
+        }
+    }
+
+    fn actions(items: &[Item]) -> Vec<(&str, Action)> {
+        items.iter().map(|i| (i.name.as_str(), i.action)).collect()
+    }
+
+    #[test]
+    fn test_plan() {
+        let local = vec![
+            entry("a", 1, 100),
+            entry("b\\c", 2, 200),
+            entry("d", 3, 300),
+        ];
+        let remote = vec![entry("a", 1, 101), entry("b/c", 2, 250), entry("e", 4, 400)];
+        let mut options = Options {
+            direction: Direction::Upload,
+            conflict: Conflict::Newer,
+            delete: true,
+            include_hidden: false,
+        };
+        let (items, base) = plan(&local, &remote, &Base::new(), &options);
+        assert_eq!(
+            actions(&items),
+            vec![
+                ("b/c", Action::Upload),
+                ("d", Action::Upload),
+                ("e", Action::DeleteRemote)
+            ]
+        );
+        assert_eq!(base.keys().collect::<Vec<_>>(), vec!["a"]);
+
+        // Without a base everything differing is a conflict or new.
+        options.direction = Direction::Both;
+        let (items, _) = plan(&local, &remote, &Base::new(), &options);
+        assert_eq!(
+            actions(&items),
+            vec![
+                ("b/c", Action::Download),
+                ("d", Action::Upload),
+                ("e", Action::Download)
+            ]
+        );
+        assert_eq!(items[0].reason, "conflict");
+
+        // "b/c" changed on the remote side only, "e" was deleted locally.
+        let base: Base = [
+            (
+                "b/c".to_owned(),
+                Stat {
+                    size: 2,
+                    mtime: 200,
+                },
+            ),
+            (
+                "e".to_owned(),
+                Stat {
+                    size: 4,
+                    mtime: 400,
+                },
+            ),
+        ]
+        .into_iter()
+        .collect();
+        options.conflict = Conflict::Skip;
+        let (items, new_base) = plan(&local, &remote, &base, &options);
+        assert_eq!(
+            actions(&items),
+            vec![
+                ("b/c", Action::Download),
+                ("d", Action::Upload),
+                ("e", Action::DeleteRemote)
+            ]
+        );
+        assert_eq!(items[0].reason, "changed");
+
+        // Only the download succeeded, the failed delete of "e" is planned again next time.
+        let after = base_after_run(&base, new_base, &items, |name| name == "b/c");
+        assert_eq!(
+            after["b/c"],
+            Stat {
+                size: 2,
+                mtime: 250
+            }
+        );
+        assert_eq!(after["e"], base["e"]);
+        assert!(!after.contains_key("d"));
+
+        assert_eq!(join("/tmp/x/", "/", "b/c"), "/tmp/x/b/c");
+        assert_eq!(join("C:\\x", "\\", "b/c"), "C:\\x\\b\\c");
+    }
+}
@@ -190,4 +190,53 @@ pub trait FileManager: Interface {
     fn rename_file(&self, act_id: i32, path: String, new_name: String, is_remote: bool) {
         self.send(Data::RenameFile((act_id, path, new_name, is_remote)));
     }
+
+    /// Plan the synchronization of `local` and `remote`, `options` are `file_sync::Options` as json.
+    fn sync_plan(&self, id: i32, local: String, remote: String, options: String) {
+        self.send(Data::SyncPlan((id, local, remote, options)));
+    }
+
+    /// The plan `id` is run by `jobs`, the json ids of the jobs by the names of their items.
+    fn sync_run(&self, id: i32, jobs: String) {
+        self.send(Data::SyncRun((id, jobs)));
+    }
+
+    /// Limit the upload job `act_id` to `kbps`, 0 for no limit, and set its priority.
+    fn set_job_limit(&self, act_id: i32, kbps: u32, priority: String) {
+        let priority = crate::file_scheduler::Priority::parse(&priority);
+        self.send(Data::SetJobLimit((act_id, kbps, priority)));
+    }
+
+    /// Write the manifest of the local files of job `act_id` to `path`.
+    fn export_manifest(&self, act_id: i32, path: String) {
+        self.send(Data::ExportManifest((act_id, path)));
+    }
+
+    /// Search the remote directory `path`, `query` is a `file_search::Query` as json.
+    fn search_files(&self, act_id: i32, path: String, query: String) {
+        self.send(Data::SearchFiles((act_id, path, query)));
+    }
+
+    /// Download the files `names` of the remote directory `path` to `to` as one job.
+    fn download_files(
+        &self,
+        act_id: i32,
+        path: String,
+        to: String,
+        names: Vec<String>,
+        include_hidden: bool,
+    ) {
+        self.send(Data::DownloadFiles((
+            act_id,
+            path,
+            to,
+            names,
+            include_hidden,
+        )));
+    }
+
+    /// Transfer the file `path` to `to`, where an older version of it is, as a delta.
+    fn delta_transfer(&self, act_id: i32, path: String, to: String, is_remote: bool) {
+        self.send(Data::DeltaTransfer((act_id, path, to, is_remote)));
+    }
 }
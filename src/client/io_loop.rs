@@ -4,10 +4,11 @@ use crate::clipboard::{update_clipboard, ClipboardSide};
 use crate::{audio_service, clipboard::CLIPBOARD_INTERVAL, ConnInner, CLIENT_SERVER};
 use crate::{
     client::{
-        self, new_voice_call_request, Client, Data, Interface, MediaData, MediaSender,
+        self, file_sync, new_voice_call_request, Client, Data, Interface, MediaData, MediaSender,
         QualityStatus, MILLI1, SEC30,
     },
     common::get_default_sound_input,
+    file_delta, file_manifest, file_scheduler, file_search,
     ui_session_interface::{InvokeUiSession, Session},
 };
 #[cfg(feature = "unix-file-copy-paste")]
@@ -41,14 +42,14 @@ use hbb_common::{
 };
 #[cfg(any(target_os = "windows", feature = "unix-file-copy-paste"))]
 use hbb_common::{tokio::sync::Mutex as TokioMutex, ResultType};
-use scrap::CodecFormat;
+use scrap::{record::RecordEventKind, CodecFormat};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     ffi::c_void,
     num::NonZeroI64,
     path::PathBuf,
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicUsize, Ordering},
         Arc, RwLock,
     },
 };
@@ -64,6 +65,17 @@ pub struct Remote<T: InvokeUiSession> {
     read_jobs: Vec<fs::TransferJob>,
     write_jobs: Vec<fs::TransferJob>,
     remove_jobs: HashMap<i32, RemoveJob>,
+    sync_jobs: HashMap<i32, SyncJob>,
+    sync_runs: HashMap<i32, SyncRun>,
+    search_jobs: HashSet<i32>,
+    delta_jobs: HashMap<i32, DeltaJob>,
+    file_scheduler: file_scheduler::Scheduler,
+    // The local path and files of the transfer jobs, for their manifests.
+    manifest_jobs: HashMap<i32, (PathBuf, Vec<String>)>,
+    // The uploads the peer asked the manifest of, to check them.
+    check_uploads: HashSet<i32>,
+    // The jobs transferring files again which did not match, with the first job and the retries.
+    check_retries: HashMap<i32, (i32, u32)>,
     timer: crate::RustDeskInterval,
     last_update_jobs_status: (Instant, HashMap<i32, u64>),
     is_connected: bool,
@@ -111,6 +123,14 @@ impl<T: InvokeUiSession> Remote<T> {
             read_jobs: Vec::new(),
             write_jobs: Vec::new(),
             remove_jobs: Default::default(),
+            sync_jobs: Default::default(),
+            sync_runs: Default::default(),
+            search_jobs: Default::default(),
+            delta_jobs: Default::default(),
+            file_scheduler: Default::default(),
+            manifest_jobs: Default::default(),
+            check_uploads: Default::default(),
+            check_retries: Default::default(),
             timer: crate::rustdesk_interval(time::interval(SEC30)),
             last_update_jobs_status: (Instant::now(), Default::default()),
             is_connected: false,
@@ -269,7 +289,17 @@ impl<T: InvokeUiSession> Remote<T> {
                                 break;
                             }
                             if !self.read_jobs.is_empty() {
-                                if let Err(err) = fs::handle_read_jobs(&mut self.read_jobs, &mut peer).await {
+                                let mut jobs = self.file_scheduler.take_ready(
+                                    &mut self.read_jobs,
+                                    file_scheduler::last_input(),
+                                );
+                                let res = if jobs.is_empty() {
+                                    Ok(Default::default())
+                                } else {
+                                    fs::handle_read_jobs(&mut jobs, &mut peer).await
+                                };
+                                self.file_scheduler.put_back(jobs, &mut self.read_jobs);
+                                if let Err(err) = res {
                                     self.handler.msgbox("error", "Connection Error", &err.to_string(), "");
                                     break;
                                 }
@@ -427,6 +457,7 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
             }
         }
+        self.sync_job_done(id, err.is_none());
         if let Some(err) = err {
             self.handler.job_error(id, err, file_num);
         } else {
@@ -434,6 +465,329 @@ impl<T: InvokeUiSession> Remote<T> {
         }
     }
 
+    fn sync_job_done(&mut self, job_id: i32, ok: bool) {
+        let id = self.sync_runs.iter_mut().find_map(|(id, run)| {
+            let name = run.pending.as_mut()?.remove(&job_id)?;
+            if ok {
+                run.succeeded.insert(name);
+            }
+            Some(*id)
+        });
+        if let Some(id) = id {
+            self.try_finish_sync_run(id);
+        }
+    }
+
+    // Save the sync base once all the jobs of the plan `id` are done.
+    fn try_finish_sync_run(&mut self, id: i32) {
+        let done = self
+            .sync_runs
+            .get(&id)
+            .and_then(|run| run.pending.as_ref())
+            .map_or(false, |pending| pending.is_empty());
+        if !done {
+            return;
+        }
+        let Some(run) = self.sync_runs.remove(&id) else {
+            return;
+        };
+        let base = file_sync::base_after_run(&run.old_base, run.base, &run.items, |name| {
+            run.succeeded.contains(name)
+        });
+        file_sync::save_base(&run.peer_id, &run.local, &run.remote, &base);
+    }
+
+    // Send the manifest of the files of upload `id` to the peer, which checks them.
+    fn send_source_manifest(&self, id: i32) {
+        let Some((root, names)) = self.manifest_jobs.get(&id).cloned() else {
+            return;
+        };
+        let sender = self.sender.clone();
+        file_manifest::spawn_build(id, root, names, move |m| {
+            let mut msg = Message::new();
+            msg.set_file_response(m.to_response());
+            sender.send(Data::Message(msg)).ok();
+        });
+    }
+
+    // Hash the files of a download and check them against the manifest of the source.
+    fn check_download(&self, source: file_manifest::Manifest) {
+        let Some((root, names)) = self.manifest_jobs.get(&source.id).cloned() else {
+            return;
+        };
+        let sender = self.sender.clone();
+        file_manifest::spawn_build(source.id, root, names, move |m| {
+            sender
+                .send(Data::FileCheck(file_manifest::compare(&source, &m)))
+                .ok();
+        });
+    }
+
+    // Transfer the files of `check` which do not match again, each as a new job.
+    fn retry_mismatched(&mut self, check: file_manifest::Check, download: bool) {
+        let (job_id, retries) = self
+            .check_retries
+            .remove(&check.id)
+            .unwrap_or((check.id, 0));
+        let names = check
+            .mismatched()
+            .map(|f| f.name.clone())
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            return;
+        }
+        if retries >= file_manifest::MAX_CHECK_RETRIES {
+            log::error!(
+                "Job {}: {} files do not match the source",
+                job_id,
+                names.len()
+            );
+            self.handler
+                .job_error(job_id, "File hash mismatch".to_owned(), -1);
+            return;
+        }
+        let Some((root, _)) = self.manifest_jobs.get(&check.id).cloned() else {
+            return;
+        };
+        let remote_root = if download {
+            &check.source
+        } else {
+            &check.destination
+        };
+        let sep = self.handler.get_path_sep(true);
+        for name in names {
+            let (local, remote) = if name.is_empty() {
+                (root.clone(), remote_root.clone())
+            } else {
+                (
+                    root.join(&name),
+                    format!(
+                        "{}{}{}",
+                        remote_root.trim_end_matches(sep),
+                        sep,
+                        name.replace('/', sep)
+                    ),
+                )
+            };
+            let local = local.to_string_lossy().to_string();
+            let (path, to) = if download {
+                (remote, local)
+            } else {
+                (local, remote)
+            };
+            let id = fs::get_next_job_id();
+            log::info!(
+                "Job {}: {} does not match, transfer it again as job {}",
+                job_id,
+                path,
+                id
+            );
+            self.check_retries.insert(id, (job_id, retries + 1));
+            self.handler.retry_job(id, job_id, &path, &to, download);
+            self.sender
+                .send(Data::SendFiles((
+                    id,
+                    fs::JobType::Generic,
+                    path,
+                    to,
+                    0,
+                    true,
+                    download,
+                )))
+                .ok();
+        }
+    }
+
+    // Handle a message of a delta transfer from the peer, see `file_delta`.
+    fn handle_delta(&mut self, delta: file_delta::Delta, data: Vec<u8>) {
+        use file_delta::Delta;
+        let id = delta.id();
+        let Some(job) = self.delta_jobs.get_mut(&id) else {
+            return;
+        };
+        match delta {
+            Delta::Ack { .. } => {
+                job.acked = true;
+                if job.download {
+                    self.start_delta_download(id);
+                }
+            }
+            Delta::Signatures { block_size, .. } if !job.download => {
+                // The peer has an older version of the file we upload.
+                let path = PathBuf::from(&job.path);
+                let stop = job.stop.clone();
+                let sender = self.sender.clone();
+                std::thread::spawn(move || {
+                    file_delta::send(id, &path, block_size, &data, &stop, |delta, data| {
+                        if let Delta::Error { error, .. } = delta {
+                            return sender.send(Data::DeltaError((id, error))).is_ok();
+                        }
+                        let mut msg = Message::new();
+                        msg.set_file_response(delta.to_response(&data));
+                        sender.send(Data::Message(msg)).is_ok()
+                    });
+                });
+            }
+            Delta::Copy { .. } | Delta::Literal { .. } | Delta::End { .. } => {
+                self.write_delta(delta, &data);
+            }
+            Delta::Done { error, .. } => {
+                self.delta_jobs.remove(&id);
+                let err = if error.is_empty() { None } else { Some(error) };
+                self.handle_job_status(id, 0, err);
+            }
+            Delta::Error { error, .. } => {
+                if let Some(job) = self.delta_jobs.remove(&id) {
+                    job.stop.store(true, Ordering::SeqCst);
+                }
+                self.handle_job_status(id, 0, Some(error));
+            }
+            _ => {}
+        }
+    }
+
+    // Hash the local file of a delta download and send the signatures of its blocks to the peer.
+    fn start_delta_download(&mut self, id: i32) {
+        let Some(job) = self.delta_jobs.get_mut(&id) else {
+            return;
+        };
+        let path = PathBuf::from(&job.to);
+        let writer = match file_delta::Writer::new(&path) {
+            Ok(writer) => writer,
+            Err(err) => {
+                log::warn!("Delta download {}: {}, transfer the whole file", id, err);
+                let error = err.to_string();
+                self.send_delta(&file_delta::Delta::Error { id, error });
+                self.delta_fallback(id);
+                return;
+            }
+        };
+        let block_size = writer.block_size();
+        job.writer = Some(writer);
+        let stop = job.stop.clone();
+        let sender = self.sender.clone();
+        std::thread::spawn(
+            move || match file_delta::signatures(&path, block_size, &stop) {
+                Ok(signatures) => {
+                    let delta = file_delta::Delta::Signatures { id, block_size };
+                    let mut msg = Message::new();
+                    msg.set_file_response(delta.to_response(&signatures));
+                    sender.send(Data::Message(msg)).ok();
+                }
+                Err(err) => {
+                    if err.kind() != std::io::ErrorKind::Interrupted {
+                        sender.send(Data::DeltaError((id, err.to_string()))).ok();
+                    }
+                }
+            },
+        );
+    }
+
+    // Apply an op of a delta download, the `End` finishes it.
+    fn write_delta(&mut self, delta: file_delta::Delta, data: &[u8]) {
+        use file_delta::Delta;
+        let id = delta.id();
+        let Some(job) = self.delta_jobs.get_mut(&id) else {
+            return;
+        };
+        let Some(writer) = job.writer.as_mut() else {
+            return;
+        };
+        let res = match &delta {
+            Delta::Copy { block, count, .. } => writer.copy(*block, *count),
+            Delta::Literal { .. } => writer.literal(data),
+            Delta::End {
+                size,
+                sha256,
+                modified,
+                ..
+            } => writer.finish(*size, sha256, *modified),
+            _ => return,
+        };
+        let end = matches!(delta, Delta::End { .. });
+        if res.is_ok() && !end {
+            if job.last_progress.elapsed() >= Duration::from_secs(1) {
+                job.last_progress = Instant::now();
+                let secs = job.started.elapsed().as_secs_f64().max(0.001);
+                let size = writer.size as f64;
+                self.handler.job_progress(id, 0, size / secs, size);
+            }
+            return;
+        }
+        let err = res.err().map(|e| e.to_string());
+        if let Some(job) = self.delta_jobs.remove(&id) {
+            if let Some(writer) = &job.writer {
+                let error = err.clone().unwrap_or_default();
+                // The result for the audit of the peer, which also stops it on an error.
+                self.send_delta(&writer.done(id, &job.path, error));
+            }
+        }
+        self.handle_job_status(id, 0, err);
+    }
+
+    // Transfer the whole file of a delta transfer the peer does not support or can not make.
+    fn delta_fallback(&mut self, id: i32) {
+        let Some(job) = self.delta_jobs.remove(&id) else {
+            return;
+        };
+        self.sender
+            .send(Data::SendFiles((
+                id,
+                fs::JobType::Generic,
+                job.path,
+                job.to,
+                0,
+                true,
+                job.download,
+            )))
+            .ok();
+    }
+
+    fn send_delta(&self, delta: &file_delta::Delta) {
+        let mut msg = Message::new();
+        msg.set_file_response(delta.to_response(&[]));
+        self.sender.send(Data::Message(msg)).ok();
+    }
+
+    fn update_sync_plan(&mut self, id: i32, job: SyncJob, remote_files: Vec<FileEntry>) {
+        let peer_id = self.handler.lc.read().unwrap().id.clone();
+        let old_base = file_sync::load_base(&peer_id, &job.local, &job.remote);
+        let (mut items, base) =
+            file_sync::plan(&job.local_files, &remote_files, &old_base, &job.options);
+        let (local_sep, remote_sep) = (
+            self.handler.get_path_sep(false),
+            self.handler.get_path_sep(true),
+        );
+        for item in items.iter_mut() {
+            item.local = file_sync::join(&job.local, local_sep, &item.name);
+            item.remote = file_sync::join(&job.remote, remote_sep, &item.name);
+        }
+        log::info!(
+            "sync plan {}: {} <-> {}, {} items",
+            id,
+            job.local,
+            job.remote,
+            items.len()
+        );
+        self.handler
+            .update_sync_plan(id, &serde_json::to_string(&items).unwrap_or_default(), "");
+        // Plans whose preview was cancelled are never run.
+        self.sync_runs.retain(|_, run| run.pending.is_some());
+        self.sync_runs.insert(
+            id,
+            SyncRun {
+                peer_id,
+                local: job.local,
+                remote: job.remote,
+                old_base,
+                base,
+                items,
+                pending: None,
+                succeeded: Default::default(),
+            },
+        );
+    }
+
     fn stop_voice_call(&mut self) {
         let voice_call_sender = std::mem::replace(&mut self.stop_voice_call_sender, None);
         if let Some(stopper) = voice_call_sender {
@@ -563,12 +917,26 @@ impl<T: InvokeUiSession> Remote<T> {
                         }
                         _ => {}
                     },
+                    Some(message::Union::MouseEvent(_))
+                    | Some(message::Union::KeyEvent(_))
+                    | Some(message::Union::PointerDeviceEvent(_)) => {
+                        file_scheduler::on_input();
+                    }
                     _ => {}
                 }
                 allow_err!(peer.send(&msg).await);
             }
             Data::SendFiles((id, r#type, path, to, file_num, include_hidden, is_remote)) => {
                 log::info!("send files, is remote {}", is_remote);
+                self.record_event(
+                    RecordEventKind::FileTransfer,
+                    format!(
+                        "{} {} -> {}",
+                        if is_remote { "receive" } else { "send" },
+                        path,
+                        to
+                    ),
+                );
                 let od = can_enable_overwrite_detection(self.handler.lc.read().unwrap().version);
                 if is_remote {
                     log::debug!("New job {}, write to {} from remote {}", id, to, path);
@@ -632,6 +1000,15 @@ impl<T: InvokeUiSession> Remote<T> {
                                 fs::transform_windows_path(&mut files);
                             }
                             let total_size = job.total_size();
+                            if let fs::DataSource::FilePath(p) = &job.data_source {
+                                self.manifest_jobs.insert(
+                                    id,
+                                    (
+                                        p.clone(),
+                                        job.files().iter().map(|f| f.name.clone()).collect(),
+                                    ),
+                                );
+                            }
                             self.read_jobs.push(job);
                             self.timer = crate::rustdesk_interval(time::interval(MILLI1));
                             allow_err!(
@@ -844,6 +1221,179 @@ impl<T: InvokeUiSession> Remote<T> {
                     }
                 }
             }
+            Data::SyncPlan((id, local, remote, options)) => {
+                let options = match serde_json::from_str::<file_sync::Options>(&options) {
+                    Ok(options) => options,
+                    Err(err) => {
+                        self.handler.update_sync_plan(id, "", &err.to_string());
+                        return true;
+                    }
+                };
+                let local_files = if std::path::Path::new(&local).exists() {
+                    fs::get_recursive_files(&local, options.include_hidden)
+                } else {
+                    Ok(vec![])
+                };
+                match local_files {
+                    Ok(local_files) => {
+                        let mut msg_out = Message::new();
+                        let mut file_action = FileAction::new();
+                        file_action.set_all_files(ReadAllFiles {
+                            id,
+                            path: remote.clone(),
+                            include_hidden: options.include_hidden,
+                            ..Default::default()
+                        });
+                        msg_out.set_file_action(file_action);
+                        allow_err!(peer.send(&msg_out).await);
+                        self.sync_jobs.insert(
+                            id,
+                            SyncJob {
+                                local,
+                                remote,
+                                local_files,
+                                options,
+                            },
+                        );
+                    }
+                    Err(err) => {
+                        self.handler.update_sync_plan(id, "", &err.to_string());
+                    }
+                }
+            }
+            Data::SearchFiles((id, path, query)) => {
+                let query = match serde_json::from_str::<file_search::Query>(&query) {
+                    Ok(query) => query,
+                    Err(err) => {
+                        self.handler.update_search_result(id, "", &err.to_string());
+                        return true;
+                    }
+                };
+                let mut msg_out = Message::new();
+                msg_out.set_file_action(file_search::Search { id, path, query }.to_action());
+                allow_err!(peer.send(&msg_out).await);
+                self.search_jobs.insert(id);
+            }
+            Data::DownloadFiles((id, path, to, names, include_hidden)) => {
+                self.record_event(
+                    RecordEventKind::FileTransfer,
+                    format!("receive {} files of {} -> {}", names.len(), path, to),
+                );
+                let od = can_enable_overwrite_detection(self.handler.lc.read().unwrap().version);
+                log::debug!(
+                    "New job {}, write to {} from remote {}, {} files",
+                    id,
+                    to,
+                    path,
+                    names.len()
+                );
+                self.write_jobs.push(fs::TransferJob::new_write(
+                    id,
+                    fs::JobType::Generic,
+                    path.clone(),
+                    fs::DataSource::FilePath(PathBuf::from(&to)),
+                    0,
+                    include_hidden,
+                    true,
+                    Vec::new(),
+                    od,
+                ));
+                let mut msg_out = fs::new_send(id, fs::JobType::Generic, path, 0, include_hidden);
+                file_search::select_names(msg_out.mut_file_action().mut_send(), &names);
+                allow_err!(peer.send(&msg_out).await);
+            }
+            Data::DeltaTransfer((id, path, to, is_remote)) => {
+                self.record_event(
+                    RecordEventKind::FileTransfer,
+                    format!(
+                        "{} {} -> {} (delta)",
+                        if is_remote { "receive" } else { "send" },
+                        path,
+                        to
+                    ),
+                );
+                let remote = if is_remote { path.clone() } else { to.clone() };
+                self.delta_jobs
+                    .insert(id, DeltaJob::new(path, to, is_remote));
+                let begin = file_delta::Delta::Begin {
+                    id,
+                    path: remote,
+                    download: is_remote,
+                };
+                let mut msg_out = Message::new();
+                msg_out.set_file_response(begin.to_response(&[]));
+                allow_err!(peer.send(&msg_out).await);
+                let sender = self.sender.clone();
+                tokio::spawn(async move {
+                    time::sleep(file_delta::ACK_TIMEOUT).await;
+                    sender.send(Data::DeltaTimeout(id)).ok();
+                });
+            }
+            Data::DeltaTimeout(id) => {
+                if self.delta_jobs.get(&id).is_some_and(|job| !job.acked) {
+                    log::info!("Delta transfer {} is not supported by the peer", id);
+                    self.delta_fallback(id);
+                }
+            }
+            Data::DeltaError((id, err)) => {
+                if self.delta_jobs.remove(&id).is_some() {
+                    self.send_delta(&file_delta::Delta::Error {
+                        id,
+                        error: err.clone(),
+                    });
+                    self.handle_job_status(id, 0, Some(err));
+                }
+            }
+            Data::SyncRun((id, jobs)) => {
+                let jobs = serde_json::from_str::<HashMap<String, i32>>(&jobs).unwrap_or_default();
+                if let Some(run) = self.sync_runs.get_mut(&id) {
+                    run.pending = Some(jobs.into_iter().map(|(name, job)| (job, name)).collect());
+                }
+                self.try_finish_sync_run(id);
+            }
+            Data::SetJobLimit((id, kbps, priority)) => {
+                if fs::get_job(id, &mut self.write_jobs).is_some() {
+                    // A download, its blocks are read and sent by the peer.
+                    let limit = file_scheduler::JobLimit { id, kbps, priority };
+                    let mut msg_out = Message::new();
+                    msg_out.set_file_action(limit.to_action());
+                    allow_err!(peer.send(&msg_out).await);
+                } else {
+                    self.file_scheduler.set_job(id, kbps, priority);
+                }
+            }
+            Data::FileCheck(check) => {
+                // Sent to the peer for its audit.
+                let mut msg_out = Message::new();
+                msg_out.set_file_action(check.to_action());
+                allow_err!(peer.send(&msg_out).await);
+                self.retry_mismatched(check, true);
+            }
+            Data::ExportManifest((id, path)) => {
+                let Some((root, names)) = self.manifest_jobs.get(&id).cloned() else {
+                    self.handler.msgbox(
+                        "custom-nook-nocancel-hasclose-error",
+                        "Export manifest",
+                        "Failed",
+                        "",
+                    );
+                    return true;
+                };
+                let handler = self.handler.clone();
+                file_manifest::spawn_build(id, root, names, move |m| {
+                    let res = serde_json::to_string_pretty(&m)
+                        .map_err(|e| e.to_string())
+                        .and_then(|s| std::fs::write(&path, s).map_err(|e| e.to_string()));
+                    if let Err(e) = res {
+                        handler.msgbox(
+                            "custom-nook-nocancel-hasclose-error",
+                            "Export manifest",
+                            &e,
+                            "",
+                        );
+                    }
+                });
+            }
             Data::CancelJob(id) => {
                 let mut msg_out = Message::new();
                 let mut file_action = FileAction::new();
@@ -858,6 +1408,15 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
                 let _ = fs::remove_job(id, &mut self.read_jobs);
                 self.remove_jobs.remove(&id);
+                self.sync_jobs.remove(&id);
+                self.search_jobs.remove(&id);
+                self.manifest_jobs.remove(&id);
+                self.check_uploads.remove(&id);
+                self.check_retries.remove(&id);
+                if let Some(job) = self.delta_jobs.remove(&id) {
+                    job.stop.store(true, Ordering::SeqCst);
+                }
+                self.sync_job_done(id, false);
             }
             Data::RemoveDir((id, path)) => {
                 let mut msg_out = Message::new();
@@ -1094,6 +1653,13 @@ impl<T: InvokeUiSession> Remote<T> {
         }
     }
 
+    // Mark an event in the outgoing session recordings of this peer.
+    #[inline]
+    fn record_event(&self, kind: RecordEventKind, detail: String) {
+        let id = self.handler.lc.read().unwrap().id.clone();
+        scrap::record::push_event(false, Some(&id), kind, detail);
+    }
+
     async fn send_toggle_privacy_mode_msg(&self, peer: &mut Stream) {
         let lc = self.handler.lc.read().unwrap();
         if lc.version >= hbb_common::get_version_number("1.2.4")
@@ -1423,6 +1989,14 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
                 Some(message::Union::Clipboard(cb)) => {
                     if !self.handler.lc.read().unwrap().disable_clipboard.v {
+                        self.record_event(
+                            RecordEventKind::Clipboard,
+                            format!(
+                                "{:?}, {} bytes",
+                                cb.format.enum_value_or_default(),
+                                cb.content.len()
+                            ),
+                        );
                         #[cfg(not(any(target_os = "android", target_os = "ios")))]
                         update_clipboard(vec![cb], ClipboardSide::Client);
                         #[cfg(target_os = "ios")]
@@ -1442,6 +2016,20 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
                 Some(message::Union::MultiClipboards(_mcb)) => {
                     if !self.handler.lc.read().unwrap().disable_clipboard.v {
+                        self.record_event(
+                            RecordEventKind::Clipboard,
+                            _mcb.clipboards
+                                .iter()
+                                .map(|cb| {
+                                    format!(
+                                        "{:?}, {} bytes",
+                                        cb.format.enum_value_or_default(),
+                                        cb.content.len()
+                                    )
+                                })
+                                .collect::<Vec<_>>()
+                                .join("; "),
+                        );
                         #[cfg(not(any(target_os = "android", target_os = "ios")))]
                         update_clipboard(_mcb.clipboards, ClipboardSide::Client);
                         #[cfg(target_os = "android")]
@@ -1476,6 +2064,8 @@ impl<T: InvokeUiSession> Remote<T> {
                                 job.set_finished_size_on_resume();
                             } else if let Some(job) = self.remove_jobs.get_mut(&fd.id) {
                                 job.files = entries;
+                            } else if let Some(job) = self.sync_jobs.remove(&fd.id) {
+                                self.update_sync_plan(fd.id, job, entries);
                             }
                         }
                         Some(file_response::Union::Digest(digest)) => {
@@ -1627,6 +2217,15 @@ impl<T: InvokeUiSession> Remote<T> {
                                 job.modify_time();
                                 err = job.job_error();
                                 job_type = job.r#type;
+                                if let fs::DataSource::FilePath(p) = &job.data_source {
+                                    self.manifest_jobs.insert(
+                                        d.id,
+                                        (
+                                            p.clone(),
+                                            job.files().iter().map(|f| f.name.clone()).collect(),
+                                        ),
+                                    );
+                                }
                                 printer_data = match job.get_buf_data().await {
                                     Ok(d) => d,
                                     Err(e) => {
@@ -1634,6 +2233,8 @@ impl<T: InvokeUiSession> Remote<T> {
                                         None
                                     }
                                 };
+                            } else if self.check_uploads.remove(&d.id) {
+                                self.send_source_manifest(d.id);
                             }
                             match job_type {
                                 fs::JobType::Generic => {
@@ -1673,6 +2274,14 @@ impl<T: InvokeUiSession> Remote<T> {
                             }
                         }
                         Some(file_response::Union::Error(e)) => {
+                            if self.sync_jobs.remove(&e.id).is_some() {
+                                self.handler.update_sync_plan(e.id, "", &e.error);
+                                return true;
+                            }
+                            if self.search_jobs.remove(&e.id) {
+                                self.handler.update_search_result(e.id, "", &e.error);
+                                return true;
+                            }
                             let job_type = fs::remove_job(e.id, &mut self.write_jobs)
                                 .map(|j| j.r#type)
                                 .unwrap_or(fs::JobType::Generic);
@@ -1685,14 +2294,46 @@ impl<T: InvokeUiSession> Remote<T> {
                                 }
                             }
                         }
+                        None => {
+                            if let Some(id) = file_manifest::requested(&fr) {
+                                if self.manifest_jobs.contains_key(&id) {
+                                    self.check_uploads.insert(id);
+                                }
+                            } else if let Some(source) = file_manifest::Manifest::from_response(&fr)
+                            {
+                                self.check_download(source);
+                            } else if let Some(check) = file_manifest::Check::from_response(&fr) {
+                                self.retry_mismatched(check, false);
+                            } else if let Some(results) = file_search::Results::from_response(&fr) {
+                                let running = if results.done {
+                                    self.search_jobs.remove(&results.id)
+                                } else {
+                                    self.search_jobs.contains(&results.id)
+                                };
+                                if running {
+                                    self.handler.update_search_result(
+                                        results.id,
+                                        &serde_json::to_string(&results).unwrap_or_default(),
+                                        &results.error,
+                                    );
+                                }
+                            } else if let Some((delta, data)) =
+                                file_delta::Delta::from_response(&fr)
+                            {
+                                self.handle_delta(delta, data);
+                            }
+                        }
                         _ => {}
                     }
                 }
                 Some(message::Union::Misc(misc)) => match misc.union {
                     Some(misc::Union::AudioFormat(f)) => {
+                        let id = self.handler.lc.read().unwrap().id.clone();
+                        scrap::record::set_audio_format(false, &id, f.sample_rate, f.channels as _);
                         self.audio_sender.send(MediaData::AudioFormat(f)).ok();
                     }
                     Some(misc::Union::ChatMessage(c)) => {
+                        self.record_event(RecordEventKind::Chat, c.text.clone());
                         self.handler.new_message(c.text);
                     }
                     Some(misc::Union::PermissionInfo(p)) => {
@@ -1750,6 +2391,10 @@ impl<T: InvokeUiSession> Remote<T> {
                         }
                     }
                     Some(misc::Union::SwitchDisplay(s)) => {
+                        self.record_event(
+                            RecordEventKind::DisplaySwitch,
+                            format!("display {}", s.display),
+                        );
                         self.handler.handle_peer_switch_display(&s);
                         if let Some(thread) = self.video_threads.get_mut(&(s.display as usize)) {
                             thread.video_sender.send(MediaData::Reset).ok();
@@ -1898,7 +2543,11 @@ impl<T: InvokeUiSession> Remote<T> {
                     self.handler.handle_test_delay(t, peer).await;
                 }
                 Some(message::Union::AudioFrame(frame)) => {
-                    if !self.handler.lc.read().unwrap().disable_audio.v {
+                    let lc = self.handler.lc.read().unwrap();
+                    if !lc.disable_audio.v {
+                        // Muted audio is not recorded either.
+                        scrap::record::push_audio(false, Some(&lc.id), &frame.data);
+                        drop(lc);
                         self.audio_sender
                             .send(MediaData::AudioFrame(Box::new(frame)))
                             .ok();
@@ -2116,6 +2765,10 @@ impl<T: InvokeUiSession> Remote<T> {
 
     #[inline(always)]
     fn update_privacy_mode(&mut self, impl_key: String, on: bool) {
+        self.record_event(
+            RecordEventKind::PrivacyMode,
+            format!("{} {}", if on { "on" } else { "off" }, impl_key),
+        );
         let mut config = self.handler.load_config();
         config.privacy_mode.v = on;
         if on {
@@ -2375,6 +3028,56 @@ impl<T: InvokeUiSession> Remote<T> {
     }
 }
 
+struct SyncJob {
+    local: String,
+    remote: String,
+    local_files: Vec<FileEntry>,
+    options: file_sync::Options,
+}
+
+// A sync plan shown as a preview, then run.
+struct SyncRun {
+    peer_id: String,
+    local: String,
+    remote: String,
+    old_base: file_sync::Base,
+    base: file_sync::Base,
+    items: Vec<file_sync::Item>,
+    // The names of the items by the ids of their jobs not done yet, `None` until the plan is run.
+    pending: Option<HashMap<i32, String>>,
+    succeeded: HashSet<String>,
+}
+
+// A delta transfer of one file, see `file_delta`.
+struct DeltaJob {
+    path: String,
+    to: String,
+    download: bool,
+    // The peer supports delta transfers.
+    acked: bool,
+    // The new file of a download, once the peer has acked.
+    writer: Option<file_delta::Writer>,
+    // Stops the thread hashing or reading the local file.
+    stop: Arc<AtomicBool>,
+    started: Instant,
+    last_progress: Instant,
+}
+
+impl DeltaJob {
+    fn new(path: String, to: String, download: bool) -> Self {
+        Self {
+            path,
+            to,
+            download,
+            acked: false,
+            writer: None,
+            stop: Default::default(),
+            started: Instant::now(),
+            last_progress: Instant::now(),
+        }
+    }
+}
+
 struct RemoveJob {
     files: Vec<FileEntry>,
     path: String,
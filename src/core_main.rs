@@ -399,6 +399,66 @@ pub fn core_main() -> Option<Vec<String>> {
                 }
             }
             return None;
+        } else if args[0] == "--trusted-devices" {
+            if crate::platform::is_installed() && is_root() {
+                let time = |t: i64| {
+                    chrono::DateTime::from_timestamp_millis(t)
+                        .map(|t| t.with_timezone(&chrono::Local).format("%F %T").to_string())
+                        .unwrap_or_default()
+                };
+                let res = match args.get(1).map(|x| x.as_str()) {
+                    None | Some("list") => crate::ipc::get_trusted_devices_info().map(|devices| {
+                        for d in devices {
+                            println!(
+                                "{} {} ({}, {}) trusted {}, expires {}, last seen {} from {}, uses {}",
+                                d.hwid,
+                                d.id,
+                                d.name,
+                                d.platform,
+                                time(d.time),
+                                if d.expires > 0 {
+                                    time(d.expires)
+                                } else {
+                                    "default".to_owned()
+                                },
+                                time(d.seen.last_seen),
+                                d.seen.ips.join(" "),
+                                d.seen.uses
+                            );
+                        }
+                    }),
+                    Some("revoked") => crate::ipc::get_revoked_trusted_devices().map(|v| {
+                        for r in v {
+                            let t = time(r.time);
+                            println!("{} {} {} ({}): {}", t, r.hwid, r.id, r.name, r.reason);
+                        }
+                    }),
+                    Some("revoke") if args.len() > 2 => {
+                        let keys = if args[2] == "all" {
+                            vec![]
+                        } else {
+                            vec![args[2].clone()]
+                        };
+                        let reason = if args.len() > 3 {
+                            args[3..].join(" ")
+                        } else {
+                            "revoked by admin".to_owned()
+                        };
+                        crate::ipc::revoke_trusted_devices(keys, reason).map(|_| println!("Done!"))
+                    }
+                    _ => {
+                        println!("Usage: --trusted-devices list | revoked");
+                        println!("       --trusted-devices revoke <hwid|id|all> [reason]");
+                        Ok(())
+                    }
+                };
+                if let Err(err) = res {
+                    println!("{err}");
+                }
+            } else {
+                println!("Installation and administrative privileges required!");
+            }
+            return None;
         } else if args[0] == "--get-id" {
             println!("{}", crate::ipc::get_id());
             return None;
@@ -453,6 +513,164 @@ pub fn core_main() -> Option<Vec<String>> {
                 println!("Installation and administrative privileges required!");
             }
             return None;
+        } else if args[0] == "--verify-audit-log" {
+            use crate::audit_log;
+            let files = if args.len() > 2 {
+                args[1..].iter().map(std::path::PathBuf::from).collect()
+            } else {
+                let dir = args
+                    .get(1)
+                    .map(std::path::PathBuf::from)
+                    .unwrap_or_else(audit_log::dir);
+                if dir.is_dir() {
+                    audit_log::files(&dir)
+                } else {
+                    vec![dir]
+                }
+            };
+            match audit_log::verify(&files) {
+                Ok(n) => println!("OK, {} records in {} files", n, files.len()),
+                Err(err) => {
+                    println!("{err}");
+                    std::process::exit(1);
+                }
+            }
+            return None;
+        } else if args[0] == "--login-bans" {
+            if crate::platform::is_installed() && is_root() {
+                let res = match args.get(1).map(|x| x.as_str()) {
+                    None | Some("list") => crate::ipc::get_login_bans().map(|bans| {
+                        let now = hbb_common::get_time() / 1000;
+                        for b in bans {
+                            println!(
+                                "{} banned for {} more minutes ({} bans): {}",
+                                b.key,
+                                (b.until - now + 59) / 60,
+                                b.count,
+                                b.reason
+                            );
+                        }
+                    }),
+                    Some("clear") if args.len() > 2 => {
+                        let keys = if args[2] == "all" {
+                            vec![]
+                        } else {
+                            args[2..].to_vec()
+                        };
+                        crate::ipc::remove_login_bans(keys).map(|_| println!("Done!"))
+                    }
+                    _ => {
+                        println!("Usage: --login-bans list | clear <ip or prefix>... | clear all");
+                        Ok(())
+                    }
+                };
+                if let Err(err) = res {
+                    println!("{err}");
+                }
+            } else {
+                println!("Installation and administrative privileges required!");
+            }
+            return None;
+        } else if args[0] == "--2fa" {
+            if crate::platform::is_installed() && is_root() {
+                use crate::auth_2fa;
+                let res = match args.get(1).map(|x| x.as_str()) {
+                    None | Some("list") => {
+                        for d in auth_2fa::list_devices() {
+                            println!(
+                                "{} {} {} digits, enrolled {}",
+                                d.label,
+                                d.algorithm,
+                                d.digits,
+                                chrono::DateTime::from_timestamp_millis(d.created_at)
+                                    .map(|t| t.with_timezone(&chrono::Local).to_string())
+                                    .unwrap_or_default()
+                            );
+                        }
+                        println!("{} recovery codes left", auth_2fa::recovery_codes_left());
+                        Ok(())
+                    }
+                    Some("add") if args.len() > 2 => {
+                        let digits = args.get(3).and_then(|x| x.parse().ok()).unwrap_or(6);
+                        let algorithm = args.get(4).cloned().unwrap_or_default();
+                        auth_2fa::generate2fa_device(args[2].clone(), digits, algorithm).and_then(
+                            |url| {
+                                println!("Add this to the authenticator app: {}", url);
+                                println!("Enter the code shown by the app:");
+                                let mut code = String::new();
+                                std::io::stdin().read_line(&mut code)?;
+                                if !auth_2fa::verify2fa(code.trim().to_owned()) {
+                                    hbb_common::bail!("Wrong 2FA code");
+                                }
+                                println!("Done!");
+                                Ok(())
+                            },
+                        )
+                    }
+                    Some("remove") if args.len() > 2 => {
+                        auth_2fa::remove_device(&args[2]).map(|_| println!("Done!"))
+                    }
+                    Some("recovery-codes") => auth_2fa::generate_recovery_codes().map(|codes| {
+                        println!("Each code can be used once instead of a 2FA code.");
+                        for code in codes {
+                            println!("{}", code);
+                        }
+                    }),
+                    _ => {
+                        println!("Usage: --2fa list | add <name> [digits] [SHA1|SHA256|SHA512]");
+                        println!("       --2fa remove <name> | recovery-codes");
+                        Ok(())
+                    }
+                };
+                if let Err(err) = res {
+                    println!("{err}");
+                }
+            } else {
+                println!("Installation and administrative privileges required!");
+            }
+            return None;
+        } else if args[0] == "--share-token" {
+            if crate::platform::is_installed() && is_root() {
+                use crate::share_token::{self, OPTION_SHARE_TOKENS};
+                let current = crate::ipc::get_options()
+                    .remove(OPTION_SHARE_TOKENS)
+                    .unwrap_or_default();
+                match args.get(1).map(|x| x.as_str()) {
+                    Some("new") => {
+                        let minutes = args.get(2).and_then(|x| x.parse().ok()).unwrap_or(60);
+                        let (token, value) = share_token::add(&current, minutes);
+                        crate::ipc::set_option(OPTION_SHARE_TOKENS, &value);
+                        println!(
+                            "{} (expires in {} minutes)",
+                            token.token,
+                            token.minutes_left()
+                        );
+                    }
+                    Some("revoke") if args.len() == 3 => {
+                        let token = if args[2] == "all" {
+                            ""
+                        } else {
+                            args[2].as_str()
+                        };
+                        crate::ipc::set_option(
+                            OPTION_SHARE_TOKENS,
+                            &share_token::remove(&current, token),
+                        );
+                        println!("Done!");
+                    }
+                    Some("list") => {
+                        for t in share_token::list(&current) {
+                            println!("{} (expires in {} minutes)", t.token, t.minutes_left());
+                        }
+                    }
+                    _ => {
+                        println!("Usage: --share-token new [minutes] | list | revoke <token|all>");
+                    }
+                }
+            } else {
+                println!("Installation and administrative privileges required!");
+            }
+            return None;
         } else if args[0] == "--assign" {
             if config::Config::no_register_device() {
                 println!("Cannot assign an unregistrable device!");
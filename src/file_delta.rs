@@ -0,0 +1,562 @@
+// Delta transfer of single files, rsync style, for files which already exist on the receiving
+// side.
+//
+// The receiving side splits its copy of the file into blocks and sends their `Signatures`, a
+// rolling weak checksum and a strong hash for every block. The sending side rolls the weak
+// checksum over its file byte by byte. Where a window matches a block of the receiving side, by
+// the weak checksum and then by the strong hash, it sends a `Copy` of the block, otherwise the
+// bytes as a `Literal`. The receiving side writes the new file next to its copy from the copied
+// blocks and the literals, checks its size and SHA-256 against the `End` and replaces its copy
+// with it.
+//
+// The messages are `FileResponse`s with only `FILE_RESPONSE_FIELD_DELTA`, a `Delta` as json, and
+// for the signatures and the literals `FILE_RESPONSE_FIELD_DELTA_DATA` with the bytes. The
+// controlling side starts a transfer with `Begin` and the controlled side answers with `Ack`.
+// For a download the controlling side then sends the signatures and the controlled side the
+// ops, for an upload the connection manager of the controlled side, which writes the files,
+// sends the signatures. The receiving side reports the result with `Done`, which the
+// controlled side adds to the file audit. Older versions skip the fields and answer nothing, so
+// the controlling side transfers the whole file as usual after `ACK_TIMEOUT`.
+//
+// The file manager of the controlling side transfers a file this way if the `file-transfer-delta`
+// local option is set, the file is at least 1MB and one with its name is in the destination.
+
+use hbb_common::{
+    message_proto::FileResponse,
+    protobuf::{Message as _, UnknownValueRef},
+};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, UNIX_EPOCH},
+};
+
+// The field numbers the delta messages are sent in. They are not in the message definitions
+// yet, so older versions skip them as unknown fields.
+pub const FILE_RESPONSE_FIELD_DELTA: u32 = 1005;
+pub const FILE_RESPONSE_FIELD_DELTA_DATA: u32 = 1006;
+pub const ACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+const MIN_BLOCK_SIZE: u64 = 8 * 1024;
+const MAX_BLOCK_SIZE: u64 = 8 * 1024 * 1024;
+const MAX_BLOCKS: u64 = 64 * 1024;
+const STRONG_SIZE: usize = 16;
+const SIGNATURE_SIZE: usize = 4 + STRONG_SIZE;
+const READ_SIZE: usize = 256 * 1024;
+const LITERAL_SIZE: usize = 128 * 1024;
+// The receiving side copies in the loop it handles the messages in, so a copy must not take long.
+const MAX_COPY_SIZE: u64 = 16 * 1024 * 1024;
+const TEMP_SUFFIX: &str = ".rddelta";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Delta {
+    /// From the controlling side, to transfer `path` of the controlled side as job `id`.
+    Begin {
+        id: i32,
+        path: String,
+        download: bool,
+    },
+    /// From the controlled side, which supports delta transfers.
+    Ack { id: i32 },
+    /// From the receiving side, with the signatures in the data.
+    Signatures { id: i32, block_size: u64 },
+    /// From the sending side, to copy `count` blocks of the receiving side from `block`.
+    Copy { id: i32, block: u64, count: u64 },
+    /// From the sending side, with the bytes in the data.
+    Literal { id: i32 },
+    /// From the sending side, after the last op.
+    End {
+        id: i32,
+        size: u64,
+        sha256: String,
+        /// Seconds since the epoch.
+        modified: u64,
+    },
+    /// From the receiving side, the result of the transfer.
+    Done {
+        id: i32,
+        path: String,
+        size: u64,
+        /// The bytes copied from the old file and sent as literals.
+        copied: u64,
+        literal: u64,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        error: String,
+    },
+    /// From the sending side, if it can not go on, or from the controlling side if it gives up.
+    Error { id: i32, error: String },
+}
+
+impl Delta {
+    pub fn id(&self) -> i32 {
+        match self {
+            Delta::Begin { id, .. }
+            | Delta::Ack { id }
+            | Delta::Signatures { id, .. }
+            | Delta::Copy { id, .. }
+            | Delta::Literal { id }
+            | Delta::End { id, .. }
+            | Delta::Done { id, .. }
+            | Delta::Error { id, .. } => *id,
+        }
+    }
+
+    pub fn to_response(&self, data: &[u8]) -> FileResponse {
+        let mut fr = FileResponse::new();
+        let fields = fr.mut_unknown_fields();
+        fields.add_length_delimited(
+            FILE_RESPONSE_FIELD_DELTA,
+            serde_json::to_vec(self).unwrap_or_default(),
+        );
+        if !data.is_empty() {
+            fields.add_length_delimited(FILE_RESPONSE_FIELD_DELTA_DATA, data.to_vec());
+        }
+        fr
+    }
+
+    /// The delta message sent in `fr` and its data, if any.
+    pub fn from_response(fr: &FileResponse) -> Option<(Self, Vec<u8>)> {
+        let fields = fr.unknown_fields();
+        let delta = match fields.get(FILE_RESPONSE_FIELD_DELTA)? {
+            UnknownValueRef::LengthDelimited(v) => serde_json::from_slice(v).ok()?,
+            _ => return None,
+        };
+        let data = match fields.get(FILE_RESPONSE_FIELD_DELTA_DATA) {
+            Some(UnknownValueRef::LengthDelimited(v)) => v.to_vec(),
+            _ => vec![],
+        };
+        Some((delta, data))
+    }
+}
+
+fn block_size(file_size: u64) -> u64 {
+    let mut size = MIN_BLOCK_SIZE;
+    while file_size / size > MAX_BLOCKS && size < MAX_BLOCK_SIZE {
+        size *= 2;
+    }
+    size
+}
+
+fn strong(block: &[u8]) -> [u8; STRONG_SIZE] {
+    let mut hash = [0u8; STRONG_SIZE];
+    hash.copy_from_slice(&Sha256::digest(block)[..STRONG_SIZE]);
+    hash
+}
+
+// The weak checksum of rsync, which can be moved forward by one byte in constant time.
+struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    fn new(window: &[u8]) -> Self {
+        let len = window.len() as u32;
+        let mut a = 0u32;
+        let mut b = 0u32;
+        for (i, &x) in window.iter().enumerate() {
+            a = a.wrapping_add(x as u32);
+            b = b.wrapping_add((len - i as u32).wrapping_mul(x as u32));
+        }
+        Self { a, b, len }
+    }
+
+    fn roll(&mut self, out: u8, new: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(new as u32);
+        self.b = self
+            .b
+            .wrapping_sub(self.len.wrapping_mul(out as u32))
+            .wrapping_add(self.a);
+    }
+
+    fn digest(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+/// The signatures of the full blocks of `path`, read in blocks of `block_size`.
+pub fn signatures(path: &Path, block_size: u64, stop: &AtomicBool) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut block = vec![0u8; block_size as usize];
+    let mut signatures = Vec::new();
+    loop {
+        if stop.load(Ordering::SeqCst) {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "Cancelled"));
+        }
+        match file.read_exact(&mut block) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        signatures.extend_from_slice(&Rolling::new(&block).digest().to_le_bytes());
+        signatures.extend_from_slice(&strong(&block));
+    }
+    Ok(signatures)
+}
+
+/// Send the ops to build `path` from the blocks with `signatures` through `f`, then the `End`,
+/// or an `Error`. Stops early if `stop` is set or `f` returns false.
+pub fn send(
+    id: i32,
+    path: &Path,
+    block_size: u64,
+    signatures: &[u8],
+    stop: &AtomicBool,
+    mut f: impl FnMut(Delta, Vec<u8>) -> bool,
+) {
+    if let Err(e) = send_(id, path, block_size, signatures, stop, &mut f) {
+        if e.kind() != io::ErrorKind::Interrupted {
+            f(
+                Delta::Error {
+                    id,
+                    error: e.to_string(),
+                },
+                vec![],
+            );
+        }
+    }
+}
+
+fn send_(
+    id: i32,
+    path: &Path,
+    block_size: u64,
+    signatures: &[u8],
+    stop: &AtomicBool,
+    f: &mut impl FnMut(Delta, Vec<u8>) -> bool,
+) -> io::Result<()> {
+    let interrupted = || io::Error::new(io::ErrorKind::Interrupted, "Cancelled");
+    if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&block_size) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Invalid block size",
+        ));
+    }
+    let size = block_size as usize;
+    let mut blocks: HashMap<u32, Vec<(u64, &[u8])>> = HashMap::new();
+    for (i, s) in signatures.chunks_exact(SIGNATURE_SIZE).enumerate() {
+        let weak = u32::from_le_bytes([s[0], s[1], s[2], s[3]]);
+        blocks.entry(weak).or_default().push((i as u64, &s[4..]));
+    }
+    let mut file = File::open(path)?;
+    let modified = file
+        .metadata()?
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let mut hasher = Sha256::new();
+    let mut total = 0u64;
+    let mut chunk = vec![0u8; READ_SIZE];
+    // The bytes from `literal` to `pos` are not matched yet, the window starts at `pos`.
+    let mut buf: Vec<u8> = Vec::new();
+    let mut literal = 0usize;
+    let mut pos = 0usize;
+    let mut eof = false;
+    let mut rolling: Option<Rolling> = None;
+    // Consecutive blocks are sent as one copy.
+    let mut copy: Option<(u64, u64)> = None;
+    loop {
+        if stop.load(Ordering::SeqCst) {
+            return Err(interrupted());
+        }
+        while !eof && buf.len() < pos + size + 1 {
+            buf.drain(..literal);
+            pos -= literal;
+            literal = 0;
+            let n = file.read(&mut chunk)?;
+            if n == 0 {
+                eof = true;
+            } else {
+                hasher.update(&chunk[..n]);
+                total += n as u64;
+                buf.extend_from_slice(&chunk[..n]);
+            }
+        }
+        if buf.len() < pos + size {
+            break;
+        }
+        let window = &buf[pos..pos + size];
+        let r = rolling.get_or_insert_with(|| Rolling::new(window));
+        let matched = blocks.get(&r.digest()).and_then(|candidates| {
+            let hash = strong(window);
+            let next = copy.map(|(block, count)| block + count);
+            let mut matched = candidates.iter().filter(|(_, s)| *s == &hash[..]);
+            let first = matched.next().map(|(i, _)| *i)?;
+            Some(
+                next.filter(|n| *n != first && matched.any(|(i, _)| i == n))
+                    .unwrap_or(first),
+            )
+        });
+        if let Some(block) = matched {
+            if literal < pos {
+                send_copy(id, &mut copy, f)?;
+                if !f(Delta::Literal { id }, buf[literal..pos].to_vec()) {
+                    return Err(interrupted());
+                }
+            }
+            match copy {
+                Some((start, ref mut count))
+                    if start + *count == block && (*count + 1) * block_size <= MAX_COPY_SIZE =>
+                {
+                    *count += 1
+                }
+                _ => {
+                    send_copy(id, &mut copy, f)?;
+                    copy = Some((block, 1));
+                }
+            }
+            pos += size;
+            literal = pos;
+            rolling = None;
+            continue;
+        }
+        if buf.len() == pos + size {
+            break;
+        }
+        r.roll(buf[pos], buf[pos + size]);
+        pos += 1;
+        if pos - literal >= LITERAL_SIZE {
+            send_copy(id, &mut copy, f)?;
+            if !f(Delta::Literal { id }, buf[literal..pos].to_vec()) {
+                return Err(interrupted());
+            }
+            literal = pos;
+        }
+    }
+    send_copy(id, &mut copy, f)?;
+    for bytes in buf[literal..].chunks(LITERAL_SIZE) {
+        if !f(Delta::Literal { id }, bytes.to_vec()) {
+            return Err(interrupted());
+        }
+    }
+    f(
+        Delta::End {
+            id,
+            size: total,
+            sha256: hex::encode(hasher.finalize()),
+            modified,
+        },
+        vec![],
+    );
+    Ok(())
+}
+
+fn send_copy(
+    id: i32,
+    copy: &mut Option<(u64, u64)>,
+    f: &mut impl FnMut(Delta, Vec<u8>) -> bool,
+) -> io::Result<()> {
+    if let Some((block, count)) = copy.take() {
+        if !f(Delta::Copy { id, block, count }, vec![]) {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "Cancelled"));
+        }
+    }
+    Ok(())
+}
+
+/// Builds the new file from the ops of the sending side, next to the old one.
+pub struct Writer {
+    path: PathBuf,
+    temp: PathBuf,
+    // The old and the new file, closed before the new one replaces the old one.
+    files: Option<(File, File)>,
+    block_size: u64,
+    hasher: Sha256,
+    pub size: u64,
+    pub copied: u64,
+    pub literal: u64,
+    done: bool,
+}
+
+impl Writer {
+    pub fn new(path: &Path) -> io::Result<Self> {
+        let old = File::open(path)?;
+        let block_size = block_size(old.metadata()?.len());
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(TEMP_SUFFIX);
+        let temp = path.with_file_name(name);
+        let new = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temp)?;
+        Ok(Self {
+            path: path.to_owned(),
+            temp,
+            files: Some((old, new)),
+            block_size,
+            hasher: Sha256::new(),
+            size: 0,
+            copied: 0,
+            literal: 0,
+            done: false,
+        })
+    }
+
+    pub fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    pub fn copy(&mut self, block: u64, count: u64) -> io::Result<()> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid block");
+        let len = count.checked_mul(self.block_size).ok_or_else(invalid)?;
+        let offset = block.checked_mul(self.block_size).ok_or_else(invalid)?;
+        self.old()?.seek(SeekFrom::Start(offset))?;
+        let mut buf = vec![0u8; READ_SIZE];
+        let mut left = len;
+        while left > 0 {
+            let n = left.min(READ_SIZE as u64) as usize;
+            self.old()?.read_exact(&mut buf[..n])?;
+            self.write(&buf[..n])?;
+            left -= n as u64;
+        }
+        self.copied += len;
+        Ok(())
+    }
+
+    pub fn literal(&mut self, data: &[u8]) -> io::Result<()> {
+        self.write(data)?;
+        self.literal += data.len() as u64;
+        Ok(())
+    }
+
+    fn old(&mut self) -> io::Result<&mut File> {
+        self.files
+            .as_mut()
+            .map(|(old, _)| old)
+            .ok_or_else(|| io::Error::other("Finished"))
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        let (_, new) = self
+            .files
+            .as_mut()
+            .ok_or_else(|| io::Error::other("Finished"))?;
+        new.write_all(data)?;
+        self.hasher.update(data);
+        self.size += data.len() as u64;
+        Ok(())
+    }
+
+    /// Check the new file against the `End` of the sending side and replace the old one.
+    pub fn finish(&mut self, size: u64, sha256: &str, modified: u64) -> io::Result<()> {
+        let hash = hex::encode(std::mem::take(&mut self.hasher).finalize());
+        if self.size != size || hash != sha256 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "File hash mismatch",
+            ));
+        }
+        if let Some((_, new)) = self.files.take() {
+            new.sync_all()?;
+            if modified > 0 {
+                new.set_modified(UNIX_EPOCH + Duration::from_secs(modified))
+                    .ok();
+            }
+        }
+        std::fs::rename(&self.temp, &self.path)?;
+        self.done = true;
+        Ok(())
+    }
+
+    /// The result to report, with the error of the transfer if it failed.
+    pub fn done(&self, id: i32, path: &str, error: String) -> Delta {
+        Delta::Done {
+            id,
+            path: path.to_owned(),
+            size: self.size,
+            copied: self.copied,
+            literal: self.literal,
+            error,
+        }
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        if !self.done {
+            std::fs::remove_file(&self.temp).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rolling() {
+        let data: Vec<u8> = (0..100u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut r = Rolling::new(&data[..32]);
+        for i in 1..=(data.len() - 32) {
+            r.roll(data[i - 1], data[i + 31]);
+            assert_eq!(r.digest(), Rolling::new(&data[i..i + 32]).digest());
+        }
+    }
+
+    #[test]
+    fn test_delta() {
+        let dir = std::env::temp_dir().join(format!("rustdesk_delta_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (old_path, new_path) = (dir.join("old"), dir.join("new"));
+        let mut seed = 1u32;
+        let old: Vec<u8> = (0..300_000)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                seed as u8
+            })
+            .collect();
+        let mut new = old.clone();
+        new[100_000..100_010].copy_from_slice(b"0123456789");
+        new.splice(200_000..200_000, b"inserted".iter().copied());
+        new.truncate(290_000);
+        std::fs::write(&old_path, &old).unwrap();
+        std::fs::write(&new_path, &new).unwrap();
+
+        let mut writer = Writer::new(&old_path).unwrap();
+        let stop = AtomicBool::new(false);
+        let signatures = signatures(&old_path, writer.block_size(), &stop).unwrap();
+        let mut ops = vec![];
+        send(
+            1,
+            &new_path,
+            writer.block_size(),
+            &signatures,
+            &stop,
+            |delta, data| {
+                let fr = delta.to_response(&data);
+                let fr = FileResponse::parse_from_bytes(&fr.write_to_bytes().unwrap()).unwrap();
+                ops.push(Delta::from_response(&fr).unwrap());
+                true
+            },
+        );
+        for (delta, data) in ops {
+            match delta {
+                Delta::Copy { block, count, .. } => writer.copy(block, count).unwrap(),
+                Delta::Literal { .. } => writer.literal(&data).unwrap(),
+                Delta::End {
+                    size,
+                    sha256,
+                    modified,
+                    ..
+                } => writer.finish(size, &sha256, modified).unwrap(),
+                delta => panic!("unexpected {:?}", delta),
+            }
+        }
+        assert!(writer.copied > writer.literal);
+        assert_eq!(std::fs::read(&old_path).unwrap(), new);
+        drop(writer);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,354 @@
+// Manifests of the files of finished transfer jobs.
+//
+// A manifest lists the path, size, SHA-256 hash and modification time of every file of a job,
+// read back from disk after the job is done. With the `file-transfer-manifest` option the
+// controlled side makes one for every finished job and attaches it to the file audit, the
+// controlling side exports one on request from the transfer list.
+//
+// With the option, the files of a job are also checked after the transfer. The side which read
+// the files sends their manifest in a `FileResponse` with only `FILE_RESPONSE_FIELD_SOURCE` after
+// the job is done, for an upload once the controlled side has asked for it with
+// `FILE_RESPONSE_FIELD_REQUEST`. The receiving side hashes the files it wrote, compares them and
+// sends the `Check` back, the controlling side in a `FileAction` with `FILE_ACTION_FIELD_CHECK`,
+// the controlled side in a `FileResponse` with `FILE_RESPONSE_FIELD_CHECK`. The controlled side
+// adds both hashes of every file to the file audit, the controlling side transfers the files
+// which do not match again, as new jobs, up to `MAX_CHECK_RETRIES` times.
+
+use hbb_common::{
+    config::Config,
+    get_time, log,
+    message_proto::{FileAction, FileResponse},
+    protobuf::{Message as _, UnknownFields, UnknownValueRef},
+};
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+pub const OPTION_FILE_TRANSFER_MANIFEST: &str = "file-transfer-manifest";
+// The field numbers the check messages are sent in as json. They are not in the message
+// definitions yet, so older versions skip them as unknown fields.
+pub const FILE_RESPONSE_FIELD_SOURCE: u32 = 1001;
+pub const FILE_RESPONSE_FIELD_REQUEST: u32 = 1002;
+pub const FILE_RESPONSE_FIELD_CHECK: u32 = 1003;
+pub const FILE_ACTION_FIELD_CHECK: u32 = 1002;
+pub const MAX_CHECK_RETRIES: u32 = 2;
+
+const READ_SIZE: usize = 256 * 1024;
+
+pub fn enabled() -> bool {
+    Config::get_bool_option(OPTION_FILE_TRANSFER_MANIFEST)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Relative to the job path, empty if the job is a single file.
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+    /// Seconds since the epoch.
+    pub modified: u64,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub id: i32,
+    pub path: String,
+    /// Milliseconds since the epoch.
+    pub created: i64,
+    pub files: Vec<Entry>,
+}
+
+impl Manifest {
+    /// The response to send `self` to the receiving side with, as the manifest of the source.
+    pub fn to_response(&self) -> FileResponse {
+        let mut fr = FileResponse::new();
+        add_json(fr.mut_unknown_fields(), FILE_RESPONSE_FIELD_SOURCE, self);
+        fr
+    }
+
+    /// The manifest of the source sent in `fr`, if any.
+    pub fn from_response(fr: &FileResponse) -> Option<Self> {
+        get_json(fr.unknown_fields(), FILE_RESPONSE_FIELD_SOURCE)
+    }
+}
+
+/// The response asking the controlling side for the manifest of upload `id` once it is done.
+pub fn request_response(id: i32) -> FileResponse {
+    let mut fr = FileResponse::new();
+    add_json(fr.mut_unknown_fields(), FILE_RESPONSE_FIELD_REQUEST, &id);
+    fr
+}
+
+/// The upload whose manifest is asked for in `fr`, if any.
+pub fn requested(fr: &FileResponse) -> Option<i32> {
+    get_json(fr.unknown_fields(), FILE_RESPONSE_FIELD_REQUEST)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CheckedFile {
+    /// Relative to the job path with `/` separators, empty if the job is a single file.
+    pub name: String,
+    /// SHA-256 of the file on either side, empty if it could not be hashed.
+    pub source: String,
+    pub destination: String,
+}
+
+impl CheckedFile {
+    pub fn matched(&self) -> bool {
+        !self.source.is_empty() && self.source == self.destination
+    }
+}
+
+/// The hashes of the files of a job on both sides.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Check {
+    pub id: i32,
+    /// The job path on the source and the destination.
+    pub source: String,
+    pub destination: String,
+    pub files: Vec<CheckedFile>,
+}
+
+impl Check {
+    pub fn mismatched(&self) -> impl Iterator<Item = &CheckedFile> {
+        self.files.iter().filter(|f| !f.matched())
+    }
+
+    pub fn to_response(&self) -> FileResponse {
+        let mut fr = FileResponse::new();
+        add_json(fr.mut_unknown_fields(), FILE_RESPONSE_FIELD_CHECK, self);
+        fr
+    }
+
+    pub fn from_response(fr: &FileResponse) -> Option<Self> {
+        get_json(fr.unknown_fields(), FILE_RESPONSE_FIELD_CHECK)
+    }
+
+    pub fn to_action(&self) -> FileAction {
+        let mut fa = FileAction::new();
+        add_json(fa.mut_unknown_fields(), FILE_ACTION_FIELD_CHECK, self);
+        fa
+    }
+
+    pub fn from_action(fa: &FileAction) -> Option<Self> {
+        get_json(fa.unknown_fields(), FILE_ACTION_FIELD_CHECK)
+    }
+}
+
+fn add_json(fields: &mut UnknownFields, field: u32, v: &impl serde::Serialize) {
+    fields.add_length_delimited(field, serde_json::to_vec(v).unwrap_or_default());
+}
+
+fn get_json<T: DeserializeOwned>(fields: &UnknownFields, field: u32) -> Option<T> {
+    match fields.get(field)? {
+        UnknownValueRef::LengthDelimited(v) => serde_json::from_slice(v).ok(),
+        _ => None,
+    }
+}
+
+// The peers may use different path separators.
+fn key(name: &str) -> String {
+    name.replace('\\', "/")
+}
+
+/// Compare the manifests of the source and the destination of a job, file by file.
+pub fn compare(source: &Manifest, destination: &Manifest) -> Check {
+    let hashed = |f: &Entry| {
+        if f.error.is_empty() {
+            f.sha256.clone()
+        } else {
+            "".to_owned()
+        }
+    };
+    let mut destination_files: HashMap<String, String> = destination
+        .files
+        .iter()
+        .map(|f| (key(&f.name), hashed(f)))
+        .collect();
+    Check {
+        id: destination.id,
+        source: source.path.clone(),
+        destination: destination.path.clone(),
+        files: source
+            .files
+            .iter()
+            .map(|f| {
+                let name = key(&f.name);
+                CheckedFile {
+                    source: hashed(f),
+                    destination: destination_files.remove(&name).unwrap_or_default(),
+                    name,
+                }
+            })
+            .collect(),
+    }
+}
+
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; READ_SIZE];
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn entry(path: &Path, name: &str) -> io::Result<Entry> {
+    let meta = std::fs::metadata(path)?;
+    let modified = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    Ok(Entry {
+        name: name.to_owned(),
+        size: meta.len(),
+        sha256: sha256_file(path)?,
+        modified,
+        error: "".to_owned(),
+    })
+}
+
+fn join(root: &Path, name: &str) -> PathBuf {
+    if name.is_empty() {
+        root.to_path_buf()
+    } else {
+        root.join(name)
+    }
+}
+
+/// Hash the files `names` of job `id` under `root`, blocking.
+pub fn build(id: i32, root: &Path, names: &[String]) -> Manifest {
+    let files = names
+        .iter()
+        .map(|name| {
+            entry(&join(root, name), name).unwrap_or_else(|e| Entry {
+                name: name.clone(),
+                error: e.to_string(),
+                ..Default::default()
+            })
+        })
+        .collect();
+    Manifest {
+        id,
+        path: root.to_string_lossy().to_string(),
+        created: get_time(),
+        files,
+    }
+}
+
+/// Build the manifest on a thread and pass it to `f`.
+pub fn spawn_build(
+    id: i32,
+    root: PathBuf,
+    names: Vec<String>,
+    f: impl FnOnce(Manifest) + Send + 'static,
+) {
+    std::thread::spawn(move || {
+        let manifest = build(id, &root, &names);
+        let failed = manifest
+            .files
+            .iter()
+            .filter(|f| !f.error.is_empty())
+            .count();
+        if failed > 0 {
+            log::warn!("Manifest of job {}: {} files not hashed", id, failed);
+        }
+        f(manifest);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let dir = std::env::temp_dir().join(format!("rustdesk_manifest_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub").join("abc.txt"), b"abc").unwrap();
+        let names = vec![
+            format!("sub{}abc.txt", std::path::MAIN_SEPARATOR),
+            "missing".to_owned(),
+        ];
+        let manifest = build(7, &dir, &names);
+        std::fs::remove_dir_all(&dir).ok();
+        assert_eq!(manifest.files.len(), 2);
+        assert_eq!(manifest.files[0].size, 3);
+        assert_eq!(
+            manifest.files[0].sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert!(manifest.files[0].error.is_empty());
+        assert!(!manifest.files[1].error.is_empty());
+        let json = serde_json::to_string(&manifest).unwrap();
+        assert_eq!(serde_json::from_str::<Manifest>(&json).unwrap(), manifest);
+    }
+
+    #[test]
+    fn test_compare() {
+        let entry = |name: &str, sha256: &str, error: &str| Entry {
+            name: name.to_owned(),
+            sha256: sha256.to_owned(),
+            error: error.to_owned(),
+            ..Default::default()
+        };
+        let source = Manifest {
+            id: 5,
+            path: "C:\\src".to_owned(),
+            files: vec![
+                entry("a\\b.txt", "11", ""),
+                entry("c.txt", "22", ""),
+                entry("d.txt", "", "denied"),
+                entry("e.txt", "44", ""),
+            ],
+            ..Default::default()
+        };
+        let destination = Manifest {
+            id: 5,
+            path: "/dst".to_owned(),
+            files: vec![
+                entry("a/b.txt", "11", ""),
+                entry("c.txt", "23", ""),
+                entry("d.txt", "", "denied"),
+            ],
+            ..Default::default()
+        };
+        let check = compare(&source, &destination);
+        assert_eq!(check.source, "C:\\src");
+        assert_eq!(check.destination, "/dst");
+        assert_eq!(check.files[0].name, "a/b.txt");
+        assert!(check.files[0].matched());
+        let mismatched = check
+            .mismatched()
+            .map(|f| f.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(mismatched, vec!["c.txt", "d.txt", "e.txt"]);
+
+        let bytes = check.to_action().write_to_bytes().unwrap();
+        let fa = FileAction::parse_from_bytes(&bytes).unwrap();
+        assert!(fa.union.is_none());
+        assert_eq!(Check::from_action(&fa), Some(check.clone()));
+        let bytes = source.to_response().write_to_bytes().unwrap();
+        let fr = FileResponse::parse_from_bytes(&bytes).unwrap();
+        assert_eq!(Manifest::from_response(&fr), Some(source));
+        assert_eq!(Check::from_response(&fr), None);
+        assert_eq!(requested(&request_response(5)), Some(5));
+    }
+}
@@ -0,0 +1,381 @@
+// Bandwidth caps and scheduling of the file transfer jobs which read and send files.
+//
+// Set by the `file-transfer-limits` option as a json object, e.g.
+//   {"session_kbps": 2048, "job_kbps": 512, "pause_while_controlling": true}
+// The option is read again every second, so changes apply to running jobs. 0 or a missing value
+// means no limit. `session_kbps` caps all the jobs of a connection together, `job_kbps` caps each
+// job unless the job has its own limit. Concurrent jobs get blocks in proportion to their
+// priority, high jobs four blocks and low jobs one block for every two blocks of normal jobs.
+// With `pause_while_controlling`, no blocks are sent for a few seconds after keyboard or mouse
+// input of a remote control session, so that the video does not compete with the files.
+//
+// The option applies to the jobs of the side it is set on: on the controlling side to uploads,
+// on the controlled side to downloads. Jobs can be given their own limit and priority from the
+// transfer list of the controlling side. For a download the limit is sent to the controlled side,
+// which reads the files, in a `FileAction` with only the `FILE_ACTION_FIELD_JOB_LIMIT` field.
+
+use hbb_common::{
+    config::Config,
+    fs::TransferJob,
+    get_time, log,
+    message_proto::FileAction,
+    protobuf::{Message as _, UnknownValueRef},
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicI64, Ordering},
+    time::{Duration, Instant},
+};
+
+pub const OPTION_FILE_TRANSFER_LIMITS: &str = "file-transfer-limits";
+// The `FileAction` field number a `JobLimit` is sent in as json. It is not in the message
+// definition yet, so older versions skip it as an unknown field.
+pub const FILE_ACTION_FIELD_JOB_LIMIT: u32 = 1001;
+
+// Milliseconds after the last input during which the jobs are paused.
+const CONTROL_ACTIVE_MS: i64 = 3_000;
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+// A bucket can always hold one block, even with a lower rate.
+const MIN_BURST: f64 = 128. * 1024.;
+
+// The last keyboard or mouse input sent by the remote control sessions of this process.
+static LAST_INPUT: AtomicI64 = AtomicI64::new(0);
+
+pub fn on_input() {
+    LAST_INPUT.store(get_time(), Ordering::SeqCst);
+}
+
+/// Time of the last input sent by this process, in milliseconds.
+pub fn last_input() -> i64 {
+    LAST_INPUT.load(Ordering::SeqCst)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct Limits {
+    #[serde(default)]
+    session_kbps: u32,
+    #[serde(default)]
+    job_kbps: u32,
+    #[serde(default)]
+    pause_while_controlling: bool,
+}
+
+fn load_limits() -> Limits {
+    let s = Config::get_option(OPTION_FILE_TRANSFER_LIMITS);
+    if s.trim().is_empty() {
+        return Limits::default();
+    }
+    serde_json::from_str(&s).unwrap_or_else(|e| {
+        log::error!("Invalid {}: {}", OPTION_FILE_TRANSFER_LIMITS, e);
+        Limits::default()
+    })
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl Priority {
+    pub fn parse(s: &str) -> Self {
+        match s {
+            "high" => Self::High,
+            "low" => Self::Low,
+            _ => Self::Normal,
+        }
+    }
+
+    fn weight(&self) -> u32 {
+        match self {
+            Self::High => 4,
+            Self::Normal => 2,
+            Self::Low => 1,
+        }
+    }
+}
+
+/// The limit and priority of a job set from the transfer list.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobLimit {
+    pub id: i32,
+    pub kbps: u32,
+    pub priority: Priority,
+}
+
+impl JobLimit {
+    /// The action to send `self` to the peer with.
+    pub fn to_action(&self) -> FileAction {
+        let mut fa = FileAction::new();
+        fa.mut_unknown_fields().add_length_delimited(
+            FILE_ACTION_FIELD_JOB_LIMIT,
+            serde_json::to_vec(self).unwrap_or_default(),
+        );
+        fa
+    }
+
+    /// The limit sent in `fa`, if any.
+    pub fn from_action(fa: &FileAction) -> Option<Self> {
+        match fa.unknown_fields().get(FILE_ACTION_FIELD_JOB_LIMIT)? {
+            UnknownValueRef::LengthDelimited(v) => serde_json::from_slice(v).ok(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    // Bytes per second, 0 for no limit.
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn new(kbps: u32, now: Instant) -> Self {
+        let rate = kbps as f64 * 1024.;
+        Self {
+            rate,
+            tokens: rate.max(MIN_BURST),
+            last: now,
+        }
+    }
+
+    fn set_kbps(&mut self, kbps: u32) {
+        self.rate = kbps as f64 * 1024.;
+        self.tokens = self.tokens.min(self.rate.max(MIN_BURST));
+    }
+
+    /// Whether a block can be sent, the bytes of a block are only known after it is read, the
+    /// bucket goes into debt for them.
+    fn ready(&mut self, now: Instant) -> bool {
+        if self.rate <= 0. {
+            return true;
+        }
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate.max(MIN_BURST));
+        self.tokens > 0.
+    }
+
+    fn spend(&mut self, bytes: u64) {
+        if self.rate > 0. {
+            self.tokens -= bytes as f64;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Job {
+    // Its own limit in KB/s, `None` to use `job_kbps`.
+    kbps: Option<u32>,
+    priority: Priority,
+    bucket: Bucket,
+    credit: u32,
+    transferred: u64,
+}
+
+#[derive(Debug)]
+pub struct Scheduler {
+    limits: Limits,
+    loaded: Option<Instant>,
+    session: Bucket,
+    jobs: HashMap<i32, Job>,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self {
+            limits: Limits::default(),
+            loaded: None,
+            session: Bucket::new(0, Instant::now()),
+            jobs: HashMap::new(),
+        }
+    }
+}
+
+impl Scheduler {
+    /// Give job `id` its own limit, 0 for none, and priority.
+    pub fn set_job(&mut self, id: i32, kbps: u32, priority: Priority) {
+        let now = Instant::now();
+        let job_kbps = self.limits.job_kbps;
+        let job = self.job(id, now);
+        job.kbps = (kbps > 0).then_some(kbps);
+        job.priority = priority;
+        job.bucket.set_kbps(job.kbps.unwrap_or(job_kbps));
+    }
+
+    fn job(&mut self, id: i32, now: Instant) -> &mut Job {
+        let job_kbps = self.limits.job_kbps;
+        self.jobs.entry(id).or_insert_with(|| Job {
+            kbps: None,
+            priority: Priority::Normal,
+            bucket: Bucket::new(job_kbps, now),
+            credit: 0,
+            transferred: 0,
+        })
+    }
+
+    fn set_limits(&mut self, limits: Limits) {
+        if limits == self.limits {
+            return;
+        }
+        self.session.set_kbps(limits.session_kbps);
+        for job in self.jobs.values_mut() {
+            job.bucket.set_kbps(job.kbps.unwrap_or(limits.job_kbps));
+        }
+        self.limits = limits;
+    }
+
+    /// The ids of the jobs which can send a block now, `last_input` is the time of the last
+    /// remote control input in milliseconds.
+    fn pick(&mut self, ids: &[i32], now: Instant, now_ms: i64, last_input: i64) -> Vec<i32> {
+        self.jobs.retain(|id, _| ids.contains(id));
+        if self.limits.pause_while_controlling && now_ms - last_input < CONTROL_ACTIVE_MS {
+            return vec![];
+        }
+        if !self.session.ready(now) {
+            return vec![];
+        }
+        let mut ready = vec![];
+        for id in ids {
+            if self.job(*id, now).bucket.ready(now) {
+                ready.push(*id);
+            }
+        }
+        let max_weight = ready
+            .iter()
+            .filter_map(|id| self.jobs.get(id))
+            .map(|j| j.priority.weight())
+            .max()
+            .unwrap_or_default();
+        ready.retain(|id| match self.jobs.get_mut(id) {
+            Some(job) => {
+                job.credit += job.priority.weight();
+                if job.credit >= max_weight {
+                    job.credit -= max_weight;
+                    true
+                } else {
+                    false
+                }
+            }
+            None => false,
+        });
+        ready
+    }
+
+    fn account(&mut self, id: i32, transferred: u64) {
+        if let Some(job) = self.jobs.get_mut(&id) {
+            let bytes = transferred.saturating_sub(job.transferred);
+            job.transferred = transferred;
+            job.bucket.spend(bytes);
+            self.session.spend(bytes);
+        }
+    }
+
+    /// Take the jobs which can send a block now out of `jobs`, to be passed to
+    /// `fs::handle_read_jobs` and returned with `put_back`.
+    pub fn take_ready(&mut self, jobs: &mut Vec<TransferJob>, last_input: i64) -> Vec<TransferJob> {
+        let now = Instant::now();
+        if self
+            .loaded
+            .map_or(true, |t| now.duration_since(t) >= RELOAD_INTERVAL)
+        {
+            self.loaded = Some(now);
+            self.set_limits(load_limits());
+        }
+        let ids: Vec<i32> = jobs.iter().map(|j| j.id()).collect();
+        for job in jobs.iter() {
+            // Only the blocks sent from here are counted, not the bytes before a resume.
+            let transferred = job.transferred();
+            self.job(job.id(), now).transferred = transferred;
+        }
+        let ready = self.pick(&ids, now, get_time(), last_input);
+        let mut taken = vec![];
+        let mut i = 0;
+        while i < jobs.len() {
+            if ready.contains(&jobs[i].id()) {
+                taken.push(jobs.remove(i));
+            } else {
+                i += 1;
+            }
+        }
+        taken
+    }
+
+    /// Account the blocks sent by `taken` and return the jobs which are not finished to `jobs`.
+    pub fn put_back(&mut self, taken: Vec<TransferJob>, jobs: &mut Vec<TransferJob>) {
+        for job in taken {
+            self.account(job.id(), job.transferred());
+            jobs.push(job);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scheduler() {
+        let now = Instant::now();
+        let mut s = Scheduler::default();
+        s.set_limits(Limits {
+            session_kbps: 0,
+            job_kbps: 256,
+            pause_while_controlling: true,
+        });
+        s.set_job(1, 0, Priority::High);
+        s.set_job(2, 0, Priority::Low);
+        let ids = [1, 2];
+        let mut picked = HashMap::<i32, u32>::new();
+        for _ in 0..8 {
+            for id in s.pick(&ids, now, 10_000, 0) {
+                *picked.entry(id).or_default() += 1;
+            }
+        }
+        assert_eq!(picked.get(&1), Some(&8));
+        assert_eq!(picked.get(&2), Some(&2));
+
+        // Paused while controlling.
+        assert!(s.pick(&ids, now, 10_000, 9_000).is_empty());
+
+        // The job limit, 256 KB/s holds two blocks of 128 KB.
+        s.account(1, 256 * 1024);
+        s.account(1, 300 * 1024);
+        assert!(!s.pick(&[1], now, 10_000, 0).contains(&1));
+        assert!(s
+            .pick(&[1], now + Duration::from_secs(1), 10_000, 0)
+            .contains(&1));
+
+        // The session limit, job 2 was removed by the last picks.
+        s.set_limits(Limits {
+            session_kbps: 128,
+            job_kbps: 0,
+            pause_while_controlling: false,
+        });
+        let later = now + Duration::from_secs(2);
+        assert_eq!(s.pick(&[1], later, 10_000, 0), vec![1]);
+        s.account(1, 600 * 1024);
+        assert!(s.pick(&[1], later, 10_000, 0).is_empty());
+        assert!(s.jobs.get(&2).is_none());
+    }
+
+    #[test]
+    fn test_job_limit_action() {
+        assert_eq!(JobLimit::from_action(&FileAction::new()), None);
+        let limit = JobLimit {
+            id: 3,
+            kbps: 100,
+            priority: Priority::Low,
+        };
+        let bytes = limit.to_action().write_to_bytes().unwrap();
+        let fa = FileAction::parse_from_bytes(&bytes).unwrap();
+        assert!(fa.union.is_none());
+        assert_eq!(JobLimit::from_action(&fa), Some(limit));
+    }
+}
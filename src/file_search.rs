@@ -0,0 +1,461 @@
+// Search of the files of the controlled side for the file manager.
+//
+// The controlling side sends a `Search` in a `FileAction` with only `FILE_ACTION_FIELD_SEARCH`,
+// tagged with a job id. The controlled side runs it on a thread, under the same permission
+// checks as reading a directory, and sends the matches back in `FileResponse`s with only
+// `FILE_RESPONSE_FIELD_SEARCH`, in batches, the last one with `done` set. Cancelling the job
+// stops the search. Names are matched by a glob (`*`, `**`, `?`) against the file name, or
+// against the path relative to the directory if the pattern has a `/`, or by a regular
+// expression against the relative path. Files can be filtered by size and modification time, and
+// by a text they contain, files larger than `MAX_CONTENT_SIZE` are not searched for text. At
+// most `max_results` matches are sent, and never more than `MAX_RESULTS`.
+//
+// The selected matches are downloaded as one job: a `FileTransferSendRequest` of the searched
+// directory, with the relative names of the files in `SEND_REQUEST_FIELD_NAMES`, reads only
+// those files. The fields are not in the message definitions yet, so older versions skip them
+// as unknown fields, and do not answer a search.
+
+use hbb_common::{
+    fs,
+    message_proto::{FileAction, FileEntry, FileResponse, FileTransferSendRequest, FileType},
+    protobuf::{Message as _, UnknownValueRef},
+    regex::{self, bytes, Regex, RegexBuilder},
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::Read,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+pub const FILE_ACTION_FIELD_SEARCH: u32 = 1003;
+pub const FILE_RESPONSE_FIELD_SEARCH: u32 = 1004;
+pub const SEND_REQUEST_FIELD_NAMES: u32 = 1001;
+pub const MAX_RESULTS: usize = 10_000;
+pub const MAX_CONTENT_SIZE: u64 = 64 << 20;
+// The searches a connection may run at the same time.
+pub const MAX_RUNNING: usize = 2;
+
+const DEFAULT_MAX_RESULTS: usize = 1000;
+// Matches are sent once there are this many, or once this long passed since the last batch.
+const BATCH_SIZE: usize = 100;
+const BATCH_INTERVAL: Duration = Duration::from_millis(300);
+const READ_SIZE: usize = 1 << 20;
+
+fn default_max_results() -> usize {
+    DEFAULT_MAX_RESULTS
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Query {
+    pub pattern: String,
+    #[serde(default)]
+    pub regex: bool,
+    #[serde(default)]
+    pub case_sensitive: bool,
+    /// Bytes.
+    #[serde(default)]
+    pub min_size: Option<u64>,
+    #[serde(default)]
+    pub max_size: Option<u64>,
+    /// Seconds since the epoch.
+    #[serde(default)]
+    pub modified_after: Option<u64>,
+    #[serde(default)]
+    pub modified_before: Option<u64>,
+    /// Text the files contain, empty to not search the contents.
+    #[serde(default)]
+    pub content: String,
+    #[serde(default = "default_max_results")]
+    pub max_results: usize,
+    #[serde(default)]
+    pub include_hidden: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Search {
+    pub id: i32,
+    pub path: String,
+    pub query: Query,
+}
+
+impl Search {
+    pub fn to_action(&self) -> FileAction {
+        let mut fa = FileAction::new();
+        fa.mut_unknown_fields().add_length_delimited(
+            FILE_ACTION_FIELD_SEARCH,
+            serde_json::to_vec(self).unwrap_or_default(),
+        );
+        fa
+    }
+
+    pub fn from_action(fa: &FileAction) -> Option<Self> {
+        match fa.unknown_fields().get(FILE_ACTION_FIELD_SEARCH)? {
+            UnknownValueRef::LengthDelimited(v) => serde_json::from_slice(v).ok(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Item {
+    /// Relative to the searched directory, with the separators of the controlled side.
+    pub name: String,
+    pub path: String,
+    pub size: u64,
+    pub modified: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Results {
+    pub id: i32,
+    pub items: Vec<Item>,
+    /// Whether this is the last batch of the search.
+    pub done: bool,
+    /// Whether there were more matches than `max_results`.
+    pub truncated: bool,
+    pub error: String,
+}
+
+impl Results {
+    pub fn to_response(&self) -> FileResponse {
+        let mut fr = FileResponse::new();
+        fr.mut_unknown_fields().add_length_delimited(
+            FILE_RESPONSE_FIELD_SEARCH,
+            serde_json::to_vec(self).unwrap_or_default(),
+        );
+        fr
+    }
+
+    pub fn from_response(fr: &FileResponse) -> Option<Self> {
+        match fr.unknown_fields().get(FILE_RESPONSE_FIELD_SEARCH)? {
+            UnknownValueRef::LengthDelimited(v) => serde_json::from_slice(v).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Read only the files `names` of the directory of `req`.
+pub fn select_names(req: &mut FileTransferSendRequest, names: &[String]) {
+    req.mut_unknown_fields().add_length_delimited(
+        SEND_REQUEST_FIELD_NAMES,
+        serde_json::to_vec(names).unwrap_or_default(),
+    );
+}
+
+/// The names of the files to read of the directory of `req`, if only some of them.
+pub fn selected_names(req: &FileTransferSendRequest) -> Option<HashSet<String>> {
+    match req.unknown_fields().get(SEND_REQUEST_FIELD_NAMES)? {
+        UnknownValueRef::LengthDelimited(v) => serde_json::from_slice(v).ok(),
+        _ => None,
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut re = "^".to_owned();
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                re.push_str(".*");
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    re
+}
+
+struct Filter {
+    re: Regex,
+    // Match the relative path instead of the file name.
+    path: bool,
+    content: Option<bytes::Regex>,
+    query: Query,
+}
+
+impl Filter {
+    fn new(query: &Query) -> Result<Self, String> {
+        let pattern = query.pattern.trim();
+        let (re, path) = if query.regex {
+            (pattern.to_owned(), true)
+        } else if pattern.is_empty() {
+            ("".to_owned(), true)
+        } else {
+            (glob_to_regex(pattern), pattern.contains('/'))
+        };
+        let re = RegexBuilder::new(&re)
+            .case_insensitive(!query.case_sensitive)
+            .size_limit(1 << 20)
+            .build()
+            .map_err(|e| e.to_string())?;
+        let content = if query.content.is_empty() {
+            None
+        } else {
+            Some(
+                bytes::RegexBuilder::new(&regex::escape(&query.content))
+                    .case_insensitive(!query.case_sensitive)
+                    .build()
+                    .map_err(|e| e.to_string())?,
+            )
+        };
+        Ok(Self {
+            re,
+            path,
+            content,
+            query: query.clone(),
+        })
+    }
+
+    // Whether the name, size and modification time of `e` match, not the contents.
+    fn is_match(&self, e: &FileEntry) -> bool {
+        if !matches!(
+            e.entry_type.enum_value(),
+            Ok(FileType::File) | Ok(FileType::FileLink)
+        ) {
+            return false;
+        }
+        let name = e.name.replace('\\', "/");
+        let name_matched = if self.path {
+            self.re.is_match(&name)
+        } else {
+            self.re
+                .is_match(name.rsplit('/').next().unwrap_or_default())
+        };
+        let query = &self.query;
+        name_matched
+            && !query.min_size.map_or(false, |s| e.size < s)
+            && !query.max_size.map_or(false, |s| e.size > s)
+            && !query.modified_after.map_or(false, |t| e.modified_time < t)
+            && !query.modified_before.map_or(false, |t| e.modified_time > t)
+    }
+
+    fn content_matches(&self, path: &Path, size: u64) -> bool {
+        let Some(re) = &self.content else {
+            return true;
+        };
+        if size > MAX_CONTENT_SIZE {
+            return false;
+        }
+        let Ok(mut file) = File::open(path) else {
+            return false;
+        };
+        // Keep the end of the last read, the text may span two reads.
+        let overlap = self.query.content.len() * 4;
+        let mut buf = Vec::with_capacity(READ_SIZE + overlap);
+        let mut chunk = vec![0u8; READ_SIZE];
+        loop {
+            let n = match file.read(&mut chunk) {
+                Ok(0) | Err(_) => return false,
+                Ok(n) => n,
+            };
+            buf.extend_from_slice(&chunk[..n]);
+            if re.is_match(&buf) {
+                return true;
+            }
+            buf.drain(..buf.len().saturating_sub(overlap));
+        }
+    }
+}
+
+/// Run `search` on this side, passing the results to `f` in batches, the last one with `done`
+/// set. Stops without a last batch once `stop` is set or `f` returns false.
+pub fn run(search: &Search, stop: &AtomicBool, mut f: impl FnMut(Results) -> bool) {
+    let new_results = || Results {
+        id: search.id,
+        ..Default::default()
+    };
+    let mut results = new_results();
+    let res = Filter::new(&search.query).and_then(|filter| {
+        fs::get_recursive_files(&search.path, search.query.include_hidden)
+            .map(|entries| (filter, entries))
+            .map_err(|e| e.to_string())
+    });
+    let (filter, entries) = match res {
+        Ok(v) => v,
+        Err(err) => {
+            results.error = err;
+            results.done = true;
+            f(results);
+            return;
+        }
+    };
+    let max_results = search.query.max_results.min(MAX_RESULTS);
+    let mut count = 0;
+    let mut last = Instant::now();
+    for e in entries {
+        if stop.load(Ordering::SeqCst) {
+            return;
+        }
+        if !filter.is_match(&e) {
+            continue;
+        }
+        let path = Path::new(&search.path).join(&e.name);
+        if !filter.content_matches(&path, e.size) {
+            continue;
+        }
+        if count >= max_results {
+            results.truncated = true;
+            break;
+        }
+        count += 1;
+        results.items.push(Item {
+            name: e.name,
+            path: path.to_string_lossy().to_string(),
+            size: e.size,
+            modified: e.modified_time,
+        });
+        if results.items.len() >= BATCH_SIZE || last.elapsed() >= BATCH_INTERVAL {
+            if !f(std::mem::replace(&mut results, new_results())) {
+                return;
+            }
+            last = Instant::now();
+        }
+    }
+    if stop.load(Ordering::SeqCst) {
+        return;
+    }
+    results.done = true;
+    f(results);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, size: u64, mtime: u64) -> FileEntry {
+        FileEntry {
+            name: name.to_owned(),
+            entry_type: FileType::File.into(),
+            size,
+            modified_time: mtime,
+            ..Default::default()
+        }
+    }
+
+    fn names(query: &str, entries: &[FileEntry]) -> Vec<String> {
+        let query: Query = serde_json::from_str(query).unwrap();
+        let filter = Filter::new(&query).unwrap();
+        entries
+            .iter()
+            .filter(|e| filter.is_match(e))
+            .map(|e| e.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_filter() {
+        let entries = vec![
+            entry("log/app.log", 100, 1000),
+            entry("log/old/App.LOG", 5000, 10),
+            entry("lib\\x.so", 10, 1000),
+            FileEntry {
+                name: "log".to_owned(),
+                entry_type: FileType::Dir.into(),
+                ..Default::default()
+            },
+        ];
+        assert_eq!(
+            names(r#"{"pattern": "*.log"}"#, &entries),
+            vec!["log/app.log", "log/old/App.LOG"]
+        );
+        assert_eq!(
+            names(r#"{"pattern": "*.log", "case_sensitive": true}"#, &entries),
+            vec!["log/app.log"]
+        );
+        assert_eq!(
+            names(r#"{"pattern": "log/*.log"}"#, &entries),
+            vec!["log/app.log"]
+        );
+        assert_eq!(
+            names(r#"{"pattern": "log/**.log"}"#, &entries),
+            vec!["log/app.log", "log/old/App.LOG"]
+        );
+        assert_eq!(
+            names(r#"{"pattern": "^lib/.\\.so$", "regex": true}"#, &entries),
+            vec!["lib\\x.so"]
+        );
+        assert_eq!(
+            names(
+                r#"{"pattern": "", "min_size": 50, "modified_after": 500}"#,
+                &entries
+            ),
+            vec!["log/app.log"]
+        );
+        let query: Query = serde_json::from_str(r#"{"pattern": "(", "regex": true}"#).unwrap();
+        assert!(Filter::new(&query).is_err());
+    }
+
+    #[test]
+    fn test_run() {
+        let dir = std::env::temp_dir().join(format!("rustdesk_search_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("a.txt"), b"nothing here").unwrap();
+        std::fs::write(dir.join("sub").join("b.txt"), b"an Error line").unwrap();
+        std::fs::write(dir.join("sub").join("c.log"), b"error").unwrap();
+        let search = |query: &str| {
+            let search = Search {
+                id: 9,
+                path: dir.to_string_lossy().to_string(),
+                query: serde_json::from_str(query).unwrap(),
+            };
+            let mut all = vec![];
+            run(&search, &AtomicBool::new(false), |r| {
+                all.push(r);
+                true
+            });
+            all
+        };
+        let results = search(r#"{"pattern": "*.txt", "content": "error"}"#);
+        let last = results.last().unwrap();
+        assert!(last.done && !last.truncated && last.error.is_empty());
+        let items = results
+            .iter()
+            .flat_map(|r| r.items.iter())
+            .collect::<Vec<_>>();
+        assert_eq!(items.len(), 1);
+        assert_eq!(
+            items[0].name,
+            format!("sub{}b.txt", std::path::MAIN_SEPARATOR)
+        );
+        assert_eq!(
+            search(r#"{"pattern": "*.txt", "content": "error", "case_sensitive": true}"#)
+                .iter()
+                .map(|r| r.items.len())
+                .sum::<usize>(),
+            0
+        );
+        let results = search(r#"{"pattern": "", "max_results": 2}"#);
+        assert!(results.last().unwrap().truncated);
+        let stopped = AtomicBool::new(true);
+        let search = Search {
+            id: 9,
+            path: dir.to_string_lossy().to_string(),
+            query: serde_json::from_str(r#"{"pattern": ""}"#).unwrap(),
+        };
+        let mut called = false;
+        run(&search, &stopped, |_| {
+            called = true;
+            true
+        });
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(!called);
+        let results = Results {
+            id: 9,
+            done: true,
+            ..Default::default()
+        };
+        let bytes = results.to_response().write_to_bytes().unwrap();
+        let fr = FileResponse::parse_from_bytes(&bytes).unwrap();
+        assert_eq!(Results::from_response(&fr).map(|r| r.id), Some(9));
+        let mut req = FileTransferSendRequest::new();
+        select_names(&mut req, &["a".to_owned()]);
+        assert!(selected_names(&req).unwrap().contains("a"));
+    }
+}
@@ -801,6 +801,38 @@ impl InvokeUiSession for FlutterHandler {
         );
     }
 
+    fn update_sync_plan(&self, id: i32, plan: &str, err: &str) {
+        let id = id.to_string();
+        self.push_event(
+            "sync_plan",
+            &[("id", id.as_str()), ("plan", plan), ("err", err)],
+            &[],
+        );
+    }
+
+    fn update_search_result(&self, id: i32, result: &str, err: &str) {
+        let id = id.to_string();
+        self.push_event(
+            "search_result",
+            &[("id", id.as_str()), ("result", result), ("err", err)],
+            &[],
+        );
+    }
+
+    fn retry_job(&self, id: i32, retry_of: i32, path: &str, to: &str, is_remote: bool) {
+        self.push_event(
+            "retry_job",
+            &[
+                ("id", &id.to_string()),
+                ("retry_of", &retry_of.to_string()),
+                ("path", path),
+                ("to", to),
+                ("is_remote", &is_remote.to_string()),
+            ],
+            &[],
+        );
+    }
+
     // unused in flutter
     fn update_transfer_list(&self) {}
 
@@ -1533,6 +1565,16 @@ pub mod connection_manager {
             self.push_event("update_voice_call_state", &[("client", &client_json)]);
         }
 
+        fn update_permission_template(&self, client: &crate::ui_cm_interface::Client) {
+            let client_json = serde_json::to_string(&client).unwrap_or("".into());
+            self.push_event("update_permission_template", &[("client", &client_json)]);
+        }
+
+        fn update_session_extension(&self, client: &crate::ui_cm_interface::Client) {
+            let client_json = serde_json::to_string(&client).unwrap_or("".into());
+            self.push_event("update_session_extension", &[("client", &client_json)]);
+        }
+
         fn file_transfer_log(&self, action: &str, log: &str) {
             self.push_event("cm_file_transfer_log", &[(action, log)]);
         }
@@ -640,6 +640,23 @@ pub fn session_open_terminal(session_id: SessionID, terminal_id: i32, rows: u32,
     }
 }
 
+pub fn session_open_terminal_with_profile(
+    session_id: SessionID,
+    terminal_id: i32,
+    rows: u32,
+    cols: u32,
+    profile: String,
+) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.open_terminal_with_profile(terminal_id, rows, cols, profile);
+    } else {
+        log::error!(
+            "[flutter_ffi] Session not found for session_id: {}",
+            session_id
+        );
+    }
+}
+
 pub fn session_send_terminal_input(session_id: SessionID, terminal_id: i32, data: String) {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         session.send_terminal_input(terminal_id, data);
@@ -744,6 +761,68 @@ pub fn session_read_dir_to_remove_recursive(
     }
 }
 
+pub fn session_sync_plan(
+    session_id: SessionID,
+    act_id: i32,
+    local: String,
+    remote: String,
+    options: String,
+) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.sync_plan(act_id, local, remote, options);
+    }
+}
+
+pub fn session_sync_run(session_id: SessionID, act_id: i32, jobs: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.sync_run(act_id, jobs);
+    }
+}
+
+pub fn session_set_job_limit(session_id: SessionID, act_id: i32, kbps: u32, priority: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.set_job_limit(act_id, kbps, priority);
+    }
+}
+
+pub fn session_export_manifest(session_id: SessionID, act_id: i32, path: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.export_manifest(act_id, path);
+    }
+}
+
+pub fn session_search_files(session_id: SessionID, act_id: i32, path: String, query: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.search_files(act_id, path, query);
+    }
+}
+
+pub fn session_download_files(
+    session_id: SessionID,
+    act_id: i32,
+    path: String,
+    to: String,
+    names: String,
+    include_hidden: bool,
+) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        let names = serde_json::from_str(&names).unwrap_or_default();
+        session.download_files(act_id, path, to, names, include_hidden);
+    }
+}
+
+pub fn session_delta_transfer(
+    session_id: SessionID,
+    act_id: i32,
+    path: String,
+    to: String,
+    is_remote: bool,
+) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.delta_transfer(act_id, path, to, is_remote);
+    }
+}
+
 pub fn session_remove_all_empty_dirs(
     session_id: SessionID,
     act_id: i32,
@@ -1533,6 +1612,14 @@ pub fn cm_handle_incoming_voice_call(id: i32, accept: bool) {
     crate::ui_cm_interface::handle_incoming_voice_call(id, accept);
 }
 
+pub fn cm_handle_permission_template(id: i32, approve: bool) {
+    crate::ui_cm_interface::handle_permission_template(id, approve);
+}
+
+pub fn cm_handle_session_extension(id: i32, approve: bool) {
+    crate::ui_cm_interface::handle_session_extension(id, approve);
+}
+
 pub fn cm_close_voice_call(id: i32) {
     crate::ui_cm_interface::close_voice_call(id);
 }
@@ -1841,6 +1928,14 @@ pub fn main_set_permanent_password(password: String) {
     set_permanent_password(password);
 }
 
+pub fn main_create_share_token(minutes: u64) -> String {
+    create_share_token(minutes)
+}
+
+pub fn main_revoke_share_token(token: String) {
+    revoke_share_token(token)
+}
+
 pub fn main_check_super_user_permission() -> bool {
     check_super_user_permission()
 }
@@ -2437,6 +2532,26 @@ pub fn main_verify2fa(code: String) -> bool {
     verify2fa(code)
 }
 
+pub fn main_generate2fa_device(label: String, digits: usize, algorithm: String) -> String {
+    generate2fa_device(label, digits, algorithm)
+}
+
+pub fn main_list2fa_devices() -> String {
+    list_2fa_devices()
+}
+
+pub fn main_remove2fa_device(label: String) -> String {
+    remove_2fa_device(label)
+}
+
+pub fn main_generate2fa_recovery_codes() -> String {
+    generate_2fa_recovery_codes()
+}
+
+pub fn main_get2fa_recovery_codes_left() -> usize {
+    get_2fa_recovery_codes_left()
+}
+
 pub fn main_has_valid_2fa_sync() -> SyncReturn<bool> {
     SyncReturn(has_valid_2fa())
 }
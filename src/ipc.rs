@@ -112,6 +112,13 @@ pub enum FS {
         path: String,
         new_name: String,
     },
+    /// Start a delta upload to `path`, see `file_delta`.
+    DeltaBegin {
+        id: i32,
+        path: String,
+    },
+    /// A `FileResponse` with an op of a delta upload, sent raw after the header like a block.
+    Delta(Bytes),
 }
 
 #[cfg(target_os = "windows")]
@@ -258,6 +265,12 @@ pub enum Data {
     VoiceCallIncoming,
     StartVoiceCall,
     VoiceCallResponse(bool),
+    // The permission template requested by the peer, with its permissions.
+    PermissionTemplateRequest((String, Vec<String>)),
+    PermissionTemplateResponse(bool),
+    // Minutes of the session extension the peer asks for.
+    SessionExtensionRequest(u64),
+    SessionExtensionResponse(bool),
     CloseVoiceCall(String),
     #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -265,6 +278,10 @@ pub enum Data {
     #[cfg(windows)]
     SyncWinCpuUsage(Option<f64>),
     FileTransferLog((String, String)),
+    /// Whether the files were received and the manifest as json.
+    FileManifest((bool, String)),
+    /// The `Done` of a delta upload as json.
+    FileDelta(String),
     #[cfg(windows)]
     ControlledSessionCount(usize),
     CmErr(String),
@@ -276,6 +293,10 @@ pub enum Data {
     HwCodecConfig(Option<String>),
     RemoveTrustedDevices(Vec<Bytes>),
     ClearTrustedDevices,
+    // Lift the login bans of the ips or IPv6 prefixes, all if empty.
+    RemoveLoginBans(Vec<String>),
+    // Revoke the trusted devices by hwid (hex) or peer id, all if empty, with the reason.
+    RevokeTrustedDevices(Vec<String>, String),
     #[cfg(all(target_os = "windows", feature = "flutter"))]
     PrinterData(Vec<u8>),
     InstallOption(Option<(String, String)>),
@@ -291,6 +312,8 @@ pub enum Data {
     SocksWs(Option<Box<(Option<config::Socks5Server>, String)>>),
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     Whiteboard((String, crate::whiteboard::CustomEvent)),
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    PortForwardRules(Option<Vec<crate::port_forward::RuleStatus>>),
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -546,6 +569,12 @@ async fn handle(data: Data, stream: &mut Connection) {
                     value = Some(Config::get_unlock_pin());
                 } else if name == "trusted-devices" {
                     value = Some(Config::get_trusted_devices_json());
+                } else if name == "login-bans" {
+                    value = serde_json::to_string(&crate::login_ban::list()).ok();
+                } else if name == "trusted-devices-info" {
+                    value = serde_json::to_string(&crate::trusted_device::list()).ok();
+                } else if name == "trusted-devices-revoked" {
+                    value = serde_json::to_string(&crate::trusted_device::revoked()).ok();
                 } else {
                     value = None;
                 }
@@ -717,6 +746,12 @@ async fn handle(data: Data, stream: &mut Connection) {
         Data::ClearTrustedDevices => {
             Config::clear_trusted_devices();
         }
+        Data::RemoveLoginBans(keys) => {
+            crate::login_ban::remove(&keys);
+        }
+        Data::RevokeTrustedDevices(keys, reason) => {
+            crate::trusted_device::revoke(&keys, &reason);
+        }
         Data::InstallOption(opt) => match opt {
             Some((_k, _v)) => {
                 #[cfg(target_os = "windows")]
@@ -1083,6 +1118,29 @@ pub fn clear_trusted_devices() {
     allow_err!(set_data(&Data::ClearTrustedDevices));
 }
 
+pub fn get_login_bans() -> ResultType<Vec<crate::login_ban::Ban>> {
+    let v = get_config("login-bans")?.unwrap_or_default();
+    Ok(serde_json::from_str(&v).unwrap_or_default())
+}
+
+pub fn remove_login_bans(keys: Vec<String>) -> ResultType<()> {
+    set_data(&Data::RemoveLoginBans(keys))
+}
+
+pub fn get_trusted_devices_info() -> ResultType<Vec<crate::trusted_device::DeviceInfo>> {
+    let v = get_config("trusted-devices-info")?.unwrap_or_default();
+    Ok(serde_json::from_str(&v).unwrap_or_default())
+}
+
+pub fn get_revoked_trusted_devices() -> ResultType<Vec<crate::trusted_device::Revoked>> {
+    let v = get_config("trusted-devices-revoked")?.unwrap_or_default();
+    Ok(serde_json::from_str(&v).unwrap_or_default())
+}
+
+pub fn revoke_trusted_devices(keys: Vec<String>, reason: String) -> ResultType<()> {
+    set_data(&Data::RevokeTrustedDevices(keys, reason))
+}
+
 pub fn get_id() -> String {
     if let Ok(Some(v)) = get_config("id") {
         // update salt also, so that next time reinstallation not causing first-time auto-login failure
@@ -258,5 +258,15 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("websocket_tip", "When using WebSocket, only relay connections are supported."),
         ("terminal-admin-login-tip", "Please input the administrator username and password of the controlled side."),
         ("elevation_username_tip", "Input username or domain\\username"),
+        ("2fa-code-or-recovery-code-tip", "2FA code must be 6 to 8 digits, or a 16 character recovery code."),
+        ("transfer-limits-tip", "The limits apply to the uploads from this device, and to the files it sends when it is controlled. Downloads are limited by the controlled side, or per job from the transfer list."),
+        ("session-closes-in-{}-minutes-tip", "The session will be closed in {} minutes. Send /extend in the chat to ask for more time."),
+        ("session-extension-requested-tip", "More time was requested, waiting for the controlled side."),
+        ("session-extended-by-{}-minutes-tip", "The session was extended by {} minutes."),
+        ("session-extension-denied-tip", "The controlled side denied more time."),
+        ("extend-session-by-{}-minutes-tip", "The peer asks to extend the session by {} minutes."),
+        ("sync-compare-tip", "Files are compared by size and modification time. A file changed without a change of both is not synchronized."),
+        ("export-manifest-tip", "The manifest records the hashes of the local files. When the controlled side makes manifests, the files are also checked against the peer after the transfer and the files which do not match are transferred again."),
+        ("remote-search-tip", "The files are searched on the remote side. The text is only looked for in files up to 64 MB, and at most 1000 files are listed."),
     ].iter().cloned().collect();
 }
@@ -721,5 +721,48 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("Show virtual joystick", "Afficher le joystick virtuel"),
         ("Edit note", "Modifier la note"),
         ("Alias", "Alias"),
+        ("2fa-code-or-recovery-code-tip", ""),
+        ("Synchronize folders", ""),
+        ("Local to remote", ""),
+        ("Remote to local", ""),
+        ("Both directions", ""),
+        ("On conflict", ""),
+        ("Newer wins", ""),
+        ("Local wins", ""),
+        ("Remote wins", ""),
+        ("Delete files missing on the other side", ""),
+        ("Nothing to synchronize", ""),
+        ("Transfer changed blocks only", ""),
+        ("Transfer limits", ""),
+        ("Session limit (KB/s)", ""),
+        ("Job limit (KB/s)", ""),
+        ("Pause transfers while controlling", ""),
+        ("transfer-limits-tip", ""),
+        ("0 means no limit", ""),
+        ("Speed limit", ""),
+        ("Priority", ""),
+        ("High", ""),
+        ("Normal", ""),
+        ("Low", ""),
+        ("Export manifest", ""),
+        ("Search remote files", ""),
+        ("Regular expression", ""),
+        ("Case sensitive", ""),
+        ("Min size (KB)", ""),
+        ("Max size (KB)", ""),
+        ("Modified in the last days", ""),
+        ("Containing text", ""),
+        ("No results", ""),
+        ("Permission template", ""),
+        ("Session time limit", ""),
+        ("Session time limit reached", ""),
+        ("session-closes-in-{}-minutes-tip", ""),
+        ("session-extension-requested-tip", ""),
+        ("session-extended-by-{}-minutes-tip", ""),
+        ("session-extension-denied-tip", ""),
+        ("extend-session-by-{}-minutes-tip", ""),
+        ("sync-compare-tip", ""),
+        ("export-manifest-tip", ""),
+        ("remote-search-tip", ""),
     ].iter().cloned().collect();
 }
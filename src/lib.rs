@@ -46,7 +46,7 @@ pub mod core_main;
 mod custom_server;
 mod lang;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
-mod port_forward;
+pub mod port_forward;
 
 #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -76,3 +76,11 @@ pub mod privacy_mode;
 pub mod virtual_display_manager;
 
 mod kcp_stream;
+
+// Used by the port forwarding of the server on every platform, not only by `port_forward`.
+mod udp_forward;
+
+pub mod file_delta;
+pub mod file_manifest;
+pub mod file_scheduler;
+pub mod file_search;
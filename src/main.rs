@@ -45,7 +45,16 @@ fn main() {
     use hbb_common::log;
     let args = format!(
         "-p, --port-forward=[PORT-FORWARD-OPTIONS] 'Format: remote-id:local-port:remote-port[:remote-host]'
+        --socks5=[SOCKS5-OPTIONS] 'Format: remote-id:local-port, serve a SOCKS5 proxy tunneled through the peer'
+        --port-forward-config=[FILE] 'Run the port-forward rules of a JSON config file as a daemon'
+        --port-forward-status 'Print the rule status of the running port-forward daemon'
         -c, --connect=[REMOTE_ID] 'test only'
+        -r, --run=[REMOTE_ID] 'Log in to REMOTE_ID and run script commands, printing one JSON line per command'
+        --script=[FILE] 'Script to run, read from stdin if not set'
+        --conn-type=[TYPE] 'remote, file or terminal, inferred from the script if not set'
+        --password=[PASSWORD] 'Peer password, RUSTDESK_PASSWORD is used if not set'
+        --permission-template=[NAME] 'Request a permission template of the peer, remembered for the peer'
+        --stop-on-error 'Stop the script at the first failed command'
         -k, --key=[KEY] ''
        -s, --server=[] 'Start server'",
     );
@@ -93,12 +102,69 @@ fn main() {
             key,
             token,
         );
+    } else if let Some(p) = matches.value_of("socks5") {
+        let options: Vec<&str> = p.split(":").collect();
+        let port = match options.get(1).map(|x| x.parse::<i32>()) {
+            Some(Ok(v)) if options.len() == 2 => v,
+            _ => {
+                log::error!("Wrong socks5 options");
+                return;
+            }
+        };
+        common::test_rendezvous_server();
+        common::test_nat_type();
+        let key = matches.value_of("key").unwrap_or("").to_owned();
+        let token = LocalConfig::get_option("access_token");
+        cli::start_socks5(options[0].to_owned(), port, key, token);
+    } else if let Some(p) = matches.value_of("port-forward-config") {
+        common::test_rendezvous_server();
+        common::test_nat_type();
+        let key = matches.value_of("key").unwrap_or("").to_owned();
+        let token = LocalConfig::get_option("access_token");
+        cli::start_port_forward_daemon(p.to_owned(), key, token);
+    } else if matches.is_present("port-forward-status") {
+        match port_forward::get_daemon_status() {
+            Ok(v) => println!("{}", serde_json::to_string_pretty(&v).unwrap_or_default()),
+            Err(err) => {
+                log::error!("Failed to get port forward status: {}", err);
+                std::process::exit(1);
+            }
+        }
     } else if let Some(p) = matches.value_of("connect") {
         common::test_rendezvous_server();
         common::test_nat_type();
         let key = matches.value_of("key").unwrap_or("").to_owned();
         let token = LocalConfig::get_option("access_token");
         cli::connect_test(p, key, token);
+    } else if let Some(p) = matches.value_of("run") {
+        let conn_type = match matches.value_of("conn-type") {
+            Some(t) => match cli::parse_conn_type(t) {
+                Some(t) => Some(t),
+                None => {
+                    log::error!("Wrong conn-type: {}", t);
+                    return;
+                }
+            },
+            None => None,
+        };
+        common::test_rendezvous_server();
+        common::test_nat_type();
+        let key = matches.value_of("key").unwrap_or("").to_owned();
+        let token = LocalConfig::get_option("access_token");
+        let code = cli::run_script(
+            p.to_owned(),
+            key,
+            token,
+            matches.value_of("script").map(|x| x.to_owned()),
+            conn_type,
+            matches.value_of("password").map(|x| x.to_owned()),
+            matches
+                .value_of("permission-template")
+                .map(|x| x.to_owned()),
+            matches.is_present("stop-on-error"),
+        );
+        common::global_clean();
+        std::process::exit(code);
     } else if let Some(p) = matches.value_of("server") {
         log::info!("id={}", hbb_common::config::Config::get_id());
         crate::start_server(true, false);
@@ -1,11 +1,16 @@
-use std::sync::{Arc, RwLock};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
 
 use crate::client::*;
+use crate::{ipc, udp_forward};
 use hbb_common::{
     allow_err, bail,
     config::READ_TIMEOUT,
     futures::{SinkExt, StreamExt},
-    log,
+    get_time, log,
     message_proto::*,
     protobuf::Message as _,
     rendezvous_proto::ConnType,
@@ -14,6 +19,9 @@ use hbb_common::{
     tokio_util::codec::{BytesCodec, Framed},
     ResultType, Stream,
 };
+use serde_derive::{Deserialize, Serialize};
+
+const DAEMON_IPC_POSTFIX: &str = "_pf";
 
 fn run_rdp(port: u16) {
     std::process::Command::new("cmdkey")
@@ -70,7 +78,7 @@ pub async fn listen(
                 let id = id.clone();
                 let password = password.clone();
                 let mut forward = Framed::new(forward, BytesCodec::new());
-                match connect_and_login(&id, &password, &mut ui_receiver, interface.clone(), &mut forward, key, token, is_rdp).await {
+                match connect_and_login(&id, &password, &mut ui_receiver, interface.clone(), Some(&mut forward), key, token, is_rdp).await {
                     Ok(Some(stream)) => {
                         let interface = interface.clone();
                         tokio::spawn(async move {
@@ -108,7 +116,7 @@ async fn connect_and_login(
     password: &str,
     ui_receiver: &mut mpsc::UnboundedReceiver<Data>,
     interface: impl Interface,
-    forward: &mut Framed<TcpStream, BytesCodec>,
+    mut forward: Option<&mut Framed<TcpStream, BytesCodec>>,
     key: &str,
     token: &str,
     is_rdp: bool,
@@ -175,10 +183,13 @@ async fn connect_and_login(
                     Some(Data::Message(msg)) => {
                         allow_err!(stream.send(&msg).await);
                     }
+                    Some(Data::Close) => {
+                        bail!("Login aborted");
+                    }
                     _ => {}
                 }
             },
-            res = forward.next() => {
+            res = async { forward.as_mut().unwrap().next().await }, if forward.is_some() => {
                 if let Some(Ok(bytes)) = res {
                     buffer.extend(bytes);
                 } else {
@@ -218,3 +229,591 @@ async fn run_forward(forward: Framed<TcpStream, BytesCodec>, stream: Stream) ->
     }
     Ok(())
 }
+
+/// A forwarding rule of the port-forward daemon config file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ForwardRule {
+    #[serde(default)]
+    pub name: String,
+    pub id: String,
+    #[serde(default)]
+    pub password: String,
+    pub local_port: i32,
+    #[serde(default = "default_remote_host")]
+    pub remote_host: String,
+    #[serde(default)]
+    pub remote_port: i32,
+    /// Serve a SOCKS5 proxy on `local_port`, the target of each CONNECT is
+    /// forwarded through the peer instead of `remote_host:remote_port`.
+    #[serde(default)]
+    pub socks5: bool,
+    /// Forward UDP datagrams instead of TCP connections.
+    #[serde(default)]
+    pub udp: bool,
+}
+
+fn default_remote_host() -> String {
+    "localhost".to_owned()
+}
+
+/// The port-forward daemon config file, JSON, e.g.
+/// `{"rules": [{"name": "db", "id": "123456789", "local_port": 15432, "remote_port": 5432}]}`
+#[derive(Debug, Clone, Deserialize)]
+pub struct ForwardConfig {
+    #[serde(default)]
+    pub key: String,
+    /// Login attempts for one accepted local connection before it is dropped.
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    #[serde(default = "default_max_backoff_secs")]
+    pub max_backoff_secs: u64,
+    pub rules: Vec<ForwardRule>,
+}
+
+fn default_max_retries() -> u32 {
+    5
+}
+
+fn default_max_backoff_secs() -> u64 {
+    60
+}
+
+impl ForwardConfig {
+    pub fn load(path: &str) -> ResultType<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    fn parse(s: &str) -> ResultType<Self> {
+        let config: Self = serde_json::from_str(s)?;
+        let mut ports = std::collections::HashSet::new();
+        for rule in config.rules.iter() {
+            if rule.id.is_empty() {
+                bail!("Rule {} has no peer id", rule.name);
+            }
+            if rule.socks5 && rule.udp {
+                bail!("Rule {} can not be both socks5 and udp", rule.name);
+            }
+            if rule.local_port <= 0 || (!rule.socks5 && rule.remote_port <= 0) {
+                bail!("Rule {} has an invalid port", rule.name);
+            }
+            if !ports.insert(rule.local_port) {
+                bail!(
+                    "Local port {} is used by more than one rule",
+                    rule.local_port
+                );
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Status of a daemon rule, reported over ipc with `ipc::Data::PortForwardRules`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RuleStatus {
+    pub name: String,
+    pub id: String,
+    pub local_port: i32,
+    pub remote: String,
+    /// "starting", "listening", "connecting", "backoff" or "error"
+    pub state: String,
+    pub active: usize,
+    pub total: usize,
+    /// Consecutive failed logins of all the connections of the rule.
+    pub failures: u32,
+    pub last_error: String,
+    /// Unix time in milliseconds of the last state change.
+    pub updated: i64,
+}
+
+type Statuses = Arc<Mutex<HashMap<i32, RuleStatus>>>;
+
+fn update_status(statuses: &Statuses, port: i32, f: impl FnOnce(&mut RuleStatus)) {
+    if let Some(s) = statuses.lock().unwrap().get_mut(&port) {
+        f(s);
+        s.updated = get_time();
+    }
+}
+
+fn backoff(failures: u32, max_secs: u64) -> Duration {
+    let secs = 1u64 << failures.min(16);
+    Duration::from_secs(secs.min(max_secs.max(1)))
+}
+
+/// Serve all rules of `config` until the process is killed. `new_interface` creates the
+/// login interface and its data receiver for each connection, it must not prompt for input.
+pub async fn run_daemon<T, F>(
+    config: ForwardConfig,
+    token: String,
+    new_interface: F,
+) -> ResultType<()>
+where
+    T: Interface,
+    F: Fn(&ForwardRule) -> (T, mpsc::UnboundedReceiver<Data>) + Send + Sync + 'static,
+{
+    let statuses: Statuses = Default::default();
+    for rule in config.rules.iter() {
+        statuses.lock().unwrap().insert(
+            rule.local_port,
+            RuleStatus {
+                name: rule.name.clone(),
+                id: rule.id.clone(),
+                local_port: rule.local_port,
+                remote: if rule.socks5 {
+                    "socks5".to_owned()
+                } else if rule.udp {
+                    format!(
+                        "{}{}:{}",
+                        udp_forward::UDP_SCHEME,
+                        rule.remote_host,
+                        rule.remote_port
+                    )
+                } else {
+                    format!("{}:{}", rule.remote_host, rule.remote_port)
+                },
+                state: "starting".to_owned(),
+                updated: get_time(),
+                ..Default::default()
+            },
+        );
+    }
+    let new_interface = Arc::new(new_interface);
+    for rule in config.rules.clone() {
+        let config = config.clone();
+        let token = token.clone();
+        let statuses = statuses.clone();
+        let new_interface = new_interface.clone();
+        tokio::spawn(async move {
+            run_rule(rule, config, token, statuses, new_interface).await;
+        });
+    }
+    serve_status(statuses).await
+}
+
+/// Log in to the peer of `rule` for `target`, retrying with backoff up to `max_retries` times.
+async fn login_with_retry<T, F>(
+    rule: &ForwardRule,
+    config: &ForwardConfig,
+    token: &str,
+    statuses: &Statuses,
+    new_interface: &F,
+    target: (String, i32),
+    mut forward: Option<&mut Framed<TcpStream, BytesCodec>>,
+) -> Option<Stream>
+where
+    T: Interface,
+    F: Fn(&ForwardRule) -> (T, mpsc::UnboundedReceiver<Data>),
+{
+    let port = rule.local_port;
+    let mut attempt = 0;
+    loop {
+        update_status(statuses, port, |s| s.state = "connecting".to_owned());
+        let (interface, mut receiver) = new_interface(rule);
+        interface.get_lch().write().unwrap().port_forward = target.clone();
+        let res = connect_and_login(
+            &rule.id,
+            &rule.password,
+            &mut receiver,
+            interface,
+            forward.as_deref_mut(),
+            &config.key,
+            token,
+            false,
+        )
+        .await;
+        let err = match res {
+            Ok(Some(stream)) => return Some(stream),
+            // The local client went away or the peer refused the target.
+            Ok(None) => return None,
+            Err(err) => err,
+        };
+        log::error!("port forward rule {} login failed: {}", rule.name, err);
+        update_status(statuses, port, |s| {
+            s.state = "backoff".to_owned();
+            s.failures += 1;
+            s.last_error = err.to_string();
+        });
+        attempt += 1;
+        if attempt >= config.max_retries {
+            return None;
+        }
+        // The backoff only counts the attempts of this connection, not the failures of the
+        // other connections of the rule or of listening.
+        tokio::time::sleep(backoff(attempt - 1, config.max_backoff_secs)).await;
+    }
+}
+
+async fn run_rule<T, F>(
+    rule: ForwardRule,
+    config: ForwardConfig,
+    token: String,
+    statuses: Statuses,
+    new_interface: Arc<F>,
+) where
+    T: Interface,
+    F: Fn(&ForwardRule) -> (T, mpsc::UnboundedReceiver<Data>) + Send + Sync + 'static,
+{
+    if rule.udp {
+        return run_udp_rule(rule, config, token, statuses, new_interface).await;
+    }
+    let port = rule.local_port;
+    let mut listen_failures = 0;
+    let listener = loop {
+        match tcp::new_listener(format!("0.0.0.0:{}", port), true).await {
+            Ok(listener) => break listener,
+            Err(err) => {
+                log::error!("Failed to listen on {}: {}", port, err);
+                update_status(&statuses, port, |s| {
+                    s.state = "error".to_owned();
+                    s.last_error = err.to_string();
+                });
+                tokio::time::sleep(backoff(listen_failures, config.max_backoff_secs)).await;
+                listen_failures += 1;
+            }
+        }
+    };
+    log::info!(
+        "port forward rule {} listening on {}, to {}:{}:{}",
+        rule.name,
+        port,
+        rule.id,
+        rule.remote_host,
+        rule.remote_port
+    );
+    update_status(&statuses, port, |s| s.state = "listening".to_owned());
+    loop {
+        let (forward, addr) = match listener.accept().await {
+            Ok(v) => v,
+            Err(err) => {
+                log::error!("Failed to accept on {}: {}", port, err);
+                continue;
+            }
+        };
+        log::info!("new connection from {:?} on {}", addr, port);
+        let rule = rule.clone();
+        let config = config.clone();
+        let token = token.clone();
+        let statuses = statuses.clone();
+        let new_interface = new_interface.clone();
+        tokio::spawn(async move {
+            let mut forward = forward;
+            let target = if rule.socks5 {
+                match socks5_handshake(&mut forward).await {
+                    Ok(target) => target,
+                    Err(err) => {
+                        log::error!("SOCKS5 handshake with {:?} failed: {}", addr, err);
+                        return;
+                    }
+                }
+            } else {
+                (rule.remote_host.clone(), rule.remote_port)
+            };
+            let mut forward = Framed::new(forward, BytesCodec::new());
+            let stream = login_with_retry(
+                &rule,
+                &config,
+                &token,
+                &statuses,
+                new_interface.as_ref(),
+                target,
+                Some(&mut forward),
+            )
+            .await;
+            if rule.socks5 {
+                allow_err!(socks5_reply(forward.get_mut(), stream.is_some()).await);
+            }
+            let Some(stream) = stream else {
+                update_status(&statuses, port, |s| s.state = "listening".to_owned());
+                return;
+            };
+            update_status(&statuses, port, |s| {
+                s.state = "listening".to_owned();
+                s.failures = 0;
+                s.active += 1;
+                s.total += 1;
+            });
+            if let Err(err) = run_forward(forward, stream).await {
+                update_status(&statuses, port, |s| s.last_error = err.to_string());
+            }
+            update_status(&statuses, port, |s| s.active -= 1);
+            log::info!("connection from {:?} on {} closed", addr, port);
+        });
+    }
+}
+
+const SOCKS5_VERSION: u8 = 5;
+
+/// Run the server side of a SOCKS5 handshake without authentication and
+/// return the target of the CONNECT request.
+async fn socks5_handshake(sock: &mut TcpStream) -> ResultType<(String, i32)> {
+    use hbb_common::tokio::io::{AsyncReadExt, AsyncWriteExt};
+    let mut buf = [0u8; 2];
+    sock.read_exact(&mut buf).await?;
+    if buf[0] != SOCKS5_VERSION {
+        bail!("Unsupported SOCKS version {}", buf[0]);
+    }
+    let mut methods = vec![0u8; buf[1] as usize];
+    sock.read_exact(&mut methods).await?;
+    if !methods.contains(&0) {
+        sock.write_all(&[SOCKS5_VERSION, 0xff]).await?;
+        bail!("No acceptable SOCKS5 authentication method");
+    }
+    sock.write_all(&[SOCKS5_VERSION, 0]).await?;
+    let mut req = [0u8; 4];
+    sock.read_exact(&mut req).await?;
+    if req[1] != 1 {
+        // command not supported, only CONNECT is
+        sock.write_all(&[SOCKS5_VERSION, 7, 0, 1, 0, 0, 0, 0, 0, 0])
+            .await?;
+        bail!("Unsupported SOCKS5 command {}", req[1]);
+    }
+    let host = match req[3] {
+        1 => {
+            let mut ip = [0u8; 4];
+            sock.read_exact(&mut ip).await?;
+            std::net::Ipv4Addr::from(ip).to_string()
+        }
+        3 => {
+            let len = sock.read_u8().await?;
+            let mut name = vec![0u8; len as usize];
+            sock.read_exact(&mut name).await?;
+            String::from_utf8(name)?
+        }
+        4 => {
+            let mut ip = [0u8; 16];
+            sock.read_exact(&mut ip).await?;
+            std::net::Ipv6Addr::from(ip).to_string()
+        }
+        t => {
+            sock.write_all(&[SOCKS5_VERSION, 8, 0, 1, 0, 0, 0, 0, 0, 0])
+                .await?;
+            bail!("Unsupported SOCKS5 address type {}", t);
+        }
+    };
+    let port = sock.read_u16().await?;
+    Ok((host, port as _))
+}
+
+async fn socks5_reply(sock: &mut TcpStream, ok: bool) -> ResultType<()> {
+    use hbb_common::tokio::io::AsyncWriteExt;
+    // The bound address is not meaningful for a tunneled connection, report 0.0.0.0:0.
+    let rep = if ok { 0 } else { 5 };
+    sock.write_all(&[SOCKS5_VERSION, rep, 0, 1, 0, 0, 0, 0, 0, 0])
+        .await?;
+    Ok(())
+}
+
+/// Each local UDP peer address gets its own tunnel, closed after `UDP_SESSION_TIMEOUT` idle.
+async fn run_udp_rule<T, F>(
+    rule: ForwardRule,
+    config: ForwardConfig,
+    token: String,
+    statuses: Statuses,
+    new_interface: Arc<F>,
+) where
+    T: Interface,
+    F: Fn(&ForwardRule) -> (T, mpsc::UnboundedReceiver<Data>) + Send + Sync + 'static,
+{
+    let port = rule.local_port;
+    let mut listen_failures = 0;
+    let socket = loop {
+        match tokio::net::UdpSocket::bind(format!("0.0.0.0:{}", port)).await {
+            Ok(socket) => break Arc::new(socket),
+            Err(err) => {
+                log::error!("Failed to bind udp {}: {}", port, err);
+                update_status(&statuses, port, |s| {
+                    s.state = "error".to_owned();
+                    s.last_error = err.to_string();
+                });
+                tokio::time::sleep(backoff(listen_failures, config.max_backoff_secs)).await;
+                listen_failures += 1;
+            }
+        }
+    };
+    log::info!(
+        "port forward rule {} listening on udp {}, to {}:{}:{}",
+        rule.name,
+        port,
+        rule.id,
+        rule.remote_host,
+        rule.remote_port
+    );
+    update_status(&statuses, port, |s| s.state = "listening".to_owned());
+    let sessions: Arc<Mutex<HashMap<std::net::SocketAddr, mpsc::UnboundedSender<Vec<u8>>>>> =
+        Default::default();
+    let mut buf = vec![0u8; udp_forward::MAX_DATAGRAM_SIZE];
+    loop {
+        let (n, addr) = match socket.recv_from(&mut buf).await {
+            Ok(v) => v,
+            Err(err) => {
+                log::error!("Failed to receive on udp {}: {}", port, err);
+                continue;
+            }
+        };
+        let datagram = buf[..n].to_vec();
+        let datagram = match sessions.lock().unwrap().get(&addr) {
+            Some(tx) => match tx.send(datagram) {
+                Ok(_) => continue,
+                Err(err) => err.0,
+            },
+            None => datagram,
+        };
+        log::info!("new udp session from {:?} on {}", addr, port);
+        let (tx, mut rx) = mpsc::unbounded_channel::<Vec<u8>>();
+        tx.send(datagram).ok();
+        sessions.lock().unwrap().insert(addr, tx);
+        let rule = rule.clone();
+        let config = config.clone();
+        let token = token.clone();
+        let statuses = statuses.clone();
+        let new_interface = new_interface.clone();
+        let socket = socket.clone();
+        let sessions = sessions.clone();
+        tokio::spawn(async move {
+            let target = (
+                format!("{}{}", udp_forward::UDP_SCHEME, rule.remote_host),
+                rule.remote_port,
+            );
+            let stream = login_with_retry(
+                &rule,
+                &config,
+                &token,
+                &statuses,
+                new_interface.as_ref(),
+                target,
+                None,
+            )
+            .await;
+            if let Some(stream) = stream {
+                update_status(&statuses, port, |s| {
+                    s.state = "listening".to_owned();
+                    s.failures = 0;
+                    s.active += 1;
+                    s.total += 1;
+                });
+                if let Err(err) = run_udp_forward(&socket, addr, &mut rx, stream).await {
+                    update_status(&statuses, port, |s| s.last_error = err.to_string());
+                }
+                update_status(&statuses, port, |s| s.active -= 1);
+            } else {
+                update_status(&statuses, port, |s| s.state = "listening".to_owned());
+            }
+            sessions.lock().unwrap().remove(&addr);
+            log::info!("udp session from {:?} on {} closed", addr, port);
+        });
+    }
+}
+
+async fn run_udp_forward(
+    socket: &tokio::net::UdpSocket,
+    addr: std::net::SocketAddr,
+    rx: &mut mpsc::UnboundedReceiver<Vec<u8>>,
+    mut stream: Stream,
+) -> ResultType<()> {
+    let mut decoder = udp_forward::Decoder::default();
+    loop {
+        tokio::select! {
+            res = rx.recv() => {
+                if let Some(datagram) = res {
+                    stream.send_bytes(udp_forward::encode(&datagram).freeze()).await?;
+                } else {
+                    break;
+                }
+            },
+            res = stream.next() => {
+                if let Some(Ok(bytes)) = res {
+                    decoder.feed(&bytes);
+                    while let Some(datagram) = decoder.next_datagram() {
+                        allow_err!(socket.send_to(&datagram, addr).await);
+                    }
+                } else {
+                    break;
+                }
+            },
+            _ = tokio::time::sleep(udp_forward::UDP_SESSION_TIMEOUT) => {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn serve_status(statuses: Statuses) -> ResultType<()> {
+    let mut incoming = ipc::new_listener(DAEMON_IPC_POSTFIX).await?;
+    while let Some(result) = incoming.next().await {
+        match result {
+            Ok(stream) => {
+                let mut stream = ipc::Connection::new(stream);
+                let statuses = statuses.clone();
+                tokio::spawn(async move {
+                    while let Ok(Some(data)) = stream.next().await {
+                        if let ipc::Data::PortForwardRules(None) = data {
+                            let mut v: Vec<RuleStatus> =
+                                statuses.lock().unwrap().values().cloned().collect();
+                            v.sort_by_key(|s| s.local_port);
+                            allow_err!(stream.send(&ipc::Data::PortForwardRules(Some(v))).await);
+                        }
+                    }
+                });
+            }
+            Err(err) => {
+                log::error!("Couldn't get client: {:?}", err);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Query the rule status of a running port-forward daemon.
+#[tokio::main(flavor = "current_thread")]
+pub async fn get_daemon_status() -> ResultType<Vec<RuleStatus>> {
+    let mut c = ipc::connect(1000, DAEMON_IPC_POSTFIX).await?;
+    c.send(&ipc::Data::PortForwardRules(None)).await?;
+    if let Some(ipc::Data::PortForwardRules(Some(v))) = c.next_timeout(1000).await? {
+        return Ok(v);
+    }
+    bail!("No status from the port-forward daemon");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_socks5_handshake() {
+        use hbb_common::tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = tokio::spawn(async move {
+            let mut c = TcpStream::connect(addr).await.unwrap();
+            c.write_all(&[5, 1, 0]).await.unwrap();
+            let mut buf = [0u8; 2];
+            c.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, [5, 0]);
+            let mut req = vec![5, 1, 0, 3, 9];
+            req.extend(b"localhost");
+            req.extend(&22u16.to_be_bytes());
+            c.write_all(&req).await.unwrap();
+        });
+        let (mut sock, _) = listener.accept().await.unwrap();
+        let target = socks5_handshake(&mut sock).await.unwrap();
+        assert_eq!(target, ("localhost".to_owned(), 22));
+        client.await.unwrap();
+    }
+
+    #[test]
+    fn test_parse() {
+        let udp = r#"{"rules": [{"name": "dns", "id": "1", "local_port": 53, "remote_port": 53, "udp": true}]}"#;
+        assert!(ForwardConfig::parse(udp).is_ok());
+        let both = r#"{"rules": [{"name": "dns", "id": "1", "local_port": 53, "udp": true, "socks5": true}]}"#;
+        let err = ForwardConfig::parse(both).unwrap_err().to_string();
+        assert!(err.contains("dns"), "{err}");
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(0, 60), Duration::from_secs(1));
+        assert_eq!(backoff(3, 60), Duration::from_secs(8));
+        assert_eq!(backoff(10, 60), Duration::from_secs(60));
+        assert_eq!(backoff(100, 0), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,858 @@
+// Tool for the session recordings, see `USAGE`.
+
+#[path = "recording/demux.rs"]
+mod demux;
+
+use demux::{AudioInfo, Frame, Reader};
+use hbb_common::{
+    bail, chrono, log,
+    message_proto::Chroma,
+    serde_json::{self, Value},
+    ResultType,
+};
+use scrap::{
+    aom::{AomDecoder, AomEncoder, AomEncoderConfig},
+    codec::{EncoderApi, EncoderCfg},
+    webm::mux::{self, AudioTrack, Segment, Track, VideoTrack},
+    CodecFormat, EncodeYuvFormat, GoogleImage, Pixfmt, VpxDecoder, VpxDecoderConfig, VpxEncoder,
+    VpxEncoderConfig, VpxVideoCodecId, STRIDE_ALIGN,
+};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+const USAGE: &str = "Usage:
+  recording list [DIR]                   List the recordings in DIR grouped by session
+  recording info FILE                    Show the tracks, duration and indexed events of FILE
+  recording play FILE... [OPTIONS]       Play files side by side, needs ffplay of FFmpeg in PATH
+  recording concat OUT FILE...           Join files of the same codec and size, without re-encoding
+  recording cut IN OUT FROM [TO]         Keep FROM..TO, starting at the key frame before FROM, without re-encoding
+  recording stitch OUT FILE... [OPTIONS] Re-encode files side by side into OUT, e.g. the displays of a session
+  recording transcode IN OUT [OPTIONS]   Re-encode IN into OUT
+
+Options:
+  --from=TIME      Start time, [[hh:]mm:]ss[.ms]
+  --to=TIME        End time
+  --codec=CODEC    vp8, vp9 or av1 [default: vp9]
+  --quality=Q      Bitrate ratio of the encoder [default: 0.67]
+  --fps=FPS        Maximum frame rate of the output [default: 30]
+
+Files of one session are aligned by the time in their names.";
+
+const DEFAULT_QUALITY: f32 = 0.67;
+const DEFAULT_FPS: u32 = 30;
+// Gap put between joined files.
+const CONCAT_GAP_MS: i64 = 40;
+
+#[derive(Debug, Clone)]
+struct Options {
+    from: i64,
+    to: Option<i64>,
+    codec: CodecFormat,
+    quality: f32,
+    fps: u32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            from: 0,
+            to: None,
+            codec: CodecFormat::VP9,
+            quality: DEFAULT_QUALITY,
+            fps: DEFAULT_FPS,
+        }
+    }
+}
+
+fn main() {
+    let mut opts = Options::default();
+    let mut args = vec![];
+    for arg in std::env::args().skip(1) {
+        let res = match arg.split_once('=') {
+            Some(("--from", v)) => parse_time(v).map(|v| opts.from = v),
+            Some(("--to", v)) => parse_time(v).map(|v| opts.to = Some(v)),
+            Some(("--codec", v)) => parse_codec(v).map(|v| opts.codec = v),
+            Some(("--quality", v)) => v.parse().map(|v| opts.quality = v).map_err(|e| e.into()),
+            Some(("--fps", v)) => v.parse().map(|v| opts.fps = v).map_err(|e| e.into()),
+            _ if arg.starts_with("--") => {
+                Err(hbb_common::anyhow::anyhow!("Unknown option {}", arg))
+            }
+            _ => {
+                args.push(arg);
+                Ok(())
+            }
+        };
+        if let Err(e) = res {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(1);
+        }
+    }
+    let args: Vec<&str> = args.iter().map(|x| x.as_str()).collect();
+    let res = match args.as_slice() {
+        ["list"] => list("."),
+        ["list", dir] => list(dir),
+        ["info", file] => info(file),
+        ["play", files @ ..] if !files.is_empty() => play(files, &opts),
+        ["concat", out, files @ ..] if !files.is_empty() => concat(out, files),
+        ["cut", input, out, from, to @ ..] if to.len() <= 1 => {
+            let to = to.first().map(|to| parse_time(to)).transpose();
+            match (parse_time(from), to) {
+                (Ok(from), Ok(to)) => cut(input, out, from, to),
+                (Err(e), _) | (_, Err(e)) => {
+                    eprintln!("{}\n\n{}", e, USAGE);
+                    std::process::exit(1);
+                }
+            }
+        }
+        ["stitch", out, files @ ..] if !files.is_empty() => stitch(out, files, &opts),
+        ["transcode", input, out] => stitch(out, &[*input], &opts),
+        _ => {
+            println!("{}", USAGE);
+            return;
+        }
+    };
+    if let Err(e) = res {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn parse_time(s: &str) -> ResultType<i64> {
+    let mut secs = 0.;
+    for part in s.split(':') {
+        let Ok(v) = part.parse::<f64>() else {
+            bail!("Invalid time {}, expected [[hh:]mm:]ss[.ms]", s);
+        };
+        if v < 0. {
+            bail!("Invalid time {}", s);
+        }
+        secs = secs * 60. + v;
+    }
+    Ok((secs * 1000.) as _)
+}
+
+fn fmt_time(ms: i64) -> String {
+    let s = ms / 1000;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        s / 3600,
+        s / 60 % 60,
+        s % 60,
+        ms % 1000
+    )
+}
+
+fn parse_codec(s: &str) -> ResultType<CodecFormat> {
+    Ok(match s.to_lowercase().as_str() {
+        "vp8" => CodecFormat::VP8,
+        "vp9" => CodecFormat::VP9,
+        "av1" => CodecFormat::AV1,
+        _ => bail!("Unsupported codec {}", s),
+    })
+}
+
+fn codec_of(codec_id: &str) -> ResultType<CodecFormat> {
+    Ok(match codec_id {
+        "V_VP8" => CodecFormat::VP8,
+        "V_VP9" => CodecFormat::VP9,
+        "V_AV1" => CodecFormat::AV1,
+        _ => bail!(
+            "Unsupported codec {}, only webm recordings are supported",
+            codec_id
+        ),
+    })
+}
+
+/// Session prefix `incoming_<id>_<time>` and the rest of a recording file name.
+fn split_name(name: &str) -> Option<(&str, &str)> {
+    if !name.starts_with("incoming_") && !name.starts_with("outgoing_") {
+        return None;
+    }
+    // The time is the last `_<17 digits>_`, ids may contain `_`.
+    let bytes = name.as_bytes();
+    (0..name.len().saturating_sub(18)).rev().find_map(|i| {
+        let digits = &bytes[i + 1..i + 18];
+        if bytes[i] == b'_'
+            && bytes.get(i + 18) == Some(&b'_')
+            && digits.iter().all(|c| c.is_ascii_digit())
+        {
+            Some((&name[..i + 18], &name[i + 19..]))
+        } else {
+            None
+        }
+    })
+}
+
+/// Start time in milliseconds from the file name, to align the files of a session.
+fn time_of(path: &str) -> Option<i64> {
+    let name = Path::new(path).file_name()?.to_str()?;
+    let (prefix, _) = split_name(name)?;
+    let digits = &prefix[prefix.len() - 17..];
+    let t = chrono::NaiveDateTime::parse_from_str(&digits[..14], "%Y%m%d%H%M%S").ok()?;
+    Some(t.and_utc().timestamp_millis() + digits[14..].parse::<i64>().ok()?)
+}
+
+fn index_of(path: &str) -> Vec<Value> {
+    std::fs::read_to_string(scrap::record::index_path(path))
+        .map(|s| {
+            s.lines()
+                .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn write_index(path: &str, events: Vec<Value>) -> ResultType<()> {
+    if events.is_empty() {
+        return Ok(());
+    }
+    let mut lines = String::new();
+    for event in events {
+        lines.push_str(&event.to_string());
+        lines.push('\n');
+    }
+    std::fs::write(scrap::record::index_path(path), lines)?;
+    Ok(())
+}
+
+// Events of `path` within `from..to`, moved by `offset`.
+fn shift_index(path: &str, from: i64, to: Option<i64>, offset: i64) -> Vec<Value> {
+    index_of(path)
+        .into_iter()
+        .filter_map(|mut e| {
+            let t = e["time_ms"].as_i64()?;
+            if t < from || to.map_or(false, |to| t > to) {
+                return None;
+            }
+            e["time_ms"] = (t - from + offset).into();
+            Some(e)
+        })
+        .collect()
+}
+
+fn list(dir: &str) -> ResultType<()> {
+    let mut sessions: BTreeMap<String, Vec<(String, u64)>> = BTreeMap::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if let Some((session, rest)) = split_name(&name) {
+            let len = entry.metadata().map(|m| m.len()).unwrap_or_default();
+            sessions
+                .entry(session.to_owned())
+                .or_default()
+                .push((rest.to_owned(), len));
+        }
+    }
+    for (session, mut files) in sessions {
+        println!("{}", session);
+        files.sort();
+        for (rest, len) in files {
+            println!("  {:<32} {:>12}", rest, len);
+        }
+    }
+    Ok(())
+}
+
+fn info(path: &str) -> ResultType<()> {
+    let mut reader = Reader::open(path)?;
+    let video = reader.video.clone().unwrap_or_default();
+    let (mut frames, mut keys, mut packets) = (0, 0, 0);
+    let (mut first, mut last) = (None, 0);
+    while let Some(frame) = reader.next_frame()? {
+        first.get_or_insert(frame.ts);
+        last = last.max(frame.ts);
+        if reader.is_video(&frame) {
+            frames += 1;
+            if frame.key {
+                keys += 1;
+            }
+        } else {
+            packets += 1;
+        }
+    }
+    let duration = last - first.unwrap_or_default();
+    println!("file:     {}", path);
+    println!("video:    {} {}x{}", video.codec, video.width, video.height);
+    println!("frames:   {} ({} key frames)", frames, keys);
+    match &reader.audio {
+        Some(a) => println!(
+            "audio:    {} {} Hz, {} channels, {} packets",
+            a.codec, a.sample_rate, a.channels, packets
+        ),
+        None => println!("audio:    none"),
+    }
+    println!("duration: {}", fmt_time(duration));
+    let events = index_of(path);
+    if !events.is_empty() {
+        println!("events:");
+        for e in events {
+            println!(
+                "  {}  {:<14} {}",
+                fmt_time(e["time_ms"].as_i64().unwrap_or_default()),
+                e["kind"].as_str().unwrap_or_default(),
+                e["detail"].as_str().unwrap_or_default()
+            );
+        }
+    }
+    Ok(())
+}
+
+struct Output {
+    webm: Option<Segment<mux::Writer<File>>>,
+    vt: VideoTrack,
+    at: Option<AudioTrack>,
+    written: bool,
+}
+
+impl Output {
+    fn create(
+        path: &str,
+        codec: CodecFormat,
+        width: usize,
+        height: usize,
+        audio: Option<&AudioInfo>,
+    ) -> ResultType<Self> {
+        let mut webm = match Segment::new(mux::Writer::new(File::create(path)?)) {
+            Some(v) => v,
+            None => bail!("Failed to create webm mux"),
+        };
+        let vt = webm.add_video_track(
+            width as _,
+            height as _,
+            None,
+            match codec {
+                CodecFormat::VP8 => mux::VideoCodecId::VP8,
+                CodecFormat::VP9 => mux::VideoCodecId::VP9,
+                _ => mux::VideoCodecId::AV1,
+            },
+        );
+        if codec == CodecFormat::AV1 && !webm.set_codec_private(vt.track_number(), &[0, 0, 0, 0]) {
+            bail!("Failed to set codec private");
+        }
+        let mut at = None;
+        if let Some(a) = audio.filter(|a| a.codec == "A_OPUS") {
+            let track = webm.add_audio_track(
+                a.sample_rate as _,
+                a.channels as _,
+                None,
+                mux::AudioCodecId::Opus,
+            );
+            if !a.codec_private.is_empty()
+                && !webm.set_codec_private(track.track_number(), &a.codec_private)
+            {
+                bail!("Failed to set audio codec private");
+            }
+            at = Some(track);
+        }
+        Ok(Self {
+            webm: Some(webm),
+            vt,
+            at,
+            written: false,
+        })
+    }
+
+    fn write(&mut self, video: bool, data: &[u8], ts: i64, key: bool) {
+        let ts = ts.max(0) as u64 * 1_000_000;
+        let ok = if video {
+            self.vt.add_frame(data, ts, key)
+        } else {
+            self.at
+                .as_mut()
+                .map(|at| at.add_frame(data, ts, true))
+                .unwrap_or(true)
+        };
+        if ok {
+            self.written |= video;
+        } else {
+            log::warn!(
+                "Failed to write a frame at {}",
+                fmt_time(ts as i64 / 1_000_000)
+            );
+        }
+    }
+
+    fn finish(mut self) -> ResultType<()> {
+        if let Some(webm) = self.webm.take() {
+            if !webm.finalize(None) {
+                bail!("Failed to finalize the output");
+            }
+        }
+        if !self.written {
+            bail!("Nothing was written");
+        }
+        Ok(())
+    }
+}
+
+fn concat(out: &str, files: &[&str]) -> ResultType<()> {
+    let readers = files
+        .iter()
+        .map(Reader::open)
+        .collect::<ResultType<Vec<_>>>()?;
+    let video = readers[0].video.clone().unwrap_or_default();
+    for (r, f) in readers.iter().zip(files) {
+        let v = r.video.clone().unwrap_or_default();
+        if v.codec != video.codec || v.width != video.width || v.height != video.height {
+            bail!(
+                "{} is {} {}x{}, use stitch to re-encode",
+                f,
+                v.codec,
+                v.width,
+                v.height
+            );
+        }
+    }
+    // Keep audio only if every file has the same audio format.
+    let audio = readers[0].audio.clone().filter(|a| {
+        readers.iter().all(|r| {
+            r.audio.as_ref().map(|b| (b.sample_rate, b.channels))
+                == Some((a.sample_rate, a.channels))
+        })
+    });
+    let mut output = Output::create(
+        out,
+        codec_of(&video.codec)?,
+        video.width,
+        video.height,
+        audio.as_ref(),
+    )?;
+    let mut offset = 0;
+    let mut events = vec![];
+    for (mut reader, file) in readers.into_iter().zip(files) {
+        let mut first = None;
+        let mut last = offset;
+        while let Some(frame) = reader.next_frame()? {
+            let first = *first.get_or_insert(frame.ts);
+            let ts = frame.ts - first + offset;
+            last = last.max(ts);
+            output.write(reader.is_video(&frame), &frame.data, ts, frame.key);
+        }
+        events.extend(shift_index(file, 0, None, offset));
+        offset = last + CONCAT_GAP_MS;
+    }
+    output.finish()?;
+    write_index(out, events)
+}
+
+fn cut(input: &str, out: &str, from: i64, to: Option<i64>) -> ResultType<()> {
+    if from < 0 || to.map_or(false, |to| to <= from) {
+        bail!("Invalid time range");
+    }
+    // The output must start with a key frame.
+    let mut reader = Reader::open(input)?;
+    let mut first = None;
+    let mut start = 0;
+    while let Some(frame) = reader.next_frame()? {
+        let first = *first.get_or_insert(frame.ts);
+        let t = frame.ts - first;
+        if t > from {
+            break;
+        }
+        if frame.key && reader.is_video(&frame) {
+            start = t;
+        }
+    }
+    let mut reader = Reader::open(input)?;
+    let video = reader.video.clone().unwrap_or_default();
+    let mut output = Output::create(
+        out,
+        codec_of(&video.codec)?,
+        video.width,
+        video.height,
+        reader.audio.as_ref(),
+    )?;
+    let mut first = None;
+    while let Some(frame) = reader.next_frame()? {
+        let first = *first.get_or_insert(frame.ts);
+        let t = frame.ts - first;
+        if t < start {
+            continue;
+        }
+        if to.map_or(false, |to| t > to) {
+            break;
+        }
+        output.write(reader.is_video(&frame), &frame.data, t - start, frame.key);
+    }
+    output.finish()?;
+    if start < from {
+        println!("Cut from the key frame at {}", fmt_time(start));
+    }
+    write_index(out, shift_index(input, start, to, 0))
+}
+
+enum Decoder {
+    Vpx(VpxDecoder),
+    Aom(AomDecoder),
+}
+
+impl Decoder {
+    fn new(codec: CodecFormat) -> ResultType<Self> {
+        Ok(match codec {
+            CodecFormat::VP8 => Self::Vpx(VpxDecoder::new(VpxDecoderConfig {
+                codec: VpxVideoCodecId::VP8,
+            })?),
+            CodecFormat::VP9 => Self::Vpx(VpxDecoder::new(VpxDecoderConfig {
+                codec: VpxVideoCodecId::VP9,
+            })?),
+            _ => Self::Aom(AomDecoder::new()?),
+        })
+    }
+
+    // Decode `data` and draw the last image into `region` of `canvas`.
+    fn decode(&mut self, data: &[u8], canvas: &mut Canvas, region: usize) -> ResultType<()> {
+        match self {
+            Self::Vpx(d) => {
+                if let Some(img) = d.decode(data)?.last() {
+                    canvas.draw(&img, region)?;
+                }
+            }
+            Self::Aom(d) => {
+                if let Some(img) = d.decode(data)?.last() {
+                    canvas.draw(&img, region)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+enum Encoder {
+    Vpx(VpxEncoder),
+    Aom(AomEncoder),
+}
+
+impl Encoder {
+    fn new(codec: CodecFormat, width: usize, height: usize, opts: &Options) -> ResultType<Self> {
+        let keyframe_interval = Some(opts.fps as usize * 5);
+        Ok(match codec {
+            CodecFormat::VP8 | CodecFormat::VP9 => Self::Vpx(VpxEncoder::new(
+                EncoderCfg::VPX(VpxEncoderConfig {
+                    width: width as _,
+                    height: height as _,
+                    quality: opts.quality,
+                    codec: if codec == CodecFormat::VP8 {
+                        VpxVideoCodecId::VP8
+                    } else {
+                        VpxVideoCodecId::VP9
+                    },
+                    keyframe_interval,
+                }),
+                false,
+            )?),
+            _ => Self::Aom(AomEncoder::new(
+                EncoderCfg::AOM(AomEncoderConfig {
+                    width: width as _,
+                    height: height as _,
+                    quality: opts.quality,
+                    keyframe_interval,
+                }),
+                false,
+            )?),
+        })
+    }
+
+    fn yuvfmt(&self) -> EncodeYuvFormat {
+        match self {
+            Self::Vpx(e) => e.yuvfmt(),
+            Self::Aom(e) => e.yuvfmt(),
+        }
+    }
+
+    fn encode(&mut self, ms: i64, yuv: &[u8], output: &mut Output) -> ResultType<()> {
+        match self {
+            Self::Vpx(e) => {
+                for f in e.encode(ms, yuv, STRIDE_ALIGN)? {
+                    output.write(true, f.data, f.pts, f.key);
+                }
+            }
+            Self::Aom(e) => {
+                for f in e.encode(ms, yuv, STRIDE_ALIGN)? {
+                    output.write(true, f.data, f.pts, f.key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self, output: &mut Output) -> ResultType<()> {
+        if let Self::Vpx(e) = self {
+            for f in e.flush()? {
+                output.write(true, f.data, f.pts, f.key);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An I420 image made of the inputs side by side.
+struct Canvas {
+    fmt: EncodeYuvFormat,
+    yuv: Vec<u8>,
+    // x and size of each input
+    regions: Vec<(usize, usize, usize)>,
+}
+
+impl Canvas {
+    // Width and height of the canvas holding images of `sizes`, all even.
+    fn size_of(sizes: &[(usize, usize)]) -> (usize, usize) {
+        let w = sizes.iter().map(|(w, _)| (w + 1) & !1).sum();
+        let h = sizes
+            .iter()
+            .map(|(_, h)| (h + 1) & !1)
+            .max()
+            .unwrap_or_default();
+        (w, h)
+    }
+
+    fn new(fmt: EncodeYuvFormat, sizes: &[(usize, usize)]) -> ResultType<Self> {
+        if fmt.pixfmt != Pixfmt::I420 {
+            bail!("Only I420 is supported");
+        }
+        let mut regions = vec![];
+        let mut x = 0;
+        for (w, h) in sizes {
+            regions.push((x, *w, *h));
+            x += (w + 1) & !1;
+        }
+        let len = fmt.v + fmt.stride[2] * ((fmt.h + 1) / 2);
+        let mut yuv = vec![16u8; len];
+        yuv[fmt.u..].fill(128);
+        Ok(Self { fmt, yuv, regions })
+    }
+
+    // A tightly packed I420 canvas, as ffplay reads it.
+    fn packed(sizes: &[(usize, usize)]) -> ResultType<Self> {
+        let (w, h) = Self::size_of(sizes);
+        let u = w * h;
+        let fmt = EncodeYuvFormat {
+            pixfmt: Pixfmt::I420,
+            w,
+            h,
+            stride: vec![w, w / 2, w / 2],
+            u,
+            v: u + u / 4,
+        };
+        Self::new(fmt, sizes)
+    }
+
+    fn draw(&mut self, img: &impl GoogleImage, region: usize) -> ResultType<()> {
+        if img.chroma() != Chroma::I420 {
+            bail!("Only I420 recordings are supported");
+        }
+        let (x, w, h) = self.regions[region];
+        let w = w.min(img.width());
+        let h = h.min(img.height()).min(self.fmt.h);
+        let stride = img.stride();
+        let (y, u, v) = img.data();
+        let fmt = &self.fmt;
+        for row in 0..h {
+            let src = &y[row * stride[0] as usize..][..w];
+            self.yuv[row * fmt.stride[0] + x..][..w].copy_from_slice(src);
+        }
+        let (cx, cw, ch) = (x / 2, (w + 1) / 2, (h + 1) / 2);
+        for row in 0..ch {
+            let src = &u[row * stride[1] as usize..][..cw];
+            self.yuv[fmt.u + row * fmt.stride[1] + cx..][..cw].copy_from_slice(src);
+            let src = &v[row * stride[2] as usize..][..cw];
+            self.yuv[fmt.v + row * fmt.stride[2] + cx..][..cw].copy_from_slice(src);
+        }
+        Ok(())
+    }
+}
+
+struct Input {
+    reader: Reader,
+    decoder: Decoder,
+    next: Option<Frame>,
+    // Added to the frame time to get the time of the output.
+    base: i64,
+}
+
+// Open `files` on a common timeline aligned by the time in their names.
+fn open_inputs(files: &[&str]) -> ResultType<Vec<Input>> {
+    let start = files.iter().filter_map(|f| time_of(f)).min();
+    let mut inputs = vec![];
+    for file in files {
+        let mut reader = Reader::open(file)?;
+        let codec = codec_of(
+            &reader
+                .video
+                .as_ref()
+                .map(|v| v.codec.clone())
+                .unwrap_or_default(),
+        )?;
+        let next = reader.next_frame()?;
+        let offset = match (start, time_of(file)) {
+            (Some(start), Some(t)) => t - start,
+            _ => 0,
+        };
+        inputs.push(Input {
+            base: offset - next.as_ref().map(|f| f.ts).unwrap_or_default(),
+            decoder: Decoder::new(codec)?,
+            reader,
+            next,
+        });
+    }
+    Ok(inputs)
+}
+
+/// Read the frames of all inputs in time order, until `to`.
+/// `f` gets the input index, the frame and its time in the output.
+fn merge(
+    inputs: &mut [Input],
+    to: Option<i64>,
+    mut f: impl FnMut(&mut Input, usize, Frame, i64) -> ResultType<()>,
+) -> ResultType<()> {
+    loop {
+        let Some((i, t)) = inputs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, input)| input.next.as_ref().map(|f| (i, f.ts + input.base)))
+            .min_by_key(|(_, t)| *t)
+        else {
+            return Ok(());
+        };
+        if to.map_or(false, |to| t > to) {
+            return Ok(());
+        }
+        let input = &mut inputs[i];
+        let frame = input.next.take().unwrap_or_else(|| unreachable!());
+        input.next = input.reader.next_frame()?;
+        f(input, i, frame, t)?;
+    }
+}
+
+fn sizes_of(inputs: &[Input]) -> Vec<(usize, usize)> {
+    inputs
+        .iter()
+        .map(|i| {
+            let v = i.reader.video.clone().unwrap_or_default();
+            (v.width, v.height)
+        })
+        .collect()
+}
+
+fn stitch(out: &str, files: &[&str], opts: &Options) -> ResultType<()> {
+    let mut inputs = open_inputs(files)?;
+    let sizes = sizes_of(&inputs);
+    let (w, h) = Canvas::size_of(&sizes);
+    let mut encoder = Encoder::new(opts.codec, w, h, opts)?;
+    let mut canvas = Canvas::new(encoder.yuvfmt(), &sizes)?;
+    // Audio of the first input that has it.
+    let audio_input = inputs.iter().position(|i| i.reader.audio.is_some());
+    let audio = audio_input.and_then(|i| inputs[i].reader.audio.clone());
+    let mut output = Output::create(out, opts.codec, w, h, audio.as_ref())?;
+    let interval = 1000 / opts.fps.max(1) as i64;
+    let mut last_encoded: Option<i64> = None;
+    let mut dirty = false;
+    let mut last = opts.from;
+    merge(&mut inputs, opts.to, |input, i, frame, t| {
+        if !input.reader.is_video(&frame) {
+            if Some(i) == audio_input && t >= opts.from {
+                output.write(false, &frame.data, t - opts.from, true);
+            }
+            return Ok(());
+        }
+        // Frames before `from` are decoded as the reference of later frames.
+        input.decoder.decode(&frame.data, &mut canvas, i)?;
+        dirty = true;
+        last = last.max(t);
+        if t >= opts.from && last_encoded.map_or(true, |l| t - l >= interval) {
+            encoder.encode(t - opts.from, &canvas.yuv, &mut output)?;
+            last_encoded = Some(t);
+            dirty = false;
+        }
+        Ok(())
+    })?;
+    if dirty && last >= opts.from {
+        encoder.encode(last - opts.from, &canvas.yuv, &mut output)?;
+    }
+    encoder.flush(&mut output)?;
+    output.finish()?;
+    // The index of the first file is kept.
+    write_index(out, shift_index(files[0], opts.from, opts.to, 0))
+}
+
+fn play(files: &[&str], opts: &Options) -> ResultType<()> {
+    let mut inputs = open_inputs(files)?;
+    let sizes = sizes_of(&inputs);
+    let mut canvas = Canvas::packed(&sizes)?;
+    let mut ffplay = Command::new("ffplay")
+        .args([
+            "-loglevel",
+            "error",
+            "-f",
+            "rawvideo",
+            "-pixel_format",
+            "yuv420p",
+            "-video_size",
+            &format!("{}x{}", canvas.fmt.w, canvas.fmt.h),
+            "-framerate",
+            &opts.fps.to_string(),
+            "-window_title",
+            files[0],
+            "-",
+        ])
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => hbb_common::anyhow::anyhow!(
+                "ffplay is not found, play needs ffplay of FFmpeg (https://ffmpeg.org) in PATH"
+            ),
+            _ => hbb_common::anyhow::anyhow!("Failed to start ffplay: {}", e),
+        })?;
+    let Some(mut stdin) = ffplay.stdin.take() else {
+        bail!("Failed to open the stdin of ffplay");
+    };
+    // The canvas is written at a constant frame rate, so ffplay plays at the real speed.
+    let interval = 1000 / opts.fps.max(1) as i64;
+    let mut next_out = opts.from;
+    let res = merge(&mut inputs, opts.to, |input, i, frame, t| {
+        if !input.reader.is_video(&frame) {
+            return Ok(());
+        }
+        while next_out <= t {
+            stdin.write_all(&canvas.yuv)?;
+            next_out += interval;
+        }
+        input.decoder.decode(&frame.data, &mut canvas, i)
+    });
+    drop(stdin);
+    // A closed ffplay window breaks the pipe.
+    ffplay.wait().ok();
+    match res {
+        Err(e)
+            if e.downcast_ref::<std::io::Error>().map(|e| e.kind())
+                == Some(std::io::ErrorKind::BrokenPipe) =>
+        {
+            Ok(())
+        }
+        res => res,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names() {
+        let name = "incoming_1_2_20240102030405678_display0_vp9.webm";
+        assert_eq!(
+            split_name(name),
+            Some(("incoming_1_2_20240102030405678", "display0_vp9.webm"))
+        );
+        assert_eq!(split_name("other_20240102030405678_x"), None);
+        let t0 = time_of("/a/incoming_1_20240102030405678_display0_vp9.webm").unwrap();
+        let t1 = time_of("incoming_1_20240102030406000_display1_vp9.webm").unwrap();
+        assert_eq!(t1 - t0, 322);
+        assert_eq!(parse_time("1:02.5").unwrap(), 62_500);
+        assert_eq!(fmt_time(3_723_004), "01:02:03.004");
+    }
+}
@@ -0,0 +1,407 @@
+// A minimal WebM reader for the files written by `scrap::record::WebmRecorder`.
+// Only what the recorder writes is supported: one video track, an optional opus track,
+// SimpleBlock or BlockGroup frames without lacing.
+// https://www.matroska.org/technical/elements.html
+
+use hbb_common::{bail, log, ResultType};
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+const ID_EBML: u32 = 0x1A45DFA3;
+const ID_SEGMENT: u32 = 0x18538067;
+const ID_INFO: u32 = 0x1549A966;
+const ID_TIMESTAMP_SCALE: u32 = 0x2AD7B1;
+const ID_DURATION: u32 = 0x4489;
+const ID_TRACKS: u32 = 0x1654AE6B;
+const ID_TRACK_ENTRY: u32 = 0xAE;
+const ID_TRACK_NUMBER: u32 = 0xD7;
+const ID_TRACK_TYPE: u32 = 0x83;
+const ID_CODEC_ID: u32 = 0x86;
+const ID_CODEC_PRIVATE: u32 = 0x63A2;
+const ID_VIDEO: u32 = 0xE0;
+const ID_PIXEL_WIDTH: u32 = 0xB0;
+const ID_PIXEL_HEIGHT: u32 = 0xBA;
+const ID_AUDIO: u32 = 0xE1;
+const ID_SAMPLING_FREQUENCY: u32 = 0xB5;
+const ID_CHANNELS: u32 = 0x9F;
+const ID_CLUSTER: u32 = 0x1F43B675;
+const ID_CLUSTER_TIMESTAMP: u32 = 0xE7;
+const ID_SIMPLE_BLOCK: u32 = 0xA3;
+const ID_BLOCK_GROUP: u32 = 0xA0;
+const ID_BLOCK: u32 = 0xA1;
+const ID_REFERENCE_BLOCK: u32 = 0xFB;
+
+const TRACK_TYPE_VIDEO: u64 = 1;
+const TRACK_TYPE_AUDIO: u64 = 2;
+
+// Size of an element whose size is unknown, it ends at the next element of an upper level.
+const UNKNOWN_SIZE: u64 = u64::MAX;
+
+#[derive(Debug, Clone, Default)]
+pub struct VideoInfo {
+    pub track: u64,
+    pub codec: String,
+    pub width: usize,
+    pub height: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AudioInfo {
+    pub track: u64,
+    pub codec: String,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub codec_private: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub track: u64,
+    /// Milliseconds.
+    pub ts: i64,
+    pub key: bool,
+    pub data: Vec<u8>,
+}
+
+pub struct Reader {
+    r: BufReader<File>,
+    // Position in the file, `stream_position` would drop the read buffer.
+    pos: u64,
+    len: u64,
+    timestamp_scale: u64,
+    pub duration_ms: Option<f64>,
+    pub video: Option<VideoInfo>,
+    pub audio: Option<AudioInfo>,
+    // End of the current cluster, None if not in a cluster.
+    cluster_end: Option<u64>,
+    cluster_ts: i64,
+    segment_end: u64,
+    // The first cluster is found while reading the headers.
+    pending_cluster: Option<(u64, u64)>,
+}
+
+impl Reader {
+    pub fn open(path: impl AsRef<Path>) -> ResultType<Self> {
+        let file = File::open(path.as_ref())?;
+        let len = file.metadata()?.len();
+        let mut reader = Self {
+            r: BufReader::new(file),
+            pos: 0,
+            len,
+            timestamp_scale: 1_000_000,
+            duration_ms: None,
+            video: None,
+            audio: None,
+            cluster_end: None,
+            cluster_ts: 0,
+            segment_end: len,
+            pending_cluster: None,
+        };
+        reader.read_headers()?;
+        if reader.video.is_none() {
+            bail!("No video track in {}", path.as_ref().display());
+        }
+        Ok(reader)
+    }
+
+    fn read_headers(&mut self) -> ResultType<()> {
+        let (id, size) = self.read_element_header()?;
+        if id != ID_EBML {
+            bail!("Not a webm file");
+        }
+        self.skip(size)?;
+        let (id, size) = self.read_element_header()?;
+        if id != ID_SEGMENT {
+            bail!("No segment");
+        }
+        let start = self.pos;
+        self.segment_end = end_of(start, size, self.len);
+        while self.pos < self.segment_end {
+            let (id, size) = self.read_element_header()?;
+            let start = self.pos;
+            match id {
+                ID_INFO => self.read_info(end_of(start, size, self.segment_end))?,
+                ID_TRACKS => self.read_tracks(end_of(start, size, self.segment_end))?,
+                ID_CLUSTER => {
+                    self.pending_cluster = Some((start, end_of(start, size, self.segment_end)));
+                    return Ok(());
+                }
+                _ => self.skip(size)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn read_info(&mut self, end: u64) -> ResultType<()> {
+        let mut duration = None;
+        while self.pos < end {
+            let (id, size) = self.read_element_header()?;
+            match id {
+                ID_TIMESTAMP_SCALE => self.timestamp_scale = self.read_uint(size)?,
+                ID_DURATION => duration = Some(self.read_float(size)?),
+                _ => self.skip(size)?,
+            }
+        }
+        // Duration is in timestamp scale units.
+        self.duration_ms = duration.map(|d| d * self.timestamp_scale as f64 / 1_000_000.);
+        Ok(())
+    }
+
+    fn read_tracks(&mut self, end: u64) -> ResultType<()> {
+        while self.pos < end {
+            let (id, size) = self.read_element_header()?;
+            if id != ID_TRACK_ENTRY {
+                self.skip(size)?;
+                continue;
+            }
+            let entry_end = self.pos + size;
+            let (mut number, mut track_type, mut codec) = (0, 0, String::new());
+            let (mut width, mut height, mut sample_rate, mut channels) = (0, 0, 0., 1);
+            let mut codec_private = vec![];
+            while self.pos < entry_end {
+                let (id, size) = self.read_element_header()?;
+                match id {
+                    ID_TRACK_NUMBER => number = self.read_uint(size)?,
+                    ID_TRACK_TYPE => track_type = self.read_uint(size)?,
+                    ID_CODEC_ID => codec = String::from_utf8_lossy(&self.read_bytes(size)?).into(),
+                    ID_CODEC_PRIVATE => codec_private = self.read_bytes(size)?,
+                    ID_VIDEO => {
+                        let video_end = self.pos + size;
+                        while self.pos < video_end {
+                            let (id, size) = self.read_element_header()?;
+                            match id {
+                                ID_PIXEL_WIDTH => width = self.read_uint(size)?,
+                                ID_PIXEL_HEIGHT => height = self.read_uint(size)?,
+                                _ => self.skip(size)?,
+                            }
+                        }
+                    }
+                    ID_AUDIO => {
+                        let audio_end = self.pos + size;
+                        while self.pos < audio_end {
+                            let (id, size) = self.read_element_header()?;
+                            match id {
+                                ID_SAMPLING_FREQUENCY => sample_rate = self.read_float(size)?,
+                                ID_CHANNELS => channels = self.read_uint(size)?,
+                                _ => self.skip(size)?,
+                            }
+                        }
+                    }
+                    _ => self.skip(size)?,
+                }
+            }
+            match track_type {
+                TRACK_TYPE_VIDEO if self.video.is_none() => {
+                    self.video = Some(VideoInfo {
+                        track: number,
+                        codec,
+                        width: width as _,
+                        height: height as _,
+                    })
+                }
+                TRACK_TYPE_AUDIO if self.audio.is_none() => {
+                    self.audio = Some(AudioInfo {
+                        track: number,
+                        codec,
+                        sample_rate: sample_rate as _,
+                        channels: channels as _,
+                        codec_private,
+                    })
+                }
+                _ => log::debug!("Ignore track {} of type {}", number, track_type),
+            }
+        }
+        Ok(())
+    }
+
+    /// The next frame of the video or audio track, None at the end of the file.
+    pub fn next_frame(&mut self) -> ResultType<Option<Frame>> {
+        loop {
+            if let Some((start, end)) = self.pending_cluster.take() {
+                self.r.seek(SeekFrom::Start(start))?;
+                self.pos = start;
+                self.cluster_end = Some(end);
+                self.cluster_ts = 0;
+            }
+            let pos = self.pos;
+            if pos >= self.segment_end {
+                return Ok(None);
+            }
+            let (id, size) = match self.read_element_header() {
+                Ok(x) => x,
+                // A recording that was not finalized may end in the middle of an element.
+                Err(_) => return Ok(None),
+            };
+            let start = self.pos;
+            let in_cluster = self.cluster_end.map_or(false, |end| pos < end);
+            match id {
+                ID_CLUSTER => {
+                    self.cluster_end = Some(end_of(start, size, self.segment_end));
+                    self.cluster_ts = 0;
+                }
+                ID_CLUSTER_TIMESTAMP if in_cluster => self.cluster_ts = self.read_uint(size)? as _,
+                ID_SIMPLE_BLOCK if in_cluster => {
+                    if let Some(frame) = self.read_block(size, None)? {
+                        return Ok(Some(frame));
+                    }
+                }
+                ID_BLOCK_GROUP if in_cluster => {
+                    let group_end = start + size;
+                    let mut block = None;
+                    let mut key = true;
+                    while self.pos < group_end {
+                        let (id, size) = self.read_element_header()?;
+                        match id {
+                            ID_BLOCK => block = Some(self.read_bytes(size)?),
+                            ID_REFERENCE_BLOCK => {
+                                key = false;
+                                self.skip(size)?;
+                            }
+                            _ => self.skip(size)?,
+                        }
+                    }
+                    if let Some(block) = block {
+                        if let Some(frame) = self.parse_block(block, Some(key))? {
+                            return Ok(Some(frame));
+                        }
+                    }
+                }
+                _ => {
+                    if size == UNKNOWN_SIZE {
+                        bail!("Unknown size of element {:X}", id);
+                    }
+                    self.skip(size)?;
+                }
+            }
+        }
+    }
+
+    fn read_block(&mut self, size: u64, key: Option<bool>) -> ResultType<Option<Frame>> {
+        let block = self.read_bytes(size)?;
+        self.parse_block(block, key)
+    }
+
+    fn parse_block(&self, block: Vec<u8>, key: Option<bool>) -> ResultType<Option<Frame>> {
+        let (track, pos) = read_vint(&mut &block[..], false)?;
+        if block.len() < pos + 3 {
+            bail!("Invalid block");
+        }
+        let rel = i16::from_be_bytes([block[pos], block[pos + 1]]);
+        let flags = block[pos + 2];
+        if flags & 0x06 != 0 {
+            log::warn!("Skip laced block of track {}", track);
+            return Ok(None);
+        }
+        let is_video = self.video.as_ref().map(|v| v.track) == Some(track);
+        let is_audio = self.audio.as_ref().map(|a| a.track) == Some(track);
+        if !is_video && !is_audio {
+            return Ok(None);
+        }
+        let ts = (self.cluster_ts + rel as i64) as i128 * self.timestamp_scale as i128 / 1_000_000;
+        Ok(Some(Frame {
+            track,
+            ts: ts as _,
+            key: key.unwrap_or(flags & 0x80 != 0),
+            data: block[pos + 3..].to_vec(),
+        }))
+    }
+
+    pub fn is_video(&self, frame: &Frame) -> bool {
+        self.video.as_ref().map(|v| v.track) == Some(frame.track)
+    }
+
+    fn read_element_header(&mut self) -> ResultType<(u32, u64)> {
+        let (id, n1) = read_vint(&mut self.r, true)?;
+        let (size, n2) = read_vint(&mut self.r, false)?;
+        self.pos += (n1 + n2) as u64;
+        Ok((id as u32, size))
+    }
+
+    fn skip(&mut self, size: u64) -> ResultType<()> {
+        if size == UNKNOWN_SIZE {
+            bail!("Can not skip an element of unknown size");
+        }
+        self.r.seek_relative(size as _)?;
+        self.pos += size;
+        Ok(())
+    }
+
+    fn read_bytes(&mut self, size: u64) -> ResultType<Vec<u8>> {
+        if size > self.len - self.pos.min(self.len) {
+            bail!("Invalid element size {}", size);
+        }
+        let mut buf = vec![0u8; size as usize];
+        self.r.read_exact(&mut buf)?;
+        self.pos += size;
+        Ok(buf)
+    }
+
+    fn read_uint(&mut self, size: u64) -> ResultType<u64> {
+        if size > 8 {
+            bail!("Invalid uint size {}", size);
+        }
+        Ok(self
+            .read_bytes(size)?
+            .iter()
+            .fold(0u64, |v, b| (v << 8) | *b as u64))
+    }
+
+    fn read_float(&mut self, size: u64) -> ResultType<f64> {
+        let b = self.read_bytes(size)?;
+        Ok(match size {
+            4 => f32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            8 => f64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]),
+            _ => 0.,
+        })
+    }
+}
+
+fn end_of(start: u64, size: u64, parent_end: u64) -> u64 {
+    if size == UNKNOWN_SIZE {
+        parent_end
+    } else {
+        (start + size).min(parent_end)
+    }
+}
+
+// EBML variable length integer and its length, an element id keeps its length marker.
+fn read_vint(r: &mut impl Read, keep_marker: bool) -> ResultType<(u64, usize)> {
+    let mut b = [0u8; 1];
+    r.read_exact(&mut b)?;
+    let len = b[0].leading_zeros() as usize + 1;
+    if len > 8 {
+        bail!("Invalid vint");
+    }
+    let mut v = if keep_marker {
+        b[0] as u64
+    } else {
+        (b[0] & (0xFF >> len)) as u64
+    };
+    let mut all_ones = v == (0xFF >> len) as u64;
+    for _ in 1..len {
+        r.read_exact(&mut b)?;
+        v = (v << 8) | b[0] as u64;
+        all_ones &= b[0] == 0xFF;
+    }
+    if !keep_marker && all_ones {
+        return Ok((UNKNOWN_SIZE, len));
+    }
+    Ok((v, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_vint() {
+        let mut r: &[u8] = &[0x1A, 0x45, 0xDF, 0xA3, 0x81, 0x40, 0x02, 0xFF];
+        assert_eq!(read_vint(&mut r, true).unwrap(), (0x1A45DFA3, 4));
+        assert_eq!(read_vint(&mut r, false).unwrap(), (1, 1));
+        assert_eq!(read_vint(&mut r, false).unwrap(), (2, 2));
+        assert_eq!(read_vint(&mut r, false).unwrap(), (UNKNOWN_SIZE, 1));
+    }
+}
@@ -35,6 +35,12 @@ use crate::ipc::Data;
 pub mod audio_service;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 pub mod terminal_service;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+mod terminal_recorder;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod terminal_exec;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod terminal_profile;
 cfg_if::cfg_if! {
 if #[cfg(not(target_os = "ios"))] {
 mod clipboard_service;
@@ -64,7 +70,19 @@ pub mod input_service {
     pub const NAME_WINDOW_FOCUS: &'static str = "";
 }
 
+pub mod access_policy;
+pub mod audit_log;
 mod connection;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod input_policy;
+pub mod login_ban;
+pub mod permission_template;
+pub mod port_forward_acl;
+pub mod remote_approval;
+pub mod session_limit;
+pub mod share_token;
+pub mod trusted_device;
+pub mod webhook;
 pub mod display_service;
 #[cfg(windows)]
 pub mod portable_service;
@@ -0,0 +1,288 @@
+// Access rules beyond the ip whitelist.
+//
+// Rules are set by the `access-policy` option as a json array and the first matching rule
+// decides, e.g.
+//   [{"name": "contractors", "peer_ids": ["1234*"], "conn_types": ["file_transfer"],
+//     "days": ["mon", "tue", "wed", "thu", "fri"], "time": "09:00-18:00",
+//     "action": "allow", "permissions": ["file"]},
+//    {"name": "contractors other", "peer_ids": ["1234*"], "action": "deny"},
+//    {"name": "office", "ips": ["10.0.0.0/8"], "action": "allow"},
+//    {"name": "others", "action": "approve"}]
+// Empty criteria match everything. `time` is local time, the end is exclusive and may be
+// before the start for windows over midnight. `approve` requires the connection to be
+// accepted in the connection manager even with a correct password. `permissions` restricts
+// an allowed connection to the listed permissions, the others are disabled and locked.
+// Without a matching rule the connection is allowed.
+
+use super::connection::AuthConnType;
+use chrono::{Datelike, Timelike};
+use cidr_utils::cidr::IpCidr;
+use hbb_common::{config::Config, log};
+use serde_derive::{Deserialize, Serialize};
+use std::{net::IpAddr, str::FromStr};
+
+pub const OPTION_ACCESS_POLICY: &str = "access-policy";
+pub const PERMISSIONS: &[&str] = &[
+    "keyboard",
+    "clipboard",
+    "audio",
+    "file",
+    "restart",
+    "recording",
+    "block_input",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    #[default]
+    Allow,
+    Deny,
+    Approve,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Rule {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub peer_ids: Vec<String>,
+    #[serde(default)]
+    pub ips: Vec<String>,
+    #[serde(default)]
+    pub conn_types: Vec<String>,
+    #[serde(default)]
+    pub days: Vec<String>,
+    #[serde(default)]
+    pub time: String,
+    #[serde(default)]
+    pub action: Action,
+    #[serde(default)]
+    pub permissions: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Decision {
+    pub rule: String,
+    pub action: Action,
+    /// Permissions to disable and lock.
+    pub denied_permissions: Vec<&'static str>,
+}
+
+// What is known about the connection, `None` before the login request.
+pub struct Context<'a> {
+    pub ip: &'a str,
+    pub peer_id: Option<&'a str>,
+    pub conn_type: Option<AuthConnType>,
+    /// Local weekday, 0 is Monday.
+    pub weekday: u32,
+    /// Local minutes since midnight.
+    pub minutes: u32,
+}
+
+impl<'a> Context<'a> {
+    pub fn now(ip: &'a str, peer_id: Option<&'a str>, conn_type: Option<AuthConnType>) -> Self {
+        let now = chrono::Local::now();
+        Self {
+            ip,
+            peer_id,
+            conn_type,
+            weekday: now.weekday().num_days_from_monday(),
+            minutes: now.hour() * 60 + now.minute(),
+        }
+    }
+}
+
+fn conn_type_name(t: AuthConnType) -> &'static str {
+    match t {
+        AuthConnType::Remote => "remote",
+        AuthConnType::FileTransfer => "file_transfer",
+        AuthConnType::PortForward => "port_forward",
+        AuthConnType::ViewCamera => "view_camera",
+        AuthConnType::Terminal => "terminal",
+    }
+}
+
+fn parse_minutes(s: &str) -> Option<u32> {
+    let (h, m) = s.trim().split_once(':')?;
+    let (h, m) = (h.parse::<u32>().ok()?, m.parse::<u32>().ok()?);
+    (h <= 24 && m < 60 && h * 60 + m <= 24 * 60).then_some(h * 60 + m)
+}
+
+fn in_window(window: &str, minutes: u32) -> bool {
+    let Some((start, end)) = window.split_once('-') else {
+        return false;
+    };
+    let (Some(start), Some(end)) = (parse_minutes(start), parse_minutes(end)) else {
+        return false;
+    };
+    if start <= end {
+        start <= minutes && minutes < end
+    } else {
+        minutes >= start || minutes < end
+    }
+}
+
+fn match_id(pattern: &str, id: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => id.starts_with(prefix),
+        None => pattern == id,
+    }
+}
+
+fn match_ip(cidr: &str, ip: &str) -> bool {
+    let ip = ip.split('%').next().unwrap_or(ip);
+    let Ok(ip) = IpAddr::from_str(ip) else {
+        return false;
+    };
+    if let Ok(addr) = IpAddr::from_str(cidr) {
+        return addr == ip;
+    }
+    IpCidr::from_str(cidr).map_or(false, |c| c.contains(ip))
+}
+
+impl Rule {
+    // `None` if the rule may match, but that is not known yet.
+    fn matches(&self, ctx: &Context) -> Option<bool> {
+        const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+        if !self.ips.is_empty() && !self.ips.iter().any(|c| match_ip(c.trim(), ctx.ip)) {
+            return Some(false);
+        }
+        if !self.days.is_empty()
+            && !self
+                .days
+                .iter()
+                .any(|d| d.to_lowercase().starts_with(DAYS[ctx.weekday as usize % 7]))
+        {
+            return Some(false);
+        }
+        if !self.time.is_empty() && !in_window(&self.time, ctx.minutes) {
+            return Some(false);
+        }
+        let mut known = true;
+        if !self.peer_ids.is_empty() {
+            match ctx.peer_id {
+                Some(id) => {
+                    if !self.peer_ids.iter().any(|p| match_id(p.trim(), id)) {
+                        return Some(false);
+                    }
+                }
+                None => known = false,
+            }
+        }
+        if !self.conn_types.is_empty() {
+            match ctx.conn_type {
+                Some(t) => {
+                    if !self.conn_types.iter().any(|x| x == conn_type_name(t)) {
+                        return Some(false);
+                    }
+                }
+                None => known = false,
+            }
+        }
+        known.then_some(true)
+    }
+
+    fn decision(&self) -> Decision {
+        let denied_permissions = match &self.permissions {
+            Some(allowed) if self.action != Action::Deny => PERMISSIONS
+                .iter()
+                .filter(|p| !allowed.iter().any(|a| a == *p))
+                .copied()
+                .collect(),
+            _ => vec![],
+        };
+        Decision {
+            rule: self.name.clone(),
+            action: self.action,
+            denied_permissions,
+        }
+    }
+}
+
+fn parse(s: &str) -> Vec<Rule> {
+    if s.trim().is_empty() {
+        return vec![];
+    }
+    serde_json::from_str::<Vec<Rule>>(s).unwrap_or_else(|e| {
+        log::error!("Invalid {}: {}", OPTION_ACCESS_POLICY, e);
+        // Fail closed, a broken policy must not open access.
+        vec![Rule {
+            name: "invalid policy".to_owned(),
+            action: Action::Deny,
+            ..Default::default()
+        }]
+    })
+}
+
+fn evaluate_rules(rules: &[Rule], ctx: &Context) -> Option<Decision> {
+    for rule in rules {
+        match rule.matches(ctx) {
+            Some(true) => return Some(rule.decision()),
+            Some(false) => continue,
+            None => return None,
+        }
+    }
+    Some(Decision::default())
+}
+
+/// The decision of the first matching rule, `None` if it can only be decided with more of
+/// the context, i.e. after the login request.
+pub fn evaluate(ctx: &Context) -> Option<Decision> {
+    evaluate_rules(&parse(&Config::get_option(OPTION_ACCESS_POLICY)), ctx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluate() {
+        let rules = parse(
+            r#"[{"name": "contractors", "peer_ids": ["1234*"], "conn_types": ["file_transfer"],
+                 "days": ["mon", "tue", "wed", "thu", "fri"], "time": "09:00-18:00",
+                 "action": "allow", "permissions": ["file"]},
+                {"name": "contractors other", "peer_ids": ["1234*"], "action": "deny"},
+                {"name": "office", "ips": ["10.0.0.0/8", "fd00::1"], "action": "allow"},
+                {"name": "others", "action": "approve"}]"#,
+        );
+        assert_eq!(rules.len(), 4);
+        let ctx = |ip, peer_id, conn_type, weekday, minutes| Context {
+            ip,
+            peer_id,
+            conn_type,
+            weekday,
+            minutes,
+        };
+        let ft = Some(AuthConnType::FileTransfer);
+        // monday 10:00
+        let d = evaluate_rules(&rules, &ctx("1.2.3.4", Some("123456"), ft, 0, 600)).unwrap();
+        assert_eq!(d.rule, "contractors");
+        assert_eq!(d.action, Action::Allow);
+        assert!(!d.denied_permissions.contains(&"file"));
+        assert!(d.denied_permissions.contains(&"keyboard"));
+        // saturday
+        let d = evaluate_rules(&rules, &ctx("1.2.3.4", Some("123456"), ft, 5, 600)).unwrap();
+        assert_eq!(d.action, Action::Deny);
+        // monday 18:00
+        let d = evaluate_rules(&rules, &ctx("1.2.3.4", Some("123456"), ft, 0, 1080)).unwrap();
+        assert_eq!(d.action, Action::Deny);
+        let d = evaluate_rules(&rules, &ctx("10.1.2.3", Some("999"), None, 0, 0)).unwrap();
+        assert_eq!(d.rule, "office");
+        let d = evaluate_rules(&rules, &ctx("fd00::1", Some("999"), None, 0, 0)).unwrap();
+        assert_eq!(d.rule, "office");
+        let d = evaluate_rules(&rules, &ctx("8.8.8.8", Some("999"), None, 0, 0)).unwrap();
+        assert_eq!(d.action, Action::Approve);
+        // Not known before the login request, during business hours.
+        assert!(evaluate_rules(&rules, &ctx("10.1.2.3", None, None, 0, 600)).is_none());
+        // Only the deny rule can match on sunday, which needs the peer id too.
+        assert!(evaluate_rules(&rules, &ctx("10.1.2.3", None, None, 6, 600)).is_none());
+        assert!(evaluate_rules(&[], &ctx("10.1.2.3", None, None, 6, 600)).is_some());
+
+        assert!(in_window("22:00-06:00", 23 * 60));
+        assert!(in_window("22:00-06:00", 60));
+        assert!(!in_window("22:00-06:00", 12 * 60));
+        assert!(!in_window("bad", 0));
+        assert_eq!(parse("not json")[0].action, Action::Deny);
+    }
+}
@@ -0,0 +1,298 @@
+// Local audit sink, independent of the API server.
+//
+// Connection, file and alarm audit events are appended to `audit.jsonl` as json lines
+// with `time` (unix ms), `type` ("conn", "file" or "alarm") and `event`.
+// With the hash chain enabled, a line also has `prev`, the hash of the previous line, and
+// ends with `hash`, the sha256 of the line before it (closed with `}`), so editing or
+// removing a line breaks the chain. The chain goes on across rotated files `audit.1.jsonl`
+// (newest) ... `audit.N.jsonl`, check it with `rustdesk --verify-audit-log [dir or files]`.
+// Events can also be sent to the local syslog (journald reads it too) on Linux and macOS.
+
+use hbb_common::{
+    bail,
+    config::{self, Config},
+    log, ResultType,
+};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{mpsc, Mutex},
+};
+
+pub const OPTION_AUDIT_LOG: &str = "enable-local-audit-log";
+pub const OPTION_AUDIT_LOG_DIR: &str = "local-audit-log-dir";
+// Rotate when the file exceeds this size in MB.
+pub const OPTION_AUDIT_LOG_MAX_SIZE: &str = "local-audit-log-max-size";
+pub const OPTION_AUDIT_LOG_MAX_FILES: &str = "local-audit-log-max-files";
+pub const OPTION_AUDIT_LOG_HASH_CHAIN: &str = "local-audit-log-hash-chain";
+pub const OPTION_AUDIT_SYSLOG: &str = "enable-audit-syslog";
+
+const FILE_NAME: &str = "audit";
+const DEFAULT_MAX_SIZE_MB: u64 = 10;
+const DEFAULT_MAX_FILES: usize = 10;
+// `,"hash":"` + 64 hex + `"}`
+const HASH_SUFFIX_LEN: usize = 75;
+
+lazy_static::lazy_static! {
+    static ref SENDER: Mutex<Option<mpsc::Sender<(&'static str, Value)>>> = Default::default();
+}
+
+#[inline]
+fn option_enabled(name: &str) -> bool {
+    config::option2bool(name, &Config::get_option(name))
+}
+
+/// Append an audit event of `typ` to the local sinks that are enabled.
+pub fn write(typ: &'static str, event: &Value) {
+    if !option_enabled(OPTION_AUDIT_LOG) && !option_enabled(OPTION_AUDIT_SYSLOG) {
+        return;
+    }
+    let mut lock = SENDER.lock().unwrap();
+    if lock.is_none() {
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || run(rx));
+        *lock = Some(tx);
+    }
+    if let Some(tx) = lock.as_ref() {
+        tx.send((typ, event.clone())).ok();
+    }
+}
+
+pub fn dir() -> PathBuf {
+    let dir = Config::get_option(OPTION_AUDIT_LOG_DIR);
+    if dir.is_empty() {
+        Config::log_path().join("audit")
+    } else {
+        PathBuf::from(dir)
+    }
+}
+
+fn file_path(dir: &Path, i: usize) -> PathBuf {
+    if i == 0 {
+        dir.join(format!("{}.jsonl", FILE_NAME))
+    } else {
+        dir.join(format!("{}.{}.jsonl", FILE_NAME, i))
+    }
+}
+
+fn run(rx: mpsc::Receiver<(&'static str, Value)>) {
+    let mut writer = Writer::default();
+    while let Ok((typ, event)) = rx.recv() {
+        let time = hbb_common::get_time();
+        if option_enabled(OPTION_AUDIT_SYSLOG) {
+            syslog(typ, &event);
+        }
+        if option_enabled(OPTION_AUDIT_LOG) {
+            if let Err(e) = writer.write(time, typ, &event) {
+                log::error!("Failed to write local audit log: {}", e);
+                writer = Writer::default();
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Writer {
+    file: Option<(PathBuf, File, u64)>,
+    last_hash: String,
+}
+
+impl Writer {
+    fn open(&mut self, dir: &Path) -> ResultType<()> {
+        fs::create_dir_all(dir)?;
+        let path = file_path(dir, 0);
+        if self.file.as_ref().map(|f| f.0 != path).unwrap_or(true) {
+            let file = OpenOptions::new().create(true).append(true).open(&path)?;
+            let size = file.metadata()?.len();
+            self.last_hash = last_hash(&path)
+                .or_else(|| last_hash(&file_path(dir, 1)))
+                .unwrap_or_default();
+            self.file = Some((path, file, size));
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, time: i64, typ: &str, event: &Value) -> ResultType<()> {
+        let dir = dir();
+        self.open(&dir)?;
+        let chain = option_enabled(OPTION_AUDIT_LOG_HASH_CHAIN);
+        let line = if chain {
+            chained_line(time, typ, event, &self.last_hash)
+        } else {
+            json!({"time": time, "type": typ, "event": event}).to_string()
+        };
+        let Some((_, file, size)) = self.file.as_mut() else {
+            bail!("audit log not opened");
+        };
+        writeln!(file, "{}", line)?;
+        file.flush()?;
+        *size += line.len() as u64 + 1;
+        if chain {
+            self.last_hash = line[line.len() - HASH_SUFFIX_LEN + 9..line.len() - 2].to_owned();
+        }
+        let max_size = Config::get_option(OPTION_AUDIT_LOG_MAX_SIZE)
+            .parse::<u64>()
+            .unwrap_or(DEFAULT_MAX_SIZE_MB)
+            .max(1)
+            * 1024
+            * 1024;
+        if *size >= max_size {
+            self.file = None;
+            rotate(&dir)?;
+        }
+        Ok(())
+    }
+}
+
+fn rotate(dir: &Path) -> ResultType<()> {
+    let max_files = Config::get_option(OPTION_AUDIT_LOG_MAX_FILES)
+        .parse::<usize>()
+        .unwrap_or(DEFAULT_MAX_FILES)
+        .max(1);
+    fs::remove_file(file_path(dir, max_files)).ok();
+    for i in (0..max_files).rev() {
+        let from = file_path(dir, i);
+        if from.exists() {
+            fs::rename(&from, file_path(dir, i + 1))?;
+        }
+    }
+    Ok(())
+}
+
+fn hash(s: &str) -> String {
+    hex::encode(Sha256::digest(s.as_bytes()))
+}
+
+fn chained_line(time: i64, typ: &str, event: &Value, prev: &str) -> String {
+    let body = json!({"time": time, "type": typ, "event": event, "prev": prev}).to_string();
+    let hash = hash(&body);
+    format!("{},\"hash\":\"{}\"}}", &body[..body.len() - 1], hash)
+}
+
+// The hash of `line` if it is well formed, and the stored `prev`.
+fn check_line(line: &str) -> Option<(String, String)> {
+    if line.len() <= HASH_SUFFIX_LEN || !line.is_char_boundary(line.len() - HASH_SUFFIX_LEN) {
+        return None;
+    }
+    let (body, suffix) = line.split_at(line.len() - HASH_SUFFIX_LEN);
+    let stored = suffix.strip_prefix(",\"hash\":\"")?.strip_suffix("\"}")?;
+    let body = format!("{}}}", body);
+    if hash(&body) != stored {
+        return None;
+    }
+    let prev = serde_json::from_str::<Value>(&body).ok()?["prev"]
+        .as_str()?
+        .to_owned();
+    Some((stored.to_owned(), prev))
+}
+
+fn last_hash(path: &Path) -> Option<String> {
+    let file = File::open(path).ok()?;
+    let last = BufReader::new(file)
+        .lines()
+        .filter_map(|l| l.ok())
+        .filter(|l| !l.is_empty())
+        .last()?;
+    check_line(&last).map(|(hash, _)| hash)
+}
+
+/// Check the hash chain of `files`, given from the oldest to the newest.
+/// Returns the number of lines checked, or the first broken line.
+pub fn verify(files: &[PathBuf]) -> ResultType<usize> {
+    let mut last: Option<String> = None;
+    let mut n = 0;
+    for path in files {
+        let reader = BufReader::new(File::open(path)?);
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let Some((hash, prev)) = check_line(&line) else {
+                bail!("{}:{}: hash mismatch", path.display(), i + 1);
+            };
+            if let Some(last) = last.as_ref() {
+                if *last != prev {
+                    bail!("{}:{}: chain broken", path.display(), i + 1);
+                }
+            }
+            last = Some(hash);
+            n += 1;
+        }
+    }
+    Ok(n)
+}
+
+/// The current and rotated files in `dir`, from the oldest to the newest.
+pub fn files(dir: &Path) -> Vec<PathBuf> {
+    let mut v: Vec<_> = (0..)
+        .map(|i| file_path(dir, i))
+        .take_while(|p| p.exists())
+        .collect();
+    v.reverse();
+    v
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn syslog(typ: &str, event: &Value) {
+    use std::os::unix::net::UnixDatagram;
+    #[cfg(target_os = "linux")]
+    const SOCKET: &str = "/dev/log";
+    #[cfg(target_os = "macos")]
+    const SOCKET: &str = "/var/run/syslog";
+    // facility authpriv, severity warning for alarms and info otherwise
+    let pri = 10 * 8 + if typ == "alarm" { 4 } else { 6 };
+    let msg = format!(
+        "<{}>{}[{}]: {} {}",
+        pri,
+        crate::get_app_name().to_lowercase(),
+        std::process::id(),
+        typ,
+        event
+    );
+    match UnixDatagram::unbound() {
+        Ok(socket) => {
+            if let Err(e) = socket.send_to(msg.as_bytes(), SOCKET) {
+                log::error!("Failed to send audit event to syslog: {}", e);
+            }
+        }
+        Err(e) => log::error!("Failed to create syslog socket: {}", e),
+    }
+}
+
+// Not supported on other platforms.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn syslog(_typ: &str, _event: &Value) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chain() {
+        let dir = std::env::temp_dir().join(format!("rustdesk-audit-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = file_path(&dir, 0);
+        let mut prev = "".to_owned();
+        let mut lines = vec![];
+        for i in 0..3 {
+            let line = chained_line(i, "conn", &json!({"action": "new", "ip": "1.2.3.4"}), &prev);
+            prev = check_line(&line).unwrap().0;
+            lines.push(line);
+        }
+        fs::write(&path, lines.join("\n") + "\n").unwrap();
+        assert_eq!(verify(&files(&dir)).unwrap(), 3);
+        assert_eq!(last_hash(&path).unwrap(), prev);
+
+        let tampered = lines.join("\n").replace("1.2.3.4", "1.2.3.5");
+        fs::write(&path, tampered).unwrap();
+        assert!(verify(&files(&dir)).is_err());
+
+        fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        assert!(verify(&files(&dir)).is_err());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -36,7 +36,7 @@ use hbb_common::{
     sha2::{Digest, Sha256},
     sleep, timeout,
     tokio::{
-        net::TcpStream,
+        net::{TcpStream, UdpSocket},
         sync::mpsc,
         time::{self, Duration, Instant},
     },
@@ -54,7 +54,10 @@ use std::{
     num::NonZeroI64,
     path::PathBuf,
     str::FromStr,
-    sync::{atomic::AtomicI64, mpsc as std_mpsc},
+    sync::{
+        atomic::{AtomicBool, AtomicI64},
+        mpsc as std_mpsc,
+    },
 };
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 use system_shutdown;
@@ -67,6 +70,11 @@ use crate::virtual_display_manager;
 use std::collections::HashSet;
 pub type Sender = mpsc::UnboundedSender<(Instant, Arc<Message>)>;
 
+enum PortForwardSocket {
+    Tcp(Framed<TcpStream, BytesCodec>),
+    Udp(UdpSocket),
+}
+
 lazy_static::lazy_static! {
     static ref LOGIN_FAILURES: [Arc::<Mutex<HashMap<String, (i32, i32, i32)>>>; 2] = Default::default();
     static ref SESSIONS: Arc::<Mutex<HashMap<SessionKey, Session>>> = Default::default();
@@ -210,16 +218,44 @@ pub struct Connection {
     server: super::ServerPtrWeak,
     hash: Hash,
     read_jobs: Vec<fs::TransferJob>,
+    file_scheduler: crate::file_scheduler::Scheduler,
+    // The path and files of the read jobs to make a manifest of when they are done.
+    manifest_jobs: HashMap<i32, (PathBuf, Vec<String>)>,
+    // For the threads hashing or searching files to send to the connection.
+    tx_file_threads: mpsc::UnboundedSender<ipc::Data>,
+    // The searches of the peer, stopped when their job is cancelled.
+    file_searches: HashMap<i32, Arc<AtomicBool>>,
+    // The uploads to check, with the first manifest of the peer's and ours, and whose it is.
+    unchecked_uploads: HashMap<i32, Option<(bool, crate::file_manifest::Manifest)>>,
+    // The delta downloads of the peer, with the path and the flag to stop sending the ops.
+    delta_downloads: HashMap<i32, (String, Arc<AtomicBool>)>,
     timer: crate::RustDeskInterval,
     file_timer: crate::RustDeskInterval,
     file_transfer: Option<(String, bool)>,
     view_camera: bool,
     terminal: bool,
-    port_forward_socket: Option<Framed<TcpStream, BytesCodec>>,
+    // Logged in with a share token, view only.
+    share_viewer: bool,
+    // 2FA was skipped for a trusted device.
+    trusted_device: bool,
+    // Permissions disabled by share tokens or the access policy, they cannot be enabled.
+    locked_permissions: Vec<&'static str>,
+    // The access policy requires accepting in the connection manager.
+    policy_approval: bool,
+    // Waiting for `remote_approval`.
+    remote_approval_pending: bool,
+    // `permission_template` requested by the peer, waiting for the connection manager.
+    requested_permission_template: Option<permission_template::Template>,
+    // Granted `permission_template`.
+    permission_template: Option<String>,
+    session_limit: Option<session_limit::SessionLimit>,
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    input_policy: Option<input_policy::InputPolicy>,
+    port_forward_socket: Option<PortForwardSocket>,
     port_forward_address: String,
     tx_to_cm: mpsc::UnboundedSender<ipc::Data>,
     authorized: bool,
-    require_2fa: Option<totp_rs::TOTP>,
+    require_2fa: Option<crate::auth_2fa::SecondFactors>,
     keyboard: bool,
     clipboard: bool,
     audio: bool,
@@ -382,17 +418,33 @@ impl Connection {
                 tx: Some(tx),
                 tx_video: Some(tx_video),
             },
-            require_2fa: crate::auth_2fa::get_2fa(None),
+            require_2fa: crate::auth_2fa::SecondFactors::get(),
             display_idx: *display_service::PRIMARY_DISPLAY_IDX,
             stream,
             server,
             hash,
             read_jobs: Vec::new(),
+            file_scheduler: Default::default(),
+            manifest_jobs: Default::default(),
+            tx_file_threads: tx_from_cm_holder.clone(),
+            file_searches: Default::default(),
+            unchecked_uploads: Default::default(),
+            delta_downloads: Default::default(),
             timer: crate::rustdesk_interval(time::interval(SEC30)),
             file_timer: crate::rustdesk_interval(time::interval(SEC30)),
             file_transfer: None,
             view_camera: false,
             terminal: false,
+            share_viewer: false,
+            trusted_device: false,
+            locked_permissions: vec![],
+            policy_approval: false,
+            remote_approval_pending: false,
+            requested_permission_template: None,
+            permission_template: None,
+            session_limit: None,
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            input_policy: None,
             port_forward_socket: None,
             port_forward_address: "".to_owned(),
             tx_to_cm,
@@ -564,7 +616,15 @@ impl Connection {
                                 break;
                             }
                         }
+                        ipc::Data::SessionExtensionResponse(approved) => {
+                            conn.answer_session_extension(approved).await;
+                        }
                         ipc::Data::ChatMessage{text} => {
+                            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                            if let Some(chord) = conn.input_policy.as_mut().and_then(|p| p.allow(&text)) {
+                                conn.send_chat(format!("{} was approved", chord)).await;
+                                continue;
+                            }
                             let mut misc = Misc::new();
                             misc.set_chat_message(ChatMessage {
                                 text,
@@ -576,79 +636,20 @@ impl Connection {
                             conn.chat_unanswered = false;
                         }
                         ipc::Data::SwitchPermission{name, enabled} => {
-                            log::info!("Change permission {} -> {}", name, enabled);
-                            if &name == "keyboard" {
-                                conn.keyboard = enabled;
-                                conn.send_permission(Permission::Keyboard, enabled).await;
-                                if let Some(s) = conn.server.upgrade() {
-                                    s.write().unwrap().subscribe(
-                                        super::clipboard_service::NAME,
-                                        conn.inner.clone(), conn.can_sub_clipboard_service());
-                                    #[cfg(feature = "unix-file-copy-paste")]
-                                    s.write().unwrap().subscribe(
-                                        super::clipboard_service::FILE_NAME,
-                                        conn.inner.clone(),
-                                        conn.can_sub_file_clipboard_service(),
-                                    );
-                                    s.write().unwrap().subscribe(
-                                        NAME_CURSOR,
-                                        conn.inner.clone(), enabled || conn.show_remote_cursor);
-                                }
-                            } else if &name == "clipboard" {
-                                conn.clipboard = enabled;
-                                conn.send_permission(Permission::Clipboard, enabled).await;
-                                if let Some(s) = conn.server.upgrade() {
-                                    s.write().unwrap().subscribe(
-                                        super::clipboard_service::NAME,
-                                        conn.inner.clone(), conn.can_sub_clipboard_service());
-                                }
-                            } else if &name == "audio" {
-                                conn.audio = enabled;
-                                conn.send_permission(Permission::Audio, enabled).await;
-                                if conn.authorized {
-                                    if let Some(s) = conn.server.upgrade() {
-                                        if conn.is_authed_view_camera_conn() {
-                                            if conn.voice_calling || !conn.audio_enabled() {
-                                                s.write().unwrap().subscribe(
-                                                    super::audio_service::NAME,
-                                                    conn.inner.clone(), conn.audio_enabled());
-                                            }
-                                        } else {
-                                            s.write().unwrap().subscribe(
-                                                super::audio_service::NAME,
-                                                conn.inner.clone(), conn.audio_enabled());
-                                        }
-                                    }
-                                }
-                            } else if &name == "file" {
-                                conn.file = enabled;
-                                conn.send_permission(Permission::File, enabled).await;
-                                #[cfg(feature = "unix-file-copy-paste")]
-                                if !enabled {
-                                    conn.try_empty_file_clipboard();
-                                }
-                                #[cfg(feature = "unix-file-copy-paste")]
-                                if let Some(s) = conn.server.upgrade() {
-                                    s.write().unwrap().subscribe(
-                                        super::clipboard_service::FILE_NAME,
-                                        conn.inner.clone(),
-                                        conn.can_sub_file_clipboard_service(),
-                                    );
-                                }
-                            } else if &name == "restart" {
-                                conn.restart = enabled;
-                                conn.send_permission(Permission::Restart, enabled).await;
-                            } else if &name == "recording" {
-                                conn.recording = enabled;
-                                conn.send_permission(Permission::Recording, enabled).await;
-                            } else if &name == "block_input" {
-                                conn.block_input = enabled;
-                                conn.send_permission(Permission::BlockInput, enabled).await;
-                            }
+                            conn.switch_permission(name, enabled).await;
+                        }
+                        ipc::Data::PermissionTemplateResponse(approved) => {
+                            conn.handle_permission_template(approved).await;
                         }
                         ipc::Data::RawMessage(bytes) => {
                             allow_err!(conn.stream.send_raw(bytes).await);
                         }
+                        ipc::Data::FileManifest((received, manifest)) => {
+                            conn.handle_file_manifest(received, &manifest).await;
+                        }
+                        ipc::Data::FileDelta(done) => {
+                            conn.handle_delta_upload_done(&done).await;
+                        }
                         #[cfg(target_os = "windows")]
                         ipc::Data::ClipboardFile(clip) => {
                             if !conn.is_remote() {
@@ -751,10 +752,18 @@ impl Connection {
                 },
                 _ = conn.file_timer.tick() => {
                     if !conn.read_jobs.is_empty() {
-                        conn.send_to_cm(ipc::Data::FileTransferLog(("transfer".to_string(), fs::serialize_transfer_jobs(&conn.read_jobs))));
-                        match fs::handle_read_jobs(&mut conn.read_jobs, &mut conn.stream).await {
+                        let mut jobs = conn.file_scheduler.take_ready(&mut conn.read_jobs, MOUSE_MOVE_TIME.load(Ordering::SeqCst));
+                        let res = if jobs.is_empty() {
+                            Ok(Default::default())
+                        } else {
+                            conn.send_to_cm(ipc::Data::FileTransferLog(("transfer".to_string(), fs::serialize_transfer_jobs(&jobs))));
+                            fs::handle_read_jobs(&mut jobs, &mut conn.stream).await
+                        };
+                        conn.file_scheduler.put_back(jobs, &mut conn.read_jobs);
+                        match res {
                             Ok(log) => {
                                 if !log.is_empty() {
+                                    conn.make_manifest(&log);
                                     conn.send_to_cm(ipc::Data::FileTransferLog(("transfer".to_string(), log)));
                                 }
                             }
@@ -846,6 +855,22 @@ impl Connection {
                 },
                 Some(data) = rx_from_authed.recv() => {
                     match data {
+                        // Answered by `remote_approval`.
+                        ipc::Data::Authorize => {
+                            conn.remote_approval_pending = false;
+                            conn.require_2fa.take();
+                            conn.send_logon_response().await;
+                            let (id, name) = (conn.lr.my_id.clone(), conn.lr.my_name.clone());
+                            conn.try_start_cm(id, name, conn.authorized);
+                            if conn.port_forward_socket.is_some() {
+                                break;
+                            }
+                        }
+                        ipc::Data::Close => {
+                            conn.send_close_reason_no_retry("Connection not allowed").await;
+                            conn.on_close("remote approval denied", true).await;
+                            break;
+                        }
                         #[cfg(all(target_os = "windows", feature = "flutter"))]
                         ipc::Data::PrinterData(data) => {
                             if config::Config::get_bool_option(config::keys::OPTION_ENABLE_REMOTE_PRINTER) {
@@ -867,6 +892,9 @@ impl Connection {
                             break;
                         }
                     }
+                    if !conn.check_session_limit().await {
+                        break;
+                    }
                     conn.file_remove_log_control.on_timer().drain(..).map(|x| conn.send_to_cm(x)).count();
                     #[cfg(feature = "hwcodec")]
                     conn.update_supported_encoding();
@@ -936,6 +964,14 @@ impl Connection {
         conn.post_conn_audit(json!({
             "action": "close",
         }));
+        // Unauthorized connections only get the rate limited connect event.
+        if conn.authorized {
+            conn.notify_webhook(
+                webhook::Event::Close,
+                format!("Connection {} closed", conn.describe_peer()),
+                Value::Null,
+            );
+        }
         if let Some(s) = conn.server.upgrade() {
             let mut s = s.write().unwrap();
             s.remove_connection(&conn.inner);
@@ -1063,10 +1099,29 @@ impl Connection {
         rx_from_cm: &mut mpsc::UnboundedReceiver<Data>,
     ) -> ResultType<()> {
         let mut last_recv_time = Instant::now();
-        if let Some(mut forward) = self.port_forward_socket.take() {
+        if let Some(socket) = self.port_forward_socket.take() {
+            // The allow-list may have changed while waiting for authorization.
+            let peer = match &socket {
+                PortForwardSocket::Tcp(forward) => forward.get_ref().peer_addr(),
+                PortForwardSocket::Udp(udp) => udp.peer_addr(),
+            };
+            if let (Some((host, port)), Ok(peer)) =
+                (self.port_forward_address.rsplit_once(':'), peer)
+            {
+                let host = crate::udp_forward::parse_host(host).1;
+                if !port_forward_acl::is_allowed(host, port.parse().unwrap_or(0), Some(peer.ip())) {
+                    bail!("{} is not in the allow-list", self.port_forward_address);
+                }
+            }
             log::info!("Running port forwarding loop");
             self.stream.set_raw();
             let mut hbbs_rx = crate::hbbs_http::sync::signal_receiver();
+            let (mut forward, udp) = match socket {
+                PortForwardSocket::Tcp(forward) => (Some(forward), None),
+                PortForwardSocket::Udp(udp) => (None, Some(udp)),
+            };
+            let mut decoder = crate::udp_forward::Decoder::default();
+            let mut buf = vec![0u8; crate::udp_forward::MAX_DATAGRAM_SIZE];
             loop {
                 tokio::select! {
                     Some(data) = rx_from_cm.recv() => {
@@ -1078,10 +1133,15 @@ impl Connection {
                                 log::error!("Connection manager error: {e}");
                                 bail!("{e}");
                             }
+                            ipc::Data::SessionExtensionResponse(approved) => {
+                                if let Some(limit) = self.session_limit.as_mut() {
+                                    limit.answer_extension(approved);
+                                }
+                            }
                             _ => {}
                         }
                     }
-                    res = forward.next() => {
+                    res = async { forward.as_mut().unwrap().next().await }, if forward.is_some() => {
                         if let Some(res) = res {
                             last_recv_time = Instant::now();
                             self.stream.send_bytes(res?.into()).await?;
@@ -1089,10 +1149,25 @@ impl Connection {
                             bail!("Forward reset by the peer");
                         }
                     },
+                    res = async { udp.as_ref().unwrap().recv(&mut buf).await }, if udp.is_some() => {
+                        let n = res?;
+                        last_recv_time = Instant::now();
+                        self.stream
+                            .send_bytes(crate::udp_forward::encode(&buf[..n]).freeze())
+                            .await?;
+                    },
                     res = self.stream.next() => {
                         if let Some(res) = res {
                             last_recv_time = Instant::now();
-                            timeout(SEND_TIMEOUT_OTHER, forward.send(res?)).await??;
+                            if let Some(forward) = forward.as_mut() {
+                                timeout(SEND_TIMEOUT_OTHER, forward.send(res?)).await??;
+                            } else if let Some(udp) = udp.as_ref() {
+                                decoder.feed(&res?);
+                                while let Some(datagram) = decoder.next_datagram() {
+                                    // Datagrams may be dropped, a failed send must not end the session.
+                                    allow_err!(udp.send(&datagram).await);
+                                }
+                            }
                         } else {
                             bail!("Stream reset by the peer");
                         }
@@ -1101,6 +1176,19 @@ impl Connection {
                         if last_recv_time.elapsed() >= H1 {
                             bail!("Timeout");
                         }
+                        if let Some(limit) = self.session_limit.as_mut() {
+                            limit.set_last_active(last_recv_time);
+                            match limit.check(Instant::now()) {
+                                session_limit::Check::Ok => {}
+                                // No messages to the controller, ask for the extension right away.
+                                session_limit::Check::Warn(_) => {
+                                    if let Some(m) = limit.request_extension() {
+                                        self.send_to_cm(ipc::Data::SessionExtensionRequest(m));
+                                    }
+                                }
+                                session_limit::Check::Close(reason) => bail!("{}", reason),
+                            }
+                        }
                     }
                     Ok(conns) = hbbs_rx.recv() => {
                         if conns.contains(&self.inner.id) {
@@ -1114,6 +1202,27 @@ impl Connection {
         Ok(())
     }
 
+    async fn connect_udp_forward(
+        resolved: Option<std::net::SocketAddr>,
+        addr: &str,
+    ) -> ResultType<PortForwardSocket> {
+        let target = match resolved {
+            Some(resolved) => resolved,
+            None => match hbb_common::tokio::net::lookup_host(addr).await?.next() {
+                Some(target) => target,
+                None => bail!("Failed to resolve {}", addr),
+            },
+        };
+        let local = if target.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let udp = UdpSocket::bind(local).await?;
+        udp.connect(target).await?;
+        Ok(PortForwardSocket::Udp(udp))
+    }
+
     async fn send_permission(&mut self, permission: Permission, enabled: bool) {
         let mut misc = Misc::new();
         misc.set_permission_info(PermissionInfo {
@@ -1178,6 +1287,18 @@ impl Connection {
             }
         }
         self.ip = addr.ip().to_string();
+        let ctx = access_policy::Context::now(&self.ip, None, None);
+        if let Some(decision) = access_policy::evaluate(&ctx) {
+            if decision.action == access_policy::Action::Deny {
+                log::info!(
+                    "{} denied by access policy rule {:?}",
+                    self.ip,
+                    decision.rule
+                );
+                self.send_login_error("Access denied by policy").await;
+                return false;
+            }
+        }
         let mut msg_out = Message::new();
         msg_out.set_hash(self.hash.clone());
         self.send(msg_out).await;
@@ -1186,6 +1307,11 @@ impl Connection {
             "ip": addr.ip(),
             "action": "new",
         }));
+        self.notify_webhook(
+            webhook::Event::Connect,
+            format!("New connection from {} to {}", self.ip, Config::get_id()),
+            Value::Null,
+        );
         true
     }
 
@@ -1203,16 +1329,25 @@ impl Connection {
     }
 
     fn post_conn_audit(&self, v: Value) {
-        if self.server_audit_conn.is_empty() {
-            return;
-        }
+        self.conn_auditor()(v);
+    }
+
+    // Posts conn audits of this connection from elsewhere, e.g. threads.
+    fn conn_auditor(&self) -> impl FnOnce(Value) + Send + 'static {
+        let (conn_id, session_id) = (self.inner.id, self.lr.session_id);
         let url = self.server_audit_conn.clone();
-        let mut v = v;
-        v["id"] = json!(Config::get_id());
-        v["uuid"] = json!(crate::encode64(hbb_common::get_uuid()));
-        v["conn_id"] = json!(self.inner.id);
-        v["session_id"] = json!(self.lr.session_id);
-        allow_err!(self.tx_post_seq.send((url, v)));
+        let tx = self.tx_post_seq.clone();
+        move |mut v| {
+            v["id"] = json!(Config::get_id());
+            v["uuid"] = json!(crate::encode64(hbb_common::get_uuid()));
+            v["conn_id"] = json!(conn_id);
+            v["session_id"] = json!(session_id);
+            audit_log::write("conn", &v);
+            if url.is_empty() {
+                return;
+            }
+            allow_err!(tx.send((url, v)));
+        }
     }
 
     fn get_files_for_audit(job_type: fs::JobType, mut files: Vec<FileEntry>) -> Vec<(String, i64)> {
@@ -1231,6 +1366,17 @@ impl Connection {
             .collect()
     }
 
+    // Mark an event in the incoming session recordings.
+    #[inline]
+    fn record_event(&self, kind: scrap::record::RecordEventKind, detail: String) {
+        scrap::record::push_event(
+            true,
+            None,
+            kind,
+            format!("{} ({}): {}", self.lr.my_name, self.lr.my_id, detail),
+        );
+    }
+
     fn post_file_audit(
         &self,
         r#type: FileAuditType,
@@ -1238,10 +1384,18 @@ impl Connection {
         files: Vec<(String, i64)>,
         info: Value,
     ) {
-        if self.server_audit_file.is_empty() {
-            return;
-        }
-        let url = self.server_audit_file.clone();
+        self.record_event(
+            scrap::record::RecordEventKind::FileTransfer,
+            format!(
+                "{} {} ({} files)",
+                match r#type {
+                    FileAuditType::RemoteSend => "send",
+                    FileAuditType::RemoteReceive => "receive",
+                },
+                path,
+                files.len()
+            ),
+        );
         let file_num = files.len();
         let mut files = files;
         files.sort_by(|a, b| b.1.cmp(&a.1));
@@ -1261,12 +1415,242 @@ impl Connection {
             "is_file":is_file,
             "info":json!(info).to_string(),
         });
+        audit_log::write("file", &v);
+        self.notify_webhook(
+            webhook::Event::File,
+            format!(
+                "{} {} {} ({} files)",
+                self.describe_peer(),
+                match r#type {
+                    FileAuditType::RemoteSend => "received",
+                    FileAuditType::RemoteReceive => "sent",
+                },
+                path,
+                file_num
+            ),
+            json!({ "path": path, "files": files }),
+        );
+        if self.server_audit_file.is_empty() {
+            return;
+        }
+        let url = self.server_audit_file.clone();
         tokio::spawn(async move {
             allow_err!(Self::post_audit_async(url, v).await);
         });
     }
 
+    /// Make the manifest of the read job finished with `log`.
+    fn make_manifest(&mut self, log: &str) {
+        let Ok(log) = serde_json::from_str::<Value>(log) else {
+            return;
+        };
+        let id = &log["id"];
+        let Some(id) = id
+            .as_i64()
+            .or_else(|| id.as_str().and_then(|s| s.parse().ok()))
+        else {
+            return;
+        };
+        let Some((root, names)) = self.manifest_jobs.remove(&(id as i32)) else {
+            return;
+        };
+        if log["done"].as_bool() != Some(true) {
+            return;
+        }
+        let tx = self.tx_file_threads.clone();
+        crate::file_manifest::spawn_build(id as _, root, names, move |m| {
+            let m = serde_json::to_string(&m).unwrap_or_default();
+            allow_err!(tx.send(ipc::Data::FileManifest((false, m))));
+        });
+    }
+
+    /// Audit the manifest of a finished job, then send it to the peer if the files were read
+    /// here, or check it against the peer's if they were received.
+    async fn handle_file_manifest(&mut self, received: bool, manifest: &str) {
+        let Ok(m) = serde_json::from_str::<crate::file_manifest::Manifest>(manifest) else {
+            return;
+        };
+        let files = m
+            .files
+            .iter()
+            .map(|f| (f.name.clone(), f.size as i64))
+            .collect();
+        let r#type = if received {
+            FileAuditType::RemoteReceive
+        } else {
+            FileAuditType::RemoteSend
+        };
+        self.post_file_audit(r#type, &m.path, files, json!({ "manifest": m }));
+        if received {
+            self.check_upload(false, m).await;
+        } else {
+            let mut msg_out = Message::new();
+            msg_out.set_file_response(m.to_response());
+            self.send(msg_out).await;
+        }
+    }
+
+    /// Check an upload once both the manifest of the peer, `source`, and ours are there.
+    async fn check_upload(&mut self, source: bool, m: crate::file_manifest::Manifest) {
+        let Some(first) = self.unchecked_uploads.get_mut(&m.id) else {
+            return;
+        };
+        let other = match first.take() {
+            Some((other_is_source, other)) if other_is_source != source => other,
+            _ => {
+                *first = Some((source, m));
+                return;
+            }
+        };
+        self.unchecked_uploads.remove(&m.id);
+        let check = if source {
+            crate::file_manifest::compare(&m, &other)
+        } else {
+            crate::file_manifest::compare(&other, &m)
+        };
+        self.post_file_check(true, &check);
+        let mut msg_out = Message::new();
+        msg_out.set_file_response(check.to_response());
+        self.send(msg_out).await;
+    }
+
+    fn post_file_check(&self, received: bool, check: &crate::file_manifest::Check) {
+        let (r#type, path) = if received {
+            (FileAuditType::RemoteReceive, &check.destination)
+        } else {
+            (FileAuditType::RemoteSend, &check.source)
+        };
+        let files = check.mismatched().map(|f| (f.name.clone(), 0)).collect();
+        self.post_file_audit(r#type, path, files, json!({ "check": check }));
+    }
+
+    /// Handle a message of a delta transfer of the peer, see `file_delta`.
+    async fn handle_delta(&mut self, delta: crate::file_delta::Delta, data: Vec<u8>) {
+        use crate::file_delta::Delta;
+        match delta {
+            Delta::Begin { id, path, download } => {
+                if download && crate::get_builtin_option(keys::OPTION_ONE_WAY_FILE_TRANSFER) == "Y"
+                {
+                    let error = "one-way-file-transfer-tip".to_owned();
+                    self.send_delta(&Delta::Error { id, error }, &[]).await;
+                    return;
+                }
+                log::info!(
+                    "Delta {} {}",
+                    if download { "download" } else { "upload" },
+                    path
+                );
+                if download {
+                    let stop = Arc::new(AtomicBool::new(false));
+                    self.delta_downloads.insert(id, (path, stop));
+                } else {
+                    self.send_fs(ipc::FS::DeltaBegin { id, path });
+                }
+                self.send_delta(&Delta::Ack { id }, &[]).await;
+            }
+            Delta::Signatures { id, block_size } => {
+                let Some((path, stop)) = self.delta_downloads.get(&id).cloned() else {
+                    return;
+                };
+                let tx = self.tx_file_threads.clone();
+                std::thread::spawn(move || {
+                    let path = PathBuf::from(path);
+                    crate::file_delta::send(id, &path, block_size, &data, &stop, |delta, data| {
+                        let mut msg = Message::new();
+                        msg.set_file_response(delta.to_response(&data));
+                        match msg.write_to_bytes() {
+                            Ok(bytes) => tx.send(ipc::Data::RawMessage(bytes)).is_ok(),
+                            Err(_) => false,
+                        }
+                    });
+                });
+            }
+            Delta::Copy { .. } | Delta::Literal { .. } | Delta::End { .. } => {
+                let bytes = delta
+                    .to_response(&data)
+                    .write_to_bytes()
+                    .unwrap_or_default();
+                self.send_fs(ipc::FS::Delta(bytes.into()));
+            }
+            Delta::Done {
+                id, ref path, size, ..
+            } => {
+                if let Some((_, stop)) = self.delta_downloads.remove(&id) {
+                    stop.store(true, Ordering::SeqCst);
+                    let files = vec![("".to_owned(), size as i64)];
+                    let info = json!({ "delta": delta });
+                    self.post_file_audit(FileAuditType::RemoteSend, path, files, info);
+                }
+            }
+            Delta::Error { id, .. } => {
+                if let Some((_, stop)) = self.delta_downloads.remove(&id) {
+                    stop.store(true, Ordering::SeqCst);
+                }
+                self.send_fs(ipc::FS::CancelWrite { id });
+            }
+            Delta::Ack { .. } => {}
+        }
+    }
+
+    /// Audit the `Done` of a delta upload from the connection manager and pass it on to the peer.
+    async fn handle_delta_upload_done(&mut self, done: &str) {
+        use crate::file_delta::Delta;
+        let Ok(delta) = serde_json::from_str::<Delta>(done) else {
+            return;
+        };
+        let Delta::Done {
+            id,
+            ref path,
+            size,
+            ref error,
+            ..
+        } = delta
+        else {
+            return;
+        };
+        if !error.is_empty() {
+            self.send_fs(ipc::FS::CancelWrite { id });
+        }
+        let files = vec![("".to_owned(), size as i64)];
+        let info = json!({ "delta": delta });
+        self.post_file_audit(FileAuditType::RemoteReceive, path, files, info);
+        self.send_delta(&delta, &[]).await;
+    }
+
+    async fn send_delta(&mut self, delta: &crate::file_delta::Delta, data: &[u8]) {
+        let mut msg_out = Message::new();
+        msg_out.set_file_response(delta.to_response(data));
+        self.send(msg_out).await;
+    }
+
     pub fn post_alarm_audit(typ: AlarmAuditType, info: Value) {
+        let ip = info["ip"].as_str().unwrap_or_default().to_owned();
+        let threshold = match info["threshold"].as_i64() {
+            Some(n) => format!(" (more than {})", n),
+            None => "".to_owned(),
+        };
+        webhook::notify(
+            webhook::Event::Alarm,
+            webhook::Notification {
+                peer_id: info["id"].as_str().unwrap_or_default().to_owned(),
+                peer_name: info["name"].as_str().unwrap_or_default().to_owned(),
+                message: format!(
+                    "Alarm on {}: {}{} from {}",
+                    Config::get_id(),
+                    typ.description(),
+                    threshold,
+                    ip
+                ),
+                ip,
+                detail: info.clone(),
+            },
+        );
+        let mut v = Value::default();
+        v["id"] = json!(Config::get_id());
+        v["uuid"] = json!(crate::encode64(hbb_common::get_uuid()));
+        v["typ"] = json!(typ as i8);
+        v["info"] = serde_json::Value::String(info.to_string());
+        audit_log::write("alarm", &v);
         let url = crate::get_audit_server(
             Config::get_option("api-server"),
             Config::get_option("custom-rendezvous-server"),
@@ -1275,16 +1659,32 @@ impl Connection {
         if url.is_empty() {
             return;
         }
-        let mut v = Value::default();
-        v["id"] = json!(Config::get_id());
-        v["uuid"] = json!(crate::encode64(hbb_common::get_uuid()));
-        v["typ"] = json!(typ as i8);
-        v["info"] = serde_json::Value::String(info.to_string());
         tokio::spawn(async move {
             allow_err!(Self::post_audit_async(url, v).await);
         });
     }
 
+    fn notify_webhook(&self, event: webhook::Event, message: String, detail: Value) {
+        webhook::notify(
+            event,
+            webhook::Notification {
+                peer_id: self.lr.my_id.clone(),
+                peer_name: self.lr.my_name.clone(),
+                ip: self.ip.clone(),
+                message,
+                detail,
+            },
+        );
+    }
+
+    fn describe_peer(&self) -> String {
+        if self.lr.my_id.is_empty() {
+            format!("from {}", self.ip)
+        } else {
+            format!("{} ({}) from {}", self.lr.my_name, self.lr.my_id, self.ip)
+        }
+    }
+
     #[inline]
     async fn post_audit_async(url: String, v: Value) -> ResultType<String> {
         crate::post_request(url, v.to_string(), "").await
@@ -1295,7 +1695,7 @@ impl Connection {
             return;
         }
         if self.require_2fa.is_some() && !self.is_recent_session(true) && !self.from_switch {
-            self.require_2fa.as_ref().map(|totp| {
+            self.require_2fa.as_ref().map(|factors| {
                 let bot = crate::auth_2fa::TelegramBot::get();
                 let bot = match bot {
                     Ok(Some(bot)) => bot,
@@ -1305,7 +1705,7 @@ impl Connection {
                     }
                     _ => return,
                 };
-                let code = totp.generate_current();
+                let code = factors.generate_current();
                 if let Ok(code) = code {
                     let text = format!(
                         "2FA code: {}\n\nA new connection has been established to your device with ID {}. The source IP address is {}.",
@@ -1326,17 +1726,19 @@ impl Connection {
             return;
         }
         self.authorized = true;
-        let (conn_type, auth_conn_type) = if self.file_transfer.is_some() {
-            (1, AuthConnType::FileTransfer)
-        } else if self.port_forward_socket.is_some() {
-            (2, AuthConnType::PortForward)
-        } else if self.view_camera {
-            (3, AuthConnType::ViewCamera)
-        } else if self.terminal {
-            (4, AuthConnType::Terminal)
-        } else {
-            (0, AuthConnType::Remote)
+        let auth_conn_type = self.auth_conn_type();
+        let conn_type = match auth_conn_type {
+            AuthConnType::Remote => 0,
+            AuthConnType::FileTransfer => 1,
+            AuthConnType::PortForward => 2,
+            AuthConnType::ViewCamera => 3,
+            AuthConnType::Terminal => 4,
         };
+        self.session_limit = session_limit::SessionLimit::new(auth_conn_type);
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        {
+            self.input_policy = input_policy::InputPolicy::new();
+        }
         self.authed_conn_id = Some(self::raii::AuthedConnID::new(
             self.inner.id(),
             auth_conn_type,
@@ -1349,8 +1751,39 @@ impl Connection {
             .unwrap()
             .get(&self.session_key())
             .map(|s| s.last_recv_time.clone());
-        self.post_conn_audit(
-            json!({"peer": ((&self.lr.my_id, &self.lr.my_name)), "type": conn_type}),
+        let mut audit = json!({"peer": ((&self.lr.my_id, &self.lr.my_name)), "type": conn_type});
+        if self.share_viewer {
+            audit["share_viewer"] = json!(true);
+        }
+        if self.trusted_device {
+            audit["trusted_device"] = json!(true);
+            if trusted_device::seen(&self.lr.hwid, &self.ip) {
+                Self::post_alarm_audit(
+                    AlarmAuditType::TrustedDeviceNewIp,
+                    json!({
+                        "ip": self.ip,
+                        "id": self.lr.my_id.clone(),
+                        "name": self.lr.my_name.clone(),
+                    }),
+                );
+            }
+        }
+        if let Some(t) = self.permission_template.as_ref() {
+            audit["permission_template"] = json!(t);
+        }
+        if self.port_forward_socket.is_some() {
+            audit["port_forward"] = json!(self.port_forward_address);
+        }
+        self.post_conn_audit(audit.clone());
+        self.notify_webhook(
+            webhook::Event::Authorize,
+            format!(
+                "{} logged in to {} ({:?})",
+                self.describe_peer(),
+                Config::get_id(),
+                auth_conn_type
+            ),
+            audit,
         );
         #[allow(unused_mut)]
         let mut username = crate::platform::get_active_username();
@@ -1371,13 +1804,7 @@ impl Connection {
             pi.hostname = DEVICE_NAME.lock().unwrap().clone();
             pi.platform = "Android".into();
         }
-        #[cfg(all(target_os = "macos", not(feature = "unix-file-copy-paste")))]
-        let platform_additions = serde_json::Map::new();
-        #[cfg(any(
-            target_os = "windows",
-            target_os = "linux",
-            all(target_os = "macos", feature = "unix-file-copy-paste")
-        ))]
+        #[cfg(any(target_os = "linux", target_os = "windows", target_os = "macos"))]
         let mut platform_additions = serde_json::Map::new();
         #[cfg(target_os = "linux")]
         {
@@ -1429,6 +1856,17 @@ impl Connection {
             platform_additions.insert("support_view_camera".into(), json!(true));
         }
 
+        #[cfg(any(target_os = "linux", target_os = "windows", target_os = "macos"))]
+        if self.terminal {
+            let profiles = super::terminal_profile::names();
+            if !profiles.is_empty() {
+                platform_additions.insert(
+                    super::terminal_profile::PLATFORM_ADDITION_KEY.into(),
+                    json!(profiles),
+                );
+            }
+        }
+
         #[cfg(any(target_os = "linux", target_os = "windows", target_os = "macos"))]
         if !platform_additions.is_empty() {
             pi.platform_additions = serde_json::to_string(&platform_additions).unwrap_or("".into());
@@ -1612,6 +2050,19 @@ impl Connection {
             self.keyboard = false;
             self.send_permission(Permission::Keyboard, false).await;
         } else if sub_service {
+            for name in self.locked_permissions.clone() {
+                let permission = match name {
+                    "keyboard" => Permission::Keyboard,
+                    "clipboard" => Permission::Clipboard,
+                    "audio" => Permission::Audio,
+                    "file" => Permission::File,
+                    "restart" => Permission::Restart,
+                    "recording" => Permission::Recording,
+                    "block_input" => Permission::BlockInput,
+                    _ => continue,
+                };
+                self.send_permission(permission, false).await;
+            }
             if !wait_session_id_confirm {
                 self.try_sub_monitor_services();
             }
@@ -1628,6 +2079,20 @@ impl Connection {
     }
 
     #[inline]
+    fn auth_conn_type(&self) -> AuthConnType {
+        if self.file_transfer.is_some() {
+            AuthConnType::FileTransfer
+        } else if self.port_forward_socket.is_some() {
+            AuthConnType::PortForward
+        } else if self.view_camera {
+            AuthConnType::ViewCamera
+        } else if self.terminal {
+            AuthConnType::Terminal
+        } else {
+            AuthConnType::Remote
+        }
+    }
+
     fn is_remote(&self) -> bool {
         self.file_transfer.is_none()
             && self.port_forward_socket.is_none()
@@ -1749,6 +2214,142 @@ impl Connection {
             && crate::get_builtin_option(keys::OPTION_ONE_WAY_FILE_TRANSFER) != "Y"
     }
 
+    fn request_remote_approval(&mut self) {
+        if self.remote_approval_pending {
+            return;
+        }
+        self.remote_approval_pending = true;
+        let req = remote_approval::Request {
+            peer_id: self.lr.my_id.clone(),
+            peer_name: self.lr.my_name.clone(),
+            ip: self.ip.clone(),
+            conn_type: self.auth_conn_type(),
+        };
+        let tx = self.tx_from_authed.clone();
+        tokio::spawn(async move {
+            let (approved, by) = remote_approval::request(req).await;
+            log::info!("Remote approval: {}, by {}", approved, by);
+            tx.send(if approved {
+                ipc::Data::Authorize
+            } else {
+                ipc::Data::Close
+            })
+            .ok();
+        });
+    }
+
+    // Returns false if the session is closed.
+    async fn check_session_limit(&mut self) -> bool {
+        let Some(limit) = self.session_limit.as_mut() else {
+            return true;
+        };
+        match limit.check(Instant::now()) {
+            session_limit::Check::Ok => true,
+            session_limit::Check::Warn(minutes) => {
+                // The controller translates the placeholder key.
+                self.send_session_limit_msgbox(format!(
+                    "session-closes-in-{{{}}}-minutes-tip",
+                    minutes
+                ))
+                .await;
+                true
+            }
+            session_limit::Check::Close(reason) => {
+                self.send_close_reason_no_retry(reason).await;
+                self.on_close(reason, true).await;
+                false
+            }
+        }
+    }
+
+    async fn send_session_limit_msgbox(&mut self, text: String) {
+        let mut msg_out = Message::new();
+        msg_out.set_message_box(MessageBox {
+            msgtype: "nook-nocancel-hasclose".to_owned(),
+            title: "Session time limit".to_owned(),
+            text,
+            link: "".to_owned(),
+            ..Default::default()
+        });
+        self.send(msg_out).await;
+    }
+
+    async fn answer_session_extension(&mut self, approved: bool) {
+        let Some(limit) = self.session_limit.as_mut() else {
+            return;
+        };
+        let text = match limit.answer_extension(approved) {
+            Some(m) => format!("session-extended-by-{{{}}}-minutes-tip", m),
+            None => "session-extension-denied-tip".to_owned(),
+        };
+        log::info!("Session extension approved: {}", approved);
+        self.send_session_limit_msgbox(text).await;
+    }
+
+    // Returns false if the input is dropped.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    async fn check_input_policy(&mut self, verdict: Option<input_policy::Verdict>) -> bool {
+        let text = match verdict {
+            None | Some(input_policy::Verdict::Allow) => return true,
+            Some(input_policy::Verdict::Blocked(chord)) => {
+                log::info!(
+                    "{} from {} blocked by the input policy",
+                    chord,
+                    self.lr.my_id
+                );
+                format!(
+                    "{} is blocked by the input policy of the remote device.",
+                    chord
+                )
+            }
+            Some(input_policy::Verdict::NeedsApproval(chord, request)) => {
+                if request {
+                    self.send_to_cm(ipc::Data::ChatMessage {
+                        text: format!(
+                            "The peer wants to press {}, reply {} {} to approve",
+                            chord,
+                            input_policy::ALLOW_COMMAND,
+                            chord
+                        ),
+                    });
+                }
+                format!(
+                    "{} needs the approval of the remote user, it has been requested.",
+                    chord
+                )
+            }
+            Some(input_policy::Verdict::RateLimited(notify)) => {
+                if !notify {
+                    return false;
+                }
+                log::info!("Input from {} rate limited", self.lr.my_id);
+                "Input is sent too fast, it is dropped by the input policy of the remote device."
+                    .to_owned()
+            }
+        };
+        let mut msg_out = Message::new();
+        msg_out.set_message_box(MessageBox {
+            msgtype: "nook-nocancel-hasclose".to_owned(),
+            title: "Input blocked".to_owned(),
+            text,
+            link: "".to_owned(),
+            ..Default::default()
+        });
+        self.send(msg_out).await;
+        false
+    }
+
+    async fn send_chat(&mut self, text: String) {
+        let mut misc = Misc::new();
+        misc.set_chat_message(ChatMessage {
+            text,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(msg_out).await;
+    }
+
     fn try_start_cm(&mut self, peer_id: String, name: String, authorized: bool) {
         self.send_to_cm(ipc::Data::Login {
             id: self.inner.id(),
@@ -1769,6 +2370,10 @@ impl Connection {
             block_input: self.block_input,
             from_switch: self.from_switch,
         });
+        if let Some(t) = self.requested_permission_template.as_ref() {
+            let request = (t.name.clone(), t.permissions.clone());
+            self.send_to_cm(ipc::Data::PermissionTemplateRequest(request));
+        }
     }
 
     #[inline]
@@ -1879,9 +2484,151 @@ impl Connection {
                 return true;
             }
         }
+        if self.is_remote() {
+            for token in share_token::valid_tokens() {
+                if self.validate_one_password(token) {
+                    log::info!("Share token login, view only");
+                    self.share_viewer = true;
+                    self.lock_permissions(share_token::DENIED_PERMISSIONS);
+                    return true;
+                }
+            }
+        }
         false
     }
 
+    async fn switch_permission(&mut self, name: String, enabled: bool) {
+        if enabled && self.locked_permissions.iter().any(|x| *x == name) {
+            log::info!("Ignore enabling locked permission {}", name);
+            return;
+        }
+        log::info!("Change permission {} -> {}", name, enabled);
+        if &name == "keyboard" {
+            self.keyboard = enabled;
+            self.send_permission(Permission::Keyboard, enabled).await;
+            if let Some(s) = self.server.upgrade() {
+                s.write().unwrap().subscribe(
+                    super::clipboard_service::NAME,
+                    self.inner.clone(),
+                    self.can_sub_clipboard_service(),
+                );
+                #[cfg(feature = "unix-file-copy-paste")]
+                s.write().unwrap().subscribe(
+                    super::clipboard_service::FILE_NAME,
+                    self.inner.clone(),
+                    self.can_sub_file_clipboard_service(),
+                );
+                s.write().unwrap().subscribe(
+                    NAME_CURSOR,
+                    self.inner.clone(),
+                    enabled || self.show_remote_cursor,
+                );
+            }
+        } else if &name == "clipboard" {
+            self.clipboard = enabled;
+            self.send_permission(Permission::Clipboard, enabled).await;
+            if let Some(s) = self.server.upgrade() {
+                s.write().unwrap().subscribe(
+                    super::clipboard_service::NAME,
+                    self.inner.clone(),
+                    self.can_sub_clipboard_service(),
+                );
+            }
+        } else if &name == "audio" {
+            self.audio = enabled;
+            self.send_permission(Permission::Audio, enabled).await;
+            if self.authorized {
+                if let Some(s) = self.server.upgrade() {
+                    if self.is_authed_view_camera_conn() {
+                        if self.voice_calling || !self.audio_enabled() {
+                            s.write().unwrap().subscribe(
+                                super::audio_service::NAME,
+                                self.inner.clone(),
+                                self.audio_enabled(),
+                            );
+                        }
+                    } else {
+                        s.write().unwrap().subscribe(
+                            super::audio_service::NAME,
+                            self.inner.clone(),
+                            self.audio_enabled(),
+                        );
+                    }
+                }
+            }
+        } else if &name == "file" {
+            self.file = enabled;
+            self.send_permission(Permission::File, enabled).await;
+            #[cfg(feature = "unix-file-copy-paste")]
+            if !enabled {
+                self.try_empty_file_clipboard();
+            }
+            #[cfg(feature = "unix-file-copy-paste")]
+            if let Some(s) = self.server.upgrade() {
+                s.write().unwrap().subscribe(
+                    super::clipboard_service::FILE_NAME,
+                    self.inner.clone(),
+                    self.can_sub_file_clipboard_service(),
+                );
+            }
+        } else if &name == "restart" {
+            self.restart = enabled;
+            self.send_permission(Permission::Restart, enabled).await;
+        } else if &name == "recording" {
+            self.recording = enabled;
+            self.send_permission(Permission::Recording, enabled).await;
+        } else if &name == "block_input" {
+            self.block_input = enabled;
+            self.send_permission(Permission::BlockInput, enabled).await;
+        }
+    }
+
+    async fn handle_permission_template(&mut self, approved: bool) {
+        let Some(t) = self.requested_permission_template.take() else {
+            return;
+        };
+        log::info!(
+            "Permission template {} {}",
+            t.name,
+            if approved { "approved" } else { "denied" }
+        );
+        if approved {
+            for &name in access_policy::PERMISSIONS {
+                let enabled = t.permissions.iter().any(|x| x == name);
+                self.switch_permission(name.to_owned(), enabled).await;
+            }
+            self.lock_permissions(&t.denied_permissions());
+            self.permission_template = Some(t.name.clone());
+        }
+        self.post_conn_audit(json!({
+            "action": "permission_template",
+            "permission_template": t.name,
+            "granted": approved,
+        }));
+    }
+
+    fn lock_permissions(&mut self, names: &[&'static str]) {
+        for &name in names {
+            self.set_permission_field(name, false);
+            if !self.locked_permissions.contains(&name) {
+                self.locked_permissions.push(name);
+            }
+        }
+    }
+
+    fn set_permission_field(&mut self, name: &str, enabled: bool) {
+        match name {
+            "keyboard" => self.keyboard = enabled,
+            "clipboard" => self.clipboard = enabled,
+            "audio" => self.audio = enabled,
+            "file" => self.file = enabled,
+            "restart" => self.restart = enabled,
+            "recording" => self.recording = enabled,
+            "block_input" => self.block_input = enabled,
+            _ => {}
+        }
+    }
+
     fn is_recent_session(&mut self, tfa: bool) -> bool {
         SESSIONS
             .lock()
@@ -1957,8 +2704,14 @@ impl Connection {
                     && device.name == lr.my_name
                     && device.platform == lr.my_platform
                 {
-                    log::info!("2FA bypassed by trusted devices");
-                    self.require_2fa = None;
+                    if trusted_device::expired(device) {
+                        trusted_device::revoke(&[hex::encode(&device.hwid)], "expired");
+                    } else {
+                        // Only marked here, the device is seen once the login is authorized.
+                        log::info!("2FA bypassed by trusted devices");
+                        self.require_2fa = None;
+                        self.trusted_device = true;
+                    }
                 }
             }
         }
@@ -2094,14 +2847,42 @@ impl Connection {
                         pf.port = 3389;
                         is_rdp = true;
                     }
-                    if pf.host.is_empty() {
-                        pf.host = "localhost".to_owned();
-                    }
+                    let (is_udp, host) = crate::udp_forward::parse_host(&pf.host);
+                    let host = if host.is_empty() { "localhost" } else { host }.to_owned();
+                    pf.host = host;
                     let mut addr = format!("{}:{}", pf.host, pf.port);
-                    self.port_forward_address = addr.clone();
-                    match timeout(3000, TcpStream::connect(&addr)).await {
-                        Ok(Ok(sock)) => {
-                            self.port_forward_socket = Some(Framed::new(sock, BytesCodec::new()));
+                    self.port_forward_address = if is_udp {
+                        format!("{}{}", crate::udp_forward::UDP_SCHEME, addr)
+                    } else {
+                        addr.clone()
+                    };
+                    let resolved = match port_forward_acl::resolve(&pf.host, pf.port).await {
+                        Ok(resolved) => resolved,
+                        Err(err) => {
+                            log::warn!("Port forward denied: {}", err);
+                            self.send_login_error(err.to_string()).await;
+                            sleep(1.).await;
+                            return false;
+                        }
+                    };
+                    let socket = if is_udp {
+                        Self::connect_udp_forward(resolved, &addr).await
+                    } else {
+                        let connect = match resolved {
+                            Some(resolved) => timeout(3000, TcpStream::connect(resolved)).await,
+                            None => timeout(3000, TcpStream::connect(&addr)).await,
+                        };
+                        match connect {
+                            Ok(Ok(sock)) => {
+                                Ok(PortForwardSocket::Tcp(Framed::new(sock, BytesCodec::new())))
+                            }
+                            Ok(Err(err)) => Err(err.into()),
+                            Err(err) => Err(err.into()),
+                        }
+                    };
+                    match socket {
+                        Ok(socket) => {
+                            self.port_forward_socket = Some(socket);
                         }
                         _ => {
                             if is_rdp {
@@ -2123,6 +2904,39 @@ impl Connection {
                 }
             }
 
+            let auth_conn_type = self.auth_conn_type();
+            let ctx = access_policy::Context::now(
+                &self.ip,
+                Some(lr.my_id.as_str()),
+                Some(auth_conn_type),
+            );
+            let decision = access_policy::evaluate(&ctx).unwrap_or_default();
+            match decision.action {
+                access_policy::Action::Deny => {
+                    log::info!(
+                        "{} ({}) denied by access policy rule {:?}",
+                        lr.my_id,
+                        self.ip,
+                        decision.rule
+                    );
+                    self.send_login_error("Access denied by policy").await;
+                    sleep(1.).await;
+                    return false;
+                }
+                access_policy::Action::Approve => self.policy_approval = true,
+                access_policy::Action::Allow => {}
+            }
+            self.lock_permissions(&decision.denied_permissions);
+            if let Some(name) = permission_template::requested(&lr) {
+                match permission_template::get(&name) {
+                    Some(t) => {
+                        log::info!("{} requested permission template {}", lr.my_id, t.name);
+                        self.requested_permission_template = Some(t);
+                    }
+                    None => log::warn!("Unknown permission template {:?}", name),
+                }
+            }
+
             #[cfg(not(any(target_os = "android", target_os = "ios")))]
             self.try_start_cm_ipc();
 
@@ -2172,8 +2986,13 @@ impl Connection {
                 && !(crate::get_builtin_option(keys::OPTION_ALLOW_LOGON_SCREEN_PASSWORD) == "Y"
                     && is_logon()))
                 || password::approve_mode() == ApproveMode::Both && !password::has_valid_password()
+                || self.policy_approval
             {
-                self.try_start_cm(lr.my_id, lr.my_name, false);
+                if remote_approval::enabled() {
+                    self.request_remote_approval();
+                } else {
+                    self.try_start_cm(lr.my_id, lr.my_name, false);
+                }
                 if hbb_common::get_version_number(&lr.version)
                     >= hbb_common::get_version_number("1.2.0")
                 {
@@ -2233,32 +3052,32 @@ impl Connection {
             if !res {
                 return true;
             }
-            if let Some(totp) = self.require_2fa.as_ref() {
-                if let Ok(res) = totp.check_current(&tfa.code) {
-                    if res {
-                        self.update_failure(failure, true, 1);
-                        self.require_2fa.take();
-                        raii::AuthedConnID::set_session_2fa(self.session_key());
-                        self.send_logon_response().await;
-                        self.try_start_cm(
-                            self.lr.my_id.to_owned(),
-                            self.lr.my_name.to_owned(),
-                            self.authorized,
-                        );
-                        if !tfa.hwid.is_empty() && Self::enable_trusted_devices() {
-                            Config::add_trusted_device(TrustedDevice {
-                                hwid: tfa.hwid,
-                                time: hbb_common::get_time(),
-                                id: self.lr.my_id.clone(),
-                                name: self.lr.my_name.clone(),
-                                platform: self.lr.my_platform.clone(),
-                            });
-                        }
-                    } else {
-                        self.update_failure(failure, false, 1);
-                        self.send_login_error(crate::client::LOGIN_MSG_2FA_WRONG)
-                            .await;
+            if let Some(factors) = self.require_2fa.as_ref() {
+                if let Some(factor) = factors.check(&tfa.code) {
+                    log::info!("2FA passed with {}", factor);
+                    self.update_failure(failure, true, 1);
+                    self.require_2fa.take();
+                    raii::AuthedConnID::set_session_2fa(self.session_key());
+                    self.send_logon_response().await;
+                    self.try_start_cm(
+                        self.lr.my_id.to_owned(),
+                        self.lr.my_name.to_owned(),
+                        self.authorized,
+                    );
+                    if !tfa.hwid.is_empty() && Self::enable_trusted_devices() {
+                        trusted_device::seen(&tfa.hwid, &self.ip);
+                        Config::add_trusted_device(TrustedDevice {
+                            hwid: tfa.hwid,
+                            time: hbb_common::get_time(),
+                            id: self.lr.my_id.clone(),
+                            name: self.lr.my_name.clone(),
+                            platform: self.lr.my_platform.clone(),
+                        });
                     }
+                } else {
+                    self.update_failure(failure, false, 1);
+                    self.send_login_error(crate::client::LOGIN_MSG_2FA_WRONG)
+                        .await;
                 }
             }
         } else if let Some(message::Union::TestDelay(t)) = msg.union {
@@ -2306,6 +3125,15 @@ impl Connection {
             if self.port_forward_socket.is_some() {
                 return true;
             }
+            // File transfer and terminal sessions are idle without input events.
+            if matches!(
+                msg.union,
+                Some(message::Union::FileAction(_))
+                    | Some(message::Union::FileResponse(_))
+                    | Some(message::Union::TerminalAction(_))
+            ) {
+                self.session_limit.as_mut().map(|l| l.active());
+            }
             match msg.union {
                 #[allow(unused_mut)]
                 Some(message::Union::MouseEvent(mut me)) => {
@@ -2318,6 +3146,21 @@ impl Connection {
                     }
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     if self.peer_keyboard_enabled() {
+                        // Moves are not counted, they come at the rate of the controller's mouse.
+                        if matches!(
+                            me.mask & 0x7,
+                            crate::input::MOUSE_TYPE_DOWN
+                                | crate::input::MOUSE_TYPE_WHEEL
+                                | crate::input::MOUSE_TYPE_TRACKPAD
+                        ) {
+                            let verdict = self
+                                .input_policy
+                                .as_mut()
+                                .map(|p| p.check_rate(Instant::now()));
+                            if !self.check_input_policy(verdict).await {
+                                return true;
+                            }
+                        }
                         if is_left_up(&me) {
                             CLICK_TIME.store(get_time(), Ordering::SeqCst);
                         } else {
@@ -2488,6 +3331,14 @@ impl Connection {
                             }
                         }
 
+                        let verdict = self
+                            .input_policy
+                            .as_mut()
+                            .map(|p| p.check_key(&me, Instant::now()));
+                        if !self.check_input_policy(verdict).await {
+                            return true;
+                        }
+
                         if is_press {
                             match me.union {
                                 Some(key_event::Union::Unicode(_))
@@ -2506,6 +3357,14 @@ impl Connection {
                 }
                 Some(message::Union::Clipboard(cb)) => {
                     if self.clipboard {
+                        self.record_event(
+                            scrap::record::RecordEventKind::Clipboard,
+                            format!(
+                                "{:?}, {} bytes",
+                                cb.format.enum_value_or_default(),
+                                cb.content.len()
+                            ),
+                        );
                         #[cfg(not(any(target_os = "android", target_os = "ios")))]
                         update_clipboard(vec![cb], ClipboardSide::Host);
                         // ios as the controlled side is actually not supported for now.
@@ -2533,6 +3392,22 @@ impl Connection {
                     }
                 }
                 Some(message::Union::MultiClipboards(_mcb)) => {
+                    if self.clipboard {
+                        self.record_event(
+                            scrap::record::RecordEventKind::Clipboard,
+                            _mcb.clipboards
+                                .iter()
+                                .map(|cb| {
+                                    format!(
+                                        "{:?}, {} bytes",
+                                        cb.format.enum_value_or_default(),
+                                        cb.content.len()
+                                    )
+                                })
+                                .collect::<Vec<_>>()
+                                .join("; "),
+                        );
+                    }
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     if self.clipboard {
                         update_clipboard(_mcb.clipboards, ClipboardSide::Host);
@@ -2630,6 +3505,24 @@ impl Connection {
                             }
                             return true;
                         }
+                        if let Some(limit) = crate::file_scheduler::JobLimit::from_action(&fa) {
+                            self.file_scheduler
+                                .set_job(limit.id, limit.kbps, limit.priority);
+                            return true;
+                        }
+                        if let Some(search) = crate::file_search::Search::from_action(&fa) {
+                            // Not allowed with only the printer.
+                            if self.file_transfer.is_some() {
+                                self.search_files(search).await;
+                            }
+                            return true;
+                        }
+                        if let Some(check) = crate::file_manifest::Check::from_action(&fa) {
+                            if crate::file_manifest::enabled() {
+                                self.post_file_check(false, &check);
+                            }
+                            return true;
+                        }
                         if crate::get_builtin_option(keys::OPTION_ONE_WAY_FILE_TRANSFER) == "Y" {
                             let mut job_id = None;
                             match &fa.union {
@@ -2717,12 +3610,34 @@ impl Connection {
                                         self.send(fs::new_error(id, err, 0)).await;
                                     }
                                     Ok(mut job) => {
+                                        if let Some(names) = crate::file_search::selected_names(&s)
+                                        {
+                                            let files = job
+                                                .files()
+                                                .iter()
+                                                .filter(|f| names.contains(&f.name))
+                                                .cloned()
+                                                .collect::<Vec<_>>();
+                                            job.total_size = files.iter().map(|f| f.size).sum();
+                                            job.set_files(files);
+                                        }
                                         self.send(fs::new_dir(id, path, job.files().to_vec()))
                                             .await;
                                         let files = job.files().to_owned();
                                         job.is_remote = true;
                                         job.conn_id = self.inner.id();
                                         let job_type = job.r#type;
+                                        if job_type == fs::JobType::Generic
+                                            && crate::file_manifest::enabled()
+                                        {
+                                            self.manifest_jobs.insert(
+                                                id,
+                                                (
+                                                    PathBuf::from(&s.path),
+                                                    files.iter().map(|f| f.name.clone()).collect(),
+                                                ),
+                                            );
+                                        }
                                         self.read_jobs.push(job);
                                         self.file_timer =
                                             crate::rustdesk_interval(time::interval(MILLI1));
@@ -2767,6 +3682,14 @@ impl Connection {
                                     Self::get_files_for_audit(fs::JobType::Generic, r.files),
                                     json!({}),
                                 );
+                                if crate::file_manifest::enabled() {
+                                    self.unchecked_uploads.insert(r.id, None);
+                                    let mut msg_out = Message::new();
+                                    msg_out.set_file_response(
+                                        crate::file_manifest::request_response(r.id),
+                                    );
+                                    self.send(msg_out).await;
+                                }
                                 self.file_transferred = true;
                             }
                             Some(file_action::Union::RemoveDir(d)) => {
@@ -2803,6 +3726,14 @@ impl Connection {
                             }
                             Some(file_action::Union::Cancel(c)) => {
                                 self.send_fs(ipc::FS::CancelWrite { id: c.id });
+                                self.manifest_jobs.remove(&c.id);
+                                self.unchecked_uploads.remove(&c.id);
+                                if let Some(stop) = self.file_searches.remove(&c.id) {
+                                    stop.store(true, Ordering::SeqCst);
+                                }
+                                if let Some((_, stop)) = self.delta_downloads.remove(&c.id) {
+                                    stop.store(true, Ordering::SeqCst);
+                                }
                                 if let Some(job) = fs::remove_job(c.id, &mut self.read_jobs) {
                                     self.send_to_cm(ipc::Data::FileTransferLog((
                                         "transfer".to_string(),
@@ -2869,6 +3800,17 @@ impl Connection {
                             err: e.error,
                         });
                     }
+                    None => {
+                        if let Some(m) = crate::file_manifest::Manifest::from_response(&fr) {
+                            self.check_upload(true, m).await;
+                        } else if let Some((delta, data)) =
+                            crate::file_delta::Delta::from_response(&fr)
+                        {
+                            if self.file_transfer.is_some() {
+                                self.handle_delta(delta, data).await;
+                            }
+                        }
+                    }
                     _ => {}
                 },
                 Some(message::Union::Misc(misc)) => match misc.union {
@@ -2889,6 +3831,21 @@ impl Connection {
                         self.toggle_privacy_mode(t).await;
                     }
                     Some(misc::Union::ChatMessage(c)) => {
+                        if c.text.trim() == session_limit::EXTEND_COMMAND {
+                            if let Some(m) = self
+                                .session_limit
+                                .as_mut()
+                                .and_then(|l| l.request_extension())
+                            {
+                                self.send_to_cm(ipc::Data::SessionExtensionRequest(m));
+                                self.send_session_limit_msgbox(
+                                    "session-extension-requested-tip".to_owned(),
+                                )
+                                .await;
+                                return true;
+                            }
+                        }
+                        self.record_event(scrap::record::RecordEventKind::Chat, c.text.clone());
                         self.send_to_cm(ipc::Data::ChatMessage { text: c.text });
                         self.chat_unanswered = true;
                         self.update_auto_disconnect_timer();
@@ -3215,6 +4172,7 @@ impl Connection {
         }
         let map_mutex = &LOGIN_FAILURES[i];
         if remove {
+            login_ban::clear(&self.ip);
             if failure.0 != 0 {
                 if let Some((p64, p56, p48)) = self.get_ipv6_prefixes() {
                     let mut m = map_mutex.lock().unwrap();
@@ -3250,7 +4208,7 @@ impl Connection {
         time: i32,
         prefix: &str,
         prefix_num: i8,
-        thresh: i32,
+        policy: &login_ban::Policy,
     ) -> Option<(((i32, i32, i32), i32), bool)> {
         let failure_prefix = LOGIN_FAILURES[i]
             .lock()
@@ -3259,7 +4217,8 @@ impl Connection {
             .copied()
             .unwrap_or((0, 0, 0));
 
-        if failure_prefix.2 > thresh {
+        if failure_prefix.2 > policy.prefix_max(prefix_num as _) {
+            Self::ban_login(prefix, "Too many wrong attempts for IPv6 prefix", policy);
             self.send_login_error(format!(
                 "Too many wrong attempts for IPv6 prefix /{}",
                 prefix_num
@@ -3271,6 +4230,7 @@ impl Connection {
                             "ip": self.ip,
                             "id": self.lr.my_id.clone(),
                             "name": self.lr.my_name.clone(),
+                            "threshold": policy.prefix_max(prefix_num as _),
                 }),
             );
             Some(((failure_prefix, time), false))
@@ -3279,18 +4239,47 @@ impl Connection {
         }
     }
 
+    // Ban `key` and reset its failure counts, the ban takes over from here.
+    fn ban_login(key: &str, reason: &str, policy: &login_ban::Policy) {
+        login_ban::ban(key, reason, policy);
+        for m in LOGIN_FAILURES.iter() {
+            m.lock().unwrap().remove(key);
+        }
+    }
+
     async fn check_failure(&mut self, i: usize) -> (((i32, i32, i32), i32), bool) {
         let time = (get_time() / 60_000) as i32;
+        let policy = login_ban::Policy::get();
+
+        let prefixes = self.get_ipv6_prefixes();
+        let mut keys = vec![self.ip.clone()];
+        if let Some((p64, p56, p48)) = prefixes.clone() {
+            keys.extend([p64, p56, p48]);
+        }
+        if let Some(secs) = keys.iter().filter_map(|k| login_ban::banned(k)).max() {
+            log::warn!("Login from banned {} rejected, {}s left", self.ip, secs);
+            self.send_login_error("Too many wrong attempts").await;
+            return (((0, 0, 0), time), false);
+        }
 
         // IPv6 addresses are cheap to make so we check prefix/netblock as well
-        if let Some((p64, p56, p48)) = self.get_ipv6_prefixes() {
-            if let Some(res) = self.check_failure_ipv6_prefix(i, time, &p64, 64, 60).await {
+        if let Some((p64, p56, p48)) = prefixes {
+            if let Some(res) = self
+                .check_failure_ipv6_prefix(i, time, &p64, 64, &policy)
+                .await
+            {
                 return res;
             }
-            if let Some(res) = self.check_failure_ipv6_prefix(i, time, &p56, 56, 80).await {
+            if let Some(res) = self
+                .check_failure_ipv6_prefix(i, time, &p56, 56, &policy)
+                .await
+            {
                 return res;
             }
-            if let Some(res) = self.check_failure_ipv6_prefix(i, time, &p48, 48, 100).await {
+            if let Some(res) = self
+                .check_failure_ipv6_prefix(i, time, &p48, 48, &policy)
+                .await
+            {
                 return res;
             }
         }
@@ -3303,7 +4292,8 @@ impl Connection {
             .copied()
             .unwrap_or((0, 0, 0));
 
-        let res = if failure.2 > 30 {
+        let res = if failure.2 > policy.max {
+            Self::ban_login(&self.ip, "Too many wrong attempts", &policy);
             self.send_login_error("Too many wrong attempts").await;
             Self::post_alarm_audit(
                 AlarmAuditType::ExceedThirtyAttempts,
@@ -3311,10 +4301,11 @@ impl Connection {
                             "ip": self.ip,
                             "id": self.lr.my_id.clone(),
                             "name": self.lr.my_name.clone(),
+                            "threshold": policy.max,
                 }),
             );
             false
-        } else if time == failure.0 && failure.1 > 6 {
+        } else if time == failure.0 && failure.1 > policy.per_minute {
             self.send_login_error("Please try 1 minute later").await;
             Self::post_alarm_audit(
                 AlarmAuditType::SixAttemptsWithinOneMinute,
@@ -3322,6 +4313,7 @@ impl Connection {
                             "ip": self.ip,
                             "id": self.lr.my_id.clone(),
                             "name": self.lr.my_name.clone(),
+                            "threshold": policy.per_minute,
                 }),
             );
             false
@@ -3345,6 +4337,10 @@ impl Connection {
     async fn handle_switch_display(&mut self, s: SwitchDisplay) {
         let display_idx = s.display as usize;
         if self.display_idx != display_idx {
+            self.record_event(
+                scrap::record::RecordEventKind::DisplaySwitch,
+                format!("display {} -> {}", self.display_idx, display_idx),
+            );
             if let Some(server) = self.server.upgrade() {
                 self.switch_display_to(display_idx, server.clone());
 
@@ -3503,6 +4499,10 @@ impl Connection {
     }
 
     async fn toggle_privacy_mode(&mut self, t: TogglePrivacyMode) {
+        self.record_event(
+            scrap::record::RecordEventKind::PrivacyMode,
+            format!("{} {}", if t.on { "on" } else { "off" }, t.impl_key),
+        );
         if t.on {
             self.turn_on_privacy(t.impl_key).await;
         } else {
@@ -4027,6 +5027,37 @@ impl Connection {
         });
     }
 
+    async fn search_files(&mut self, search: crate::file_search::Search) {
+        self.file_searches
+            .retain(|_, stop| Arc::strong_count(stop) > 1);
+        if self.file_searches.len() >= crate::file_search::MAX_RUNNING {
+            let results = crate::file_search::Results {
+                id: search.id,
+                done: true,
+                error: "Too many searches".to_owned(),
+                ..Default::default()
+            };
+            let mut msg_out = Message::new();
+            msg_out.set_file_response(results.to_response());
+            self.send(msg_out).await;
+            return;
+        }
+        log::info!("Search {} for {:?}", search.path, search.query);
+        let stop = Arc::new(AtomicBool::new(false));
+        self.file_searches.insert(search.id, stop.clone());
+        let tx = self.tx_file_threads.clone();
+        std::thread::spawn(move || {
+            crate::file_search::run(&search, &stop, |results| {
+                let mut msg = Message::new();
+                msg.set_file_response(results.to_response());
+                match msg.write_to_bytes() {
+                    Ok(bytes) => tx.send(ipc::Data::RawMessage(bytes)).is_ok(),
+                    Err(_) => false,
+                }
+            });
+        });
+    }
+
     #[inline]
     async fn send(&mut self, msg: Message) {
         allow_err!(self.stream.send(&msg).await);
@@ -4110,6 +5141,7 @@ impl Connection {
         self.auto_disconnect_timer
             .as_mut()
             .map(|t| t.0 = Instant::now());
+        self.session_limit.as_mut().map(|l| l.active());
     }
 
     #[cfg(feature = "hwcodec")]
@@ -4288,6 +5320,11 @@ impl Connection {
             Some(self.terminal_persistent),
             user_token.to_terminal_service_token(),
         );
+        if let Some(terminal_action::Union::Data(data)) = &action.union {
+            if super::terminal_exec::is_exec_id(data.terminal_id) {
+                proxy.set_exec_audit(Box::new(self.conn_auditor()));
+            }
+        }
 
         match proxy.handle_action(&action) {
             Ok(Some(response)) => {
@@ -4451,6 +5488,9 @@ async fn start_ipc(
                             compressed}) = data {
                                 stream.send(&Data::FS(ipc::FS::WriteBlock{id, file_num, data: Bytes::new(), compressed})).await?;
                                 stream.send_raw(data).await?;
+                        } else if let Data::FS(ipc::FS::Delta(data)) = data {
+                            stream.send(&Data::FS(ipc::FS::Delta(Bytes::new()))).await?;
+                            stream.send_raw(data).await?;
                         } else {
                             stream.send(&data).await?;
                         }
@@ -4482,6 +5522,19 @@ pub enum AlarmAuditType {
     // MultipleLoginsAttemptsWithinOneMinute = 4,
     // MultipleLoginsAttemptsWithinOneHour = 5,
     ExceedIPv6PrefixAttempts = 6,
+    TrustedDeviceNewIp = 7,
+}
+
+impl AlarmAuditType {
+    fn description(&self) -> &'static str {
+        match self {
+            AlarmAuditType::IpWhitelist => "ip not in whitelist",
+            AlarmAuditType::ExceedThirtyAttempts => "too many wrong attempts",
+            AlarmAuditType::SixAttemptsWithinOneMinute => "too many wrong attempts per minute",
+            AlarmAuditType::ExceedIPv6PrefixAttempts => "too many wrong attempts for ipv6 prefix",
+            AlarmAuditType::TrustedDeviceNewIp => "trusted device from a new ip",
+        }
+    }
 }
 
 pub enum FileAuditType {
@@ -0,0 +1,541 @@
+// Key chord filtering and rate limiting of the input injected by the controller.
+//
+// Set by the `input-policy` option as a json object, e.g.
+//   {"blocked": ["ctrl+alt+del", "alt+f4", "alt+sysrq"],
+//    "approve": ["win+l"],
+//    "max_events_per_second": 100}
+// Chords are modifiers (ctrl, alt, shift, win) and a key joined by `+`, a chord also matches
+// when more modifiers are held. Blocked chords are dropped. Chords to approve are dropped
+// until the connection manager answers the chat request with `/allow <chord>`, then they are
+// allowed for the rest of the connection. The held modifiers are tracked from the key events
+// themselves, the map and translate modes send them as separate key events. Key presses, mouse
+// button presses and wheel events above `max_events_per_second` are dropped, releases always
+// pass so that nothing is left pressed. The controller is told about the dropped input with a
+// message box.
+
+use hbb_common::{
+    config::Config,
+    log,
+    message_proto::{key_event, ControlKey, KeyEvent, KeyboardMode},
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{fmt, time::Instant};
+
+pub const OPTION_INPUT_POLICY: &str = "input-policy";
+pub const ALLOW_COMMAND: &str = "/allow";
+
+// Seconds between two notices about rate limited input.
+const RATE_NOTICE_INTERVAL: u64 = 5;
+
+const CTRL: u8 = 1;
+const ALT: u8 = 2;
+const SHIFT: u8 = 4;
+const META: u8 = 8;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Policy {
+    #[serde(default)]
+    blocked: Vec<String>,
+    #[serde(default)]
+    approve: Vec<String>,
+    #[serde(default)]
+    max_events_per_second: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chord {
+    modifiers: u8,
+    key: String,
+}
+
+fn key_alias(key: &str) -> String {
+    let key = key.trim().to_lowercase();
+    match key.as_str() {
+        "del" => "delete".to_owned(),
+        "sysrq" | "prtsc" | "print" | "snapshot" => "printscreen".to_owned(),
+        "esc" => "escape".to_owned(),
+        "enter" => "return".to_owned(),
+        _ => {
+            if key.len() == 1 && key.chars().all(|c| c.is_ascii_digit()) {
+                format!("num{}", key)
+            } else {
+                key
+            }
+        }
+    }
+}
+
+impl Chord {
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut modifiers = 0;
+        let mut key = None;
+        for part in s.split('+') {
+            match part.trim().to_lowercase().as_str() {
+                "ctrl" | "control" => modifiers |= CTRL,
+                "alt" => modifiers |= ALT,
+                "shift" => modifiers |= SHIFT,
+                "win" | "meta" | "super" | "cmd" => modifiers |= META,
+                "" => return None,
+                k => {
+                    if key.is_some() {
+                        return None;
+                    }
+                    key = Some(key_alias(k));
+                }
+            }
+        }
+        Some(Self {
+            modifiers,
+            key: key?,
+        })
+    }
+
+    fn matches(&self, pressed: &Chord) -> bool {
+        self.key == pressed.key && pressed.modifiers & self.modifiers == self.modifiers
+    }
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (m, name) in [
+            (CTRL, "Ctrl"),
+            (ALT, "Alt"),
+            (SHIFT, "Shift"),
+            (META, "Win"),
+        ] {
+            if self.modifiers & m != 0 {
+                write!(f, "{}+", name)?;
+            }
+        }
+        let mut chars = self.key.chars();
+        match chars.next() {
+            Some(c) => write!(f, "{}{}", c.to_uppercase(), chars.as_str()),
+            None => Ok(()),
+        }
+    }
+}
+
+fn control_key_modifier(ck: ControlKey) -> u8 {
+    match ck {
+        ControlKey::Control | ControlKey::RControl => CTRL,
+        ControlKey::Alt | ControlKey::RAlt => ALT,
+        ControlKey::Shift | ControlKey::RShift => SHIFT,
+        ControlKey::Meta | ControlKey::RWin => META,
+        _ => 0,
+    }
+}
+
+fn rdev_key_modifier(key: &rdev::Key) -> u8 {
+    match key {
+        rdev::Key::ControlLeft | rdev::Key::ControlRight => CTRL,
+        rdev::Key::Alt | rdev::Key::AltGr => ALT,
+        rdev::Key::ShiftLeft | rdev::Key::ShiftRight => SHIFT,
+        rdev::Key::MetaLeft | rdev::Key::MetaRight => META,
+        _ => 0,
+    }
+}
+
+// The key of a map or translate mode event.
+fn rdev_key(evt: &KeyEvent) -> Option<rdev::Key> {
+    let Some(key_event::Union::Chr(code)) = evt.union else {
+        return None;
+    };
+    match evt.mode.enum_value() {
+        Ok(KeyboardMode::Map) => Some(crate::keyboard::keycode_to_rdev_key(code)),
+        Ok(KeyboardMode::Translate) => {
+            Some(crate::keyboard::keycode_to_rdev_key(code & 0x0000FFFF))
+        }
+        _ => None,
+    }
+}
+
+/// The modifier `evt` presses or releases, 0 if it is not a modifier key.
+fn event_modifier(evt: &KeyEvent) -> u8 {
+    match &evt.union {
+        Some(key_event::Union::ControlKey(ck)) => {
+            ck.enum_value().map(control_key_modifier).unwrap_or(0)
+        }
+        _ => rdev_key(evt).map(|k| rdev_key_modifier(&k)).unwrap_or(0),
+    }
+}
+
+/// The chord pressed by `evt` with the `held` modifiers, `None` for modifiers and unknown keys.
+pub fn chord(evt: &KeyEvent, held: u8) -> Option<Chord> {
+    if event_modifier(evt) != 0 {
+        return None;
+    }
+    let mut modifiers = evt
+        .modifiers
+        .iter()
+        .filter_map(|m| m.enum_value().ok())
+        .fold(held, |acc, m| acc | control_key_modifier(m));
+    let key = match &evt.union {
+        Some(key_event::Union::ControlKey(ck)) => match ck.enum_value() {
+            Ok(ControlKey::CtrlAltDel) => {
+                modifiers |= CTRL | ALT;
+                "delete".to_owned()
+            }
+            Ok(ControlKey::LockScreen) => {
+                modifiers |= META;
+                "l".to_owned()
+            }
+            Ok(ck) => key_alias(&format!("{:?}", ck)),
+            Err(_) => return None,
+        },
+        Some(key_event::Union::Chr(code)) => match rdev_key(evt) {
+            Some(key) => rdev_key_name(key),
+            None => key_alias(&char::from_u32(*code)?.to_string()),
+        },
+        // The virtual key code in the high 16 bits, see `keyboard::try_fill_win2win_hotkey`.
+        Some(key_event::Union::Win2winHotkey(code)) => vk_key_name(code >> 16)?,
+        _ => return None,
+    };
+    Some(Chord { modifiers, key })
+}
+
+// The name of a Windows virtual key code.
+fn vk_key_name(vk: u32) -> Option<String> {
+    let name = match vk {
+        0x08 => "backspace",
+        0x09 => "tab",
+        0x0D => "return",
+        0x1B => "escape",
+        0x20 => "space",
+        0x2C => "printscreen",
+        0x2D => "insert",
+        0x2E => "delete",
+        0x30..=0x39 => return Some(format!("num{}", vk - 0x30)),
+        0x41..=0x5A => return char::from_u32(vk).map(|c| c.to_ascii_lowercase().to_string()),
+        0x70..=0x87 => return Some(format!("f{}", vk - 0x6F)),
+        _ => return None,
+    };
+    Some(name.to_owned())
+}
+
+fn rdev_key_name(key: rdev::Key) -> String {
+    let name = format!("{:?}", key).to_lowercase();
+    match name.strip_prefix("key") {
+        Some(k) if !k.is_empty() => k.to_owned(),
+        _ => key_alias(&name),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    Blocked(String),
+    /// The chord and whether the connection manager has to be asked.
+    NeedsApproval(String, bool),
+    /// Whether the controller has to be told.
+    RateLimited(bool),
+}
+
+#[derive(Debug)]
+struct RateLimit {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+    last_notice: Option<Instant>,
+}
+
+impl RateLimit {
+    fn take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn notice(&mut self, now: Instant) -> bool {
+        match self.last_notice {
+            Some(t) if now.saturating_duration_since(t).as_secs() < RATE_NOTICE_INTERVAL => false,
+            _ => {
+                self.last_notice = Some(now);
+                true
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InputPolicy {
+    // The modifiers held down.
+    modifiers: u8,
+    blocked: Vec<Chord>,
+    approve: Vec<Chord>,
+    requested: Vec<Chord>,
+    approved: Vec<Chord>,
+    rate_limit: Option<RateLimit>,
+}
+
+fn parse_chords(chords: &[String]) -> Vec<Chord> {
+    chords
+        .iter()
+        .filter_map(|s| {
+            let chord = Chord::parse(s);
+            if chord.is_none() {
+                log::error!("Invalid chord in {}: {}", OPTION_INPUT_POLICY, s);
+            }
+            chord
+        })
+        .collect()
+}
+
+impl InputPolicy {
+    /// The configured policy, `None` if there is none.
+    pub fn new() -> Option<Self> {
+        let s = Config::get_option(OPTION_INPUT_POLICY);
+        if s.trim().is_empty() {
+            return None;
+        }
+        match serde_json::from_str::<Policy>(&s) {
+            Ok(policy) => Some(Self::with_policy(&policy, Instant::now())),
+            Err(e) => {
+                log::error!("Invalid {}: {}", OPTION_INPUT_POLICY, e);
+                None
+            }
+        }
+    }
+
+    fn with_policy(policy: &Policy, now: Instant) -> Self {
+        let rate = policy.max_events_per_second as f64;
+        Self {
+            modifiers: 0,
+            blocked: parse_chords(&policy.blocked),
+            approve: parse_chords(&policy.approve),
+            requested: vec![],
+            approved: vec![],
+            rate_limit: (policy.max_events_per_second > 0).then(|| RateLimit {
+                rate,
+                tokens: rate,
+                last: now,
+                last_notice: None,
+            }),
+        }
+    }
+
+    /// Check a key event, releases are always allowed.
+    pub fn check_key(&mut self, evt: &KeyEvent, now: Instant) -> Verdict {
+        let modifier = event_modifier(evt);
+        if evt.down {
+            self.modifiers |= modifier;
+        } else if !evt.press {
+            self.modifiers &= !modifier;
+            return Verdict::Allow;
+        }
+        if let Some(chord) = chord(evt, self.modifiers) {
+            if let Some(c) = self.blocked.iter().find(|c| c.matches(&chord)) {
+                return Verdict::Blocked(c.to_string());
+            }
+            if let Some(c) = self.approve.iter().find(|c| c.matches(&chord)) {
+                if !self.approved.contains(c) {
+                    let request = !self.requested.contains(c);
+                    if request {
+                        self.requested.push(c.clone());
+                    }
+                    return Verdict::NeedsApproval(c.to_string(), request);
+                }
+            }
+        }
+        self.check_rate(now)
+    }
+
+    /// Check a mouse button press or wheel event.
+    pub fn check_rate(&mut self, now: Instant) -> Verdict {
+        match self.rate_limit.as_mut() {
+            Some(limit) if !limit.take(now) => Verdict::RateLimited(limit.notice(now)),
+            _ => Verdict::Allow,
+        }
+    }
+
+    /// Handle an `/allow <chord>` answer from the connection manager, returns the approved chord.
+    pub fn allow(&mut self, text: &str) -> Option<String> {
+        let chord = Chord::parse(text.trim().strip_prefix(ALLOW_COMMAND)?)?;
+        let i = self.requested.iter().position(|c| *c == chord)?;
+        let chord = self.requested.remove(i);
+        let name = chord.to_string();
+        self.approved.push(chord);
+        Some(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rdev::Key;
+    use std::time::Duration;
+
+    fn code(key: Key) -> u32 {
+        #[cfg(target_os = "windows")]
+        return rdev::win_scancode_from_key(key).unwrap_or_default() as _;
+        #[cfg(target_os = "linux")]
+        return rdev::linux_keycode_from_key(key).unwrap_or_default() as _;
+        #[cfg(target_os = "macos")]
+        return rdev::macos_keycode_from_key(key).unwrap_or_default() as _;
+    }
+
+    fn chr(mode: KeyboardMode, key: Key, down: bool) -> KeyEvent {
+        let mut evt = KeyEvent::new();
+        evt.set_chr(code(key));
+        evt.mode = mode.into();
+        evt.down = down;
+        evt
+    }
+
+    fn map(key: Key, down: bool) -> KeyEvent {
+        chr(KeyboardMode::Map, key, down)
+    }
+
+    fn translate(key: Key, down: bool) -> KeyEvent {
+        chr(KeyboardMode::Translate, key, down)
+    }
+
+    fn hotkey(vk: u32, down: bool) -> KeyEvent {
+        let mut evt = map(Key::Alt, down);
+        evt.set_win2win_hotkey(vk << 16);
+        evt
+    }
+
+    fn policy(max_events_per_second: u32) -> InputPolicy {
+        let policy = Policy {
+            blocked: vec![
+                "ctrl+alt+del".to_owned(),
+                "alt+sysrq".to_owned(),
+                "alt+f4".to_owned(),
+            ],
+            approve: vec!["win+l".to_owned()],
+            max_events_per_second,
+        };
+        InputPolicy::with_policy(&policy, Instant::now())
+    }
+
+    fn check(p: &mut InputPolicy, events: &[KeyEvent]) -> Vec<Verdict> {
+        let now = Instant::now();
+        events.iter().map(|evt| p.check_key(evt, now)).collect()
+    }
+
+    fn blocked(chord: &str) -> Verdict {
+        Verdict::Blocked(chord.to_owned())
+    }
+
+    #[test]
+    fn test_chord() {
+        let ctrl_alt_del = Chord::parse("Ctrl+Alt+Del").unwrap();
+        assert_eq!(ctrl_alt_del.to_string(), "Ctrl+Alt+Delete");
+        assert!(Chord::parse("ctrl+").is_none());
+        assert!(Chord::parse("a+b").is_none());
+        assert!(ctrl_alt_del.matches(&Chord::parse("ctrl+shift+alt+delete").unwrap()));
+        assert!(!ctrl_alt_del.matches(&Chord::parse("ctrl+delete").unwrap()));
+
+        assert_eq!(chord(&map(Key::KeyL, true), META), Chord::parse("win+l"));
+        assert_eq!(
+            chord(&translate(Key::F4, true), ALT),
+            Chord::parse("alt+f4")
+        );
+        assert_eq!(chord(&map(Key::ControlLeft, true), 0), None);
+        assert_eq!(chord(&hotkey(0x73, true), ALT), Chord::parse("alt+f4"));
+        let mut evt = KeyEvent::new();
+        evt.set_control_key(ControlKey::CtrlAltDel);
+        assert_eq!(chord(&evt, 0), Some(ctrl_alt_del));
+    }
+
+    #[test]
+    fn test_map_mode() {
+        let mut p = policy(0);
+        let verdicts = check(
+            &mut p,
+            &[
+                map(Key::Alt, true),
+                map(Key::F4, true),
+                map(Key::F4, false),
+                map(Key::Alt, false),
+                map(Key::F4, true),
+                map(Key::F4, false),
+            ],
+        );
+        assert_eq!(verdicts[1], blocked("Alt+F4"));
+        // F4 alone passes once Alt is released.
+        assert!(verdicts
+            .iter()
+            .enumerate()
+            .all(|(i, v)| i == 1 || *v == Verdict::Allow));
+        let verdicts = check(
+            &mut p,
+            &[
+                map(Key::ControlLeft, true),
+                map(Key::Alt, true),
+                map(Key::Delete, true),
+                map(Key::Delete, false),
+                map(Key::Alt, false),
+                map(Key::Delete, true),
+            ],
+        );
+        assert_eq!(verdicts[2], blocked("Ctrl+Alt+Delete"));
+        assert_eq!(verdicts[5], Verdict::Allow);
+        // The hotkeys of a Windows controller are sent with the held modifiers as map events.
+        let verdicts = check(
+            &mut p,
+            &[
+                map(Key::ControlLeft, false),
+                map(Key::Alt, true),
+                hotkey(0x73, true),
+                hotkey(0x73, false),
+            ],
+        );
+        assert_eq!(verdicts[2], blocked("Alt+F4"));
+        assert_eq!(verdicts[3], Verdict::Allow);
+    }
+
+    #[test]
+    fn test_translate_mode() {
+        let mut p = policy(0);
+        let mut win_l = vec![translate(Key::MetaLeft, true), translate(Key::KeyL, true)];
+        assert_eq!(
+            check(&mut p, &win_l),
+            vec![
+                Verdict::Allow,
+                Verdict::NeedsApproval("Win+L".to_owned(), true)
+            ]
+        );
+        win_l.push(translate(Key::KeyL, false));
+        win_l.push(translate(Key::MetaLeft, false));
+        assert_eq!(
+            check(&mut p, &win_l)[1],
+            Verdict::NeedsApproval("Win+L".to_owned(), false)
+        );
+        assert_eq!(p.allow("/allow win+a"), None);
+        assert_eq!(p.allow("/allow Win+L"), Some("Win+L".to_owned()));
+        assert_eq!(check(&mut p, &win_l), vec![Verdict::Allow; 4]);
+        assert_eq!(
+            check(
+                &mut p,
+                &[translate(Key::Alt, true), translate(Key::PrintScreen, true)]
+            )[1],
+            blocked("Alt+Printscreen")
+        );
+    }
+
+    #[test]
+    fn test_rate() {
+        let mut p = policy(2);
+        let now = Instant::now();
+        assert_eq!(p.check_key(&map(Key::KeyA, true), now), Verdict::Allow);
+        assert_eq!(p.check_key(&map(Key::KeyA, false), now), Verdict::Allow);
+        assert_eq!(p.check_rate(now), Verdict::Allow);
+        assert_eq!(p.check_rate(now), Verdict::RateLimited(true));
+        assert_eq!(
+            p.check_key(&map(Key::KeyA, true), now),
+            Verdict::RateLimited(false)
+        );
+        assert_eq!(p.check_key(&map(Key::KeyA, false), now), Verdict::Allow);
+        assert_eq!(
+            p.check_rate(now + Duration::from_millis(500)),
+            Verdict::Allow
+        );
+    }
+}
@@ -0,0 +1,205 @@
+// Brute-force lockout policy and the persistent ban list.
+//
+// Wrong password and 2FA attempts are counted per ip and IPv6 /64, /56 and /48 prefix in
+// `LOGIN_FAILURES`. More than `login-fail-per-minute` attempts in one minute are rejected until
+// the next minute. More than `login-fail-max` attempts (2x, 8/3x and 10/3x for the prefixes)
+// ban the ip or prefix for `login-ban-minutes`, doubled for each following ban up to
+// `login-ban-max-minutes`. Bans are saved in `login_bans.json`, so restarting the service
+// does not lift them, and can be listed or removed with `rustdesk --login-bans`.
+
+use hbb_common::{config::Config, log};
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::HashMap, path::PathBuf, sync::Mutex};
+
+pub const OPTION_LOGIN_FAIL_PER_MINUTE: &str = "login-fail-per-minute";
+pub const OPTION_LOGIN_FAIL_MAX: &str = "login-fail-max";
+pub const OPTION_LOGIN_BAN_MINUTES: &str = "login-ban-minutes";
+pub const OPTION_LOGIN_BAN_MAX_MINUTES: &str = "login-ban-max-minutes";
+
+const DEFAULT_PER_MINUTE: i32 = 6;
+const DEFAULT_MAX: i32 = 30;
+const DEFAULT_BAN_MINUTES: i64 = 10;
+const DEFAULT_BAN_MAX_MINUTES: i64 = 24 * 60;
+
+lazy_static::lazy_static! {
+    static ref BANS: Mutex<Option<HashMap<String, Ban>>> = Default::default();
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Policy {
+    pub per_minute: i32,
+    pub max: i32,
+    pub ban_minutes: i64,
+    pub ban_max_minutes: i64,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            per_minute: DEFAULT_PER_MINUTE,
+            max: DEFAULT_MAX,
+            ban_minutes: DEFAULT_BAN_MINUTES,
+            ban_max_minutes: DEFAULT_BAN_MAX_MINUTES,
+        }
+    }
+}
+
+fn get_number<T: std::str::FromStr + PartialOrd + Default>(name: &str, default: T) -> T {
+    match Config::get_option(name).trim().parse::<T>() {
+        Ok(v) if v > T::default() => v,
+        _ => default,
+    }
+}
+
+impl Policy {
+    pub fn get() -> Self {
+        let ban_minutes = get_number(OPTION_LOGIN_BAN_MINUTES, DEFAULT_BAN_MINUTES);
+        Self {
+            per_minute: get_number(OPTION_LOGIN_FAIL_PER_MINUTE, DEFAULT_PER_MINUTE),
+            max: get_number(OPTION_LOGIN_FAIL_MAX, DEFAULT_MAX),
+            ban_minutes,
+            ban_max_minutes: get_number(OPTION_LOGIN_BAN_MAX_MINUTES, DEFAULT_BAN_MAX_MINUTES)
+                .max(ban_minutes),
+        }
+    }
+
+    /// The threshold of an IPv6 prefix, larger for shorter prefixes.
+    pub fn prefix_max(&self, prefix: u8) -> i32 {
+        match prefix {
+            64 => self.max * 2,
+            56 => self.max * 8 / 3,
+            _ => self.max * 10 / 3,
+        }
+    }
+
+    // The ban length in seconds after `count` earlier bans.
+    fn ban_secs(&self, count: u32) -> i64 {
+        let minutes = self.ban_minutes.saturating_mul(1 << count.min(20));
+        minutes.min(self.ban_max_minutes) * 60
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Ban {
+    /// The ip or IPv6 prefix.
+    pub key: String,
+    /// Unix time in seconds.
+    pub until: i64,
+    /// Number of bans so far, for the backoff.
+    pub count: u32,
+    pub reason: String,
+}
+
+fn path() -> PathBuf {
+    Config::path("login_bans.json")
+}
+
+fn now() -> i64 {
+    hbb_common::get_time() / 1000
+}
+
+fn with_bans<T>(f: impl FnOnce(&mut HashMap<String, Ban>) -> T) -> T {
+    let mut lock = BANS.lock().unwrap();
+    let bans = lock.get_or_insert_with(|| {
+        std::fs::read_to_string(path())
+            .ok()
+            .and_then(|s| serde_json::from_str::<Vec<Ban>>(&s).ok())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|b| (b.key.clone(), b))
+            .collect()
+    });
+    f(bans)
+}
+
+fn save(bans: &HashMap<String, Ban>) {
+    let mut v: Vec<_> = bans.values().cloned().collect();
+    v.sort_by(|a, b| a.key.cmp(&b.key));
+    let res = serde_json::to_string(&v)
+        .map_err(|e| e.to_string())
+        .and_then(|s| std::fs::write(path(), s).map_err(|e| e.to_string()));
+    if let Err(e) = res {
+        log::error!("Failed to save login bans: {}", e);
+    }
+}
+
+/// Seconds left of the ban of `key`, if it is banned.
+pub fn banned(key: &str) -> Option<i64> {
+    let now = now();
+    with_bans(|bans| bans.get(key).map(|b| b.until - now).filter(|x| *x > 0))
+}
+
+/// Ban `key`, returns the ban length in seconds.
+pub fn ban(key: &str, reason: &str, policy: &Policy) -> i64 {
+    let now = now();
+    with_bans(|bans| {
+        // Forget bans ended long ago, so that their backoff does not apply any more.
+        let forget = policy.ban_max_minutes * 60;
+        bans.retain(|_, b| b.until + forget > now);
+        let count = bans.get(key).map(|b| b.count).unwrap_or(0);
+        let secs = policy.ban_secs(count);
+        bans.insert(
+            key.to_owned(),
+            Ban {
+                key: key.to_owned(),
+                until: now + secs,
+                count: count + 1,
+                reason: reason.to_owned(),
+            },
+        );
+        save(bans);
+        log::warn!("Login banned {} for {}s: {}", key, secs, reason);
+        secs
+    })
+}
+
+/// Forget `key` after a successful login.
+pub fn clear(key: &str) {
+    with_bans(|bans| {
+        if bans.remove(key).is_some() {
+            save(bans);
+        }
+    });
+}
+
+/// The active bans.
+pub fn list() -> Vec<Ban> {
+    let now = now();
+    let mut v: Vec<_> =
+        with_bans(|bans| bans.values().filter(|b| b.until > now).cloned().collect());
+    v.sort_by(|a, b| a.key.cmp(&b.key));
+    v
+}
+
+/// Lift the bans of `keys`, or all bans if empty.
+pub fn remove(keys: &[String]) {
+    with_bans(|bans| {
+        if keys.is_empty() {
+            bans.clear();
+        } else {
+            for key in keys {
+                bans.remove(key);
+            }
+        }
+        save(bans);
+    });
+    log::info!("Login bans removed: {:?}", keys);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy() {
+        let p = Policy::default();
+        assert_eq!(p.prefix_max(64), 60);
+        assert_eq!(p.prefix_max(56), 80);
+        assert_eq!(p.prefix_max(48), 100);
+        assert_eq!(p.ban_secs(0), 600);
+        assert_eq!(p.ban_secs(1), 1200);
+        assert_eq!(p.ban_secs(3), 4800);
+        assert_eq!(p.ban_secs(10), 24 * 3600);
+        assert_eq!(p.ban_secs(100), 24 * 3600);
+    }
+}
@@ -0,0 +1,118 @@
+// Named permission sets the controlling side can ask for at login.
+//
+// Templates are set by the `permission-templates` option as a json array, e.g.
+//   [{"name": "view only", "permissions": ["audio"]},
+//    {"name": "support", "permissions": ["keyboard", "clipboard", "audio", "file"]}]
+// and default to "view only", "support" and "full admin". The controller requests one with the
+// `permission-template` peer option, sent in the `LOGIN_REQUEST_FIELD_PERMISSION_TEMPLATE` field
+// of the login request. The connection manager approves or denies the request: on approval the
+// permissions of the template are enabled, the others are disabled and locked. The decision is
+// added to the connection audit.
+
+use super::access_policy::PERMISSIONS;
+use crate::client::LOGIN_REQUEST_FIELD_PERMISSION_TEMPLATE;
+use hbb_common::{
+    config::Config,
+    log,
+    message_proto::LoginRequest,
+    protobuf::{Message as _, UnknownValueRef},
+};
+use serde_derive::{Deserialize, Serialize};
+
+pub const OPTION_PERMISSION_TEMPLATES: &str = "permission-templates";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Template {
+    pub name: String,
+    #[serde(default)]
+    pub permissions: Vec<String>,
+}
+
+impl Template {
+    /// Permissions to disable and lock.
+    pub fn denied_permissions(&self) -> Vec<&'static str> {
+        PERMISSIONS
+            .iter()
+            .filter(|p| !self.permissions.iter().any(|x| x == *p))
+            .copied()
+            .collect()
+    }
+}
+
+fn builtin() -> Vec<Template> {
+    let template = |name: &str, permissions: &[&str]| Template {
+        name: name.to_owned(),
+        permissions: permissions.iter().map(|x| x.to_string()).collect(),
+    };
+    vec![
+        template("view only", &["audio"]),
+        template("support", &["keyboard", "clipboard", "audio", "file"]),
+        template("full admin", PERMISSIONS),
+    ]
+}
+
+fn parse(s: &str) -> Vec<Template> {
+    if s.trim().is_empty() {
+        return builtin();
+    }
+    serde_json::from_str::<Vec<Template>>(s).unwrap_or_else(|e| {
+        log::error!("Invalid {}: {}", OPTION_PERMISSION_TEMPLATES, e);
+        vec![]
+    })
+}
+
+pub fn list() -> Vec<Template> {
+    parse(&Config::get_option(OPTION_PERMISSION_TEMPLATES))
+}
+
+/// The template named `name`, case insensitive.
+pub fn get(name: &str) -> Option<Template> {
+    list()
+        .into_iter()
+        .find(|t| t.name.eq_ignore_ascii_case(name.trim()))
+}
+
+/// The name of the permission template requested in `lr`.
+pub fn requested(lr: &LoginRequest) -> Option<String> {
+    match lr
+        .unknown_fields()
+        .get(LOGIN_REQUEST_FIELD_PERMISSION_TEMPLATE)?
+    {
+        UnknownValueRef::LengthDelimited(v) => {
+            let name = String::from_utf8_lossy(v).trim().to_owned();
+            (!name.is_empty()).then_some(name)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_templates() {
+        let templates = parse("");
+        assert_eq!(templates.len(), 3);
+        let denied = templates[0].denied_permissions();
+        assert!(denied.contains(&"keyboard"));
+        assert!(!denied.contains(&"audio"));
+        assert!(templates[2].denied_permissions().is_empty());
+        let templates = parse(r#"[{"name": "files", "permissions": ["file"]}]"#);
+        assert_eq!(
+            templates[0].denied_permissions().len(),
+            PERMISSIONS.len() - 1
+        );
+        assert!(parse("not json").is_empty());
+
+        let mut lr = LoginRequest::new();
+        assert_eq!(requested(&lr), None);
+        lr.mut_unknown_fields().add_length_delimited(
+            LOGIN_REQUEST_FIELD_PERMISSION_TEMPLATE,
+            b"Support ".to_vec(),
+        );
+        let bytes = lr.write_to_bytes().unwrap();
+        let lr = LoginRequest::parse_from_bytes(&bytes).unwrap();
+        assert_eq!(requested(&lr), Some("Support".to_owned()));
+    }
+}
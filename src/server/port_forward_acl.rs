@@ -0,0 +1,157 @@
+// Allow-list of destinations reachable through IP tunneling (port forwarding).
+//
+// The `port-forward-allow-list` option is a comma separated list of `target[:ports]`,
+// empty means every destination is allowed.
+//   target: `*`, a host name (`*.example.com` matches sub domains), an IP or a CIDR,
+//           IPv6 must be in brackets, e.g. `[fd00::/8]`
+//   ports:  `*`, `22` or `8000-8100`, all ports if omitted
+
+use cidr_utils::cidr::IpCidr;
+use hbb_common::{bail, config::Config, ResultType};
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+pub const OPTION_PORT_FORWARD_ALLOW_LIST: &str = "port-forward-allow-list";
+
+enum Target {
+    Any,
+    Host(String),
+    Cidr(IpCidr),
+}
+
+struct Rule {
+    target: Target,
+    ports: (i32, i32),
+}
+
+impl Rule {
+    fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let (target, ports) = if let Some(rest) = s.strip_prefix('[') {
+            let (target, rest) = rest.split_once(']')?;
+            (target, rest.strip_prefix(':'))
+        } else if s.matches(':').count() > 1 {
+            // bare IPv6 without ports
+            (s, None)
+        } else {
+            match s.split_once(':') {
+                Some((t, p)) => (t, Some(p)),
+                None => (s, None),
+            }
+        };
+        let ports = match ports {
+            None | Some("*") => (0, 65535),
+            Some(p) => match p.split_once('-') {
+                Some((a, b)) => (a.parse().ok()?, b.parse().ok()?),
+                None => {
+                    let p = p.parse().ok()?;
+                    (p, p)
+                }
+            },
+        };
+        let target = if target == "*" {
+            Target::Any
+        } else if let Ok(cidr) = IpCidr::from_str(target) {
+            Target::Cidr(cidr)
+        } else if !target.is_empty() {
+            Target::Host(target.to_lowercase())
+        } else {
+            return None;
+        };
+        Some(Self { target, ports })
+    }
+
+    fn matches(&self, host: &str, port: i32, ip: Option<IpAddr>) -> bool {
+        if port < self.ports.0 || port > self.ports.1 {
+            return false;
+        }
+        match &self.target {
+            Target::Any => true,
+            Target::Host(h) => {
+                let host = host.to_lowercase();
+                match h.strip_prefix("*.") {
+                    Some(suffix) => host.ends_with(&format!(".{}", suffix)),
+                    None => &host == h,
+                }
+            }
+            Target::Cidr(cidr) => ip
+                .or_else(|| host.trim_matches(|c| c == '[' || c == ']').parse().ok())
+                .map_or(false, |ip| cidr.contains(ip)),
+        }
+    }
+}
+
+fn rules_from(list: &str) -> Vec<Rule> {
+    list.split(',')
+        .filter(|x| !x.trim().is_empty())
+        .filter_map(|x| {
+            let rule = Rule::parse(x);
+            if rule.is_none() {
+                hbb_common::log::warn!("Invalid {} entry: {}", OPTION_PORT_FORWARD_ALLOW_LIST, x);
+            }
+            rule
+        })
+        .collect()
+}
+
+fn rules() -> Option<Vec<Rule>> {
+    let list = Config::get_option(OPTION_PORT_FORWARD_ALLOW_LIST);
+    if list.trim().is_empty() {
+        None
+    } else {
+        Some(rules_from(&list))
+    }
+}
+
+/// Whether `host:port`, connected at `ip` if known, is allowed.
+pub fn is_allowed(host: &str, port: i32, ip: Option<IpAddr>) -> bool {
+    rules().map_or(true, |rules| {
+        rules.iter().any(|r| r.matches(host, port, ip))
+    })
+}
+
+/// Resolve the destination of a port forward request against the allow-list.
+/// Returns `None` if `host` can be connected by name, or the resolved address
+/// that is allowed by an IP rule.
+pub async fn resolve(host: &str, port: i32) -> ResultType<Option<SocketAddr>> {
+    let Some(rules) = rules() else {
+        return Ok(None);
+    };
+    if rules.iter().any(|r| r.matches(host, port, None)) {
+        return Ok(None);
+    }
+    if let Ok(addrs) = hbb_common::tokio::net::lookup_host((host, port as u16)).await {
+        for addr in addrs {
+            if rules.iter().any(|r| r.matches(host, port, Some(addr.ip()))) {
+                return Ok(Some(addr));
+            }
+        }
+    }
+    bail!("{}:{} is not in the allow-list of the peer", host, port);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules() {
+        let rules = rules_from("localhost:22, *.lan:8000-8100, 10.0.0.0/8:*, [fd00::/8]:53, bad:x");
+        assert_eq!(rules.len(), 4);
+        let allowed = |host: &str, port: i32, ip: Option<&str>| {
+            let ip = ip.map(|x| x.parse().unwrap());
+            rules.iter().any(|r| r.matches(host, port, ip))
+        };
+        assert!(allowed("LOCALHOST", 22, None));
+        assert!(!allowed("localhost", 23, None));
+        assert!(allowed("nas.lan", 8080, None));
+        assert!(!allowed("lan", 8080, None));
+        assert!(allowed("10.1.2.3", 3389, None));
+        assert!(allowed("db.corp", 5432, Some("10.9.9.9")));
+        assert!(!allowed("db.corp", 5432, Some("192.168.1.1")));
+        assert!(allowed("fd00::1", 53, None));
+        assert!(!allowed("fd00::1", 54, None));
+    }
+}
@@ -0,0 +1,336 @@
+// Delegated approval of connections that need to be accepted, for unattended machines.
+//
+// With `approval-webhook` set, a connection waiting for click approval is not shown in the
+// connection manager. An approval request is posted to `approval-webhook` as json instead, with
+// `approve_url` and `deny_url`, signed links to the callback server listening on
+// `approval-callback-listen` (default 127.0.0.1:21120), reachable at `approval-callback-url`
+// (default http://127.0.0.1:21120). The callback server is plain http, it only listens on other
+// interfaces if `approval-callback-listen` says so, otherwise it is meant to be behind a proxy.
+// The connection is closed if nobody approves within `approval-timeout` seconds (60).
+//
+// Approval by another RustDesk peer id is not implemented: delivering the request to a peer that
+// is not connected to this machine needs a rendezvous message, and the id a peer logs in with is
+// self-reported, so it can not decide an approval.
+
+use super::connection::AuthConnType;
+use hbb_common::{
+    config::Config,
+    log,
+    rand::{self, Rng},
+    tokio::{
+        self,
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        sync::oneshot,
+    },
+    ResultType,
+};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+pub const OPTION_APPROVAL_WEBHOOK: &str = "approval-webhook";
+pub const OPTION_APPROVAL_TIMEOUT: &str = "approval-timeout";
+pub const OPTION_APPROVAL_CALLBACK_LISTEN: &str = "approval-callback-listen";
+pub const OPTION_APPROVAL_CALLBACK_URL: &str = "approval-callback-url";
+
+const DEFAULT_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_PORT: u16 = 21120;
+const MAX_REQUEST_LEN: usize = 4096;
+
+lazy_static::lazy_static! {
+    static ref PENDING: Mutex<HashMap<String, oneshot::Sender<(bool, String)>>> = Default::default();
+    // Signs the callback urls, links do not survive a restart.
+    static ref KEY: [u8; 32] = rand::random();
+}
+
+static CALLBACK_SERVER_STARTED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub peer_id: String,
+    pub peer_name: String,
+    pub ip: String,
+    pub conn_type: AuthConnType,
+}
+
+fn webhook_url() -> String {
+    Config::get_option(OPTION_APPROVAL_WEBHOOK)
+        .trim()
+        .to_owned()
+}
+
+pub fn enabled() -> bool {
+    !webhook_url().is_empty()
+}
+
+fn timeout() -> Duration {
+    let secs = Config::get_option(OPTION_APPROVAL_TIMEOUT)
+        .trim()
+        .parse::<u64>()
+        .unwrap_or(DEFAULT_TIMEOUT_SECS);
+    Duration::from_secs(secs.clamp(10, 3600))
+}
+
+fn hmac_sha256(key: &[u8], msg: &str) -> Vec<u8> {
+    let mut ipad = [0x36u8; 64];
+    let mut opad = [0x5cu8; 64];
+    for (i, b) in key.iter().take(64).enumerate() {
+        ipad[i] ^= b;
+        opad[i] ^= b;
+    }
+    let inner = Sha256::new()
+        .chain_update(ipad)
+        .chain_update(msg.as_bytes())
+        .finalize();
+    Sha256::new()
+        .chain_update(opad)
+        .chain_update(inner)
+        .finalize()
+        .to_vec()
+}
+
+fn sign(key: &[u8], code: &str, action: &str, expires: i64) -> String {
+    hex::encode(hmac_sha256(
+        key,
+        &format!("{}:{}:{}", code, action, expires),
+    ))
+}
+
+fn callback_url(code: &str, action: &str, expires: i64) -> String {
+    let mut base = Config::get_option(OPTION_APPROVAL_CALLBACK_URL)
+        .trim()
+        .trim_end_matches('/')
+        .to_owned();
+    if base.is_empty() {
+        base = format!("http://127.0.0.1:{}", DEFAULT_PORT);
+    }
+    format!(
+        "{}/approval?code={}&action={}&expires={}&sig={}",
+        base,
+        code,
+        action,
+        expires,
+        sign(&*KEY, code, action, expires)
+    )
+}
+
+fn conn_type_name(t: AuthConnType) -> &'static str {
+    match t {
+        AuthConnType::Remote => "remote desktop",
+        AuthConnType::FileTransfer => "file transfer",
+        AuthConnType::PortForward => "port forward",
+        AuthConnType::ViewCamera => "view camera",
+        AuthConnType::Terminal => "terminal",
+    }
+}
+
+/// Post the approval request, returns whether the connection is approved and by whom.
+pub async fn request(req: Request) -> (bool, String) {
+    let (tx, rx) = oneshot::channel();
+    let code = {
+        let mut pending = PENDING.lock().unwrap();
+        pending.retain(|_, tx| !tx.is_closed());
+        let mut rng = rand::thread_rng();
+        let code = loop {
+            let code = rng.gen_range(100_000..1_000_000).to_string();
+            if !pending.contains_key(&code) {
+                break code;
+            }
+        };
+        pending.insert(code.clone(), tx);
+        code
+    };
+    let timeout = timeout();
+    let message = format!(
+        "{} ({}) from {} requests {} access to {}, code {}",
+        req.peer_name,
+        req.peer_id,
+        req.ip,
+        conn_type_name(req.conn_type),
+        Config::get_id(),
+        code
+    );
+    log::info!("Remote approval requested: {}", message);
+    let url = webhook_url();
+    if !url.is_empty() {
+        start_callback_server();
+        let expires = hbb_common::get_time() / 1000 + timeout.as_secs() as i64;
+        let body = json!({
+            "event": "approval",
+            "id": Config::get_id(),
+            "hostname": crate::whoami_hostname(),
+            "code": code,
+            "peer_id": req.peer_id,
+            "peer_name": req.peer_name,
+            "ip": req.ip,
+            "conn_type": conn_type_name(req.conn_type),
+            "message": message,
+            "expires": expires,
+            "approve_url": callback_url(&code, "approve", expires),
+            "deny_url": callback_url(&code, "deny", expires),
+        });
+        tokio::spawn(async move {
+            if let Err(e) = post(&url, body.to_string()).await {
+                log::error!("Failed to post approval request to {}: {}", url, e);
+            }
+        });
+    }
+    let res = match tokio::time::timeout(timeout, rx).await {
+        Ok(Ok(res)) => res,
+        _ => (false, "timeout".to_owned()),
+    };
+    PENDING.lock().unwrap().remove(&code);
+    res
+}
+
+async fn post(url: &str, body: String) -> ResultType<()> {
+    let resp = crate::hbbs_http::create_http_client_async()
+        .post(url)
+        .timeout(Duration::from_secs(10))
+        .header("Content-Type", "application/json")
+        .body(body)
+        .send()
+        .await?;
+    if !resp.status().is_success() {
+        hbb_common::bail!("status {}", resp.status());
+    }
+    Ok(())
+}
+
+/// Answer the pending request `code`, returns false if there is none.
+pub fn resolve(code: &str, approved: bool, by: &str) -> bool {
+    let Some(tx) = PENDING.lock().unwrap().remove(code) else {
+        return false;
+    };
+    log::info!(
+        "Remote approval {} {} by {}",
+        code,
+        if approved { "approved" } else { "denied" },
+        by
+    );
+    tx.send((approved, by.to_owned())).is_ok()
+}
+
+fn start_callback_server() {
+    if CALLBACK_SERVER_STARTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let mut addr = Config::get_option(OPTION_APPROVAL_CALLBACK_LISTEN)
+        .trim()
+        .to_owned();
+    if addr.is_empty() {
+        addr = format!("127.0.0.1:{}", DEFAULT_PORT);
+    }
+    tokio::spawn(async move {
+        let listener = match TcpListener::bind(&addr).await {
+            Ok(l) => l,
+            Err(e) => {
+                log::error!("Failed to listen on {} for approval callbacks: {}", addr, e);
+                CALLBACK_SERVER_STARTED.store(false, Ordering::SeqCst);
+                return;
+            }
+        };
+        log::info!("Approval callbacks listening on {}", addr);
+        loop {
+            let Ok((mut stream, from)) = listener.accept().await else {
+                continue;
+            };
+            tokio::spawn(async move {
+                let mut buf = vec![0u8; MAX_REQUEST_LEN];
+                let n = match tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+                    .await
+                {
+                    Ok(Ok(n)) => n,
+                    _ => return,
+                };
+                let req = String::from_utf8_lossy(&buf[..n]);
+                let (status, text) = handle_callback(&req, &from.to_string());
+                let resp = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    text.len(),
+                    text
+                );
+                stream.write_all(resp.as_bytes()).await.ok();
+            });
+        }
+    });
+}
+
+fn handle_callback(req: &str, from: &str) -> (&'static str, String) {
+    let line = req.lines().next().unwrap_or_default();
+    let mut it = line.split_whitespace();
+    let (Some("GET"), Some(target)) = (it.next(), it.next()) else {
+        return ("400 Bad Request", "Bad request".to_owned());
+    };
+    let Some(query) = target.strip_prefix("/approval?") else {
+        return ("404 Not Found", "Not found".to_owned());
+    };
+    let params: HashMap<&str, &str> = query.split('&').filter_map(|x| x.split_once('=')).collect();
+    let get = |k: &str| params.get(k).copied().unwrap_or_default();
+    let (code, action, sig) = (get("code"), get("action"), get("sig"));
+    let expires = get("expires").parse::<i64>().unwrap_or(0);
+    if !verify(
+        &*KEY,
+        code,
+        action,
+        expires,
+        sig,
+        hbb_common::get_time() / 1000,
+    ) {
+        return ("403 Forbidden", "Invalid or expired link".to_owned());
+    }
+    if resolve(code, action == "approve", &format!("webhook ({})", from)) {
+        let done = if action == "approve" {
+            "Approved"
+        } else {
+            "Denied"
+        };
+        ("200 OK", done.to_owned())
+    } else {
+        ("404 Not Found", "No pending request".to_owned())
+    }
+}
+
+fn verify(key: &[u8], code: &str, action: &str, expires: i64, sig: &str, now: i64) -> bool {
+    if (action != "approve" && action != "deny") || expires < now {
+        return false;
+    }
+    let expected = sign(key, code, action, expires);
+    // constant time
+    expected.len() == sig.len()
+        && expected
+            .bytes()
+            .zip(sig.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            hex::encode(hmac_sha256(b"Jefe", "what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        let key = [7u8; 32];
+        let sig = sign(&key, "123456", "approve", 100);
+        assert!(verify(&key, "123456", "approve", 100, &sig, 99));
+        assert!(!verify(&key, "123456", "approve", 100, &sig, 101));
+        assert!(!verify(&key, "123456", "deny", 100, &sig, 99));
+        assert!(!verify(&key, "654321", "approve", 100, &sig, 99));
+        assert!(!verify(&[8u8; 32], "123456", "approve", 100, &sig, 99));
+    }
+}
@@ -0,0 +1,214 @@
+// Session time limits and idle timeouts per connection type.
+//
+// Set by the `session-limits` option as a json object, e.g.
+//   {"remote": {"max_minutes": 240},
+//    "file_transfer": {"max_minutes": 60, "idle_minutes": 10},
+//    "terminal": {"idle_minutes": 30},
+//    "port_forward": {"max_minutes": 480, "idle_minutes": 60},
+//    "warn_minutes": 5, "extend_minutes": 30}
+// 0 or a missing value means no limit. The controller is warned `warn_minutes` before the
+// session is closed for its length and can ask for `extend_minutes` more by sending `/extend`
+// in the chat. Port forwarding sessions have no messages, the extension is asked for them when
+// the warning is due. The connection manager approves or denies the extension.
+
+use super::connection::AuthConnType;
+use hbb_common::{config::Config, log};
+use serde_derive::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+pub const OPTION_SESSION_LIMITS: &str = "session-limits";
+pub const EXTEND_COMMAND: &str = "/extend";
+
+const DEFAULT_WARN_MINUTES: u64 = 5;
+const DEFAULT_EXTEND_MINUTES: u64 = 30;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Limit {
+    #[serde(default)]
+    pub max_minutes: u64,
+    #[serde(default)]
+    pub idle_minutes: u64,
+}
+
+fn default_warn_minutes() -> u64 {
+    DEFAULT_WARN_MINUTES
+}
+
+fn default_extend_minutes() -> u64 {
+    DEFAULT_EXTEND_MINUTES
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Policy {
+    #[serde(default)]
+    remote: Limit,
+    #[serde(default)]
+    file_transfer: Limit,
+    #[serde(default)]
+    port_forward: Limit,
+    #[serde(default)]
+    view_camera: Limit,
+    #[serde(default)]
+    terminal: Limit,
+    #[serde(default = "default_warn_minutes")]
+    warn_minutes: u64,
+    #[serde(default = "default_extend_minutes")]
+    extend_minutes: u64,
+}
+
+impl Policy {
+    fn limit(&self, conn_type: AuthConnType) -> Limit {
+        match conn_type {
+            AuthConnType::Remote => self.remote,
+            AuthConnType::FileTransfer => self.file_transfer,
+            AuthConnType::PortForward => self.port_forward,
+            AuthConnType::ViewCamera => self.view_camera,
+            AuthConnType::Terminal => self.terminal,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Check {
+    Ok,
+    /// Minutes left before the session is closed.
+    Warn(u64),
+    Close(&'static str),
+}
+
+#[derive(Debug)]
+pub struct SessionLimit {
+    max: Option<Duration>,
+    idle: Option<Duration>,
+    warn: Duration,
+    extend: Duration,
+    start: Instant,
+    last_active: Instant,
+    extended: Duration,
+    warned: bool,
+    extension_requested: bool,
+}
+
+fn minutes(m: u64) -> Option<Duration> {
+    (m > 0).then(|| Duration::from_secs(m * 60))
+}
+
+impl SessionLimit {
+    /// The limits of an authorized connection of `conn_type`, `None` if it has none.
+    pub fn new(conn_type: AuthConnType) -> Option<Self> {
+        let s = Config::get_option(OPTION_SESSION_LIMITS);
+        if s.trim().is_empty() {
+            return None;
+        }
+        match serde_json::from_str::<Policy>(&s) {
+            Ok(policy) => Self::with_policy(&policy, conn_type, Instant::now()),
+            Err(e) => {
+                log::error!("Invalid {}: {}", OPTION_SESSION_LIMITS, e);
+                None
+            }
+        }
+    }
+
+    fn with_policy(policy: &Policy, conn_type: AuthConnType, now: Instant) -> Option<Self> {
+        let limit = policy.limit(conn_type);
+        let (max, idle) = (minutes(limit.max_minutes), minutes(limit.idle_minutes));
+        if max.is_none() && idle.is_none() {
+            return None;
+        }
+        Some(Self {
+            max,
+            idle,
+            warn: Duration::from_secs(policy.warn_minutes * 60),
+            extend: Duration::from_secs(policy.extend_minutes.max(1) * 60),
+            start: now,
+            last_active: now,
+            extended: Duration::ZERO,
+            warned: false,
+            extension_requested: false,
+        })
+    }
+
+    pub fn active(&mut self) {
+        self.last_active = Instant::now();
+    }
+
+    pub fn set_last_active(&mut self, t: Instant) {
+        self.last_active = t;
+    }
+
+    pub fn check(&mut self, now: Instant) -> Check {
+        if let Some(idle) = self.idle {
+            if now.duration_since(self.last_active) >= idle {
+                return Check::Close("Connection failed due to inactivity");
+            }
+        }
+        if let Some(max) = self.max {
+            let deadline = self.start + max + self.extended;
+            if now >= deadline {
+                return Check::Close("Session time limit reached");
+            }
+            if !self.warned && now + self.warn >= deadline {
+                self.warned = true;
+                let left = deadline.duration_since(now).as_secs();
+                return Check::Warn((left + 59) / 60);
+            }
+        }
+        Check::Ok
+    }
+
+    /// Ask for an extension, returns its minutes or `None` if there is no time limit.
+    pub fn request_extension(&mut self) -> Option<u64> {
+        self.max?;
+        self.extension_requested = true;
+        Some(self.extend.as_secs() / 60)
+    }
+
+    /// Answer the requested extension, returns its minutes if it was granted.
+    pub fn answer_extension(&mut self, approved: bool) -> Option<u64> {
+        if !self.extension_requested {
+            return None;
+        }
+        self.extension_requested = false;
+        if !approved {
+            return None;
+        }
+        self.extended += self.extend;
+        self.warned = false;
+        Some(self.extend.as_secs() / 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits() {
+        let policy: Policy = serde_json::from_str(
+            r#"{"file_transfer": {"max_minutes": 60, "idle_minutes": 10}, "warn_minutes": 5}"#,
+        )
+        .unwrap();
+        let now = Instant::now();
+        assert!(SessionLimit::with_policy(&policy, AuthConnType::Remote, now).is_none());
+        let mut l = SessionLimit::with_policy(&policy, AuthConnType::FileTransfer, now).unwrap();
+        let min = |m: u64| now + Duration::from_secs(m * 60);
+        assert_eq!(l.check(min(9)), Check::Ok);
+        assert!(matches!(l.check(min(10)), Check::Close(_)));
+        l.last_active = min(50);
+        assert_eq!(l.check(min(54)), Check::Ok);
+        assert_eq!(l.check(min(55)), Check::Warn(5));
+        assert_eq!(l.check(min(56)), Check::Ok);
+        assert_eq!(l.answer_extension(true), None);
+        assert_eq!(l.request_extension(), Some(30));
+        assert_eq!(l.answer_extension(false), None);
+        assert_eq!(l.answer_extension(true), None);
+        assert_eq!(l.request_extension(), Some(30));
+        assert_eq!(l.answer_extension(true), Some(30));
+        l.last_active = min(59);
+        assert_eq!(l.check(min(60)), Check::Ok);
+        l.last_active = min(80);
+        assert_eq!(l.check(min(85)), Check::Warn(5));
+        l.last_active = min(89);
+        assert!(matches!(l.check(min(90)), Check::Close(_)));
+    }
+}
@@ -0,0 +1,143 @@
+// Time-limited share tokens for view-only presentation.
+//
+// The controlled side issues tokens with `rustdesk --share-token new <minutes>` or from the UI,
+// they are kept encrypted in the `share-tokens` option as a json array. A peer joins a remote desktop
+// session by entering a valid token as the password. Keyboard, clipboard, file transfer,
+// restart and block input permissions are then disabled for that connection and cannot be
+// enabled from the connection manager.
+
+use hbb_common::{
+    config::Config,
+    log,
+    password_security::{decrypt_str_or_original, encrypt_str_or_original},
+};
+use serde_derive::{Deserialize, Serialize};
+
+pub const OPTION_SHARE_TOKENS: &str = "share-tokens";
+pub const TOKEN_LEN: usize = 10;
+pub const MAX_MINUTES: u64 = 7 * 24 * 60;
+// Encrypted like the permanent password, the server needs the plain token to check a login.
+const ENCRYPT_VERSION: &str = "00";
+const ENCRYPT_MAX_LEN: usize = 128;
+
+// Permissions named as in `ipc::Data::SwitchPermission`, locked for share viewers.
+pub const DENIED_PERMISSIONS: &[&str] =
+    &["keyboard", "clipboard", "file", "restart", "block_input"];
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ShareToken {
+    pub token: String,
+    /// Unix time in seconds.
+    pub expires: i64,
+}
+
+impl ShareToken {
+    pub fn minutes_left(&self) -> i64 {
+        ((self.expires - now()) / 60).max(0)
+    }
+}
+
+fn parse(s: &str) -> Vec<ShareToken> {
+    if s.trim().is_empty() {
+        return vec![];
+    }
+    let tokens = serde_json::from_str::<Vec<ShareToken>>(s).unwrap_or_else(|e| {
+        log::error!("Invalid {}: {}", OPTION_SHARE_TOKENS, e);
+        vec![]
+    });
+    tokens
+        .into_iter()
+        .map(|mut t| {
+            t.token = decrypt_str_or_original(&t.token, ENCRYPT_VERSION).0;
+            t
+        })
+        .collect()
+}
+
+fn to_value(tokens: &[ShareToken]) -> String {
+    if tokens.is_empty() {
+        return "".to_owned();
+    }
+    let tokens: Vec<_> = tokens
+        .iter()
+        .map(|t| ShareToken {
+            token: encrypt_str_or_original(&t.token, ENCRYPT_VERSION, ENCRYPT_MAX_LEN),
+            expires: t.expires,
+        })
+        .collect();
+    serde_json::to_string(&tokens).unwrap_or_default()
+}
+
+fn unexpired(s: &str, now: i64) -> Vec<ShareToken> {
+    parse(s)
+        .into_iter()
+        .filter(|t| !t.token.is_empty() && t.expires > now)
+        .collect()
+}
+
+fn now() -> i64 {
+    hbb_common::get_time() / 1000
+}
+
+/// Tokens that are not expired yet.
+pub fn valid_tokens() -> Vec<String> {
+    unexpired(&Config::get_option(OPTION_SHARE_TOKENS), now())
+        .into_iter()
+        .map(|t| t.token)
+        .collect()
+}
+
+/// Add a new token valid for `minutes` to the option value `s`, dropping the expired ones.
+/// Returns the token and the new option value.
+pub fn add(s: &str, minutes: u64) -> (ShareToken, String) {
+    let now = now();
+    let mut tokens = unexpired(s, now);
+    let token = ShareToken {
+        token: Config::get_auto_password(TOKEN_LEN),
+        expires: now + minutes.clamp(1, MAX_MINUTES) as i64 * 60,
+    };
+    tokens.push(token.clone());
+    (token, to_value(&tokens))
+}
+
+/// Remove `token` from the option value `s`, or all tokens if `token` is empty.
+pub fn remove(s: &str, token: &str) -> String {
+    if token.is_empty() {
+        return "".to_owned();
+    }
+    let tokens: Vec<_> = unexpired(s, now())
+        .into_iter()
+        .filter(|t| t.token != token)
+        .collect();
+    to_value(&tokens)
+}
+
+pub fn list(s: &str) -> Vec<ShareToken> {
+    unexpired(s, now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens() {
+        let now = now();
+        let s = format!(
+            r#"[{{"token":"old","expires":{}}},{{"token":"live","expires":{}}}]"#,
+            now - 1,
+            now + 60
+        );
+        assert_eq!(unexpired(&s, now).len(), 1);
+        let (token, s) = add(&s, 5);
+        assert_eq!(token.token.len(), TOKEN_LEN);
+        assert!(!s.contains(&token.token) && !s.contains("live"));
+        let tokens = unexpired(&s, now);
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].token, "live");
+        assert!(tokens[1].expires >= now + 300);
+        assert_eq!(list(&remove(&s, "live")), vec![token]);
+        assert!(remove(&s, "").is_empty());
+        assert!(unexpired("not json", now).is_empty());
+    }
+}
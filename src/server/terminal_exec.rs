@@ -0,0 +1,252 @@
+// One-shot command execution over the terminal permission.
+//
+// A request is a `TerminalAction::Data` whose `terminal_id` is negative, `data` is the
+// json `ExecRequest`. The result is sent back as `TerminalResponse::Data` with the same
+// `terminal_id` and the json `ExecResult` as `data`. Negative ids are never used by PTY terminals.
+// At most a few requests run at a time per terminal service, more are rejected with a
+// `TerminalError`. The command line and exit status of each request go to the conn audit.
+
+use super::terminal_service::UserToken;
+use hbb_common::{anyhow::anyhow, log, ResultType};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    io::Read,
+    thread,
+    time::{Duration, Instant},
+};
+
+pub const DEFAULT_TIMEOUT_MS: u64 = 30_000;
+pub const MAX_TIMEOUT_MS: u64 = 10 * 60_000;
+pub const DEFAULT_MAX_OUTPUT: usize = 64 * 1024;
+pub const MAX_OUTPUT: usize = 4 * 1024 * 1024;
+
+#[inline]
+pub fn is_exec_id(terminal_id: i32) -> bool {
+    terminal_id < 0
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExecRequest {
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub timeout_ms: u64,
+    /// Cap of each of stdout and stderr in bytes, the rest is discarded.
+    #[serde(default)]
+    pub max_output: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExecResult {
+    /// -1 if the process was killed or did not start.
+    pub exit_code: i32,
+    pub stdout: String,
+    pub stderr: String,
+    pub timed_out: bool,
+    pub truncated: bool,
+    pub elapsed_ms: u64,
+    #[serde(default)]
+    pub error: String,
+}
+
+impl ExecRequest {
+    fn timeout(&self) -> Duration {
+        let ms = if self.timeout_ms == 0 {
+            DEFAULT_TIMEOUT_MS
+        } else {
+            self.timeout_ms.min(MAX_TIMEOUT_MS)
+        };
+        Duration::from_millis(ms)
+    }
+
+    fn max_output(&self) -> usize {
+        if self.max_output == 0 {
+            DEFAULT_MAX_OUTPUT
+        } else {
+            self.max_output.min(MAX_OUTPUT)
+        }
+    }
+}
+
+// Grace period for readers after the process exited, grandchildren may keep the pipes open.
+const READER_GRACE: Duration = Duration::from_secs(1);
+
+struct CappedReader {
+    out: std::sync::Arc<std::sync::Mutex<(Vec<u8>, bool)>>,
+    done: std::sync::mpsc::Receiver<()>,
+}
+
+impl CappedReader {
+    // Read all of `r` in a thread, keeping at most `cap` bytes.
+    fn spawn(mut r: impl Read + Send + 'static, cap: usize) -> Self {
+        let out: std::sync::Arc<std::sync::Mutex<(Vec<u8>, bool)>> = Default::default();
+        let (tx, done) = std::sync::mpsc::channel();
+        let out2 = out.clone();
+        thread::spawn(move || {
+            let mut buf = [0u8; 8192];
+            loop {
+                match r.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        let mut out = out2.lock().unwrap();
+                        let room = cap.saturating_sub(out.0.len());
+                        if n > room {
+                            out.1 = true;
+                        }
+                        out.0.extend_from_slice(&buf[..n.min(room)]);
+                    }
+                }
+            }
+            tx.send(()).ok();
+        });
+        Self { out, done }
+    }
+
+    // The output read so far, waiting up to `READER_GRACE` for the end of the stream.
+    fn finish(self) -> (String, bool) {
+        self.done.recv_timeout(READER_GRACE).ok();
+        let out = std::mem::take(&mut *self.out.lock().unwrap());
+        (String::from_utf8_lossy(&out.0).to_string(), out.1)
+    }
+}
+
+/// Run `req` to completion, blocking. Windows sessions with a user token run the command
+/// through a PTY as that user, then stderr is merged into stdout.
+pub fn run(req: &ExecRequest, user_token: Option<UserToken>) -> ExecResult {
+    let start = Instant::now();
+    let res = if cfg!(windows) && user_token.is_some() {
+        run_pty(req, user_token)
+    } else {
+        run_process(req)
+    };
+    let mut result = match res {
+        Ok(r) => r,
+        Err(e) => ExecResult {
+            exit_code: -1,
+            error: e.to_string(),
+            ..Default::default()
+        },
+    };
+    result.elapsed_ms = start.elapsed().as_millis() as _;
+    log::info!(
+        "Terminal exec {:?} exited with {}, timed out: {}",
+        req.program,
+        result.exit_code,
+        result.timed_out
+    );
+    result
+}
+
+fn run_process(req: &ExecRequest) -> ResultType<ExecResult> {
+    use std::process::{Command, Stdio};
+    let mut child = Command::new(&req.program)
+        .args(&req.args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let cap = req.max_output();
+    let stdout = CappedReader::spawn(child.stdout.take().ok_or(anyhow!("no stdout"))?, cap);
+    let stderr = CappedReader::spawn(child.stderr.take().ok_or(anyhow!("no stderr"))?, cap);
+    let deadline = Instant::now() + req.timeout();
+    let mut timed_out = false;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break Some(status);
+        }
+        if Instant::now() >= deadline {
+            timed_out = true;
+            child.kill().ok();
+            child.wait().ok();
+            break None;
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+    let (stdout, t1) = stdout.finish();
+    let (stderr, t2) = stderr.finish();
+    Ok(ExecResult {
+        exit_code: status.and_then(|s| s.code()).unwrap_or(-1),
+        stdout,
+        stderr,
+        timed_out,
+        truncated: t1 || t2,
+        ..Default::default()
+    })
+}
+
+fn run_pty(req: &ExecRequest, _user_token: Option<UserToken>) -> ResultType<ExecResult> {
+    use portable_pty::{CommandBuilder, PtySize};
+    let pty_pair = portable_pty::native_pty_system().openpty(PtySize {
+        rows: 24,
+        cols: 200,
+        pixel_width: 0,
+        pixel_height: 0,
+    })?;
+    #[allow(unused_mut)]
+    let mut cmd = CommandBuilder::new(&req.program);
+    cmd.args(&req.args);
+    #[cfg(target_os = "windows")]
+    if let Some(token) = _user_token {
+        cmd.set_user_token(token as _);
+    }
+    let mut child = pty_pair.slave.spawn_command(cmd)?;
+    let stdout = CappedReader::spawn(pty_pair.master.try_clone_reader()?, req.max_output());
+    let deadline = Instant::now() + req.timeout();
+    let mut timed_out = false;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break Some(status);
+        }
+        if Instant::now() >= deadline {
+            timed_out = true;
+            child.kill().ok();
+            break None;
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+    // Closing the pty ends the reader.
+    drop(pty_pair);
+    let (stdout, truncated) = stdout.finish();
+    Ok(ExecResult {
+        exit_code: status.map(|s| s.exit_code() as i32).unwrap_or(-1),
+        stdout,
+        timed_out,
+        truncated,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(windows))]
+    #[test]
+    fn test_run() {
+        let r = run(
+            &ExecRequest {
+                program: "sh".to_owned(),
+                args: vec!["-c".to_owned(), "echo out; echo err >&2; exit 3".to_owned()],
+                ..Default::default()
+            },
+            None,
+        );
+        assert_eq!(r.exit_code, 3);
+        assert_eq!(r.stdout, "out\n");
+        assert_eq!(r.stderr, "err\n");
+        assert!(!r.timed_out && !r.truncated);
+
+        let r = run(
+            &ExecRequest {
+                program: "sh".to_owned(),
+                args: vec!["-c".to_owned(), "yes | head -c 100; sleep 5".to_owned()],
+                timeout_ms: 200,
+                max_output: 10,
+            },
+            None,
+        );
+        assert!(r.timed_out && r.truncated);
+        assert_eq!(r.stdout.len(), 10);
+    }
+}
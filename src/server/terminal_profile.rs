@@ -0,0 +1,122 @@
+// Named shell profiles the controlling side can choose from when opening a terminal.
+//
+// Profiles are set by the `terminal-shell-profiles` option as a json array, e.g.
+//   [{"name": "bash login", "command": "/bin/bash", "login": true},
+//    {"name": "tmux attach", "command": "tmux", "args": ["new", "-A", "-s", "main"]}]
+// Their names are published in `PeerInfo.platform_additions` as `terminal_shell_profiles`.
+//
+// To choose a profile, the client sends a `TerminalAction::Data` for the not yet opened
+// `terminal_id` with the json `ProfileSelection` as `data`, right before the `OpenTerminal`.
+// A selection is never written to a shell, it is ignored for terminals which are already open,
+// and dropped if no `OpenTerminal` follows in time or the terminal is closed.
+// Older peers ignore data for unknown terminals and open the default shell. On the desktop,
+// the tab menu of the terminal window has a new tab entry for each profile of the peer.
+
+use hbb_common::{config::Config, log};
+use portable_pty::CommandBuilder;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const OPTION_TERMINAL_SHELL_PROFILES: &str = "terminal-shell-profiles";
+pub const PLATFORM_ADDITION_KEY: &str = "terminal_shell_profiles";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ShellProfile {
+    pub name: String,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub cwd: String,
+    /// Start as a login shell, ignored on Windows.
+    #[serde(default)]
+    pub login: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileSelection {
+    pub profile: String,
+}
+
+impl ProfileSelection {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        serde_json::from_slice::<Self>(data)
+            .ok()
+            .filter(|x| !x.profile.is_empty())
+    }
+}
+
+fn parse(s: &str) -> Vec<ShellProfile> {
+    if s.trim().is_empty() {
+        return vec![];
+    }
+    match serde_json::from_str::<Vec<ShellProfile>>(s) {
+        Ok(v) => v
+            .into_iter()
+            .filter(|p| !p.name.is_empty() && !p.command.is_empty())
+            .collect(),
+        Err(e) => {
+            log::error!("Invalid {}: {}", OPTION_TERMINAL_SHELL_PROFILES, e);
+            vec![]
+        }
+    }
+}
+
+pub fn profiles() -> Vec<ShellProfile> {
+    parse(&Config::get_option(OPTION_TERMINAL_SHELL_PROFILES))
+}
+
+pub fn names() -> Vec<String> {
+    profiles().into_iter().map(|p| p.name).collect()
+}
+
+pub fn get(name: &str) -> Option<ShellProfile> {
+    profiles().into_iter().find(|p| p.name == name)
+}
+
+impl ShellProfile {
+    pub fn to_command(&self) -> CommandBuilder {
+        let mut cmd = CommandBuilder::new(&self.command);
+        #[cfg(not(target_os = "windows"))]
+        if self.login {
+            cmd.arg("-l");
+        }
+        cmd.args(&self.args);
+        for (k, v) in self.env.iter() {
+            cmd.env(k, v);
+        }
+        if !self.cwd.is_empty() {
+            cmd.cwd(&self.cwd);
+        }
+        cmd
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let v = parse(
+            r#"[{"name": "zsh", "command": "/bin/zsh", "login": true, "env": {"A": "1"}},
+                {"name": "", "command": "sh"},
+                {"name": "tmux", "command": "tmux", "args": ["attach"], "cwd": "/tmp"}]"#,
+        );
+        assert_eq!(v.len(), 2);
+        assert!(v[0].login && v[0].env["A"] == "1");
+        assert_eq!(v[1].args, vec!["attach"]);
+        assert!(parse("not json").is_empty());
+        assert!(ProfileSelection::parse(b"ls\r").is_none());
+        assert_eq!(
+            ProfileSelection::parse(br#"{"profile":"zsh"}"#)
+                .unwrap()
+                .profile,
+            "zsh"
+        );
+        assert!(ProfileSelection::parse(br#"{"profile":"zsh","x":1}"#).is_none());
+    }
+}
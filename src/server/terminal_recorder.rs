@@ -0,0 +1,147 @@
+// Records terminal sessions as asciicast v2 files.
+// https://docs.asciinema.org/manual/asciicast/v2/
+
+use hbb_common::{
+    config::{self, Config},
+    log, ResultType,
+};
+use scrap::record::{RecordState, RecorderContext};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    sync::mpsc::Sender,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+const MIN_SECS: u64 = 1;
+
+pub struct TerminalRecorder {
+    filename: String,
+    writer: Option<BufWriter<File>>,
+    tx: Option<Sender<RecordState>>,
+    start: Instant,
+    written: bool,
+    // Trailing bytes of an incomplete utf-8 sequence from the last output.
+    pending: Vec<u8>,
+}
+
+pub fn is_enabled() -> bool {
+    config::option2bool(
+        "allow-auto-record-incoming",
+        &Config::get_option("allow-auto-record-incoming"),
+    )
+}
+
+impl TerminalRecorder {
+    pub fn new(terminal_id: i32, rows: u16, cols: u16, shell: &str) -> ResultType<Self> {
+        use crate::hbbs_http::record_upload;
+
+        #[cfg(windows)]
+        let root = crate::platform::is_root();
+        #[cfg(not(windows))]
+        let root = false;
+        let tx = if record_upload::is_enable() {
+            let (tx, rx) = std::sync::mpsc::channel();
+            record_upload::run(rx);
+            Some(tx)
+        } else {
+            None
+        };
+        let ctx = RecorderContext {
+            server: true,
+            id: Config::get_id(),
+            dir: crate::ui_interface::video_save_directory(root),
+            display_idx: 0,
+            camera: false,
+            tx: None,
+        };
+        let filename = ctx.terminal_filename(terminal_id)?;
+        let mut writer = BufWriter::new(File::create(&filename)?);
+        let header = serde_json::json!({
+            "version": 2,
+            "width": cols,
+            "height": rows,
+            "timestamp": SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default(),
+            "title": format!("Terminal {}", terminal_id),
+            "env": { "SHELL": shell, "TERM": "xterm-256color" },
+        });
+        writeln!(writer, "{}", header)?;
+        writer.flush()?;
+        log::info!("Recording terminal {} to {}", terminal_id, filename);
+        let recorder = Self {
+            filename,
+            writer: Some(writer),
+            tx,
+            start: Instant::now(),
+            written: false,
+            pending: Vec::new(),
+        };
+        recorder.send_state(RecordState::NewFile(recorder.filename.clone()));
+        Ok(recorder)
+    }
+
+    pub fn write_output(&mut self, data: &[u8]) {
+        self.pending.extend_from_slice(data);
+        let text = match std::str::from_utf8(&self.pending) {
+            Ok(s) => {
+                let s = s.to_owned();
+                self.pending.clear();
+                s
+            }
+            // Keep an incomplete sequence at the end for the next output.
+            Err(e) if e.error_len().is_none() => {
+                let rest = self.pending.split_off(e.valid_up_to());
+                let s = String::from_utf8_lossy(&self.pending).to_string();
+                self.pending = rest;
+                s
+            }
+            Err(_) => String::from_utf8_lossy(&std::mem::take(&mut self.pending)).to_string(),
+        };
+        if !text.is_empty() {
+            self.write_event("o", &text);
+        }
+    }
+
+    pub fn write_resize(&mut self, rows: u16, cols: u16) {
+        self.write_event("r", &format!("{}x{}", cols, rows));
+    }
+
+    fn write_event(&mut self, code: &str, data: &str) {
+        let Some(writer) = self.writer.as_mut() else {
+            return;
+        };
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let line = serde_json::json!([elapsed, code, data]);
+        if let Err(e) = writeln!(writer, "{}", line).and_then(|_| writer.flush()) {
+            log::error!(
+                "Failed to write terminal recording {}: {}",
+                self.filename,
+                e
+            );
+            self.writer = None;
+            return;
+        }
+        self.written = true;
+        self.send_state(RecordState::NewFrame);
+    }
+
+    fn send_state(&self, state: RecordState) {
+        self.tx.as_ref().map(|tx| tx.send(state));
+    }
+}
+
+impl Drop for TerminalRecorder {
+    fn drop(&mut self) {
+        if !self.pending.is_empty() {
+            let rest = String::from_utf8_lossy(&std::mem::take(&mut self.pending)).to_string();
+            self.write_event("o", &rest);
+        }
+        self.writer.take().map(|mut w| w.flush().ok());
+        let mut state = RecordState::WriteTail;
+        if !self.written || self.start.elapsed().as_secs() < MIN_SECS {
+            std::fs::remove_file(&self.filename).ok();
+            state = RecordState::RemoveFile;
+        }
+        self.send_state(state);
+    }
+}
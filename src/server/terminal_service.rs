@@ -1,4 +1,8 @@
-use super::*;
+use super::{
+    terminal_profile::{self, ProfileSelection},
+    terminal_recorder::TerminalRecorder,
+    *,
+};
 use hbb_common::{
     anyhow::{anyhow, Context, Result},
     compress,
@@ -23,6 +27,8 @@ const MAX_SERVICES: usize = 100; // Maximum number of persistent terminal servic
 const SERVICE_IDLE_TIMEOUT: Duration = Duration::from_secs(3600); // 1 hour idle timeout
 const CHANNEL_BUFFER_SIZE: usize = 100; // Number of messages to buffer in channel
 const COMPRESS_THRESHOLD: usize = 512; // Compress terminal data larger than this
+const PROFILE_SELECTION_TIMEOUT: Duration = Duration::from_secs(30); // Until the OpenTerminal
+const MAX_RUNNING_EXECS: usize = 4; // Concurrent exec requests per service
 
 lazy_static::lazy_static! {
     // Global registry of persistent terminal services indexed by service_id
@@ -458,6 +464,7 @@ pub struct TerminalSession {
     // Track if we've already sent the closed message
     closed_message_sent: bool,
     is_opened: bool,
+    recorder: Option<TerminalRecorder>,
 }
 
 impl TerminalSession {
@@ -479,6 +486,7 @@ impl TerminalSession {
             cols,
             closed_message_sent: false,
             is_opened: false,
+            recorder: None,
         }
     }
 
@@ -538,6 +546,9 @@ impl TerminalSession {
             let _ = child.kill();
             add_to_reaper(child);
         }
+
+        // Finish the recording file.
+        self.recorder = None;
     }
 }
 
@@ -557,6 +568,11 @@ pub struct PersistentTerminalService {
     pub is_persistent: bool,
     needs_session_sync: bool,
     is_specified_user: bool,
+    // Results of finished exec requests, sent by `read_outputs`.
+    exec_results: Vec<TerminalResponse>,
+    running_execs: usize,
+    // Shell profiles chosen for terminals not opened yet, with the time they were chosen.
+    profile_selections: HashMap<i32, (String, Instant)>,
 }
 
 impl PersistentTerminalService {
@@ -569,6 +585,9 @@ impl PersistentTerminalService {
             is_persistent,
             needs_session_sync: false,
             is_specified_user,
+            exec_results: Vec::new(),
+            running_execs: 0,
+            profile_selections: HashMap::new(),
         }
     }
 
@@ -576,6 +595,20 @@ impl PersistentTerminalService {
         self.last_activity = Instant::now();
     }
 
+    fn select_profile(&mut self, terminal_id: i32, profile: String) {
+        self.profile_selections
+            .retain(|_, (_, t)| t.elapsed() < PROFILE_SELECTION_TIMEOUT);
+        self.profile_selections
+            .insert(terminal_id, (profile, Instant::now()));
+    }
+
+    fn take_profile_selection(&mut self, terminal_id: i32) -> Option<String> {
+        self.profile_selections
+            .remove(&terminal_id)
+            .filter(|(_, t)| t.elapsed() < PROFILE_SELECTION_TIMEOUT)
+            .map(|(profile, _)| profile)
+    }
+
     /// Get list of terminal metadata
     pub fn list_terminals(&self) -> Vec<(i32, String, u32, Instant)> {
         self.sessions
@@ -622,11 +655,15 @@ impl PersistentTerminalService {
     }
 }
 
+/// Called with the audit event of a finished exec request.
+pub type ExecAudit = Box<dyn FnOnce(serde_json::Value) + Send>;
+
 pub struct TerminalServiceProxy {
     service_id: String,
     is_persistent: bool,
     #[cfg(target_os = "windows")]
     user_token: Option<UserToken>,
+    exec_audit: Option<ExecAudit>,
 }
 
 pub fn set_persistent(service_id: &str, is_persistent: bool) -> Result<()> {
@@ -656,6 +693,7 @@ impl TerminalServiceProxy {
             is_persistent,
             #[cfg(target_os = "windows")]
             user_token: _user_token,
+            exec_audit: None,
         }
     }
 
@@ -663,6 +701,10 @@ impl TerminalServiceProxy {
         &self.service_id
     }
 
+    pub fn set_exec_audit(&mut self, audit: ExecAudit) {
+        self.exec_audit = Some(audit);
+    }
+
     pub fn handle_action(&mut self, action: &TerminalAction) -> Result<Option<TerminalResponse>> {
         let service = match get_service(&self.service_id) {
             Some(s) => s,
@@ -688,13 +730,24 @@ impl TerminalServiceProxy {
                     .cloned();
                 self.handle_resize(session, resize)
             }
+            Some(terminal_action::Union::Data(data))
+                if super::terminal_exec::is_exec_id(data.terminal_id) =>
+            {
+                self.handle_exec(&mut service.lock().unwrap(), data)
+            }
             Some(terminal_action::Union::Data(data)) => {
-                let session = service
-                    .lock()
-                    .unwrap()
-                    .sessions
-                    .get(&data.terminal_id)
-                    .cloned();
+                let session = {
+                    let mut service = service.lock().unwrap();
+                    let session = service.sessions.get(&data.terminal_id).cloned();
+                    // Never typed into a shell, ignored if the terminal is already open.
+                    if let Some(sel) = ProfileSelection::parse(&data.data) {
+                        if session.is_none() {
+                            service.select_profile(data.terminal_id, sel.profile);
+                        }
+                        return Ok(None);
+                    }
+                    session
+                };
                 self.handle_data(session, data)
             }
             Some(terminal_action::Union::Close(close)) => {
@@ -767,12 +820,32 @@ impl TerminalServiceProxy {
         let pty_system = portable_pty::native_pty_system();
         let pty_pair = pty_system.openpty(pty_size).context("Failed to open PTY")?;
 
-        // Use default shell for the platform
-        let shell = get_default_shell();
+        // Use the chosen profile, or the default shell for the platform
+        let profile = match service.take_profile_selection(open.terminal_id) {
+            Some(name) => match terminal_profile::get(&name) {
+                Some(profile) => Some(profile),
+                None => {
+                    let mut opened = TerminalOpened::new();
+                    opened.terminal_id = open.terminal_id;
+                    opened.success = false;
+                    opened.message = format!("Shell profile \"{}\" not found", name);
+                    response.set_opened(opened);
+                    return Ok(Some(response));
+                }
+            },
+            None => None,
+        };
+        let shell = profile
+            .as_ref()
+            .map(|p| p.command.clone())
+            .unwrap_or_else(get_default_shell);
         log::debug!("Using shell: {}", shell);
 
         #[allow(unused_mut)]
-        let mut cmd = CommandBuilder::new(&shell);
+        let mut cmd = match &profile {
+            Some(profile) => profile.to_command(),
+            None => CommandBuilder::new(&shell),
+        };
 
         #[cfg(target_os = "windows")]
         if let Some(token) = &self.user_token {
@@ -886,6 +959,12 @@ impl TerminalServiceProxy {
         session.reader_thread = Some(reader_thread);
         session.writer_thread = Some(writer_thread);
         session.is_opened = true;
+        if super::terminal_recorder::is_enabled() {
+            match TerminalRecorder::new(open.terminal_id, session.rows, session.cols, &shell) {
+                Ok(recorder) => session.recorder = Some(recorder),
+                Err(e) => log::error!("Failed to record terminal {}: {}", open.terminal_id, e),
+            }
+        }
 
         let mut opened = TerminalOpened::new();
         opened.terminal_id = open.terminal_id;
@@ -925,6 +1004,10 @@ impl TerminalServiceProxy {
             session.update_activity();
             session.rows = resize.rows as u16;
             session.cols = resize.cols as u16;
+            let (rows, cols) = (session.rows, session.cols);
+            if let Some(recorder) = session.recorder.as_mut() {
+                recorder.write_resize(rows, cols);
+            }
 
             if let Some(pty_pair) = &session.pty_pair {
                 pty_pair.master.resize(PtySize {
@@ -961,12 +1044,98 @@ impl TerminalServiceProxy {
         Ok(None)
     }
 
+    fn handle_exec(
+        &mut self,
+        service: &mut PersistentTerminalService,
+        data: &TerminalData,
+    ) -> Result<Option<TerminalResponse>> {
+        use super::terminal_exec::{self, ExecRequest};
+
+        let terminal_id = data.terminal_id;
+        let req: ExecRequest = match serde_json::from_slice(&data.data) {
+            Ok(req) => req,
+            Err(e) => {
+                let mut response = TerminalResponse::new();
+                let mut error = TerminalError::new();
+                error.terminal_id = terminal_id;
+                error.message = format!("Invalid exec request: {}", e);
+                response.set_error(error);
+                return Ok(Some(response));
+            }
+        };
+        if service.running_execs >= MAX_RUNNING_EXECS {
+            let mut response = TerminalResponse::new();
+            let mut error = TerminalError::new();
+            error.terminal_id = terminal_id;
+            error.message = format!(
+                "Too many commands running, at most {} at a time",
+                MAX_RUNNING_EXECS
+            );
+            response.set_error(error);
+            return Ok(Some(response));
+        }
+        service.running_execs += 1;
+        log::info!(
+            "Terminal exec {} in {}: {} {:?}",
+            terminal_id,
+            self.service_id,
+            req.program,
+            req.args
+        );
+        #[cfg(target_os = "windows")]
+        let user_token = self.user_token;
+        #[cfg(not(target_os = "windows"))]
+        let user_token = None;
+        let service_id = self.service_id.clone();
+        let audit = self.exec_audit.take();
+        std::thread::spawn(move || {
+            let result = terminal_exec::run(&req, user_token);
+            log::info!(
+                "Terminal exec {} in {} exited with {}, timed out: {}",
+                terminal_id,
+                service_id,
+                result.exit_code,
+                result.timed_out
+            );
+            if let Some(audit) = audit {
+                audit(serde_json::json!({
+                    "action": "terminal_exec",
+                    "program": req.program,
+                    "args": req.args,
+                    "exit_code": result.exit_code,
+                    "timed_out": result.timed_out,
+                    "error": result.error,
+                }));
+            }
+            let data = serde_json::to_vec(&result).unwrap_or_default();
+            let mut terminal_data = TerminalData::new();
+            terminal_data.terminal_id = terminal_id;
+            if data.len() > COMPRESS_THRESHOLD {
+                terminal_data.data = bytes::Bytes::from(compress::compress(&data));
+                terminal_data.compressed = true;
+            } else {
+                terminal_data.data = bytes::Bytes::from(data);
+            }
+            let mut response = TerminalResponse::new();
+            response.set_data(terminal_data);
+            if let Some(service) = get_service(&service_id) {
+                let mut service = service.lock().unwrap();
+                service.running_execs = service.running_execs.saturating_sub(1);
+                service.exec_results.push(response);
+            } else {
+                log::warn!("Terminal service {} is gone, drop exec result", service_id);
+            }
+        });
+        Ok(None)
+    }
+
     fn handle_close(
         &self,
         service: &mut PersistentTerminalService,
         close: &CloseTerminal,
     ) -> Result<Option<TerminalResponse>> {
         let mut response = TerminalResponse::new();
+        service.profile_selections.remove(&close.terminal_id);
 
         // Always close and remove the terminal
         if let Some(session_arc) = service.sessions.remove(&close.terminal_id) {
@@ -998,16 +1167,18 @@ impl TerminalServiceProxy {
         };
 
         // Get session references with minimal service lock time
-        let sessions: Vec<(i32, Arc<Mutex<TerminalSession>>)> = {
-            let service = service.lock().unwrap();
-            service
-                .sessions
-                .iter()
-                .map(|(id, session)| (*id, session.clone()))
-                .collect()
+        let (sessions, mut responses): (Vec<(i32, Arc<Mutex<TerminalSession>>)>, _) = {
+            let mut service = service.lock().unwrap();
+            (
+                service
+                    .sessions
+                    .iter()
+                    .map(|(id, session)| (*id, session.clone()))
+                    .collect(),
+                std::mem::take(&mut service.exec_results),
+            )
         };
 
-        let mut responses = Vec::new();
         let mut closed_terminals = Vec::new();
 
         // Process each session with its own lock
@@ -1049,6 +1220,9 @@ impl TerminalServiceProxy {
                 // Update buffer after reading
                 for data in &received_data {
                     session.output_buffer.append(data);
+                    if let Some(recorder) = session.recorder.as_mut() {
+                        recorder.write_output(data);
+                    }
                 }
 
                 // Process received data for responses
@@ -0,0 +1,243 @@
+// Trust lifetime, last-seen tracking and revocation of trusted devices.
+//
+// A trusted device skips 2FA. `trusted-device-days` limits how long a device stays trusted
+// after it was added and `trusted-device-idle-days` how long it may go unused, on top of the
+// built-in expiry. Every 2FA bypass updates the last-seen time and ip of the device, an alarm
+// is raised when a device shows up from an ip it has not used before. The tracking and the
+// revocation history are saved in `trusted_devices_seen.json`. List and revoke devices with
+// `rustdesk --trusted-devices`.
+
+use hbb_common::{
+    bytes::Bytes,
+    config::{Config, TrustedDevice},
+    log,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::HashMap, path::PathBuf, sync::Mutex};
+
+pub const OPTION_TRUSTED_DEVICE_DAYS: &str = "trusted-device-days";
+pub const OPTION_TRUSTED_DEVICE_IDLE_DAYS: &str = "trusted-device-idle-days";
+
+const MAX_IPS: usize = 16;
+const MAX_REVOKED: usize = 100;
+const DAY_MS: i64 = 24 * 3600 * 1000;
+
+lazy_static::lazy_static! {
+    static ref STATE: Mutex<Option<State>> = Default::default();
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Seen {
+    /// Unix time in ms.
+    pub last_seen: i64,
+    /// The latest ips, most recent last.
+    pub ips: Vec<String>,
+    pub uses: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Revoked {
+    pub hwid: String,
+    pub id: String,
+    pub name: String,
+    /// Unix time in ms.
+    pub time: i64,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct State {
+    #[serde(default)]
+    seen: HashMap<String, Seen>,
+    #[serde(default)]
+    revoked: Vec<Revoked>,
+}
+
+/// A trusted device with its tracking, for listing.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    /// Hex encoded.
+    pub hwid: String,
+    pub id: String,
+    pub name: String,
+    pub platform: String,
+    /// When it was trusted, unix time in ms.
+    pub time: i64,
+    /// When it stops being trusted, unix time in ms, 0 for the built-in expiry only.
+    pub expires: i64,
+    #[serde(flatten)]
+    pub seen: Seen,
+}
+
+fn path() -> PathBuf {
+    Config::path("trusted_devices_seen.json")
+}
+
+fn with_state<T>(f: impl FnOnce(&mut State) -> T) -> T {
+    let mut lock = STATE.lock().unwrap();
+    let state = lock.get_or_insert_with(|| {
+        std::fs::read_to_string(path())
+            .ok()
+            .and_then(|s| serde_json::from_str::<State>(&s).ok())
+            .unwrap_or_default()
+    });
+    f(state)
+}
+
+fn save(state: &State) {
+    let res = serde_json::to_string(state)
+        .map_err(|e| e.to_string())
+        .and_then(|s| std::fs::write(path(), s).map_err(|e| e.to_string()));
+    if let Err(e) = res {
+        log::error!("Failed to save trusted devices: {}", e);
+    }
+}
+
+fn days(name: &str) -> i64 {
+    Config::get_option(name)
+        .trim()
+        .parse::<i64>()
+        .unwrap_or(0)
+        .max(0)
+}
+
+fn expires(device: &TrustedDevice, seen: Option<&Seen>, lifetime: i64, idle: i64) -> i64 {
+    let mut expires = 0;
+    if lifetime > 0 {
+        expires = device.time + lifetime * DAY_MS;
+    }
+    if idle > 0 {
+        let last = seen.map(|s| s.last_seen).unwrap_or(0).max(device.time);
+        let t = last + idle * DAY_MS;
+        if expires == 0 || t < expires {
+            expires = t;
+        }
+    }
+    expires
+}
+
+/// Whether `device` is no longer trusted by the configured lifetimes.
+pub fn expired(device: &TrustedDevice) -> bool {
+    let key = hex::encode(&device.hwid);
+    let seen = with_state(|state| state.seen.get(&key).cloned());
+    let t = expires(
+        device,
+        seen.as_ref(),
+        days(OPTION_TRUSTED_DEVICE_DAYS),
+        days(OPTION_TRUSTED_DEVICE_IDLE_DAYS),
+    );
+    t > 0 && t <= hbb_common::get_time()
+}
+
+/// Record a use of the device from `ip`, returns true if the device has used other ips
+/// before but not this one.
+pub fn seen(hwid: &[u8], ip: &str) -> bool {
+    let key = hex::encode(hwid);
+    with_state(|state| {
+        let seen = state.seen.entry(key).or_default();
+        let new_ip = !seen.ips.is_empty() && !seen.ips.iter().any(|x| x == ip);
+        seen.ips.retain(|x| x != ip);
+        seen.ips.push(ip.to_owned());
+        if seen.ips.len() > MAX_IPS {
+            seen.ips.remove(0);
+        }
+        seen.last_seen = hbb_common::get_time();
+        seen.uses += 1;
+        save(state);
+        new_ip
+    })
+}
+
+pub fn list() -> Vec<DeviceInfo> {
+    let (lifetime, idle) = (
+        days(OPTION_TRUSTED_DEVICE_DAYS),
+        days(OPTION_TRUSTED_DEVICE_IDLE_DAYS),
+    );
+    let devices = Config::get_trusted_devices();
+    with_state(|state| {
+        devices
+            .iter()
+            .map(|d| {
+                let seen = state.seen.get(&hex::encode(&d.hwid));
+                DeviceInfo {
+                    hwid: hex::encode(&d.hwid),
+                    id: d.id.clone(),
+                    name: d.name.clone(),
+                    platform: d.platform.clone(),
+                    time: d.time,
+                    expires: expires(d, seen, lifetime, idle),
+                    seen: seen.cloned().unwrap_or_default(),
+                }
+            })
+            .collect()
+    })
+}
+
+pub fn revoked() -> Vec<Revoked> {
+    with_state(|state| state.revoked.clone())
+}
+
+/// Revoke the devices whose hwid (hex) or peer id is in `keys`, all devices if empty.
+/// Returns the number of revoked devices.
+pub fn revoke(keys: &[String], reason: &str) -> usize {
+    let devices: Vec<_> = Config::get_trusted_devices()
+        .into_iter()
+        .filter(|d| {
+            keys.is_empty()
+                || keys
+                    .iter()
+                    .any(|k| *k == d.id || *k == hex::encode(&d.hwid))
+        })
+        .collect();
+    if devices.is_empty() {
+        return 0;
+    }
+    let hwids: Vec<Bytes> = devices.iter().map(|d| d.hwid.clone()).collect();
+    Config::remove_trusted_devices(&hwids);
+    let now = hbb_common::get_time();
+    with_state(|state| {
+        for d in devices.iter() {
+            let hwid = hex::encode(&d.hwid);
+            log::info!("Trusted device {} ({}) revoked: {}", d.id, hwid, reason);
+            state.seen.remove(&hwid);
+            state.revoked.push(Revoked {
+                hwid,
+                id: d.id.clone(),
+                name: d.name.clone(),
+                time: now,
+                reason: reason.to_owned(),
+            });
+        }
+        let n = state.revoked.len();
+        if n > MAX_REVOKED {
+            state.revoked.drain(..n - MAX_REVOKED);
+        }
+        save(state);
+    });
+    devices.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expires() {
+        let device = TrustedDevice {
+            hwid: Bytes::from_static(b"hwid"),
+            time: 1000 * DAY_MS,
+            id: "123456789".to_owned(),
+            name: "test".to_owned(),
+            platform: "Linux".to_owned(),
+        };
+        assert_eq!(expires(&device, None, 0, 0), 0);
+        assert_eq!(expires(&device, None, 30, 0), 1030 * DAY_MS);
+        assert_eq!(expires(&device, None, 0, 7), 1007 * DAY_MS);
+        let seen = Seen {
+            last_seen: 1010 * DAY_MS,
+            ..Default::default()
+        };
+        assert_eq!(expires(&device, Some(&seen), 0, 7), 1017 * DAY_MS);
+        assert_eq!(expires(&device, Some(&seen), 12, 7), 1012 * DAY_MS);
+    }
+}
@@ -44,6 +44,10 @@ const ADJUST_RATIO_INTERVAL: usize = 3; // Adjust quality ratio every 3 seconds
 const DYNAMIC_SCREEN_THRESHOLD: usize = 2; // Allow increase quality ratio if encode more than 2 times in one second
 const DELAY_THRESHOLD_150MS: u32 = 150; // 150ms is the threshold for good network condition
 
+// Percentile of users the fps and ratio adapt to, 100 means the slowest user.
+// Lower values keep the quality for most viewers of a broadcast when a few have bad networks.
+const OPTION_QOS_PERCENTILE: &str = "qos-viewer-percentile";
+
 #[derive(Default, Debug, Clone)]
 struct UserDelay {
     response_delayed: bool,
@@ -111,6 +115,7 @@ pub struct VideoQoS {
     adjust_ratio_instant: Instant,
     abr_config: bool,
     new_user_instant: Instant,
+    percentile: u32,
 }
 
 impl Default for VideoQoS {
@@ -124,6 +129,7 @@ impl Default for VideoQoS {
             adjust_ratio_instant: Instant::now(),
             abr_config: true,
             new_user_instant: Instant::now(),
+            percentile: 100,
         }
     }
 }
@@ -186,6 +192,11 @@ impl VideoQoS {
     pub fn on_connection_open(&mut self, id: i32) {
         self.users.insert(id, UserData::default());
         self.abr_config = Config::get_option("enable-abr") != "N";
+        self.percentile = Config::get_option(OPTION_QOS_PERCENTILE)
+            .trim()
+            .parse::<u32>()
+            .unwrap_or(100)
+            .clamp(1, 100);
         self.new_user_instant = Instant::now();
     }
 
@@ -417,9 +428,9 @@ impl VideoQoS {
         if !self.in_vbr_state() {
             return;
         }
-        // Get maximum delay from all users
-        let max_delay = self.users.iter().map(|u| u.1.delay.avg_delay()).max();
-        let Some(max_delay) = max_delay else {
+        // Get maximum delay from all users, or the delay at the configured percentile
+        let delays = self.users.iter().map(|u| u.1.delay.avg_delay()).collect();
+        let Some(max_delay) = percentile(delays, self.percentile) else {
             return;
         };
 
@@ -510,15 +521,20 @@ impl VideoQoS {
     // Adjust fps based on network delay and user response time
     fn adjust_fps(&mut self) {
         let highest_fps = self.highest_fps();
-        // Get minimum fps from all users
-        let mut fps = self
+        // Get minimum fps from all users, or the fps at the configured percentile
+        let fpss = self
             .users
             .iter()
             .map(|u| u.1.delay.fps.unwrap_or(INIT_FPS))
-            .min()
-            .unwrap_or(INIT_FPS);
+            .collect();
+        let mut fps = percentile(fpss, 100 - self.percentile).unwrap_or(INIT_FPS);
 
-        if self.users.iter().any(|u| u.1.delay.response_delayed) {
+        let delayed = self
+            .users
+            .iter()
+            .filter(|u| u.1.delay.response_delayed)
+            .count();
+        if delayed * 100 > self.users.len() * (100 - self.percentile) as usize {
             if fps > MIN_FPS + 1 {
                 fps = MIN_FPS + 1;
             }
@@ -536,6 +552,16 @@ impl VideoQoS {
     }
 }
 
+// Nearest-rank percentile, 0 is the minimum and 100 the maximum.
+fn percentile(mut v: Vec<u32>, p: u32) -> Option<u32> {
+    if v.is_empty() {
+        return None;
+    }
+    v.sort_unstable();
+    let rank = (p.min(100) as usize * v.len() + 99) / 100;
+    Some(v[rank.max(1) - 1])
+}
+
 #[derive(Default, Debug, Clone)]
 struct RttCalculator {
     min_rtt: Option<u32>,        // Historical minimum RTT ever observed
@@ -593,3 +619,19 @@ impl RttCalculator {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        assert_eq!(percentile(vec![], 50), None);
+        let v: Vec<u32> = (1..=20).rev().collect();
+        assert_eq!(percentile(v.clone(), 100), Some(20));
+        assert_eq!(percentile(v.clone(), 90), Some(18));
+        assert_eq!(percentile(v.clone(), 0), Some(1));
+        assert_eq!(percentile(v, 10), Some(2));
+        assert_eq!(percentile(vec![7], 30), Some(7));
+    }
+}
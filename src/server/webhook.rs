@@ -0,0 +1,304 @@
+// HTTP webhook notifications for connection lifecycle events.
+//
+// Hooks are set by the `webhooks` option as a json array, e.g.
+//   [{"url": "https://hooks.slack.com/services/...", "events": ["connect", "alarm"],
+//     "template": "{\"text\": \"{{message}}\"}"},
+//    {"url": "https://ntfy.sh/my-topic", "template": "{{message}}",
+//     "headers": {"Content-Type": "text/plain", "Priority": "high"}, "retries": 5}]
+// Empty `events` means all events. `{{name}}` in the template is replaced by the json escaped
+// variable, see `Notification::vars`. Without a template, all variables are posted as a json object.
+//
+// `connect` and `alarm` can be caused by peers which are not authenticated, each of them is sent at
+// most once a minute per ip, and at most `PRE_AUTH_MAX_PER_MINUTE` times a minute in total. The
+// events are limited separately, so that connects from an ip do not suppress its alarms.
+
+use hbb_common::{config::Config, log, tokio, ResultType};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+pub const OPTION_WEBHOOKS: &str = "webhooks";
+const DEFAULT_RETRIES: u32 = 3;
+const MAX_RETRIES: u32 = 10;
+const TIMEOUT: Duration = Duration::from_secs(10);
+const PRE_AUTH_INTERVAL: Duration = Duration::from_secs(60);
+const PRE_AUTH_MAX_PER_MINUTE: usize = 30;
+
+lazy_static::lazy_static! {
+    static ref PRE_AUTH_LIMITER: Mutex<Limiter> = Default::default();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    // A peer opened a connection, before authentication.
+    Connect,
+    Authorize,
+    Alarm,
+    File,
+    // An authorized connection was closed.
+    Close,
+}
+
+impl Event {
+    // Events which a peer can cause without authentication.
+    fn pre_auth(&self) -> bool {
+        matches!(self, Event::Connect | Event::Alarm)
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Event::Connect => "connect",
+            Event::Authorize => "authorize",
+            Event::Alarm => "alarm",
+            Event::File => "file",
+            Event::Close => "close",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+    #[serde(default)]
+    pub events: Vec<Event>,
+    #[serde(default)]
+    pub template: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub retries: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Notification {
+    pub peer_id: String,
+    pub peer_name: String,
+    pub ip: String,
+    pub message: String,
+    pub detail: Value,
+}
+
+impl Notification {
+    fn vars(&self, event: Event) -> Map<String, Value> {
+        let mut m = Map::new();
+        m.insert("event".into(), json!(event.as_str()));
+        m.insert("id".into(), json!(Config::get_id()));
+        m.insert("hostname".into(), json!(crate::whoami_hostname()));
+        m.insert("time".into(), json!(hbb_common::get_time()));
+        m.insert("peer_id".into(), json!(self.peer_id));
+        m.insert("peer_name".into(), json!(self.peer_name));
+        m.insert("ip".into(), json!(self.ip));
+        m.insert("message".into(), json!(self.message));
+        m.insert("detail".into(), self.detail.clone());
+        m
+    }
+}
+
+fn parse(s: &str) -> Vec<Webhook> {
+    if s.trim().is_empty() {
+        return vec![];
+    }
+    match serde_json::from_str::<Vec<Webhook>>(s) {
+        Ok(v) => v.into_iter().filter(|h| !h.url.is_empty()).collect(),
+        Err(e) => {
+            log::error!("Invalid {}: {}", OPTION_WEBHOOKS, e);
+            vec![]
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Limiter {
+    last: HashMap<(Event, String), Instant>,
+    sent: HashMap<Event, VecDeque<Instant>>,
+}
+
+impl Limiter {
+    fn allow(&mut self, event: Event, ip: &str, now: Instant) -> bool {
+        self.last
+            .retain(|_, t| now.saturating_duration_since(*t) < PRE_AUTH_INTERVAL);
+        let sent = self.sent.entry(event).or_default();
+        while sent.front().map_or(false, |t| {
+            now.saturating_duration_since(*t) >= PRE_AUTH_INTERVAL
+        }) {
+            sent.pop_front();
+        }
+        let key = (event, ip.to_owned());
+        if self.last.contains_key(&key) || sent.len() >= PRE_AUTH_MAX_PER_MINUTE {
+            return false;
+        }
+        self.last.insert(key, now);
+        sent.push_back(now);
+        true
+    }
+}
+
+fn render(template: &str, vars: &Map<String, Value>) -> String {
+    if template.is_empty() {
+        return Value::Object(vars.clone()).to_string();
+    }
+    // One pass over the template, so that `{{name}}` in the values, e.g. a peer name, is kept.
+    let mut body = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        body.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let var = after
+            .find("}}")
+            .and_then(|end| vars.get(&after[..end]).map(|v| (v, end)));
+        match var {
+            Some((v, end)) => {
+                // Strings are inserted without quotes, so they can be used inside json strings.
+                match v {
+                    Value::String(s) => {
+                        let s = Value::String(s.clone()).to_string();
+                        body.push_str(&s[1..s.len() - 1]);
+                    }
+                    v => body.push_str(&v.to_string()),
+                }
+                rest = &after[end + 2..];
+            }
+            None => {
+                body.push_str("{{");
+                rest = after;
+            }
+        }
+    }
+    body.push_str(rest);
+    body
+}
+
+/// Send `n` to the webhooks subscribed to `event`, in the background.
+pub fn notify(event: Event, n: Notification) {
+    let hooks: Vec<_> = parse(&Config::get_option(OPTION_WEBHOOKS))
+        .into_iter()
+        .filter(|h| h.events.is_empty() || h.events.contains(&event))
+        .collect();
+    if hooks.is_empty() {
+        return;
+    }
+    if event.pre_auth()
+        && !PRE_AUTH_LIMITER
+            .lock()
+            .unwrap()
+            .allow(event, &n.ip, Instant::now())
+    {
+        log::debug!("Webhook {} from {} is rate limited", event.as_str(), n.ip);
+        return;
+    }
+    let vars = n.vars(event);
+    for hook in hooks {
+        let body = render(&hook.template, &vars);
+        let run = async move {
+            let retries = hook.retries.unwrap_or(DEFAULT_RETRIES).min(MAX_RETRIES);
+            let mut delay = Duration::from_secs(1);
+            for i in 0..=retries {
+                match post(&hook, body.clone()).await {
+                    Ok(()) => return,
+                    Err(e) => log::warn!(
+                        "Webhook {} for {} failed ({}/{}): {}",
+                        hook.url,
+                        event.as_str(),
+                        i + 1,
+                        retries + 1,
+                        e
+                    ),
+                }
+                if i < retries {
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+            }
+        };
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            handle.spawn(run);
+        } else {
+            std::thread::spawn(move || {
+                if let Ok(rt) = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                {
+                    rt.block_on(run);
+                }
+            });
+        }
+    }
+}
+
+async fn post(hook: &Webhook, body: String) -> ResultType<()> {
+    let mut req = crate::hbbs_http::create_http_client_async()
+        .post(&hook.url)
+        .timeout(TIMEOUT);
+    if !hook
+        .headers
+        .keys()
+        .any(|k| k.eq_ignore_ascii_case("content-type"))
+    {
+        req = req.header("Content-Type", "application/json");
+    }
+    for (k, v) in hook.headers.iter() {
+        req = req.header(k, v);
+    }
+    let resp = req.body(body).send().await?;
+    if !resp.status().is_success() {
+        hbb_common::bail!("status {}", resp.status());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_render() {
+        let hooks = parse(
+            r#"[{"url": "https://example.com/a", "events": ["connect", "close"]},
+                {"url": "", "events": []},
+                {"url": "https://example.com/b", "template": "{\"text\": \"{{message}} {{time}}\"}"}]"#,
+        );
+        assert_eq!(hooks.len(), 2);
+        assert_eq!(hooks[0].events, vec![Event::Connect, Event::Close]);
+        assert!(parse(r#"[{"url": "x", "events": ["unknown"]}]"#).is_empty());
+
+        let mut vars = Map::new();
+        vars.insert("message".into(), json!("peer \"a\"\nconnected"));
+        vars.insert("time".into(), json!(12));
+        let body = render(&hooks[1].template, &vars);
+        assert_eq!(body, r#"{"text": "peer \"a\"\nconnected 12"}"#);
+        let v: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(v["text"], "peer \"a\"\nconnected 12");
+        assert_eq!(
+            serde_json::from_str::<Value>(&render("", &vars)).unwrap()["time"],
+            12
+        );
+        vars.insert("message".into(), json!("{{time}} {{"));
+        assert_eq!(
+            render("{{message}}|{{unknown}}|{{time}}", &vars),
+            "{{time}} {{|{{unknown}}|12"
+        );
+    }
+
+    #[test]
+    fn test_limiter() {
+        let now = Instant::now();
+        let mut limiter = Limiter::default();
+        let (connect, alarm) = (Event::Connect, Event::Alarm);
+        assert!(limiter.allow(connect, "1.1.1.1", now));
+        assert!(!limiter.allow(connect, "1.1.1.1", now + Duration::from_secs(59)));
+        assert!(limiter.allow(alarm, "1.1.1.1", now + Duration::from_secs(1)));
+        assert!(limiter.allow(connect, "1.1.1.1", now + PRE_AUTH_INTERVAL));
+        let later = now + Duration::from_secs(120);
+        for i in 0..PRE_AUTH_MAX_PER_MINUTE {
+            assert!(limiter.allow(connect, &i.to_string(), later));
+        }
+        assert!(!limiter.allow(connect, "2.2.2.2", later));
+        assert!(limiter.allow(alarm, "2.2.2.2", later));
+        assert!(limiter.allow(connect, "2.2.2.2", later + PRE_AUTH_INTERVAL));
+    }
+}
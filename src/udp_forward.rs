@@ -0,0 +1,76 @@
+// UDP port forwarding shares the PORT_FORWARD connection type with TCP.
+// The controlling side marks a UDP target by prefixing `PortForward.host` with `udp://`.
+// After login the raw stream carries datagrams, each prefixed with its length as a big
+// endian u16, so that datagram boundaries survive however the stream chunks the bytes.
+
+use bytes::{Buf, BufMut, BytesMut};
+
+pub const UDP_SCHEME: &str = "udp://";
+/// Local sessions without traffic for this long are closed.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub const UDP_SESSION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(120);
+pub const MAX_DATAGRAM_SIZE: usize = u16::MAX as usize;
+
+/// Split `udp://host` into `(true, host)`, other hosts are TCP.
+pub fn parse_host(host: &str) -> (bool, &str) {
+    match host.strip_prefix(UDP_SCHEME) {
+        Some(h) => (true, h),
+        None => (false, host),
+    }
+}
+
+pub fn encode(datagram: &[u8]) -> BytesMut {
+    let len = datagram.len().min(MAX_DATAGRAM_SIZE);
+    let mut buf = BytesMut::with_capacity(2 + len);
+    buf.put_u16(len as u16);
+    buf.put_slice(&datagram[..len]);
+    buf
+}
+
+/// Reassembles length-prefixed datagrams from stream chunks.
+#[derive(Default)]
+pub struct Decoder {
+    buf: BytesMut,
+}
+
+impl Decoder {
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    pub fn next_datagram(&mut self) -> Option<BytesMut> {
+        if self.buf.len() < 2 {
+            return None;
+        }
+        let len = u16::from_be_bytes([self.buf[0], self.buf[1]]) as usize;
+        if self.buf.len() < 2 + len {
+            return None;
+        }
+        self.buf.advance(2);
+        Some(self.buf.split_to(len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decoder() {
+        let mut stream = encode(b"hello").to_vec();
+        stream.extend(encode(b"").to_vec());
+        stream.extend(encode(b"world!").to_vec());
+        let mut d = Decoder::default();
+        let mut out = vec![];
+        // feed byte by byte to simulate arbitrary chunking
+        for b in stream {
+            d.feed(&[b]);
+            while let Some(x) = d.next_datagram() {
+                out.push(x.to_vec());
+            }
+        }
+        assert_eq!(out, vec![b"hello".to_vec(), vec![], b"world!".to_vec()]);
+        assert_eq!(parse_host("udp://10.0.0.1"), (true, "10.0.0.1"));
+        assert_eq!(parse_host("localhost"), (false, "localhost"));
+    }
+}
@@ -71,6 +71,12 @@ impl InvokeUiCM for SciterHandler {
     }
 
     fn file_transfer_log(&self, _action: &str, _log: &str) {}
+
+    // Not supported, the requested permission template is never granted.
+    fn update_permission_template(&self, _client: &crate::ui_cm_interface::Client) {}
+
+    // Not supported, the session extension is never granted.
+    fn update_session_extension(&self, _client: &crate::ui_cm_interface::Client) {}
 }
 
 impl SciterHandler {
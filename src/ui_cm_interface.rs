@@ -63,11 +63,37 @@ pub struct Client {
     pub from_switch: bool,
     pub in_voice_call: bool,
     pub incoming_voice_call: bool,
+    // The permission template requested by the peer, waiting for approval.
+    pub permission_template: Option<PermissionTemplateRequest>,
+    // Minutes of the session extension the peer asks for, 0 if none.
+    pub session_extension: u64,
     #[serde(skip)]
     #[cfg(not(any(target_os = "ios")))]
     tx: UnboundedSender<Data>,
 }
 
+#[derive(Serialize, Clone)]
+pub struct PermissionTemplateRequest {
+    pub name: String,
+    pub permissions: Vec<String>,
+}
+
+#[cfg(any(target_os = "android", feature = "flutter"))]
+impl Client {
+    fn set_permission(&mut self, name: &str, enabled: bool) {
+        match name {
+            "keyboard" => self.keyboard = enabled,
+            "clipboard" => self.clipboard = enabled,
+            "audio" => self.audio = enabled,
+            "file" => self.file = enabled,
+            "restart" => self.restart = enabled,
+            "recording" => self.recording = enabled,
+            "block_input" => self.block_input = enabled,
+            _ => {}
+        }
+    }
+}
+
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 struct IpcTaskRunner<T: InvokeUiCM> {
     stream: Connection,
@@ -109,6 +135,10 @@ pub trait InvokeUiCM: Send + Clone + 'static + Sized {
 
     fn update_voice_call_state(&self, client: &Client);
 
+    fn update_permission_template(&self, client: &Client);
+
+    fn update_session_extension(&self, client: &Client);
+
     fn file_transfer_log(&self, action: &str, log: &str);
 }
 
@@ -169,6 +199,8 @@ impl<T: InvokeUiCM> ConnectionManager<T> {
             tx,
             in_voice_call: false,
             incoming_voice_call: false,
+            permission_template: None,
+            session_extension: 0,
         };
         CLIENTS
             .write()
@@ -255,6 +287,22 @@ impl<T: InvokeUiCM> ConnectionManager<T> {
             self.ui_handler.update_voice_call_state(client);
         }
     }
+
+    #[cfg(not(target_os = "ios"))]
+    fn permission_template_requested(&self, id: i32, name: String, permissions: Vec<String>) {
+        if let Some(client) = CLIENTS.write().unwrap().get_mut(&id) {
+            client.permission_template = Some(PermissionTemplateRequest { name, permissions });
+            self.ui_handler.update_permission_template(client);
+        }
+    }
+
+    #[cfg(not(target_os = "ios"))]
+    fn session_extension_requested(&self, id: i32, minutes: u64) {
+        if let Some(client) = CLIENTS.write().unwrap().get_mut(&id) {
+            client.session_extension = minutes;
+            self.ui_handler.update_session_extension(client);
+        }
+    }
 }
 
 #[inline]
@@ -351,6 +399,7 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
 
         // for tmp use, without real conn id
         let mut write_jobs: Vec<fs::TransferJob> = Vec::new();
+        let mut delta_writers = HashMap::new();
 
         #[cfg(target_os = "windows")]
         let is_authorized = self.cm.is_authorized(self.conn_id);
@@ -443,10 +492,15 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
                                     if let ipc::FS::WriteBlock { id, file_num, data: _, compressed } = fs {
                                         if let Ok(bytes) = self.stream.next_raw().await {
                                             fs = ipc::FS::WriteBlock{id, file_num, data:bytes.into(), compressed};
-                                            handle_fs(fs, &mut write_jobs, &self.tx, Some(&tx_log)).await;
+                                            handle_fs(fs, &mut write_jobs, &mut delta_writers, &self.tx, Some(&tx_log)).await;
+                                        }
+                                    } else if let ipc::FS::Delta(_) = fs {
+                                        if let Ok(bytes) = self.stream.next_raw().await {
+                                            fs = ipc::FS::Delta(bytes.into());
+                                            handle_fs(fs, &mut write_jobs, &mut delta_writers, &self.tx, Some(&tx_log)).await;
                                         }
                                     } else {
-                                        handle_fs(fs, &mut write_jobs, &self.tx, Some(&tx_log)).await;
+                                        handle_fs(fs, &mut write_jobs, &mut delta_writers, &self.tx, Some(&tx_log)).await;
                                     }
                                     let log = fs::serialize_transfer_jobs(&write_jobs);
                                     self.cm.ui_handler.file_transfer_log("transfer", &log);
@@ -502,6 +556,12 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
                                 Data::CloseVoiceCall(reason) => {
                                     self.cm.voice_call_closed(self.conn_id, reason.as_str());
                                 }
+                                Data::PermissionTemplateRequest((name, permissions)) => {
+                                    self.cm.permission_template_requested(self.conn_id, name, permissions);
+                                }
+                                Data::SessionExtensionRequest(minutes) => {
+                                    self.cm.session_extension_requested(self.conn_id, minutes);
+                                }
                                 #[cfg(target_os = "windows")]
                                 Data::ClipboardNonFile(_) => {
                                     match crate::clipboard::check_clipboard_cm() {
@@ -674,6 +734,7 @@ pub async fn start_listen<T: InvokeUiCM>(
 ) {
     let mut current_id = 0;
     let mut write_jobs: Vec<fs::TransferJob> = Vec::new();
+    let mut delta_writers = HashMap::new();
     loop {
         match rx.recv().await {
             Some(Data::Login {
@@ -720,7 +781,7 @@ pub async fn start_listen<T: InvokeUiCM>(
                 cm.new_message(current_id, text);
             }
             Some(Data::FS(fs)) => {
-                handle_fs(fs, &mut write_jobs, &tx, None).await;
+                handle_fs(fs, &mut write_jobs, &mut delta_writers, &tx, None).await;
             }
             Some(Data::Close) => {
                 break;
@@ -734,6 +795,12 @@ pub async fn start_listen<T: InvokeUiCM>(
             Some(Data::CloseVoiceCall(reason)) => {
                 cm.voice_call_closed(current_id, reason.as_str());
             }
+            Some(Data::PermissionTemplateRequest((name, permissions))) => {
+                cm.permission_template_requested(current_id, name, permissions);
+            }
+            Some(Data::SessionExtensionRequest(minutes)) => {
+                cm.session_extension_requested(current_id, minutes);
+            }
             None => {
                 break;
             }
@@ -747,6 +814,8 @@ pub async fn start_listen<T: InvokeUiCM>(
 async fn handle_fs(
     fs: ipc::FS,
     write_jobs: &mut Vec<fs::TransferJob>,
+    // The delta uploads by job, with their path.
+    delta_writers: &mut HashMap<i32, (String, crate::file_delta::Writer)>,
     tx: &UnboundedSender<Data>,
     tx_log: Option<&UnboundedSender<String>>,
 ) {
@@ -814,6 +883,7 @@ async fn handle_fs(
             write_jobs.push(job);
         }
         ipc::FS::CancelWrite { id } => {
+            delta_writers.remove(&id);
             if let Some(job) = fs::remove_job(id, write_jobs) {
                 job.remove_download_file();
                 tx_log.map(|tx: &UnboundedSender<String>| {
@@ -826,6 +896,16 @@ async fn handle_fs(
                 job.modify_time();
                 send_raw(fs::new_done(id, file_num), tx);
                 tx_log.map(|tx| tx.send(serialize_transfer_job(&job, true, false, "")));
+                if let fs::DataSource::FilePath(p) = &job.data_source {
+                    if crate::file_manifest::enabled() {
+                        let names = job.files().iter().map(|f| f.name.clone()).collect();
+                        let tx = tx.clone();
+                        crate::file_manifest::spawn_build(id, p.clone(), names, move |m| {
+                            let m = serde_json::to_string(&m).unwrap_or_default();
+                            allow_err!(tx.send(Data::FileManifest((true, m))));
+                        });
+                    }
+                }
             }
         }
         ipc::FS::WriteError { id, file_num, err } => {
@@ -922,10 +1002,88 @@ async fn handle_fs(
         ipc::FS::Rename { id, path, new_name } => {
             rename_file(path, new_name, id, tx).await;
         }
+        ipc::FS::DeltaBegin { id, path } => {
+            use crate::file_delta::{Delta, Writer};
+            let writer = match Writer::new(&PathBuf::from(&path)) {
+                Ok(writer) => writer,
+                Err(err) => {
+                    let done = Delta::Done {
+                        id,
+                        path,
+                        size: 0,
+                        copied: 0,
+                        literal: 0,
+                        error: err.to_string(),
+                    };
+                    send_delta_done(&done, tx);
+                    return;
+                }
+            };
+            let block_size = writer.block_size();
+            let done = writer.done(id, &path, "".to_owned());
+            delta_writers.insert(id, (path.clone(), writer));
+            let tx = tx.clone();
+            std::thread::spawn(move || {
+                let stop = std::sync::atomic::AtomicBool::new(false);
+                match crate::file_delta::signatures(&PathBuf::from(&path), block_size, &stop) {
+                    Ok(signatures) => {
+                        let mut msg = Message::new();
+                        msg.set_file_response(
+                            Delta::Signatures { id, block_size }.to_response(&signatures),
+                        );
+                        send_raw(msg, &tx);
+                    }
+                    Err(err) => {
+                        let mut done = done;
+                        if let Delta::Done { error, .. } = &mut done {
+                            *error = err.to_string();
+                        }
+                        send_delta_done(&done, &tx);
+                    }
+                }
+            });
+        }
+        ipc::FS::Delta(bytes) => {
+            use crate::file_delta::Delta;
+            let Some((delta, data)) = FileResponse::parse_from_bytes(&bytes)
+                .ok()
+                .and_then(|fr| Delta::from_response(&fr))
+            else {
+                return;
+            };
+            let id = delta.id();
+            let Some((_, writer)) = delta_writers.get_mut(&id) else {
+                return;
+            };
+            let res = match &delta {
+                Delta::Copy { block, count, .. } => writer.copy(*block, *count),
+                Delta::Literal { .. } => writer.literal(&data),
+                Delta::End {
+                    size,
+                    sha256,
+                    modified,
+                    ..
+                } => writer.finish(*size, sha256, *modified),
+                _ => return,
+            };
+            if res.is_err() || matches!(delta, Delta::End { .. }) {
+                if let Some((path, writer)) = delta_writers.remove(&id) {
+                    let error = res.err().map(|e| e.to_string()).unwrap_or_default();
+                    send_delta_done(&writer.done(id, &path, error), tx);
+                }
+            }
+        }
         _ => {}
     }
 }
 
+// The connection audits the result of a delta upload before it passes it on.
+fn send_delta_done(done: &crate::file_delta::Delta, tx: &UnboundedSender<Data>) {
+    allow_err!(tx.send(Data::FileDelta(
+        serde_json::to_string(done).unwrap_or_default()
+    )));
+}
+
 #[cfg(not(any(target_os = "ios")))]
 async fn read_empty_dirs(dir: &str, include_hidden: bool, tx: &UnboundedSender<Data>) {
     let path = dir.to_owned();
@@ -1089,6 +1247,39 @@ pub fn handle_incoming_voice_call(id: i32, accept: bool) {
     };
 }
 
+// Approve or deny the requested permission template, the ui updates the permissions itself.
+#[cfg(any(target_os = "android", target_os = "ios", feature = "flutter"))]
+pub fn handle_permission_template(id: i32, approve: bool) {
+    if let Some(client) = CLIENTS.write().unwrap().get_mut(&id) {
+        let Some(_t) = client.permission_template.take() else {
+            return;
+        };
+        // Not handled in iOS yet.
+        #[cfg(not(any(target_os = "ios")))]
+        {
+            if approve {
+                for name in crate::server::access_policy::PERMISSIONS {
+                    client.set_permission(name, _t.permissions.iter().any(|x| x == *name));
+                }
+            }
+            allow_err!(client.tx.send(Data::PermissionTemplateResponse(approve)));
+        }
+    };
+}
+
+#[cfg(any(target_os = "android", target_os = "ios", feature = "flutter"))]
+pub fn handle_session_extension(id: i32, approve: bool) {
+    if let Some(client) = CLIENTS.write().unwrap().get_mut(&id) {
+        if client.session_extension == 0 {
+            return;
+        }
+        client.session_extension = 0;
+        // Not handled in iOS yet.
+        #[cfg(not(any(target_os = "ios")))]
+        allow_err!(client.tx.send(Data::SessionExtensionResponse(approve)));
+    };
+}
+
 #[cfg(any(target_os = "android", target_os = "ios", feature = "flutter"))]
 #[inline]
 pub fn close_voice_call(id: i32) {
@@ -575,6 +575,23 @@ pub fn update_temporary_password() {
     allow_err!(ipc::update_temporary_password());
 }
 
+/// Issue a share token valid for `minutes`, for view-only peers.
+pub fn create_share_token(minutes: u64) -> String {
+    let current = get_option(crate::share_token::OPTION_SHARE_TOKENS);
+    let (token, value) = crate::share_token::add(&current, minutes);
+    set_option(crate::share_token::OPTION_SHARE_TOKENS.to_owned(), value);
+    token.token
+}
+
+/// Revoke `token`, or all share tokens if empty.
+pub fn revoke_share_token(token: String) {
+    let current = get_option(crate::share_token::OPTION_SHARE_TOKENS);
+    set_option(
+        crate::share_token::OPTION_SHARE_TOKENS.to_owned(),
+        crate::share_token::remove(&current, &token),
+    );
+}
+
 #[inline]
 pub fn permanent_password() -> String {
     #[cfg(any(target_os = "android", target_os = "ios"))]
@@ -1447,6 +1464,36 @@ pub fn verify2fa(code: String) -> bool {
     res
 }
 
+/// Start enrolling an additional 2FA device, returns the otpauth url or an empty string.
+pub fn generate2fa_device(label: String, digits: usize, algorithm: String) -> String {
+    crate::auth_2fa::generate2fa_device(label, digits, algorithm).unwrap_or_else(|e| {
+        log::error!("Failed to generate 2fa device: {}", e);
+        "".to_owned()
+    })
+}
+
+pub fn list_2fa_devices() -> String {
+    serde_json::to_string(&crate::auth_2fa::list_devices()).unwrap_or_default()
+}
+
+/// Returns the error, empty on success.
+pub fn remove_2fa_device(label: String) -> String {
+    let res = crate::auth_2fa::remove_device(&label);
+    refresh_options();
+    res.err().map(|e| e.to_string()).unwrap_or_default()
+}
+
+/// New recovery codes separated by newlines, replacing the old ones.
+pub fn generate_2fa_recovery_codes() -> String {
+    let res = crate::auth_2fa::generate_recovery_codes();
+    refresh_options();
+    res.map(|codes| codes.join("\n")).unwrap_or_default()
+}
+
+pub fn get_2fa_recovery_codes_left() -> usize {
+    crate::auth_2fa::recovery_codes_left()
+}
+
 pub fn has_valid_bot() -> bool {
     crate::auth_2fa::TelegramBot::get().map_or(false, |bot| bot.is_some())
 }
@@ -762,6 +762,33 @@ impl<T: InvokeUiSession> Session<T> {
 
     // Terminal methods
     pub fn open_terminal(&self, terminal_id: i32, rows: u32, cols: u32) {
+        self.open_terminal_with_profile(terminal_id, rows, cols, String::new());
+    }
+
+    /// Open a terminal with a shell profile published in `platform_additions`,
+    /// the default shell of the peer if `profile` is empty.
+    pub fn open_terminal_with_profile(
+        &self,
+        terminal_id: i32,
+        rows: u32,
+        cols: u32,
+        profile: String,
+    ) {
+        if !profile.is_empty() {
+            let mut action = TerminalAction::new();
+            action.set_data(TerminalData {
+                terminal_id,
+                data: bytes::Bytes::from(
+                    serde_json::json!({ "profile": profile })
+                        .to_string()
+                        .into_bytes(),
+                ),
+                ..Default::default()
+            });
+            let mut msg_out = Message::new();
+            msg_out.set_terminal_action(action);
+            self.send(Data::Message(msg_out));
+        }
         let mut action = TerminalAction::new();
         action.set_open(OpenTerminal {
             terminal_id,
@@ -1665,6 +1692,9 @@ pub trait InvokeUiSession: Send + Sync + Clone + 'static + Sized + Default {
     fn is_multi_ui_session(&self) -> bool;
     fn update_record_status(&self, start: bool);
     fn update_empty_dirs(&self, _res: ReadEmptyDirsResponse) {}
+    fn update_sync_plan(&self, _id: i32, _plan: &str, _err: &str) {}
+    fn update_search_result(&self, _id: i32, _result: &str, _err: &str) {}
+    fn retry_job(&self, _id: i32, _retry_of: i32, _path: &str, _to: &str, _is_remote: bool) {}
     fn printer_request(&self, id: i32, path: String);
     fn handle_screenshot_resp(&self, sid: String, msg: String);
     fn handle_terminal_response(&self, response: TerminalResponse);